@@ -0,0 +1,403 @@
+//! Derive macros for the BER-TLV data objects of the
+//! [`pcsc`](https://docs.rs/pcsc) crate.
+//!
+//! This crate implements `#[derive(TlvDecode, TlvEncode)]`. Use it through
+//! the `derive` feature of `pcsc`, which re-exports the macros with their
+//! traits in `pcsc::tlv`; see `pcsc::tlv::TlvDecode` for the attributes.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{Attribute, Data, DeriveInput, Fields, GenericArgument, Ident, LitInt, PathArguments, Type};
+
+// How a field holds its data objects.
+enum Kind {
+    Required,
+    Optional,
+    Repeated,
+}
+
+struct Field {
+    ident: Ident,
+    ty: Type,
+    tag: u32,
+    kind: Kind,
+}
+
+impl Field {
+    // The local holding the field while decoding, named apart from the
+    // other locals of the generated code.
+    fn local(&self) -> Ident {
+        format_ident!("__tlv_field_{}", self.ident)
+    }
+}
+
+struct Struct {
+    tag: Option<u32>,
+    fields: Vec<Field>,
+}
+
+// Parse the `#[tlv(...)]` attributes: the tag, and for fields whether they
+// are repeated.
+fn parse_attributes(attrs: &[Attribute]) -> syn::Result<(Option<u32>, bool)> {
+    let mut tag = None;
+    let mut repeated = false;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("tlv")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                let lit: LitInt = meta.value()?.parse()?;
+                let value = lit.base10_parse::<u32>()?;
+                if value == 0 {
+                    return Err(syn::Error::new(lit.span(), "tag 00 is not valid"));
+                }
+                tag = Some(value);
+                Ok(())
+            } else if meta.path.is_ident("repeated") {
+                repeated = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `tag = ...` or `repeated`"))
+            }
+        })?;
+    }
+    Ok((tag, repeated))
+}
+
+// The `T` of `name<T>`, such as `Option<T>` or `Vec<T>`, if the type is
+// of that form.
+fn generic_inner<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let path = match *ty {
+        Type::Path(ref path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != name {
+        return None;
+    }
+    match segment.arguments {
+        PathArguments::AngleBracketed(ref args) if args.args.len() == 1 => match args.args[0] {
+            GenericArgument::Type(ref ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn parse_struct(input: &DeriveInput) -> syn::Result<Struct> {
+    let (tag, repeated) = parse_attributes(&input.attrs)?;
+    if repeated {
+        return Err(syn::Error::new(input.span(), "`repeated` applies to fields only"));
+    }
+    let named = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(syn::Error::new(input.span(), "expected a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new(input.span(), "expected a struct with named fields")),
+    };
+    let mut fields: Vec<Field> = Vec::new();
+    for field in named {
+        let (tag, repeated) = parse_attributes(&field.attrs)?;
+        let tag = tag.ok_or_else(|| syn::Error::new(field.span(), "missing `#[tlv(tag = ...)]`"))?;
+        if fields.iter().any(|other| other.tag == tag) {
+            return Err(syn::Error::new(field.span(), format!("duplicate tag {:X}", tag)));
+        }
+        let (ty, kind) = if repeated {
+            let inner = generic_inner(&field.ty, "Vec")
+                .ok_or_else(|| syn::Error::new(field.ty.span(), "a `repeated` field must be a `Vec`"))?;
+            (inner.clone(), Kind::Repeated)
+        } else if let Some(inner) = generic_inner(&field.ty, "Option") {
+            (inner.clone(), Kind::Optional)
+        } else {
+            (field.ty.clone(), Kind::Required)
+        };
+        fields.push(Field {
+            ident: field.ident.clone().expect("named field"),
+            ty,
+            tag,
+            kind,
+        });
+    }
+    Ok(Struct { tag, fields })
+}
+
+/// Derive `pcsc::tlv::TlvDecode` for a struct whose fields are data
+/// objects.
+#[proc_macro_derive(TlvDecode, attributes(tlv))]
+pub fn derive_tlv_decode(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match parse_struct(&input) {
+        Ok(parsed) => decode_impl(&input, &parsed).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn decode_impl(input: &DeriveInput, parsed: &Struct) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let declarations = parsed.fields.iter().map(|field| {
+        let local = field.local();
+        let ty = &field.ty;
+        match field.kind {
+            Kind::Repeated => quote! { let mut #local: ::std::vec::Vec<#ty> = ::std::vec::Vec::new(); },
+            _ => quote! { let mut #local: ::std::option::Option<#ty> = ::std::option::Option::None; },
+        }
+    });
+    let arms = parsed.fields.iter().map(|field| {
+        let local = field.local();
+        let ty = &field.ty;
+        let tag = field.tag;
+        let decode = quote! {
+            <#ty as ::pcsc::tlv::TlvDecode>::decode_value(__tlv_object.value())
+                .map_err(|__tlv_err| __tlv_err.within(::pcsc::tlv::Tag::new(#tag)))?
+        };
+        match field.kind {
+            Kind::Repeated => quote! { #tag => #local.push(#decode), },
+            _ => quote! {
+                #tag => {
+                    if #local.is_none() {
+                        #local = ::std::option::Option::Some(#decode);
+                    }
+                }
+            },
+        }
+    });
+    let initializers = parsed.fields.iter().map(|field| {
+        let ident = &field.ident;
+        let local = field.local();
+        let tag = field.tag;
+        match field.kind {
+            Kind::Required => quote! {
+                #ident: #local.ok_or_else(|| {
+                    ::pcsc::tlv::DecodeError::new(::pcsc::tlv::DecodeErrorKind::Missing)
+                        .within(::pcsc::tlv::Tag::new(#tag))
+                })?,
+            },
+            _ => quote! { #ident: #local, },
+        }
+    });
+    let decode_tlv = parsed.tag.map(|tag| {
+        quote! {
+            fn decode_tlv(__tlv_bytes: &[u8]) -> ::std::result::Result<Self, ::pcsc::tlv::DecodeError> {
+                let __tlv_tag = ::pcsc::tlv::Tag::new(#tag);
+                match ::pcsc::tlv::parse(__tlv_bytes).find_tag(__tlv_tag)? {
+                    ::std::option::Option::Some(__tlv_object) => {
+                        Self::decode_value(__tlv_object.value()).map_err(|__tlv_err| __tlv_err.within(__tlv_tag))
+                    }
+                    ::std::option::Option::None => ::std::result::Result::Err(
+                        ::pcsc::tlv::DecodeError::new(::pcsc::tlv::DecodeErrorKind::Missing).within(__tlv_tag),
+                    ),
+                }
+            }
+        }
+    });
+
+    quote! {
+        impl #impl_generics ::pcsc::tlv::TlvDecode for #name #ty_generics #where_clause {
+            fn decode_value(__tlv_value: &[u8]) -> ::std::result::Result<Self, ::pcsc::tlv::DecodeError> {
+                #(#declarations)*
+                for __tlv_object in ::pcsc::tlv::parse(__tlv_value) {
+                    let __tlv_object = __tlv_object?;
+                    match __tlv_object.tag().value() {
+                        #(#arms)*
+                        _ => {}
+                    }
+                }
+                ::std::result::Result::Ok(#name {
+                    #(#initializers)*
+                })
+            }
+
+            #decode_tlv
+        }
+    }
+}
+
+/// Derive `pcsc::tlv::TlvEncode` for a struct whose fields are data
+/// objects.
+#[proc_macro_derive(TlvEncode, attributes(tlv))]
+pub fn derive_tlv_encode(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match parse_struct(&input) {
+        Ok(parsed) => encode_impl(&input, &parsed).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn encode_impl(input: &DeriveInput, parsed: &Struct) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let statements = parsed.fields.iter().map(|field| {
+        let ident = &field.ident;
+        let ty = &field.ty;
+        let tag = field.tag;
+        let encode = quote! {
+            <#ty as ::pcsc::tlv::TlvEncode>::encode_with_tag(__tlv_value, ::pcsc::tlv::Tag::new(#tag), __tlv_out);
+        };
+        match field.kind {
+            Kind::Required => quote! {
+                let __tlv_value = &self.#ident;
+                #encode
+            },
+            Kind::Optional => quote! {
+                if let ::std::option::Option::Some(ref __tlv_value) = self.#ident {
+                    #encode
+                }
+            },
+            Kind::Repeated => quote! {
+                for __tlv_value in &self.#ident {
+                    #encode
+                }
+            },
+        }
+    });
+    let encode_tlv = parsed.tag.map(|tag| {
+        quote! {
+            fn encode_tlv(&self) -> ::std::vec::Vec<u8> {
+                let mut __tlv_out = ::std::vec::Vec::new();
+                self.encode_with_tag(::pcsc::tlv::Tag::new(#tag), &mut __tlv_out);
+                __tlv_out
+            }
+        }
+    });
+
+    quote! {
+        impl #impl_generics ::pcsc::tlv::TlvEncode for #name #ty_generics #where_clause {
+            fn encode_value(&self, __tlv_out: &mut ::std::vec::Vec<u8>) {
+                #(#statements)*
+            }
+
+            #encode_tlv
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::ToTokens;
+    use syn::parse_quote;
+
+    fn parse_error(input: DeriveInput) -> String {
+        match parse_struct(&input) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let input: DeriveInput = parse_quote! {
+            #[tlv(tag = 0x6F)]
+            struct Fci {
+                #[tlv(tag = 0x84)]
+                df_name: Vec<u8>,
+                #[tlv(tag = 0x50)]
+                label: Option<String>,
+                #[tlv(tag = 0xA5, repeated)]
+                templates: Vec<Template>,
+            }
+        };
+        let parsed = parse_struct(&input).unwrap();
+        assert_eq!(parsed.tag, Some(0x6F));
+        assert_eq!(parsed.fields.len(), 3);
+
+        let field = &parsed.fields[0];
+        assert_eq!(field.tag, 0x84);
+        assert!(matches!(field.kind, Kind::Required));
+        assert_eq!(field.ty.to_token_stream().to_string(), "Vec < u8 >");
+
+        let field = &parsed.fields[1];
+        assert_eq!(field.tag, 0x50);
+        assert!(matches!(field.kind, Kind::Optional));
+        assert_eq!(field.ty.to_token_stream().to_string(), "String");
+
+        let field = &parsed.fields[2];
+        assert_eq!(field.tag, 0xA5);
+        assert!(matches!(field.kind, Kind::Repeated));
+        assert_eq!(field.ty.to_token_stream().to_string(), "Template");
+    }
+
+    #[test]
+    fn locals() {
+        let input: DeriveInput = parse_quote! {
+            struct Shadowing {
+                #[tlv(tag = 0x80)]
+                value: u8,
+                #[tlv(tag = 0x81)]
+                r#type: u8,
+            }
+        };
+        let parsed = parse_struct(&input).unwrap();
+        assert_eq!(parsed.fields[0].local(), "__tlv_field_value");
+        assert_eq!(parsed.fields[1].local(), "__tlv_field_type");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_error(parse_quote! {
+                struct S {
+                    field: u8,
+                }
+            }),
+            "missing `#[tlv(tag = ...)]`"
+        );
+        assert_eq!(
+            parse_error(parse_quote! {
+                struct S {
+                    #[tlv(tag = 0x80)]
+                    a: u8,
+                    #[tlv(tag = 128)]
+                    b: u8,
+                }
+            }),
+            "duplicate tag 80"
+        );
+        assert_eq!(
+            parse_error(parse_quote! {
+                struct S {
+                    #[tlv(tag = 0)]
+                    field: u8,
+                }
+            }),
+            "tag 00 is not valid"
+        );
+        assert_eq!(
+            parse_error(parse_quote! {
+                struct S {
+                    #[tlv(tag = 0x80, repeated)]
+                    field: Option<u8>,
+                }
+            }),
+            "a `repeated` field must be a `Vec`"
+        );
+        assert_eq!(
+            parse_error(parse_quote! {
+                #[tlv(tag = 0x6F, repeated)]
+                struct S {}
+            }),
+            "`repeated` applies to fields only"
+        );
+        assert_eq!(
+            parse_error(parse_quote! {
+                struct S {
+                    #[tlv(length = 2)]
+                    field: u8,
+                }
+            }),
+            "expected `tag = ...` or `repeated`"
+        );
+        assert_eq!(
+            parse_error(parse_quote! { struct S(u8); }),
+            "expected a struct with named fields"
+        );
+        assert_eq!(
+            parse_error(parse_quote! { enum E {} }),
+            "expected a struct with named fields"
+        );
+    }
+}
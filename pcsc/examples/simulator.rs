@@ -0,0 +1,51 @@
+// Example of how to use the simulator backend, without a PC/SC service.
+use std::thread;
+use std::time::Duration;
+
+use pcsc::simulator::{Simulator, VirtualCard};
+use pcsc::*;
+
+const READER: &str = "Virtual Reader 00 00";
+
+fn main() {
+    let sim = Simulator::new();
+    sim.add_reader(READER).expect("failed to add reader");
+
+    let ctx = Context::establish_with_backend(sim.clone(), Scope::User).expect("failed to establish context");
+
+    // Insert a card from another thread, after a while.
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        // A card which answers every command with "9000" (success).
+        let handler = |command: &[u8]| {
+            println!("Card received command: {:?}", command);
+            Ok(vec![0x90, 0x00])
+        };
+        sim.insert_card(READER, VirtualCard::new(&[0x3B, 0x80, 0x80, 0x01, 0x01], handler))
+            .expect("failed to insert card");
+    });
+
+    // Wait for the card.
+    let readers = ctx.list_readers_owned().expect("failed to list readers");
+    let mut reader_states = vec![ReaderState::new(readers[0].clone(), State::UNAWARE)];
+    loop {
+        ctx.get_status_change(None, &mut reader_states)
+            .expect("failed to get status change");
+        let rs = &mut reader_states[0];
+        println!("{:?} {:?} {:?}", rs.name(), rs.event_state(), rs.atr());
+        if rs.event_state().contains(State::PRESENT) {
+            break;
+        }
+        rs.sync_current_state();
+    }
+
+    let reader = reader_states[0].name();
+    let card = ctx
+        .connect(reader, ShareMode::Shared, Protocols::ANY)
+        .expect("failed to connect to card");
+
+    let apdu = b"\x00\xa4\x04\x00\x0A\xA0\x00\x00\x00\x62\x03\x01\x0C\x06\x01";
+    let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+    let rapdu = card.transmit(apdu, &mut rapdu_buf).expect("failed to transmit APDU");
+    println!("APDU response: {:?}", rapdu);
+}
@@ -0,0 +1,1374 @@
+//! ISO 7816-4 command and response APDUs.
+//!
+//! [`Command`](struct.Command.html) builds a command APDU from its fields,
+//! and takes care of encoding it in the right case (1 to 4), in short or
+//! extended form. It can be sent with `Card::transmit_command()`.
+//!
+//! ```no_run
+//! use pcsc::apdu::Command;
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//!
+//! // SELECT by AID.
+//! let select = Command::new(0x00, 0xA4, 0x04, 0x00)
+//!     .with_data(&[0xA0, 0x00, 0x00, 0x00, 0x62, 0x03, 0x01, 0x0C, 0x06, 0x01])
+//!     .with_le(256);
+//! let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+//! let rapdu = card.transmit_command(&select, &mut rapdu_buf).unwrap();
+//! ```
+//!
+//! [`Response`](struct.Response.html) splits a response APDU into its data
+//! and [`StatusWord`](enum.StatusWord.html). `Card::exchange()` sends a
+//! command and returns its response:
+//!
+//! ```no_run
+//! # use pcsc::apdu::Command;
+//! # use pcsc::*;
+//! # let ctx = Context::establish(Scope::User).unwrap();
+//! # let readers = ctx.list_readers_owned().unwrap();
+//! # let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//! let get_uid = Command::new(0xFF, 0xCA, 0x00, 0x00).with_le(256);
+//! let uid = card.exchange(&get_uid).unwrap().into_result().unwrap();
+//! ```
+
+use std::error;
+use std::fmt;
+
+use crate::atr::CardCapabilities;
+use crate::{Card, Error, MAX_BUFFER_SIZE, MAX_BUFFER_SIZE_EXTENDED};
+
+/// The maximum length of the data field of a short command.
+pub const MAX_SHORT_DATA: usize = 255;
+/// The maximum expected response length of a short command.
+pub const MAX_SHORT_LE: usize = 256;
+/// The maximum length of the data field of an extended command.
+pub const MAX_EXTENDED_DATA: usize = 65535;
+/// The maximum expected response length of an extended command.
+pub const MAX_EXTENDED_LE: usize = 65536;
+
+/// An error encoding or parsing a command APDU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// The data field is longer than `MAX_EXTENDED_DATA`, or, for
+    /// `Card::exchange_chained()`, longer than `MAX_SHORT_DATA` when the
+    /// card supports neither extended length nor command chaining.
+    DataTooLong(usize),
+    /// The expected response length is 0 or longer than
+    /// `MAX_EXTENDED_LE`.
+    InvalidLe(usize),
+    /// The encoded command does not fit in the maximum buffer size.
+    TooLong(usize),
+    /// The bytes are too short to hold a command header.
+    Truncated,
+    /// The length fields do not match the length of the bytes.
+    InvalidLength,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandError::DataTooLong(len) => write!(f, "command data too long ({} bytes)", len),
+            CommandError::InvalidLe(le) => write!(f, "invalid expected response length {}", le),
+            CommandError::TooLong(len) => write!(f, "encoded command too long ({} bytes)", len),
+            CommandError::Truncated => f.write_str("command shorter than its header"),
+            CommandError::InvalidLength => f.write_str("command length fields do not match its length"),
+        }
+    }
+}
+
+impl error::Error for CommandError {}
+
+/// An ISO 7816-4 command APDU.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Command {
+    cla: u8,
+    ins: u8,
+    p1: u8,
+    p2: u8,
+    data: Vec<u8>,
+    le: Option<usize>,
+}
+
+impl Command {
+    /// Create a command with the given header, without data and without
+    /// expecting response data.
+    pub fn new(cla: u8, ins: u8, p1: u8, p2: u8) -> Command {
+        Command {
+            cla,
+            ins,
+            p1,
+            p2,
+            data: Vec::new(),
+            le: None,
+        }
+    }
+
+    /// Set the data field.
+    pub fn with_data(mut self, data: &[u8]) -> Command {
+        self.data = data.to_vec();
+        self
+    }
+
+    /// Set the expected response length (Le), in bytes.
+    ///
+    /// Use `MAX_SHORT_LE` (256) to accept any response of a short command,
+    /// and `MAX_EXTENDED_LE` (65536) to accept any response of an extended
+    /// command.
+    pub fn with_le(mut self, le: usize) -> Command {
+        self.le = Some(le);
+        self
+    }
+
+    /// The class byte.
+    pub fn cla(&self) -> u8 {
+        self.cla
+    }
+
+    /// The instruction byte.
+    pub fn ins(&self) -> u8 {
+        self.ins
+    }
+
+    /// The first parameter byte.
+    pub fn p1(&self) -> u8 {
+        self.p1
+    }
+
+    /// The second parameter byte.
+    pub fn p2(&self) -> u8 {
+        self.p2
+    }
+
+    /// The data field.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The expected response length (Le), if response data is expected.
+    pub fn le(&self) -> Option<usize> {
+        self.le
+    }
+
+    /// Whether the command needs the extended length encoding.
+    pub fn is_extended(&self) -> bool {
+        self.data.len() > MAX_SHORT_DATA || self.le.map_or(false, |le| le > MAX_SHORT_LE)
+    }
+
+    fn validate(&self) -> Result<(), CommandError> {
+        if self.data.len() > MAX_EXTENDED_DATA {
+            return Err(CommandError::DataTooLong(self.data.len()));
+        }
+        match self.le {
+            Some(le) if le == 0 || le > MAX_EXTENDED_LE => Err(CommandError::InvalidLe(le)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Encode the command.
+    ///
+    /// The short form is used if possible, and the extended form
+    /// otherwise.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CommandError> {
+        self.encode(self.is_extended())
+    }
+
+    /// Encode the command in the extended form, even if it would fit in
+    /// the short form.
+    pub fn to_bytes_extended(&self) -> Result<Vec<u8>, CommandError> {
+        self.encode(true)
+    }
+
+    fn encode(&self, extended: bool) -> Result<Vec<u8>, CommandError> {
+        self.validate()?;
+
+        let mut bytes = Vec::with_capacity(4 + 3 + self.data.len() + 2);
+        bytes.extend_from_slice(&[self.cla, self.ins, self.p1, self.p2]);
+        if extended {
+            if !self.data.is_empty() {
+                bytes.push(0x00);
+                bytes.extend_from_slice(&(self.data.len() as u16).to_be_bytes());
+                bytes.extend_from_slice(&self.data);
+            }
+            if let Some(le) = self.le {
+                if self.data.is_empty() {
+                    bytes.push(0x00);
+                }
+                // 65536 is encoded as 0000.
+                bytes.extend_from_slice(&(le as u16).to_be_bytes());
+            }
+        } else {
+            if !self.data.is_empty() {
+                bytes.push(self.data.len() as u8);
+                bytes.extend_from_slice(&self.data);
+            }
+            if let Some(le) = self.le {
+                // 256 is encoded as 00.
+                bytes.push(le as u8);
+            }
+        }
+
+        let max_len = if extended {
+            MAX_BUFFER_SIZE_EXTENDED
+        } else {
+            MAX_BUFFER_SIZE
+        };
+        if bytes.len() > max_len {
+            return Err(CommandError::TooLong(bytes.len()));
+        }
+        Ok(bytes)
+    }
+
+    /// Parse an encoded command.
+    pub fn parse(bytes: &[u8]) -> Result<Command, CommandError> {
+        if bytes.len() < 4 {
+            return Err(CommandError::Truncated);
+        }
+        let mut command = Command::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+        let body = &bytes[4..];
+
+        match body.len() {
+            // Case 1.
+            0 => {}
+            // Case 2S.
+            1 => command.le = Some(short_le(body[0])),
+            _ if body[0] != 0 => {
+                // Case 3S or 4S.
+                let lc = body[0] as usize;
+                if body.len() == 1 + lc {
+                    command.data = body[1..].to_vec();
+                } else if body.len() == 2 + lc {
+                    command.data = body[1..1 + lc].to_vec();
+                    command.le = Some(short_le(body[1 + lc]));
+                } else {
+                    return Err(CommandError::InvalidLength);
+                }
+            }
+            // Case 2E.
+            3 => command.le = Some(extended_le(body[1], body[2])),
+            len if len > 3 => {
+                // Case 3E or 4E.
+                let lc = u16::from_be_bytes([body[1], body[2]]) as usize;
+                if lc == 0 {
+                    return Err(CommandError::InvalidLength);
+                }
+                if body.len() == 3 + lc {
+                    command.data = body[3..].to_vec();
+                } else if body.len() == 5 + lc {
+                    command.data = body[3..3 + lc].to_vec();
+                    command.le = Some(extended_le(body[3 + lc], body[4 + lc]));
+                } else {
+                    return Err(CommandError::InvalidLength);
+                }
+            }
+            _ => return Err(CommandError::InvalidLength),
+        }
+
+        Ok(command)
+    }
+}
+
+fn short_le(byte: u8) -> usize {
+    match byte {
+        0 => MAX_SHORT_LE,
+        le => le as usize,
+    }
+}
+
+fn extended_le(high: u8, low: u8) -> usize {
+    match u16::from_be_bytes([high, low]) {
+        0 => MAX_EXTENDED_LE,
+        le => le as usize,
+    }
+}
+
+// The CLA byte of a GET RESPONSE following a command with the given CLA:
+// the logical channel of an interindustry class is kept, without chaining
+// and secure messaging. Proprietary classes use the basic channel.
+fn get_response_cla(cla: u8) -> u8 {
+    match cla {
+        0x00..=0x1F => cla & 0x03,
+        0x40..=0x7F => cla & 0x4F,
+        _ => 0x00,
+    }
+}
+
+/// An ISO 7816-4 status word (SW1-SW2).
+///
+/// The interindustry status words are decoded into their own variants;
+/// other values are kept as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusWord {
+    /// 9000: Normal processing.
+    Success,
+    /// 61XX: Normal processing, XX more response bytes are available (0
+    /// means 256 or more). They can be retrieved with GET RESPONSE.
+    BytesAvailable(u8),
+
+    /// 6200: Warning, no information given, non-volatile memory unchanged.
+    WarningUnchanged,
+    /// 6281: Part of returned data may be corrupted.
+    CorruptedData,
+    /// 6282: End of file or record reached before reading Le bytes.
+    EndOfFile,
+    /// 6283: Selected file deactivated.
+    FileDeactivated,
+    /// 6284: File control information not formatted correctly.
+    InvalidFileControlInformation,
+    /// 6285: Selected file in termination state.
+    FileTerminated,
+    /// 6286: No input data available from a sensor on the card.
+    NoSensorInput,
+    /// 6300: Warning, no information given, non-volatile memory changed.
+    WarningChanged,
+    /// 6381: File filled up by the last write.
+    FileFilledUp,
+    /// 63CX: Verification failed, the counter (usually retries left) is X.
+    Counter(u8),
+
+    /// 6400: Execution error, non-volatile memory unchanged.
+    ExecutionError,
+    /// 6401: Immediate response required by the card.
+    ImmediateResponseRequired,
+    /// 6500: Execution error, non-volatile memory changed.
+    MemoryChanged,
+    /// 6581: Memory failure.
+    MemoryFailure,
+
+    /// 6700: Wrong length.
+    WrongLength,
+    /// 6800: Functions in CLA not supported.
+    ClaFunctionsNotSupported,
+    /// 6881: Logical channel not supported.
+    LogicalChannelNotSupported,
+    /// 6882: Secure messaging not supported.
+    SecureMessagingNotSupported,
+    /// 6883: Last command of the chain expected.
+    LastCommandExpected,
+    /// 6884: Command chaining not supported.
+    ChainingNotSupported,
+    /// 6900: Command not allowed.
+    CommandNotAllowed,
+    /// 6981: Command incompatible with file structure.
+    IncompatibleFileStructure,
+    /// 6982: Security status not satisfied.
+    SecurityStatusNotSatisfied,
+    /// 6983: Authentication method blocked.
+    AuthenticationBlocked,
+    /// 6984: Reference data not usable.
+    ReferenceDataNotUsable,
+    /// 6985: Conditions of use not satisfied.
+    ConditionsNotSatisfied,
+    /// 6986: Command not allowed (no current EF).
+    NoCurrentEf,
+    /// 6987: Expected secure messaging data objects missing.
+    SecureMessagingDataMissing,
+    /// 6988: Incorrect secure messaging data objects.
+    SecureMessagingDataIncorrect,
+    /// 6A00: Wrong parameters P1-P2.
+    WrongParameters,
+    /// 6A80: Incorrect parameters in the command data field.
+    IncorrectData,
+    /// 6A81: Function not supported.
+    FunctionNotSupported,
+    /// 6A82: File or application not found.
+    FileNotFound,
+    /// 6A83: Record not found.
+    RecordNotFound,
+    /// 6A84: Not enough memory space in the file.
+    NotEnoughMemory,
+    /// 6A85: Nc inconsistent with TLV structure.
+    LcInconsistentWithTlv,
+    /// 6A86: Incorrect parameters P1-P2.
+    IncorrectP1P2,
+    /// 6A87: Nc inconsistent with parameters P1-P2.
+    LcInconsistentWithP1P2,
+    /// 6A88: Referenced data or reference data not found.
+    ReferencedDataNotFound,
+    /// 6A89: File already exists.
+    FileAlreadyExists,
+    /// 6A8A: DF name already exists.
+    DfNameAlreadyExists,
+    /// 6B00: Wrong parameters P1-P2.
+    WrongP1P2,
+    /// 6CXX: Wrong Le field; XX is the exact number of available bytes (0
+    /// means 256).
+    WrongLe(u8),
+    /// 6D00: Instruction code not supported or invalid.
+    InsNotSupported,
+    /// 6E00: Class not supported.
+    ClaNotSupported,
+    /// 6F00: No precise diagnosis.
+    NoPreciseDiagnosis,
+
+    /// Any other status word.
+    Other(u8, u8),
+}
+
+impl StatusWord {
+    /// Decode a status word from its two bytes.
+    pub fn new(sw1: u8, sw2: u8) -> StatusWord {
+        use StatusWord::*;
+        match (sw1, sw2) {
+            (0x90, 0x00) => Success,
+            (0x61, xx) => BytesAvailable(xx),
+            (0x62, 0x00) => WarningUnchanged,
+            (0x62, 0x81) => CorruptedData,
+            (0x62, 0x82) => EndOfFile,
+            (0x62, 0x83) => FileDeactivated,
+            (0x62, 0x84) => InvalidFileControlInformation,
+            (0x62, 0x85) => FileTerminated,
+            (0x62, 0x86) => NoSensorInput,
+            (0x63, 0x00) => WarningChanged,
+            (0x63, 0x81) => FileFilledUp,
+            (0x63, x) if x & 0xF0 == 0xC0 => Counter(x & 0x0F),
+            (0x64, 0x00) => ExecutionError,
+            (0x64, 0x01) => ImmediateResponseRequired,
+            (0x65, 0x00) => MemoryChanged,
+            (0x65, 0x81) => MemoryFailure,
+            (0x67, 0x00) => WrongLength,
+            (0x68, 0x00) => ClaFunctionsNotSupported,
+            (0x68, 0x81) => LogicalChannelNotSupported,
+            (0x68, 0x82) => SecureMessagingNotSupported,
+            (0x68, 0x83) => LastCommandExpected,
+            (0x68, 0x84) => ChainingNotSupported,
+            (0x69, 0x00) => CommandNotAllowed,
+            (0x69, 0x81) => IncompatibleFileStructure,
+            (0x69, 0x82) => SecurityStatusNotSatisfied,
+            (0x69, 0x83) => AuthenticationBlocked,
+            (0x69, 0x84) => ReferenceDataNotUsable,
+            (0x69, 0x85) => ConditionsNotSatisfied,
+            (0x69, 0x86) => NoCurrentEf,
+            (0x69, 0x87) => SecureMessagingDataMissing,
+            (0x69, 0x88) => SecureMessagingDataIncorrect,
+            (0x6A, 0x00) => WrongParameters,
+            (0x6A, 0x80) => IncorrectData,
+            (0x6A, 0x81) => FunctionNotSupported,
+            (0x6A, 0x82) => FileNotFound,
+            (0x6A, 0x83) => RecordNotFound,
+            (0x6A, 0x84) => NotEnoughMemory,
+            (0x6A, 0x85) => LcInconsistentWithTlv,
+            (0x6A, 0x86) => IncorrectP1P2,
+            (0x6A, 0x87) => LcInconsistentWithP1P2,
+            (0x6A, 0x88) => ReferencedDataNotFound,
+            (0x6A, 0x89) => FileAlreadyExists,
+            (0x6A, 0x8A) => DfNameAlreadyExists,
+            (0x6B, 0x00) => WrongP1P2,
+            (0x6C, xx) => WrongLe(xx),
+            (0x6D, 0x00) => InsNotSupported,
+            (0x6E, 0x00) => ClaNotSupported,
+            (0x6F, 0x00) => NoPreciseDiagnosis,
+            (sw1, sw2) => Other(sw1, sw2),
+        }
+    }
+
+    /// The two bytes of the status word.
+    pub fn to_bytes(self) -> [u8; 2] {
+        use StatusWord::*;
+        match self {
+            Success => [0x90, 0x00],
+            BytesAvailable(xx) => [0x61, xx],
+            WarningUnchanged => [0x62, 0x00],
+            CorruptedData => [0x62, 0x81],
+            EndOfFile => [0x62, 0x82],
+            FileDeactivated => [0x62, 0x83],
+            InvalidFileControlInformation => [0x62, 0x84],
+            FileTerminated => [0x62, 0x85],
+            NoSensorInput => [0x62, 0x86],
+            WarningChanged => [0x63, 0x00],
+            FileFilledUp => [0x63, 0x81],
+            Counter(x) => [0x63, 0xC0 | (x & 0x0F)],
+            ExecutionError => [0x64, 0x00],
+            ImmediateResponseRequired => [0x64, 0x01],
+            MemoryChanged => [0x65, 0x00],
+            MemoryFailure => [0x65, 0x81],
+            WrongLength => [0x67, 0x00],
+            ClaFunctionsNotSupported => [0x68, 0x00],
+            LogicalChannelNotSupported => [0x68, 0x81],
+            SecureMessagingNotSupported => [0x68, 0x82],
+            LastCommandExpected => [0x68, 0x83],
+            ChainingNotSupported => [0x68, 0x84],
+            CommandNotAllowed => [0x69, 0x00],
+            IncompatibleFileStructure => [0x69, 0x81],
+            SecurityStatusNotSatisfied => [0x69, 0x82],
+            AuthenticationBlocked => [0x69, 0x83],
+            ReferenceDataNotUsable => [0x69, 0x84],
+            ConditionsNotSatisfied => [0x69, 0x85],
+            NoCurrentEf => [0x69, 0x86],
+            SecureMessagingDataMissing => [0x69, 0x87],
+            SecureMessagingDataIncorrect => [0x69, 0x88],
+            WrongParameters => [0x6A, 0x00],
+            IncorrectData => [0x6A, 0x80],
+            FunctionNotSupported => [0x6A, 0x81],
+            FileNotFound => [0x6A, 0x82],
+            RecordNotFound => [0x6A, 0x83],
+            NotEnoughMemory => [0x6A, 0x84],
+            LcInconsistentWithTlv => [0x6A, 0x85],
+            IncorrectP1P2 => [0x6A, 0x86],
+            LcInconsistentWithP1P2 => [0x6A, 0x87],
+            ReferencedDataNotFound => [0x6A, 0x88],
+            FileAlreadyExists => [0x6A, 0x89],
+            DfNameAlreadyExists => [0x6A, 0x8A],
+            WrongP1P2 => [0x6B, 0x00],
+            WrongLe(xx) => [0x6C, xx],
+            InsNotSupported => [0x6D, 0x00],
+            ClaNotSupported => [0x6E, 0x00],
+            NoPreciseDiagnosis => [0x6F, 0x00],
+            Other(sw1, sw2) => [sw1, sw2],
+        }
+    }
+
+    /// The status word as a 16-bit value, e.g. `0x9000`.
+    pub fn to_u16(self) -> u16 {
+        u16::from_be_bytes(self.to_bytes())
+    }
+
+    /// Whether the status word indicates normal processing (9000 or 61XX).
+    pub fn is_success(self) -> bool {
+        matches!(self, StatusWord::Success | StatusWord::BytesAvailable(_))
+    }
+
+    /// Whether the status word is a warning (62XX or 63XX).
+    pub fn is_warning(self) -> bool {
+        matches!(self.to_bytes()[0], 0x62 | 0x63)
+    }
+
+    /// Whether the status word is an error (64XX to 6FXX).
+    pub fn is_error(self) -> bool {
+        matches!(self.to_bytes()[0], 0x64..=0x6F)
+    }
+
+    fn description(self) -> &'static str {
+        use StatusWord::*;
+        match self {
+            Success => "Normal processing",
+            BytesAvailable(_) => "Response bytes still available",
+            WarningUnchanged => "Warning: state of non-volatile memory unchanged",
+            CorruptedData => "Part of returned data may be corrupted",
+            EndOfFile => "End of file or record reached before reading Le bytes",
+            FileDeactivated => "Selected file deactivated",
+            InvalidFileControlInformation => "File control information not formatted correctly",
+            FileTerminated => "Selected file in termination state",
+            NoSensorInput => "No input data available from a sensor on the card",
+            WarningChanged => "Warning: state of non-volatile memory changed",
+            FileFilledUp => "File filled up by the last write",
+            Counter(_) => "Verification failed",
+            ExecutionError => "Execution error: state of non-volatile memory unchanged",
+            ImmediateResponseRequired => "Immediate response required by the card",
+            MemoryChanged => "Execution error: state of non-volatile memory changed",
+            MemoryFailure => "Memory failure",
+            WrongLength => "Wrong length",
+            ClaFunctionsNotSupported => "Functions in CLA not supported",
+            LogicalChannelNotSupported => "Logical channel not supported",
+            SecureMessagingNotSupported => "Secure messaging not supported",
+            LastCommandExpected => "Last command of the chain expected",
+            ChainingNotSupported => "Command chaining not supported",
+            CommandNotAllowed => "Command not allowed",
+            IncompatibleFileStructure => "Command incompatible with file structure",
+            SecurityStatusNotSatisfied => "Security status not satisfied",
+            AuthenticationBlocked => "Authentication method blocked",
+            ReferenceDataNotUsable => "Reference data not usable",
+            ConditionsNotSatisfied => "Conditions of use not satisfied",
+            NoCurrentEf => "Command not allowed (no current EF)",
+            SecureMessagingDataMissing => "Expected secure messaging data objects missing",
+            SecureMessagingDataIncorrect => "Incorrect secure messaging data objects",
+            WrongParameters => "Wrong parameters P1-P2",
+            IncorrectData => "Incorrect parameters in the command data field",
+            FunctionNotSupported => "Function not supported",
+            FileNotFound => "File or application not found",
+            RecordNotFound => "Record not found",
+            NotEnoughMemory => "Not enough memory space in the file",
+            LcInconsistentWithTlv => "Nc inconsistent with TLV structure",
+            IncorrectP1P2 => "Incorrect parameters P1-P2",
+            LcInconsistentWithP1P2 => "Nc inconsistent with parameters P1-P2",
+            ReferencedDataNotFound => "Referenced data or reference data not found",
+            FileAlreadyExists => "File already exists",
+            DfNameAlreadyExists => "DF name already exists",
+            WrongP1P2 => "Wrong parameters P1-P2",
+            WrongLe(_) => "Wrong Le field",
+            InsNotSupported => "Instruction code not supported or invalid",
+            ClaNotSupported => "Class not supported",
+            NoPreciseDiagnosis => "No precise diagnosis",
+            Other(..) => "Unknown status",
+        }
+    }
+}
+
+impl fmt::Display for StatusWord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [sw1, sw2] = self.to_bytes();
+        match *self {
+            StatusWord::BytesAvailable(0) => write!(f, "256 or more response bytes available")?,
+            StatusWord::BytesAvailable(xx) => write!(f, "{} response bytes available", xx)?,
+            StatusWord::Counter(x) => write!(f, "Verification failed, {} tries left", x)?,
+            StatusWord::WrongLe(xx) => write!(f, "Wrong Le field, {} bytes available", short_le(xx))?,
+            _ => f.write_str(self.description())?,
+        }
+        write!(f, " ({:02X}{:02X})", sw1, sw2)
+    }
+}
+
+impl From<[u8; 2]> for StatusWord {
+    fn from(bytes: [u8; 2]) -> StatusWord {
+        StatusWord::new(bytes[0], bytes[1])
+    }
+}
+
+impl From<StatusWord> for u16 {
+    fn from(sw: StatusWord) -> u16 {
+        sw.to_u16()
+    }
+}
+
+/// An error exchanging APDUs with a card.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApduError {
+    /// The PC/SC call failed.
+    Pcsc(Error),
+    /// The card returned a status word which does not indicate success.
+    Status(StatusWord),
+    /// The command could not be encoded.
+    InvalidCommand(CommandError),
+    /// The response is shorter than a status word.
+    MalformedResponse,
+}
+
+impl fmt::Display for ApduError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApduError::Pcsc(ref err) => write!(f, "{}", err),
+            ApduError::Status(sw) => write!(f, "{}", sw),
+            ApduError::InvalidCommand(ref err) => write!(f, "{}", err),
+            ApduError::MalformedResponse => f.write_str("response shorter than a status word"),
+        }
+    }
+}
+
+impl error::Error for ApduError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ApduError::Pcsc(ref err) => Some(err),
+            ApduError::InvalidCommand(ref err) => Some(err),
+            ApduError::Status(_) | ApduError::MalformedResponse => None,
+        }
+    }
+}
+
+impl From<Error> for ApduError {
+    fn from(err: Error) -> ApduError {
+        ApduError::Pcsc(err)
+    }
+}
+
+impl From<CommandError> for ApduError {
+    fn from(err: CommandError) -> ApduError {
+        ApduError::InvalidCommand(err)
+    }
+}
+
+/// An ISO 7816-4 response APDU: the response data and the status word.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Response {
+    data: Vec<u8>,
+    status: StatusWord,
+}
+
+impl Response {
+    /// Create a response from its parts.
+    pub fn new(data: Vec<u8>, status: StatusWord) -> Response {
+        Response { data, status }
+    }
+
+    /// Parse a raw response APDU.
+    ///
+    /// Fails with `ApduError::MalformedResponse` if `bytes` is shorter than
+    /// 2 bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Response, ApduError> {
+        if bytes.len() < 2 {
+            return Err(ApduError::MalformedResponse);
+        }
+        let (data, sw) = bytes.split_at(bytes.len() - 2);
+        Ok(Response {
+            data: data.to_vec(),
+            status: StatusWord::new(sw[0], sw[1]),
+        })
+    }
+
+    /// The response data, without the status word.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The status word.
+    pub fn status(&self) -> StatusWord {
+        self.status
+    }
+
+    /// Consume the response and return the response data.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Encode the response back into raw bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() + 2);
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&self.status.to_bytes());
+        bytes
+    }
+
+    /// Return the response data if the status word indicates success
+    /// (9000 or 61XX), and `ApduError::Status` otherwise.
+    ///
+    /// Note that warnings (62XX and 63XX) are treated as errors.
+    pub fn into_result(self) -> Result<Vec<u8>, ApduError> {
+        if self.status.is_success() {
+            Ok(self.data)
+        } else {
+            Err(ApduError::Status(self.status))
+        }
+    }
+}
+
+/// Options for automatic response handling in `Card::transmit_owned()`
+/// and `Card::exchange()`.
+///
+/// By default, no automatic handling is done. The options of a card are
+/// set with `Card::set_apdu_options()`.
+///
+/// ```no_run
+/// use pcsc::apdu::ApduOptions;
+/// use pcsc::*;
+///
+/// let ctx = Context::establish(Scope::User).unwrap();
+/// let readers = ctx.list_readers_owned().unwrap();
+/// let mut card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+/// card.set_apdu_options(ApduOptions::new().with_get_response(true).with_retry_wrong_le(true));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ApduOptions {
+    get_response: bool,
+    retry_wrong_le: bool,
+    extended_length: bool,
+    command_chaining: bool,
+    max_iterations: usize,
+    max_response_len: usize,
+}
+
+impl ApduOptions {
+    /// The default options: no automatic handling, no extended length
+    /// support, command chaining support, at most 64 exchanges per command
+    /// and at most `MAX_EXTENDED_LE` bytes of response data.
+    pub fn new() -> ApduOptions {
+        ApduOptions {
+            get_response: false,
+            retry_wrong_le: false,
+            extended_length: false,
+            command_chaining: true,
+            max_iterations: 64,
+            max_response_len: MAX_EXTENDED_LE,
+        }
+    }
+
+    /// Whether to issue GET RESPONSE when the card answers 61XX, until all
+    /// response data is collected.
+    ///
+    /// GET RESPONSE is sent on the logical channel of the command if its
+    /// class is interindustry, and with class 00 otherwise.
+    pub fn with_get_response(mut self, get_response: bool) -> ApduOptions {
+        self.get_response = get_response;
+        self
+    }
+
+    /// Whether to re-send the command with the corrected Le when the card
+    /// answers 6CXX.
+    pub fn with_retry_wrong_le(mut self, retry_wrong_le: bool) -> ApduOptions {
+        self.retry_wrong_le = retry_wrong_le;
+        self
+    }
+
+    /// Whether the card supports extended length commands.
+    ///
+    /// `Card::exchange_chained()` sends long commands with the extended
+    /// length encoding if supported, and with command chaining otherwise.
+    pub fn with_extended_length(mut self, extended_length: bool) -> ApduOptions {
+        self.extended_length = extended_length;
+        self
+    }
+
+    /// Whether the card supports command chaining.
+    ///
+    /// If the card supports neither extended length nor command chaining,
+    /// `Card::exchange_chained()` fails for commands with more than
+    /// `MAX_SHORT_DATA` bytes of data.
+    pub fn with_command_chaining(mut self, command_chaining: bool) -> ApduOptions {
+        self.command_chaining = command_chaining;
+        self
+    }
+
+    /// Set extended length and command chaining support as indicated by
+    /// the card capabilities in the card's ATR.
+    pub fn with_card_capabilities(self, capabilities: &CardCapabilities) -> ApduOptions {
+        self.with_extended_length(capabilities.extended_length())
+            .with_command_chaining(capabilities.command_chaining())
+    }
+
+    /// The maximum number of commands sent for a single command, including
+    /// the command itself.
+    ///
+    /// When reached, the last response is returned as is, e.g. with a
+    /// 61XX status word.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `max_iterations` is 0.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> ApduOptions {
+        assert!(max_iterations > 0);
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// The maximum total length of the response data.
+    ///
+    /// When exceeded, `Error::InsufficientBuffer` is returned.
+    pub fn with_max_response_len(mut self, max_response_len: usize) -> ApduOptions {
+        self.max_response_len = max_response_len;
+        self
+    }
+
+    /// Whether GET RESPONSE is issued automatically.
+    pub fn get_response(&self) -> bool {
+        self.get_response
+    }
+
+    /// Whether commands are re-sent with the corrected Le automatically.
+    pub fn retry_wrong_le(&self) -> bool {
+        self.retry_wrong_le
+    }
+
+    /// Whether the card supports extended length commands.
+    pub fn extended_length(&self) -> bool {
+        self.extended_length
+    }
+
+    /// Whether the card supports command chaining.
+    pub fn command_chaining(&self) -> bool {
+        self.command_chaining
+    }
+
+    /// The maximum number of commands sent for a single command.
+    pub fn max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+
+    /// The maximum total length of the response data.
+    pub fn max_response_len(&self) -> usize {
+        self.max_response_len
+    }
+}
+
+impl Default for ApduOptions {
+    fn default() -> ApduOptions {
+        ApduOptions::new()
+    }
+}
+
+impl Card {
+    /// Transmit a command APDU to the card.
+    ///
+    /// This function works like [transmit](#method.transmit), but encodes
+    /// `command` first. If the command cannot be encoded,
+    /// `Error::InvalidParameter` is returned.
+    ///
+    /// `receive_buffer` is a buffer that should be large enough to hold
+    /// the APDU response.
+    ///
+    /// Returns a slice into `receive_buffer` containing the APDU
+    /// response.
+    pub fn transmit_command<'buf>(
+        &self,
+        command: &Command,
+        receive_buffer: &'buf mut [u8],
+    ) -> Result<&'buf [u8], Error> {
+        let send_buffer = command.to_bytes().map_err(|_| Error::InvalidParameter)?;
+        self.transmit(&send_buffer, receive_buffer)
+    }
+
+    /// The options for automatic response handling.
+    pub fn apdu_options(&self) -> ApduOptions {
+        self.apdu_options
+    }
+
+    /// Set the options for automatic response handling.
+    ///
+    /// The options apply to `transmit_owned()` and `exchange()`, also when
+    /// called through a `Transaction`.
+    pub fn set_apdu_options(&mut self, options: ApduOptions) {
+        self.apdu_options = options;
+    }
+
+    /// Transmit an APDU command to the card, returning the response in a
+    /// new buffer.
+    ///
+    /// Unlike [transmit](#method.transmit), this function applies the
+    /// card's `ApduOptions`: if enabled, GET RESPONSE is issued while the
+    /// card answers 61XX, and the command is re-sent with the corrected Le
+    /// when the card answers 6CXX. The response data of all exchanges is
+    /// concatenated, followed by the last status word.
+    ///
+    /// If the total response data exceeds
+    /// `ApduOptions::max_response_len()`, `Error::InsufficientBuffer` is
+    /// returned.
+    pub fn transmit_owned(&self, send_buffer: &[u8]) -> Result<Vec<u8>, Error> {
+        let options = self.apdu_options;
+        let mut receive_buffer = vec![0; MAX_BUFFER_SIZE_EXTENDED];
+        let mut command = send_buffer.to_vec();
+        let mut data = Vec::new();
+        let mut iterations = 0;
+
+        loop {
+            let response = self.transmit(&command, &mut receive_buffer)?;
+            if response.len() < 2 {
+                // Not a valid response; leave it for the caller.
+                data.extend_from_slice(response);
+                return Ok(data);
+            }
+            let (body, sw) = response.split_at(response.len() - 2);
+            data.extend_from_slice(body);
+            if data.len() > options.max_response_len {
+                return Err(Error::InsufficientBuffer);
+            }
+            iterations += 1;
+
+            let next = match (sw[0], sw[1]) {
+                (0x61, xx) if options.get_response => Command::new(get_response_cla(command[0]), 0xC0, 0x00, 0x00)
+                    .with_le(short_le(xx))
+                    .to_bytes()
+                    .ok(),
+                (0x6C, xx) if options.retry_wrong_le && body.is_empty() => Command::parse(&command)
+                    .ok()
+                    .and_then(|parsed| parsed.with_le(short_le(xx)).to_bytes().ok()),
+                _ => None,
+            };
+            match next {
+                Some(next) if iterations < options.max_iterations => command = next,
+                _ => {
+                    data.extend_from_slice(sw);
+                    return Ok(data);
+                }
+            }
+        }
+    }
+
+    /// Exchange a command APDU with the card.
+    ///
+    /// The command is encoded and sent with
+    /// [transmit_owned](#method.transmit_owned), so the card's
+    /// `ApduOptions` apply, and the response is split into the data and
+    /// the status word.
+    ///
+    /// The status word is not checked; use `Response::into_result()` to
+    /// turn unsuccessful status words into errors.
+    pub fn exchange(&self, command: &Command) -> Result<Response, ApduError> {
+        let send_buffer = command.to_bytes()?;
+        let response = self.transmit_owned(&send_buffer)?;
+        Response::from_bytes(&response)
+    }
+
+    // Exchange a command with plain `transmit()`, ignoring the card's
+    // `ApduOptions`. This is for the class FF pseudo-APDUs of PC/SC Part 3,
+    // which the reader answers itself.
+    pub(crate) fn exchange_pseudo(&self, command: &Command) -> Result<Response, ApduError> {
+        let send_buffer = command.to_bytes()?;
+        let mut receive_buffer = vec![0; MAX_BUFFER_SIZE_EXTENDED];
+        let response = self.transmit(&send_buffer, &mut receive_buffer)?;
+        Response::from_bytes(response)
+    }
+
+    /// Exchange a command APDU with the card, using command chaining if
+    /// needed.
+    ///
+    /// If the command does not fit in a short APDU and the card's
+    /// `ApduOptions` do not declare extended length support, the command
+    /// is sent using ISO 7816-4 command chaining: the data is split into
+    /// chunks of at most `MAX_SHORT_DATA` bytes, sent with the chaining bit
+    /// (0x10) set in the CLA byte for all but the last chunk. If an
+    /// intermediate chunk is not answered with 9000,
+    /// `ApduError::Status` is returned. In this case, an Le over
+    /// `MAX_SHORT_LE` is reduced to `MAX_SHORT_LE`, so enable GET RESPONSE
+    /// handling to receive longer responses. If the `ApduOptions` disable
+    /// command chaining and the data does not fit in a single chunk,
+    /// `CommandError::DataTooLong` is returned.
+    ///
+    /// Otherwise, this function works like [exchange](#method.exchange).
+    pub fn exchange_chained(&self, command: &Command) -> Result<Response, ApduError> {
+        if !command.is_extended() || self.apdu_options.extended_length {
+            return self.exchange(command);
+        }
+        command.validate()?;
+
+        // All chunks but the last are full.
+        let last_len = match command.data.len() {
+            0 => 0,
+            len => (len - 1) % MAX_SHORT_DATA + 1,
+        };
+        let (init, last) = command.data.split_at(command.data.len() - last_len);
+        if !init.is_empty() && !self.apdu_options.command_chaining {
+            return Err(CommandError::DataTooLong(command.data.len()).into());
+        }
+        for chunk in init.chunks(MAX_SHORT_DATA) {
+            let part = Command::new(command.cla | 0x10, command.ins, command.p1, command.p2).with_data(chunk);
+            let response = self.exchange(&part)?;
+            if response.status() != StatusWord::Success {
+                return Err(ApduError::Status(response.status()));
+            }
+        }
+
+        let mut part = Command::new(command.cla, command.ins, command.p1, command.p2).with_data(last);
+        part.le = command.le.map(|le| le.min(MAX_SHORT_LE));
+        self.exchange(&part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::test_card;
+
+    // Check that `command` encodes to `bytes`, and parses back.
+    fn assert_encoding(command: &Command, bytes: &[u8]) {
+        assert_eq!(command.to_bytes().unwrap(), bytes);
+        assert_eq!(&Command::parse(bytes).unwrap(), command);
+    }
+
+    #[test]
+    fn short_commands() {
+        // Case 1.
+        let command = Command::new(0x00, 0xA4, 0x04, 0x00);
+        assert!(!command.is_extended());
+        assert_encoding(&command, &[0x00, 0xA4, 0x04, 0x00]);
+
+        // Case 2S.
+        assert_encoding(&command.clone().with_le(0x10), &[0x00, 0xA4, 0x04, 0x00, 0x10]);
+        assert_encoding(&command.clone().with_le(MAX_SHORT_LE), &[0x00, 0xA4, 0x04, 0x00, 0x00]);
+
+        // Case 3S.
+        let command = command.with_data(&[0xA0, 0x00, 0x00]);
+        assert_eq!(command.data(), [0xA0, 0x00, 0x00]);
+        assert_eq!(command.le(), None);
+        assert_encoding(&command, &[0x00, 0xA4, 0x04, 0x00, 0x03, 0xA0, 0x00, 0x00]);
+
+        // Case 4S.
+        let command = command.with_le(MAX_SHORT_LE);
+        assert!(!command.is_extended());
+        assert_encoding(&command, &[0x00, 0xA4, 0x04, 0x00, 0x03, 0xA0, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            (command.cla(), command.ins(), command.p1(), command.p2()),
+            (0x00, 0xA4, 0x04, 0x00)
+        );
+
+        let data = [0x55; MAX_SHORT_DATA];
+        let bytes = Command::new(0x00, 0xD6, 0x00, 0x00)
+            .with_data(&data)
+            .to_bytes()
+            .unwrap();
+        assert_eq!(bytes.len(), 5 + MAX_SHORT_DATA);
+        assert_eq!(bytes[4], 0xFF);
+    }
+
+    #[test]
+    fn extended_commands() {
+        // Case 2E.
+        let command = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(MAX_EXTENDED_LE);
+        assert!(command.is_extended());
+        assert_encoding(&command, &[0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_encoding(
+            &command.clone().with_le(MAX_SHORT_LE + 1),
+            &[0x00, 0xB0, 0x00, 0x00, 0x00, 0x01, 0x01],
+        );
+
+        // Case 3E.
+        let data = vec![0x55; MAX_SHORT_DATA + 1];
+        let command = Command::new(0x00, 0xD6, 0x00, 0x00).with_data(&data);
+        assert!(command.is_extended());
+        let mut bytes = vec![0x00, 0xD6, 0x00, 0x00, 0x00, 0x01, 0x00];
+        bytes.extend_from_slice(&data);
+        assert_encoding(&command, &bytes);
+
+        // Case 4E.
+        let command = command.with_le(MAX_EXTENDED_LE);
+        bytes.extend_from_slice(&[0x00, 0x00]);
+        assert_encoding(&command, &bytes);
+
+        let data = vec![0x55; MAX_EXTENDED_DATA];
+        let bytes = Command::new(0x00, 0xD6, 0x00, 0x00)
+            .with_data(&data)
+            .to_bytes()
+            .unwrap();
+        assert_eq!(bytes[4..7], [0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn forced_extended_commands() {
+        let command = Command::new(0x00, 0xCA, 0x00, 0x6E);
+        assert_eq!(command.to_bytes_extended().unwrap(), [0x00, 0xCA, 0x00, 0x6E]);
+
+        let command = command.with_le(MAX_SHORT_LE);
+        let bytes = command.to_bytes_extended().unwrap();
+        assert_eq!(bytes, [0x00, 0xCA, 0x00, 0x6E, 0x00, 0x01, 0x00]);
+        assert_eq!(Command::parse(&bytes).unwrap(), command);
+
+        let command = command.with_data(&[0x5C, 0x01, 0x6E]);
+        let bytes = command.to_bytes_extended().unwrap();
+        assert_eq!(
+            bytes,
+            [0x00, 0xCA, 0x00, 0x6E, 0x00, 0x00, 0x03, 0x5C, 0x01, 0x6E, 0x01, 0x00]
+        );
+        assert_eq!(Command::parse(&bytes).unwrap(), command);
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let command = Command::new(0x00, 0xD6, 0x00, 0x00);
+        assert_eq!(
+            command.clone().with_data(&vec![0; MAX_EXTENDED_DATA + 1]).to_bytes(),
+            Err(CommandError::DataTooLong(MAX_EXTENDED_DATA + 1))
+        );
+        assert_eq!(command.clone().with_le(0).to_bytes(), Err(CommandError::InvalidLe(0)));
+        assert_eq!(
+            command.with_le(MAX_EXTENDED_LE + 1).to_bytes_extended(),
+            Err(CommandError::InvalidLe(MAX_EXTENDED_LE + 1))
+        );
+
+        assert_eq!(Command::parse(&[0x00, 0xA4, 0x04]), Err(CommandError::Truncated));
+        for bytes in [
+            // Lc does not match the data.
+            &[0x00, 0xA4, 0x04, 0x00, 0x02, 0xA0][..],
+            &[0x00, 0xA4, 0x04, 0x00, 0x01, 0xA0, 0x00, 0x00],
+            // Too short for an extended Le.
+            &[0x00, 0xB0, 0x00, 0x00, 0x00, 0x01],
+            // Extended Lc of 0.
+            &[0x00, 0xD6, 0x00, 0x00, 0x00, 0x00, 0x00, 0x55],
+            // Extended Lc does not match the data.
+            &[0x00, 0xD6, 0x00, 0x00, 0x00, 0x00, 0x02, 0x55],
+        ] {
+            assert_eq!(
+                Command::parse(bytes),
+                Err(CommandError::InvalidLength),
+                "{:02X?}",
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn status_words() {
+        for sw1 in 0..=0xFF {
+            for sw2 in 0..=0xFF {
+                let sw = StatusWord::new(sw1, sw2);
+                assert_eq!(sw.to_bytes(), [sw1, sw2]);
+                assert_eq!(StatusWord::from([sw1, sw2]), sw);
+                assert_eq!(u16::from(sw), u16::from_be_bytes([sw1, sw2]));
+            }
+        }
+
+        assert_eq!(StatusWord::new(0x90, 0x00), StatusWord::Success);
+        assert_eq!(StatusWord::new(0x61, 0x10), StatusWord::BytesAvailable(0x10));
+        assert_eq!(StatusWord::new(0x63, 0xC2), StatusWord::Counter(2));
+        assert_eq!(StatusWord::new(0x63, 0x02), StatusWord::Other(0x63, 0x02));
+        assert_eq!(StatusWord::new(0x6A, 0x82), StatusWord::FileNotFound);
+        assert_eq!(StatusWord::new(0x6C, 0x00), StatusWord::WrongLe(0x00));
+        assert_eq!(StatusWord::new(0x91, 0x00), StatusWord::Other(0x91, 0x00));
+        assert_eq!(StatusWord::FileNotFound.to_u16(), 0x6A82);
+
+        assert!(StatusWord::Success.is_success());
+        assert!(StatusWord::BytesAvailable(0).is_success());
+        assert!(StatusWord::EndOfFile.is_warning());
+        assert!(StatusWord::Counter(0).is_warning());
+        assert!(StatusWord::WrongLength.is_error());
+        assert!(StatusWord::NoPreciseDiagnosis.is_error());
+        let other = StatusWord::Other(0x91, 0x00);
+        assert!(!other.is_success() && !other.is_warning() && !other.is_error());
+    }
+
+    #[test]
+    fn status_word_display() {
+        assert_eq!(StatusWord::Success.to_string(), "Normal processing (9000)");
+        assert_eq!(
+            StatusWord::FileNotFound.to_string(),
+            "File or application not found (6A82)"
+        );
+        assert_eq!(
+            StatusWord::BytesAvailable(0x10).to_string(),
+            "16 response bytes available (6110)"
+        );
+        assert_eq!(
+            StatusWord::BytesAvailable(0).to_string(),
+            "256 or more response bytes available (6100)"
+        );
+        assert_eq!(
+            StatusWord::Counter(3).to_string(),
+            "Verification failed, 3 tries left (63C3)"
+        );
+        assert_eq!(
+            StatusWord::WrongLe(0).to_string(),
+            "Wrong Le field, 256 bytes available (6C00)"
+        );
+        assert_eq!(StatusWord::Other(0x91, 0x00).to_string(), "Unknown status (9100)");
+    }
+
+    #[test]
+    fn responses() {
+        let response = Response::from_bytes(&[0x01, 0x02, 0x90, 0x00]).unwrap();
+        assert_eq!(response.data(), [0x01, 0x02]);
+        assert_eq!(response.status(), StatusWord::Success);
+        assert_eq!(response.to_bytes(), [0x01, 0x02, 0x90, 0x00]);
+        assert_eq!(response, Response::new(vec![0x01, 0x02], StatusWord::Success));
+        assert_eq!(response.clone().into_data(), [0x01, 0x02]);
+        assert_eq!(response.into_result(), Ok(vec![0x01, 0x02]));
+
+        let response = Response::from_bytes(&[0x6A, 0x82]).unwrap();
+        assert_eq!(response.data(), []);
+        assert_eq!(response.into_result(), Err(ApduError::Status(StatusWord::FileNotFound)));
+
+        // Warnings are errors.
+        let response = Response::from_bytes(&[0x01, 0x62, 0x82]).unwrap();
+        assert_eq!(response.into_result(), Err(ApduError::Status(StatusWord::EndOfFile)));
+
+        assert_eq!(Response::from_bytes(&[]), Err(ApduError::MalformedResponse));
+        assert_eq!(Response::from_bytes(&[0x90]), Err(ApduError::MalformedResponse));
+    }
+
+    #[test]
+    fn get_response_cla() {
+        assert_eq!(super::get_response_cla(0x00), 0x00);
+        assert_eq!(super::get_response_cla(0x13), 0x03);
+        assert_eq!(super::get_response_cla(0x0C), 0x00);
+        assert_eq!(super::get_response_cla(0x51), 0x41);
+        assert_eq!(super::get_response_cla(0x6F), 0x4F);
+        assert_eq!(super::get_response_cla(0x80), 0x00);
+        assert_eq!(super::get_response_cla(0x94), 0x00);
+        assert_eq!(super::get_response_cla(0xFF), 0x00);
+    }
+
+    #[test]
+    fn get_response() {
+        let (mut card, commands) = test_card(|command| match command[1] {
+            0xC0 if command[4] == 2 => vec![0x03, 0x04, 0x90, 0x00],
+            0xC0 => vec![0x6C, 0x02],
+            _ => vec![0x01, 0x02, 0x61, 0x02],
+        });
+        card.set_apdu_options(ApduOptions::new().with_get_response(true));
+
+        let response = card
+            .exchange(&Command::new(0xFF, 0xCA, 0x00, 0x00).with_le(256))
+            .unwrap();
+        assert_eq!(response, Response::new(vec![1, 2, 3, 4], StatusWord::Success));
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [[0xFF, 0xCA, 0x00, 0x00, 0x00], [0x00, 0xC0, 0x00, 0x00, 0x02]]
+        );
+
+        commands.lock().unwrap().clear();
+        card.exchange(&Command::new(0x01, 0xB0, 0x00, 0x00).with_le(256))
+            .unwrap();
+        assert_eq!(commands.lock().unwrap()[1], [0x01, 0xC0, 0x00, 0x00, 0x02]);
+
+        // Without GET RESPONSE handling, 61XX is returned as is.
+        card.set_apdu_options(ApduOptions::new());
+        let response = card
+            .exchange(&Command::new(0x00, 0xB0, 0x00, 0x00).with_le(256))
+            .unwrap();
+        assert_eq!(response.status(), StatusWord::new(0x61, 0x02));
+    }
+
+    #[test]
+    fn retry_wrong_le() {
+        let (mut card, commands) = test_card(|command| match command[4] {
+            4 => vec![0x01, 0x02, 0x03, 0x04, 0x90, 0x00],
+            _ => vec![0x6C, 0x04],
+        });
+        card.set_apdu_options(ApduOptions::new().with_retry_wrong_le(true));
+
+        let data = card.exchange(&Command::new(0x00, 0xB0, 0x00, 0x00).with_le(2)).unwrap();
+        assert_eq!(data.into_result().unwrap(), [1, 2, 3, 4]);
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [[0x00, 0xB0, 0x00, 0x00, 0x02], [0x00, 0xB0, 0x00, 0x00, 0x04]]
+        );
+    }
+
+    #[test]
+    fn max_iterations() {
+        let (mut card, commands) = test_card(|_| vec![0x00, 0x61, 0x01]);
+        card.set_apdu_options(ApduOptions::new().with_get_response(true).with_max_iterations(3));
+        let response = card
+            .exchange(&Command::new(0x00, 0xB0, 0x00, 0x00).with_le(256))
+            .unwrap();
+        assert_eq!(response, Response::new(vec![0; 3], StatusWord::new(0x61, 0x01)));
+        assert_eq!(commands.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn exchange_chained() {
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let (card, commands) = test_card(|_| vec![0x01, 0x90, 0x00]);
+        let command = Command::new(0x00, 0xDA, 0x01, 0x02).with_data(&data).with_le(512);
+        let response = card.exchange_chained(&command).unwrap();
+        assert_eq!(response, Response::new(vec![0x01], StatusWord::Success));
+
+        let part = |cla: u8, chunk: &[u8]| {
+            let mut bytes = vec![cla, 0xDA, 0x01, 0x02, chunk.len() as u8];
+            bytes.extend_from_slice(chunk);
+            bytes
+        };
+        let mut last = part(0x00, &data[510..]);
+        // Only the last chunk has an Le, clamped to 256.
+        last.push(0x00);
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [part(0x10, &data[..255]), part(0x10, &data[255..510]), last]
+        );
+    }
+
+    #[test]
+    fn exchange_chained_error() {
+        let data = [0; 600];
+        let (card, commands) = test_card(|command| match command[0] {
+            0x10 => vec![0x6A, 0x80],
+            _ => vec![0x90, 0x00],
+        });
+        let command = Command::new(0x00, 0xDA, 0x01, 0x02).with_data(&data);
+        assert_eq!(
+            card.exchange_chained(&command),
+            Err(ApduError::Status(StatusWord::new(0x6A, 0x80)))
+        );
+        assert_eq!(commands.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn exchange_chained_options() {
+        let data = [0; 600];
+        let command = Command::new(0x00, 0xDA, 0x01, 0x02).with_data(&data).with_le(512);
+
+        let (mut card, commands) = test_card(|_| vec![0x90, 0x00]);
+        card.set_apdu_options(ApduOptions::new().with_command_chaining(false));
+        assert_eq!(
+            card.exchange_chained(&command),
+            Err(ApduError::InvalidCommand(CommandError::DataTooLong(600)))
+        );
+        assert!(commands.lock().unwrap().is_empty());
+
+        // A short command is sent as is.
+        let short = Command::new(0x00, 0xDA, 0x01, 0x02).with_data(&data[..255]);
+        card.exchange_chained(&short).unwrap();
+        assert_eq!(*commands.lock().unwrap(), [short.to_bytes().unwrap()]);
+
+        commands.lock().unwrap().clear();
+        card.set_apdu_options(ApduOptions::new().with_extended_length(true));
+        card.exchange_chained(&command).unwrap();
+        assert_eq!(*commands.lock().unwrap(), [command.to_bytes().unwrap()]);
+    }
+}
@@ -0,0 +1,1060 @@
+//! Parsing of card ATRs (Answer To Reset), per ISO 7816-3.
+//!
+//! The ATR of a card is available from `ReaderState::atr()`,
+//! `CardStatus::atr()` and `Attribute::AtrString`, as raw bytes.
+//! [`Atr::parse()`](struct.Atr.html#method.parse) decodes it.
+//!
+//! ```
+//! use pcsc::atr::Atr;
+//!
+//! let atr = Atr::parse(&[
+//!     0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F, 0x0C, 0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00, 0x00,
+//!     0x00, 0x00, 0x6A,
+//! ])
+//! .unwrap();
+//! assert_eq!(atr.protocols(), vec![0, 1]);
+//! assert_eq!(atr.historical_bytes().len(), 15);
+//! println!("{}", atr);
+//! ```
+//!
+//! The historical bytes are decoded with `Atr::historical()`, into
+//! compact-TLV objects such as the
+//! [`CardCapabilities`](struct.CardCapabilities.html), which tell whether
+//! the card supports command chaining and extended length commands.
+//! `Card::apply_card_capabilities()` configures the card's `ApduOptions`
+//! from them.
+
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+use bitflags::bitflags;
+
+use crate::apdu::StatusWord;
+use crate::{hex, Card, Error, MAX_ATR_SIZE};
+
+/// An error parsing an ATR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtrError {
+    /// The ATR is empty.
+    Empty,
+    /// The initial character TS is neither 3B nor 3F.
+    InvalidTs(u8),
+    /// The ATR ends before all the bytes announced by its format and
+    /// interface bytes.
+    Truncated,
+    /// The ATR is longer than `MAX_ATR_SIZE`.
+    TooLong(usize),
+    /// There are more bytes after the last expected byte.
+    TrailingBytes(usize),
+    /// The check byte TCK does not match.
+    InvalidChecksum {
+        /// The TCK value which would be correct.
+        expected: u8,
+        /// The TCK value in the ATR.
+        found: u8,
+    },
+    /// The category indicator of the historical bytes is not one of 00,
+    /// 10 or 80.
+    UnsupportedCategory(u8),
+    /// The historical bytes are not valid for their category indicator.
+    InvalidHistoricalBytes,
+}
+
+impl fmt::Display for AtrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AtrError::Empty => f.write_str("empty ATR"),
+            AtrError::InvalidTs(ts) => write!(f, "invalid initial character TS {:02X}", ts),
+            AtrError::Truncated => f.write_str("truncated ATR"),
+            AtrError::TooLong(len) => write!(f, "ATR too long ({} bytes)", len),
+            AtrError::TrailingBytes(count) => write!(f, "{} trailing bytes after the ATR", count),
+            AtrError::InvalidChecksum { expected, found } => {
+                write!(f, "invalid TCK {:02X}, expected {:02X}", found, expected)
+            }
+            AtrError::UnsupportedCategory(category) => {
+                write!(f, "unsupported historical bytes category {:02X}", category)
+            }
+            AtrError::InvalidHistoricalBytes => f.write_str("invalid historical bytes"),
+        }
+    }
+}
+
+impl error::Error for AtrError {}
+
+/// The transmission convention, indicated by the initial character TS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Convention {
+    /// TS = 3B.
+    Direct,
+    /// TS = 3F.
+    Inverse,
+}
+
+/// The interface bytes TAi, TBi, TCi and TDi of one group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct InterfaceBytes {
+    pub ta: Option<u8>,
+    pub tb: Option<u8>,
+    pub tc: Option<u8>,
+    pub td: Option<u8>,
+}
+
+impl InterfaceBytes {
+    /// The protocol T indicated by TDi, if present.
+    pub fn protocol(&self) -> Option<u8> {
+        self.td.map(|td| td & 0x0F)
+    }
+}
+
+/// Parameters of the T=1 protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct T1Parameters {
+    /// The maximum information field size of the card (IFSC).
+    pub ifsc: u8,
+    /// The block waiting time integer (BWI).
+    pub bwi: u8,
+    /// The character waiting time integer (CWI).
+    pub cwi: u8,
+    /// Whether the error detection code is CRC (rather than LRC).
+    pub crc: bool,
+}
+
+const FI_TABLE: [Option<u16>; 16] = [
+    Some(372),
+    Some(372),
+    Some(558),
+    Some(744),
+    Some(1116),
+    Some(1488),
+    Some(1860),
+    None,
+    None,
+    Some(512),
+    Some(768),
+    Some(1024),
+    Some(1536),
+    Some(2048),
+    None,
+    None,
+];
+
+const FMAX_TABLE: [Option<f32>; 16] = [
+    Some(4.0),
+    Some(5.0),
+    Some(6.0),
+    Some(8.0),
+    Some(12.0),
+    Some(16.0),
+    Some(20.0),
+    None,
+    None,
+    Some(5.0),
+    Some(7.5),
+    Some(10.0),
+    Some(15.0),
+    Some(20.0),
+    None,
+    None,
+];
+
+const DI_TABLE: [Option<u8>; 16] = [
+    None,
+    Some(1),
+    Some(2),
+    Some(4),
+    Some(8),
+    Some(16),
+    Some(32),
+    Some(64),
+    Some(12),
+    Some(20),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+/// A parsed ATR.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Atr {
+    bytes: Vec<u8>,
+    convention: Convention,
+    interface_bytes: Vec<InterfaceBytes>,
+    historical_start: usize,
+    historical_len: usize,
+    tck: Option<u8>,
+}
+
+impl Atr {
+    /// Parse an ATR.
+    ///
+    /// The check byte TCK is validated when present.
+    pub fn parse(bytes: &[u8]) -> Result<Atr, AtrError> {
+        if bytes.is_empty() {
+            return Err(AtrError::Empty);
+        }
+        if bytes.len() > MAX_ATR_SIZE {
+            return Err(AtrError::TooLong(bytes.len()));
+        }
+        let convention = match bytes[0] {
+            0x3B => Convention::Direct,
+            0x3F => Convention::Inverse,
+            ts => return Err(AtrError::InvalidTs(ts)),
+        };
+
+        let byte_at = |pos: usize| bytes.get(pos).copied().ok_or(AtrError::Truncated);
+        let t0 = byte_at(1)?;
+        let historical_len = (t0 & 0x0F) as usize;
+        let mut pos = 2;
+        let mut interface_bytes = Vec::new();
+        let mut y = t0 >> 4;
+        let mut tck_present = false;
+        loop {
+            let mut group = InterfaceBytes::default();
+            for (bit, field) in [&mut group.ta, &mut group.tb, &mut group.tc, &mut group.td]
+                .iter_mut()
+                .enumerate()
+            {
+                if y & (1 << bit) != 0 {
+                    **field = Some(byte_at(pos)?);
+                    pos += 1;
+                }
+            }
+            interface_bytes.push(group);
+            match group.td {
+                Some(td) => {
+                    // TCK is present if any protocol other than T=0 is
+                    // indicated.
+                    if td & 0x0F != 0 {
+                        tck_present = true;
+                    }
+                    y = td >> 4;
+                }
+                None => break,
+            }
+        }
+
+        let historical_start = pos;
+        pos += historical_len;
+        if pos > bytes.len() {
+            return Err(AtrError::Truncated);
+        }
+
+        let tck = if tck_present {
+            let tck = byte_at(pos)?;
+            let expected = bytes[1..pos].iter().fold(0, |acc, byte| acc ^ byte);
+            if expected != tck {
+                return Err(AtrError::InvalidChecksum { expected, found: tck });
+            }
+            pos += 1;
+            Some(tck)
+        } else {
+            None
+        };
+        if pos < bytes.len() {
+            return Err(AtrError::TrailingBytes(bytes.len() - pos));
+        }
+
+        Ok(Atr {
+            bytes: bytes.to_vec(),
+            convention,
+            interface_bytes,
+            historical_start,
+            historical_len,
+            tck,
+        })
+    }
+
+    /// The raw ATR bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The transmission convention.
+    pub fn convention(&self) -> Convention {
+        self.convention
+    }
+
+    /// The format byte T0.
+    pub fn t0(&self) -> u8 {
+        self.bytes[1]
+    }
+
+    /// The groups of interface bytes; element 0 holds TA1, TB1, TC1 and
+    /// TD1, and so on.
+    pub fn interface_bytes(&self) -> &[InterfaceBytes] {
+        &self.interface_bytes
+    }
+
+    /// The historical bytes.
+    pub fn historical_bytes(&self) -> &[u8] {
+        &self.bytes[self.historical_start..self.historical_start + self.historical_len]
+    }
+
+    /// The check byte TCK, if present.
+    pub fn tck(&self) -> Option<u8> {
+        self.tck
+    }
+
+    /// Decode the historical bytes.
+    pub fn historical(&self) -> Result<HistoricalBytes, AtrError> {
+        HistoricalBytes::parse(self.historical_bytes())
+    }
+
+    /// The protocols offered by the card, in order of preference.
+    ///
+    /// If no protocol is indicated, the card uses T=0. T=15 is not a
+    /// transmission protocol, but indicates global interface bytes; it is
+    /// included if indicated.
+    pub fn protocols(&self) -> Vec<u8> {
+        let mut protocols = Vec::new();
+        for group in &self.interface_bytes {
+            if let Some(protocol) = group.protocol() {
+                if !protocols.contains(&protocol) {
+                    protocols.push(protocol);
+                }
+            }
+        }
+        if protocols.is_empty() {
+            protocols.push(0);
+        }
+        protocols
+    }
+
+    fn ta1(&self) -> u8 {
+        // The default is Fi = 372, Di = 1.
+        self.interface_bytes[0].ta.unwrap_or(0x11)
+    }
+
+    /// The clock rate conversion integer Fi, from TA1.
+    ///
+    /// Returns `None` if TA1 indicates a reserved value.
+    pub fn fi(&self) -> Option<u16> {
+        FI_TABLE[(self.ta1() >> 4) as usize]
+    }
+
+    /// The maximum clock frequency in MHz, from TA1.
+    pub fn f_max(&self) -> Option<f32> {
+        FMAX_TABLE[(self.ta1() >> 4) as usize]
+    }
+
+    /// The baud rate adjustment integer Di, from TA1.
+    ///
+    /// Returns `None` if TA1 indicates a reserved value.
+    pub fn di(&self) -> Option<u8> {
+        DI_TABLE[(self.ta1() & 0x0F) as usize]
+    }
+
+    /// The extra guard time integer N, from TC1.
+    pub fn extra_guard_time(&self) -> u8 {
+        self.interface_bytes[0].tc.unwrap_or(0)
+    }
+
+    // The first interface bytes specific to protocol T: TAi, TBi and TCi
+    // for i > 2, following the first TD(i-1) indicating T.
+    fn specific_bytes(&self, protocol: u8) -> Option<&InterfaceBytes> {
+        self.interface_bytes
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find(|(_, group)| group.protocol() == Some(protocol))
+            .and_then(|(index, _)| self.interface_bytes.get(index + 1))
+    }
+
+    /// The T=1 parameters, if the card offers T=1.
+    ///
+    /// Parameters not present in the ATR have their default values.
+    pub fn t1_parameters(&self) -> Option<T1Parameters> {
+        if !self.protocols().contains(&1) {
+            return None;
+        }
+        let specific = self.specific_bytes(1).copied().unwrap_or_default();
+        let tb = specific.tb.unwrap_or(0x4D);
+        Some(T1Parameters {
+            ifsc: specific.ta.unwrap_or(32),
+            bwi: tb >> 4,
+            cwi: tb & 0x0F,
+            crc: specific.tc.map_or(false, |tc| tc & 0x01 != 0),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Atr {
+    type Error = AtrError;
+
+    fn try_from(bytes: &[u8]) -> Result<Atr, AtrError> {
+        Atr::parse(bytes)
+    }
+}
+
+fn describe_ta1(ta1: u8) -> String {
+    let fi = FI_TABLE[(ta1 >> 4) as usize].map_or_else(|| "RFU".to_owned(), |fi| fi.to_string());
+    let di = DI_TABLE[(ta1 & 0x0F) as usize].map_or_else(|| "RFU".to_owned(), |di| di.to_string());
+    format!("Fi = {}, Di = {}", fi, di)
+}
+
+impl fmt::Display for Atr {
+    /// Renders a readable breakdown of the ATR, one item per line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ATR: {}", hex::encode(&self.bytes))?;
+        let convention = match self.convention {
+            Convention::Direct => "direct",
+            Convention::Inverse => "inverse",
+        };
+        writeln!(f, "  TS = {:02X}: {} convention", self.bytes[0], convention)?;
+        writeln!(
+            f,
+            "  T0 = {:02X}: Y1 = {:X}, K = {} historical bytes",
+            self.t0(),
+            self.t0() >> 4,
+            self.historical_len
+        )?;
+
+        // The protocol the interface bytes of each group relate to.
+        let mut protocol = None;
+        for (index, group) in self.interface_bytes.iter().enumerate() {
+            let i = index + 1;
+            if let Some(ta) = group.ta {
+                let meaning = match (i, protocol) {
+                    (1, _) => describe_ta1(ta),
+                    (2, _) => format!(
+                        "specific mode, T = {}{}",
+                        ta & 0x0F,
+                        if ta & 0x10 != 0 { ", implicit parameters" } else { "" }
+                    ),
+                    (_, Some(15)) => format!("clock stop {:#04b}, class {:#08b}", ta >> 6, ta & 0x3F),
+                    (_, Some(1)) => format!("IFSC = {}", ta),
+                    _ => String::new(),
+                };
+                writeln!(f, "  TA{} = {:02X}: {}", i, ta, meaning)?;
+            }
+            if let Some(tb) = group.tb {
+                let meaning = match (i, protocol) {
+                    (1, _) | (2, _) => "VPP (deprecated)".to_owned(),
+                    (_, Some(1)) => format!("BWI = {}, CWI = {}", tb >> 4, tb & 0x0F),
+                    _ => String::new(),
+                };
+                writeln!(f, "  TB{} = {:02X}: {}", i, tb, meaning)?;
+            }
+            if let Some(tc) = group.tc {
+                let meaning = match (i, protocol) {
+                    (1, _) => format!("extra guard time N = {}", tc),
+                    (2, _) => format!("work waiting time integer WI = {}", tc),
+                    (_, Some(1)) => format!("error detection {}", if tc & 0x01 != 0 { "CRC" } else { "LRC" }),
+                    _ => String::new(),
+                };
+                writeln!(f, "  TC{} = {:02X}: {}", i, tc, meaning)?;
+            }
+            if let Some(td) = group.td {
+                writeln!(
+                    f,
+                    "  TD{} = {:02X}: Y{} = {:X}, T = {}",
+                    i,
+                    td,
+                    i + 1,
+                    td >> 4,
+                    td & 0x0F
+                )?;
+                protocol = Some(td & 0x0F);
+            }
+        }
+
+        writeln!(f, "  Historical bytes: {}", hex::encode(self.historical_bytes()))?;
+        if let Some(tck) = self.tck {
+            writeln!(f, "  TCK = {:02X} (correct)", tck)?;
+        }
+        Ok(())
+    }
+}
+
+// Compact-TLV tags, per ISO 7816-4.
+const TAG_CARD_SERVICE_DATA: u8 = 0x3;
+const TAG_INITIAL_ACCESS_DATA: u8 = 0x4;
+const TAG_CARD_ISSUER_DATA: u8 = 0x5;
+const TAG_PRE_ISSUING_DATA: u8 = 0x6;
+const TAG_CARD_CAPABILITIES: u8 = 0x7;
+const TAG_STATUS_INDICATOR: u8 = 0x8;
+const TAG_APPLICATION_IDENTIFIER: u8 = 0xF;
+
+// Introduces an application identifier with an explicit length byte, as in
+// the ATRs of PC/SC Part 3 storage cards.
+const AID_HEADER: u8 = 0x4F;
+
+/// A compact-TLV data object of the historical bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompactTlv {
+    /// The tag, from 0x0 to 0xF.
+    pub tag: u8,
+    /// The value, at most 15 bytes.
+    ///
+    /// An application identifier introduced by the `4F` header of PC/SC
+    /// Part 3 has tag 0xF, and its value may be up to 255 bytes.
+    pub value: Vec<u8>,
+}
+
+/// The status indicator: the card life cycle status and a status word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatusIndicator {
+    /// The life cycle status byte (LCS).
+    pub life_cycle: Option<u8>,
+    /// The status word, where 9000 means the card is operational.
+    pub status: Option<StatusWord>,
+}
+
+impl StatusIndicator {
+    fn parse(bytes: &[u8]) -> Option<StatusIndicator> {
+        match *bytes {
+            [lcs] => Some(StatusIndicator {
+                life_cycle: Some(lcs),
+                status: None,
+            }),
+            [sw1, sw2] => Some(StatusIndicator {
+                life_cycle: None,
+                status: Some(StatusWord::new(sw1, sw2)),
+            }),
+            [lcs, sw1, sw2] => Some(StatusIndicator {
+                life_cycle: Some(lcs),
+                status: Some(StatusWord::new(sw1, sw2)),
+            }),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    /// The selection methods supported by the card, from the first byte of
+    /// the card capabilities.
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+    pub struct SelectionMethods: u8 {
+        const FULL_DF_NAME = 0x80;
+        const PARTIAL_DF_NAME = 0x40;
+        const PATH = 0x20;
+        const FILE_IDENTIFIER = 0x10;
+        const IMPLICIT_DF = 0x08;
+        const SHORT_EF_IDENTIFIER = 0x04;
+        const RECORD_NUMBER = 0x02;
+        const RECORD_IDENTIFIER = 0x01;
+    }
+}
+
+/// The card capabilities, from the historical bytes.
+///
+/// The capabilities consist of up to three bytes; the absent ones are
+/// taken as 00.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CardCapabilities {
+    bytes: [u8; 3],
+    len: usize,
+}
+
+impl CardCapabilities {
+    /// Decode the value of a card capabilities object.
+    ///
+    /// Returns `None` if `bytes` is empty or longer than 3 bytes.
+    pub fn parse(bytes: &[u8]) -> Option<CardCapabilities> {
+        if bytes.is_empty() || bytes.len() > 3 {
+            return None;
+        }
+        let mut capabilities = CardCapabilities {
+            bytes: [0; 3],
+            len: bytes.len(),
+        };
+        capabilities.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(capabilities)
+    }
+
+    /// The raw bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// The supported selection methods.
+    pub fn selection_methods(&self) -> SelectionMethods {
+        SelectionMethods::from_bits_retain(self.bytes[0])
+    }
+
+    /// The data coding byte, if present.
+    pub fn data_coding(&self) -> Option<u8> {
+        if self.len >= 2 {
+            Some(self.bytes[1])
+        } else {
+            None
+        }
+    }
+
+    /// Whether the card supports command chaining.
+    pub fn command_chaining(&self) -> bool {
+        self.bytes[2] & 0x80 != 0
+    }
+
+    /// Whether the card supports extended Lc and Le fields.
+    pub fn extended_length(&self) -> bool {
+        self.bytes[2] & 0x40 != 0
+    }
+
+    /// Whether the extended length information is in EF.ATR/INFO.
+    pub fn extended_length_info(&self) -> bool {
+        self.bytes[2] & 0x20 != 0
+    }
+
+    /// Whether logical channels are assigned by the card.
+    pub fn card_assigns_channels(&self) -> bool {
+        self.bytes[2] & 0x10 != 0
+    }
+
+    /// Whether logical channels are assigned by the interface device.
+    pub fn host_assigns_channels(&self) -> bool {
+        self.bytes[2] & 0x08 != 0
+    }
+
+    /// The maximum number of logical channels, including the basic
+    /// channel.
+    ///
+    /// The value 8 means 8 or more.
+    pub fn logical_channels(&self) -> u8 {
+        if self.card_assigns_channels() || self.host_assigns_channels() {
+            (self.bytes[2] & 0x07) + 1
+        } else {
+            1
+        }
+    }
+}
+
+/// The decoded historical bytes of an ATR, per ISO 7816-4.
+///
+/// The category indicator, the first historical byte, determines the
+/// format:
+///
+/// - `00`: compact-TLV objects, followed by a mandatory 3-byte status
+///   indicator.
+/// - `80`: compact-TLV objects, optionally including a status indicator.
+///   An application identifier may be given as `4F`, a length byte and
+///   the identifier, as storage cards do per PC/SC Part 3.
+/// - `10`: a DIR data reference.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HistoricalBytes {
+    category: u8,
+    objects: Vec<CompactTlv>,
+    status_indicator: Option<StatusIndicator>,
+    dir_data_reference: Option<u8>,
+}
+
+impl HistoricalBytes {
+    /// Decode historical bytes.
+    pub fn parse(bytes: &[u8]) -> Result<HistoricalBytes, AtrError> {
+        let (&category, rest) = bytes.split_first().ok_or(AtrError::Empty)?;
+        let mut historical = HistoricalBytes {
+            category,
+            objects: Vec::new(),
+            status_indicator: None,
+            dir_data_reference: None,
+        };
+        match category {
+            0x00 => {
+                if rest.len() < 3 {
+                    return Err(AtrError::InvalidHistoricalBytes);
+                }
+                let (objects, status) = rest.split_at(rest.len() - 3);
+                historical.objects = parse_compact_tlv(objects)?;
+                historical.status_indicator = StatusIndicator::parse(status);
+            }
+            0x80 => {
+                historical.objects = parse_compact_tlv(rest)?;
+                if let Some(status) = historical.get(TAG_STATUS_INDICATOR) {
+                    historical.status_indicator =
+                        Some(StatusIndicator::parse(status).ok_or(AtrError::InvalidHistoricalBytes)?);
+                }
+            }
+            0x10 => match *rest {
+                [reference] => historical.dir_data_reference = Some(reference),
+                _ => return Err(AtrError::InvalidHistoricalBytes),
+            },
+            _ => return Err(AtrError::UnsupportedCategory(category)),
+        }
+        Ok(historical)
+    }
+
+    /// The category indicator.
+    pub fn category(&self) -> u8 {
+        self.category
+    }
+
+    /// The compact-TLV objects.
+    ///
+    /// For category 00, the trailing status indicator is not included.
+    pub fn objects(&self) -> &[CompactTlv] {
+        &self.objects
+    }
+
+    /// The value of the first compact-TLV object with tag `tag`.
+    pub fn get(&self, tag: u8) -> Option<&[u8]> {
+        self.objects
+            .iter()
+            .find(|object| object.tag == tag)
+            .map(|object| &object.value[..])
+    }
+
+    /// The card service data byte.
+    pub fn card_service_data(&self) -> Option<u8> {
+        self.get(TAG_CARD_SERVICE_DATA).and_then(|value| value.first().copied())
+    }
+
+    /// The initial access data.
+    pub fn initial_access_data(&self) -> Option<&[u8]> {
+        self.get(TAG_INITIAL_ACCESS_DATA)
+    }
+
+    /// The card issuer's data.
+    pub fn card_issuer_data(&self) -> Option<&[u8]> {
+        self.get(TAG_CARD_ISSUER_DATA)
+    }
+
+    /// The pre-issuing data.
+    pub fn pre_issuing_data(&self) -> Option<&[u8]> {
+        self.get(TAG_PRE_ISSUING_DATA)
+    }
+
+    /// The application identifier.
+    pub fn application_identifier(&self) -> Option<&[u8]> {
+        self.get(TAG_APPLICATION_IDENTIFIER)
+    }
+
+    /// The card capabilities.
+    pub fn card_capabilities(&self) -> Option<CardCapabilities> {
+        self.get(TAG_CARD_CAPABILITIES).and_then(CardCapabilities::parse)
+    }
+
+    /// The status indicator.
+    pub fn status_indicator(&self) -> Option<StatusIndicator> {
+        self.status_indicator
+    }
+
+    /// The DIR data reference, for category 10.
+    pub fn dir_data_reference(&self) -> Option<u8> {
+        self.dir_data_reference
+    }
+}
+
+fn parse_compact_tlv(mut bytes: &[u8]) -> Result<Vec<CompactTlv>, AtrError> {
+    let mut objects = Vec::new();
+    while let Some((&header, rest)) = bytes.split_first() {
+        let (tag, len, rest) = match (header, rest) {
+            (AID_HEADER, [len, rest @ ..]) => (TAG_APPLICATION_IDENTIFIER, *len as usize, rest),
+            _ => (header >> 4, (header & 0x0F) as usize, rest),
+        };
+        if len > rest.len() {
+            return Err(AtrError::InvalidHistoricalBytes);
+        }
+        let (value, rest) = rest.split_at(len);
+        objects.push(CompactTlv {
+            tag,
+            value: value.to_vec(),
+        });
+        bytes = rest;
+    }
+    Ok(objects)
+}
+
+impl Card {
+    /// The card capabilities from the historical bytes of the card's ATR.
+    ///
+    /// Returns `None` if the ATR or its historical bytes cannot be decoded,
+    /// or do not include card capabilities.
+    pub fn card_capabilities(&self) -> Result<Option<CardCapabilities>, Error> {
+        let status = self.status2_owned()?;
+        Ok(Atr::parse(status.atr())
+            .ok()
+            .and_then(|atr| atr.historical().ok())
+            .and_then(|historical| historical.card_capabilities()))
+    }
+
+    /// Configure the card's `ApduOptions` from its card capabilities.
+    ///
+    /// Extended length and command chaining support are set as indicated
+    /// by the card, see `ApduOptions::with_card_capabilities()`. If the
+    /// card does not indicate its capabilities, the options are left
+    /// unchanged.
+    ///
+    /// Returns the card capabilities, if any.
+    pub fn apply_card_capabilities(&mut self) -> Result<Option<CardCapabilities>, Error> {
+        let capabilities = self.card_capabilities()?;
+        if let Some(ref capabilities) = capabilities {
+            self.apdu_options = self.apdu_options.with_card_capabilities(capabilities);
+        }
+        Ok(capabilities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A MIFARE Classic 1K, per PC/SC Part 3.
+    const STORAGE_CARD_ATR: &[u8] = &[
+        0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F, 0x0C, 0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x6A,
+    ];
+
+    // A YubiKey 4, offering T=1.
+    const T1_ATR: &[u8] = &[
+        0x3B, 0xF8, 0x13, 0x00, 0x00, 0x81, 0x31, 0xFE, 0x15, 0x59, 0x75, 0x62, 0x69, 0x6B, 0x65, 0x79, 0x34, 0xD4,
+    ];
+
+    #[test]
+    fn minimal() {
+        let atr = Atr::parse(&[0x3B, 0x00]).unwrap();
+        assert_eq!(atr.as_bytes(), [0x3B, 0x00]);
+        assert_eq!(atr.convention(), Convention::Direct);
+        assert_eq!(atr.t0(), 0x00);
+        assert_eq!(atr.interface_bytes(), [InterfaceBytes::default()]);
+        assert_eq!(atr.historical_bytes(), []);
+        assert_eq!(atr.tck(), None);
+        assert_eq!(atr.protocols(), vec![0]);
+        assert_eq!(atr.fi(), Some(372));
+        assert_eq!(atr.f_max(), Some(5.0));
+        assert_eq!(atr.di(), Some(1));
+        assert_eq!(atr.extra_guard_time(), 0);
+        assert_eq!(atr.t1_parameters(), None);
+        assert_eq!(atr.historical(), Err(AtrError::Empty));
+
+        let atr = Atr::try_from(&[0x3F, 0x00][..]).unwrap();
+        assert_eq!(atr.convention(), Convention::Inverse);
+    }
+
+    #[test]
+    fn interface_bytes() {
+        let atr = Atr::parse(T1_ATR).unwrap();
+        assert_eq!(atr.as_bytes(), T1_ATR);
+        assert_eq!(
+            atr.interface_bytes(),
+            [
+                InterfaceBytes {
+                    ta: Some(0x13),
+                    tb: Some(0x00),
+                    tc: Some(0x00),
+                    td: Some(0x81),
+                },
+                InterfaceBytes {
+                    ta: None,
+                    tb: None,
+                    tc: None,
+                    td: Some(0x31),
+                },
+                InterfaceBytes {
+                    ta: Some(0xFE),
+                    tb: Some(0x15),
+                    tc: None,
+                    td: None,
+                },
+            ]
+        );
+        assert_eq!(atr.historical_bytes(), b"Yubikey4");
+        assert_eq!(atr.tck(), Some(0xD4));
+        assert_eq!(atr.protocols(), vec![1]);
+        assert_eq!(atr.fi(), Some(372));
+        assert_eq!(atr.di(), Some(4));
+        assert_eq!(
+            atr.t1_parameters(),
+            Some(T1Parameters {
+                ifsc: 254,
+                bwi: 1,
+                cwi: 5,
+                crc: false,
+            })
+        );
+        assert_eq!(atr.historical(), Err(AtrError::UnsupportedCategory(0x59)));
+    }
+
+    #[test]
+    fn fi_di() {
+        // TA1 = 96: Fi = 512, Di = 32.
+        let atr = Atr::parse(&[0x3B, 0x10, 0x96]).unwrap();
+        assert_eq!(atr.fi(), Some(512));
+        assert_eq!(atr.f_max(), Some(5.0));
+        assert_eq!(atr.di(), Some(32));
+
+        // TA1 = 7A: both reserved.
+        let atr = Atr::parse(&[0x3B, 0x10, 0x7A]).unwrap();
+        assert_eq!(atr.fi(), None);
+        assert_eq!(atr.f_max(), None);
+        assert_eq!(atr.di(), None);
+        assert!(atr.to_string().contains("TA1 = 7A: Fi = RFU, Di = RFU"));
+
+        // T=1 without specific interface bytes has the default parameters.
+        let atr = Atr::parse(&[0x3B, 0x80, 0x01, 0x81]).unwrap();
+        assert_eq!(atr.protocols(), vec![1]);
+        assert_eq!(
+            atr.t1_parameters(),
+            Some(T1Parameters {
+                ifsc: 32,
+                bwi: 4,
+                cwi: 13,
+                crc: false,
+            })
+        );
+    }
+
+    #[test]
+    fn checksum() {
+        let mut bytes = T1_ATR.to_vec();
+        *bytes.last_mut().unwrap() = 0xD5;
+        assert_eq!(
+            Atr::parse(&bytes),
+            Err(AtrError::InvalidChecksum {
+                expected: 0xD4,
+                found: 0xD5,
+            })
+        );
+        assert_eq!(
+            AtrError::InvalidChecksum {
+                expected: 0xD4,
+                found: 0xD5,
+            }
+            .to_string(),
+            "invalid TCK D5, expected D4"
+        );
+
+        // TCK is required for T=1, and absent for T=0 only.
+        assert_eq!(Atr::parse(&T1_ATR[..T1_ATR.len() - 1]), Err(AtrError::Truncated));
+        let atr = Atr::parse(&[0x3B, 0x80, 0x00]).unwrap();
+        assert_eq!(atr.protocols(), vec![0]);
+        assert_eq!(atr.tck(), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Atr::parse(&[]), Err(AtrError::Empty));
+        assert_eq!(Atr::parse(&[0x3C, 0x00]), Err(AtrError::InvalidTs(0x3C)));
+        assert_eq!(Atr::parse(&[0x3B]), Err(AtrError::Truncated));
+        assert_eq!(Atr::parse(&[0x3B, 0x02, 0x80]), Err(AtrError::Truncated));
+        assert_eq!(Atr::parse(&[0x3B, 0xF0, 0x11, 0x00]), Err(AtrError::Truncated));
+        assert_eq!(Atr::parse(&[0x3B, 0x00, 0x00, 0x00]), Err(AtrError::TrailingBytes(2)));
+        assert_eq!(
+            Atr::parse(&[0x3B; MAX_ATR_SIZE + 1]),
+            Err(AtrError::TooLong(MAX_ATR_SIZE + 1))
+        );
+    }
+
+    #[test]
+    fn display() {
+        let display = Atr::parse(T1_ATR).unwrap().to_string();
+        let lines: Vec<&str> = display.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "ATR: 3BF81300008131FE15597562696B657934D4",
+                "  TS = 3B: direct convention",
+                "  T0 = F8: Y1 = F, K = 8 historical bytes",
+                "  TA1 = 13: Fi = 372, Di = 4",
+                "  TB1 = 00: VPP (deprecated)",
+                "  TC1 = 00: extra guard time N = 0",
+                "  TD1 = 81: Y2 = 8, T = 1",
+                "  TD2 = 31: Y3 = 3, T = 1",
+                "  TA3 = FE: IFSC = 254",
+                "  TB3 = 15: BWI = 1, CWI = 5",
+                "  Historical bytes: 597562696B657934",
+                "  TCK = D4 (correct)",
+            ]
+        );
+    }
+
+    #[test]
+    fn historical_categories() {
+        // Category 00, with a status indicator at the end.
+        let historical = HistoricalBytes::parse(&[0x00, 0x31, 0xC0, 0x73, 0xBE, 0x21, 0xC0, 0x05, 0x90, 0x00]).unwrap();
+        assert_eq!(historical.category(), 0x00);
+        assert_eq!(historical.card_service_data(), Some(0xC0));
+        assert_eq!(
+            historical.status_indicator(),
+            Some(StatusIndicator {
+                life_cycle: Some(0x05),
+                status: Some(StatusWord::new(0x90, 0x00)),
+            })
+        );
+        let capabilities = historical.card_capabilities().unwrap();
+        assert_eq!(capabilities.as_bytes(), [0xBE, 0x21, 0xC0]);
+        assert_eq!(
+            capabilities.selection_methods(),
+            SelectionMethods::FULL_DF_NAME
+                | SelectionMethods::PATH
+                | SelectionMethods::FILE_IDENTIFIER
+                | SelectionMethods::IMPLICIT_DF
+                | SelectionMethods::SHORT_EF_IDENTIFIER
+                | SelectionMethods::RECORD_NUMBER
+        );
+        assert_eq!(capabilities.data_coding(), Some(0x21));
+        assert!(capabilities.command_chaining());
+        assert!(capabilities.extended_length());
+        assert_eq!(capabilities.logical_channels(), 1);
+        assert_eq!(
+            HistoricalBytes::parse(&[0x00, 0x90, 0x00]),
+            Err(AtrError::InvalidHistoricalBytes)
+        );
+
+        // Category 80, with an optional status indicator object.
+        let historical = HistoricalBytes::parse(&[0x80, 0x71, 0x80, 0x82, 0x90, 0x00]).unwrap();
+        assert_eq!(historical.objects().len(), 2);
+        assert_eq!(
+            historical.status_indicator(),
+            Some(StatusIndicator {
+                life_cycle: None,
+                status: Some(StatusWord::new(0x90, 0x00)),
+            })
+        );
+        let capabilities = historical.card_capabilities().unwrap();
+        assert_eq!(capabilities.data_coding(), None);
+        assert!(!capabilities.command_chaining());
+        assert_eq!(
+            HistoricalBytes::parse(&[0x80, 0x84, 0x01, 0x02, 0x03, 0x04]),
+            Err(AtrError::InvalidHistoricalBytes)
+        );
+        assert_eq!(
+            HistoricalBytes::parse(&[0x80, 0x72, 0x80]),
+            Err(AtrError::InvalidHistoricalBytes)
+        );
+
+        // Category 10, with a DIR data reference.
+        let historical = HistoricalBytes::parse(&[0x10, 0x2A]).unwrap();
+        assert_eq!(historical.dir_data_reference(), Some(0x2A));
+        assert_eq!(historical.objects(), []);
+        assert_eq!(HistoricalBytes::parse(&[0x10]), Err(AtrError::InvalidHistoricalBytes));
+
+        assert_eq!(
+            HistoricalBytes::parse(&[0x41]),
+            Err(AtrError::UnsupportedCategory(0x41))
+        );
+    }
+
+    #[test]
+    fn storage_card_historical_bytes() {
+        let atr = Atr::parse(STORAGE_CARD_ATR).unwrap();
+        let historical = atr.historical().unwrap();
+        assert_eq!(historical.category(), 0x80);
+        assert_eq!(
+            historical.objects(),
+            [CompactTlv {
+                tag: 0xF,
+                value: vec![0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00],
+            }]
+        );
+        assert_eq!(
+            historical.application_identifier(),
+            Some(&[0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00][..])
+        );
+        assert_eq!(historical.card_capabilities(), None);
+    }
+
+    #[test]
+    fn compact_tlv_aid_header() {
+        let historical = HistoricalBytes::parse(&[0x80, 0x4F, 0x02, 0xA0, 0x00, 0x31, 0xC0]).unwrap();
+        assert_eq!(historical.application_identifier(), Some(&[0xA0, 0x00][..]));
+        assert_eq!(historical.card_service_data(), Some(0xC0));
+
+        for bytes in [&[0x80, 0x4F][..], &[0x80, 0x4F, 0x03, 0xA0, 0x00]] {
+            assert_eq!(HistoricalBytes::parse(bytes), Err(AtrError::InvalidHistoricalBytes));
+        }
+    }
+}
@@ -0,0 +1,568 @@
+//! Identification of cards by their ATR.
+//!
+//! An [`AtrList`](struct.AtrList.html) holds ATR patterns with card
+//! descriptions, in the `smartcard_list.txt` format of
+//! [pcsc-tools](https://pcsc-tools.apdu.fr/), and finds the descriptions
+//! matching an ATR, like `pcsc_scan` does.
+//!
+//! A small list of well-known ATRs is built in, see
+//! `AtrList::embedded()`. The comprehensive list maintained by pcsc-tools
+//! can be downloaded from <https://pcsc-tools.apdu.fr/smartcard_list.txt>
+//! and loaded with `AtrList::load()`.
+//!
+//! ```
+//! use pcsc::atr_list::AtrList;
+//!
+//! let list = AtrList::embedded();
+//! let atr = [
+//!     0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F, 0x0C, 0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00, 0x00,
+//!     0x00, 0x00, 0x6A,
+//! ];
+//! for description in list.identify(&atr) {
+//!     println!("{}", description);
+//! }
+//! ```
+//!
+//! ## List format
+//!
+//! ```text
+//! # A comment.
+//! 3B 8F 80 01 80 4F 0C A0 00 00 03 06 .. .. .. 00 00 00 00 ..
+//!     Contactless storage card
+//! ```
+//!
+//! Each entry is a line with an ATR pattern, followed by one or more lines
+//! with a description, indented with a tab (or spaces). The pattern is a regular
+//! expression matched against the whole ATR, written as hex bytes
+//! separated by spaces, ignoring case. The supported syntax is `.`,
+//! bracket expressions such as `[0-9A-F]`, the repetitions `*`, `+`, `?`
+//! and `{n,m}`, groups with `(...)` and alternatives with `|`.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// An error parsing an ATR list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAtrListError {
+    line: usize,
+    message: String,
+}
+
+impl ParseAtrListError {
+    fn new(line: usize, message: &str) -> ParseAtrListError {
+        ParseAtrListError {
+            line,
+            message: message.to_owned(),
+        }
+    }
+
+    /// The line number (starting at 1) at which the error occurred.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseAtrListError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid ATR list at line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for ParseAtrListError {}
+
+// A node of a parsed pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Byte(u8),
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(u8, u8)>,
+    },
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+struct PatternParser<'a> {
+    pattern: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatternParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<u8, &'static str> {
+        let c = self.peek().ok_or("unexpected end of pattern")?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>, &'static str> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some(b'|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, &'static str> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            let atom = match c {
+                b'|' | b')' => break,
+                b'(' => {
+                    self.pos += 1;
+                    let alternatives = self.alternatives()?;
+                    if self.next()? != b')' {
+                        return Err("unbalanced parenthesis");
+                    }
+                    Node::Group(alternatives)
+                }
+                b'[' => {
+                    self.pos += 1;
+                    self.class()?
+                }
+                b'.' => {
+                    self.pos += 1;
+                    Node::Any
+                }
+                b'\\' => {
+                    self.pos += 1;
+                    Node::Byte(self.next()?)
+                }
+                b'*' | b'+' | b'?' | b'{' => return Err("repetition without an operand"),
+                c => {
+                    self.pos += 1;
+                    Node::Byte(c)
+                }
+            };
+            nodes.push(self.repetitions(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn class(&mut self) -> Result<Node, &'static str> {
+        let negated = self.peek() == Some(b'^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let start = self.next()?;
+            if start == b']' && !ranges.is_empty() {
+                break;
+            }
+            let end = if self.peek() == Some(b'-') && self.pattern.get(self.pos + 1) != Some(&b']') {
+                self.pos += 1;
+                self.next()?
+            } else {
+                start
+            };
+            if end < start {
+                return Err("invalid range in bracket expression");
+            }
+            ranges.push((start, end));
+        }
+        Ok(Node::Class { negated, ranges })
+    }
+
+    fn repetitions(&mut self, mut node: Node) -> Result<Node, &'static str> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some(b'*') => (0, None),
+                Some(b'+') => (1, None),
+                Some(b'?') => (0, Some(1)),
+                Some(b'{') => {
+                    self.pos += 1;
+                    self.bounds()?
+                }
+                _ => return Ok(node),
+            };
+            // Skip the operator, or the closing brace.
+            self.pos += 1;
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    // The bounds of `{n}`, `{n,}` or `{n,m}`, up to the closing brace.
+    fn bounds(&mut self) -> Result<(usize, Option<usize>), &'static str> {
+        let min = self.number()?.ok_or("invalid repetition bounds")?;
+        let max = if self.peek() == Some(b',') {
+            self.pos += 1;
+            self.number()?
+        } else {
+            Some(min)
+        };
+        if self.peek() != Some(b'}') || max.map_or(false, |max| max < min) {
+            return Err("invalid repetition bounds");
+        }
+        Ok((min, max))
+    }
+
+    fn number(&mut self) -> Result<Option<usize>, &'static str> {
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Ok(None);
+        }
+        std::str::from_utf8(&self.pattern[start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .map(Some)
+            .ok_or("invalid repetition bounds")
+    }
+}
+
+fn match_sequence(nodes: &[Node], input: &[u8], pos: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+    match nodes.split_first() {
+        None => k(pos),
+        Some((node, rest)) => match_node(node, input, pos, &mut |pos| match_sequence(rest, input, pos, k)),
+    }
+}
+
+fn match_node(node: &Node, input: &[u8], pos: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+    match *node {
+        Node::Byte(byte) => input.get(pos) == Some(&byte) && k(pos + 1),
+        Node::Any => pos < input.len() && k(pos + 1),
+        Node::Class { negated, ref ranges } => {
+            input.get(pos).map_or(false, |&c| {
+                ranges.iter().any(|&(start, end)| start <= c && c <= end) != negated
+            }) && k(pos + 1)
+        }
+        Node::Group(ref alternatives) => alternatives
+            .iter()
+            .any(|alternative| match_sequence(alternative, input, pos, k)),
+        Node::Repeat { ref node, min, max } => match_repeat(node, min, max, input, pos, 0, k),
+    }
+}
+
+// Greedy repetition, backtracking to fewer repetitions.
+fn match_repeat(
+    node: &Node,
+    min: usize,
+    max: Option<usize>,
+    input: &[u8],
+    pos: usize,
+    count: usize,
+    k: &mut dyn FnMut(usize) -> bool,
+) -> bool {
+    if max.map_or(true, |max| count < max)
+        && match_node(node, input, pos, &mut |next| {
+            // Stop repeating an empty match once the minimum is reached.
+            (next != pos || count < min) && match_repeat(node, min, max, input, next, count + 1, k)
+        })
+    {
+        return true;
+    }
+    count >= min && k(pos)
+}
+
+// An ATR pattern, a regular expression over the ATR in uppercase hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    root: Node,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Pattern, &'static str> {
+        let pattern = pattern.to_ascii_uppercase();
+        let mut parser = PatternParser {
+            pattern: pattern.as_bytes(),
+            pos: 0,
+        };
+        let alternatives = parser.alternatives()?;
+        if parser.pos < pattern.len() {
+            return Err("unbalanced parenthesis");
+        }
+        Ok(Pattern {
+            root: Node::Group(alternatives),
+        })
+    }
+
+    fn is_match(&self, input: &[u8]) -> bool {
+        match_node(&self.root, input, 0, &mut |end| end == input.len())
+    }
+}
+
+// Format an ATR the way patterns are written: "3B 8F 80 01 ...".
+fn format_atr(atr: &[u8]) -> String {
+    atr.iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// An ATR pattern with the descriptions of the matching cards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtrListEntry {
+    source: String,
+    pattern: Pattern,
+    descriptions: Vec<String>,
+}
+
+impl AtrListEntry {
+    /// The ATR pattern, as written in the list.
+    pub fn pattern(&self) -> &str {
+        &self.source
+    }
+
+    /// The descriptions of the matching cards, one per line in the list.
+    pub fn descriptions(&self) -> &[String] {
+        &self.descriptions
+    }
+
+    /// Whether the pattern matches `atr`.
+    pub fn matches(&self, atr: &[u8]) -> bool {
+        self.pattern.is_match(format_atr(atr).as_bytes())
+    }
+}
+
+/// A list of ATR patterns and card descriptions.
+///
+/// Lists are parsed from the `smartcard_list.txt` format with
+/// `str::parse()`, loaded from a file with `AtrList::load()`, or taken
+/// from the built-in list with `AtrList::embedded()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AtrList {
+    entries: Vec<AtrListEntry>,
+}
+
+impl AtrList {
+    /// The built-in list of well-known ATRs.
+    ///
+    /// The list is small; it covers the ATRs which PC/SC readers build for
+    /// contactless cards, and some common tokens.
+    pub fn embedded() -> AtrList {
+        include_str!("atr_list.txt")
+            .parse()
+            .expect("the embedded ATR list is valid")
+    }
+
+    /// Load a list from a file in the `smartcard_list.txt` format.
+    ///
+    /// The file may be in UTF-8 or, as older versions of the list, in
+    /// Latin-1. If the file cannot be parsed, an error of kind
+    /// `io::ErrorKind::InvalidData` is returned, wrapping a
+    /// `ParseAtrListError`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<AtrList> {
+        let bytes = fs::read(path)?;
+        let text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(err) => err.into_bytes().iter().map(|&byte| byte as char).collect(),
+        };
+        text.parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// The entries of the list, in order.
+    pub fn entries(&self) -> &[AtrListEntry] {
+        &self.entries
+    }
+
+    /// Append the entries of another list.
+    ///
+    /// This allows combining a downloaded list with the built-in one.
+    pub fn extend(&mut self, other: AtrList) {
+        self.entries.extend(other.entries);
+    }
+
+    /// The entries matching `atr`, in order.
+    pub fn matches(&self, atr: &[u8]) -> impl Iterator<Item = &AtrListEntry> + '_ {
+        let formatted = format_atr(atr);
+        self.entries
+            .iter()
+            .filter(move |entry| entry.pattern.is_match(formatted.as_bytes()))
+    }
+
+    /// The descriptions of all entries matching `atr`, in order.
+    ///
+    /// An ATR may match several entries, for example both an entry for a
+    /// specific card and a more generic one. Returns an empty `Vec` if the
+    /// card is unknown.
+    pub fn identify(&self, atr: &[u8]) -> Vec<&str> {
+        self.matches(atr)
+            .flat_map(|entry| entry.descriptions.iter().map(|description| &description[..]))
+            .collect()
+    }
+}
+
+impl FromStr for AtrList {
+    type Err = ParseAtrListError;
+
+    fn from_str(s: &str) -> Result<AtrList, ParseAtrListError> {
+        let mut entries: Vec<AtrListEntry> = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let line_number = index + 1;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            if line.starts_with(|c: char| c.is_whitespace()) {
+                let entry = entries
+                    .last_mut()
+                    .ok_or_else(|| ParseAtrListError::new(line_number, "description without an ATR pattern"))?;
+                entry.descriptions.push(line.trim().to_owned());
+            } else {
+                let source = line.trim_end();
+                let pattern = Pattern::parse(source).map_err(|message| ParseAtrListError::new(line_number, message))?;
+                entries.push(AtrListEntry {
+                    source: source.to_owned(),
+                    pattern,
+                    descriptions: Vec::new(),
+                });
+            }
+        }
+        Ok(AtrList { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, atr: &str) -> bool {
+        Pattern::parse(pattern).unwrap().is_match(atr.as_bytes())
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(is_match("3B 00", "3B 00"));
+        assert!(is_match("3b 0a", "3B 0A"));
+        assert!(!is_match("3B 00", "3B 00 00"));
+        assert!(!is_match("3B 00 00", "3B 00"));
+
+        assert!(is_match("3B ..", "3B 8F"));
+        assert!(!is_match("3B ..", "3B 8"));
+        assert!(is_match("3B 8[0-9A-E]", "3B 8A"));
+        assert!(!is_match("3B 8[0-9A-E]", "3B 8F"));
+        assert!(is_match("3B 8[^0-9]", "3B 8F"));
+        assert!(!is_match("3B 8[^0-9]", "3B 81"));
+        assert!(is_match("3B [-8]1", "3B 81"));
+        assert!(is_match("3B []]", "3B ]"));
+
+        assert!(is_match("3B.*", "3B"));
+        assert!(is_match("3B.*", "3B 00 11 22"));
+        assert!(is_match("3B( ..)+", "3B 00 11"));
+        assert!(!is_match("3B( ..)+", "3B"));
+        assert!(is_match("3B 00( 11)?", "3B 00"));
+        assert!(is_match("3B 00( 11)?", "3B 00 11"));
+        assert!(is_match("3B( ..){2}", "3B 00 11"));
+        assert!(!is_match("3B( ..){2}", "3B 00 11 22"));
+        assert!(is_match("3B( ..){1,}", "3B 00 11 22"));
+        assert!(is_match("3B( ..){1,2}", "3B 00"));
+        assert!(!is_match("3B( ..){1,2}", "3B 00 11 22"));
+        assert!(is_match("3B( ..)* 90 00", "3B 90 00 12 90 00"));
+        assert!(is_match("3B (()|00)*", "3B 0000"));
+
+        assert!(is_match("3B (80|81) 80", "3B 81 80"));
+        assert!(!is_match("3B (80|81) 80", "3B 82 80"));
+        assert!(is_match("3B 00|3F 00", "3F 00"));
+        assert!(is_match("3B \\.", "3B ."));
+        assert!(!is_match("3B \\.", "3B 0"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for (pattern, message) in [
+            ("3B (00", "unexpected end of pattern"),
+            ("3B 00)", "unbalanced parenthesis"),
+            ("*3B", "repetition without an operand"),
+            ("3B .{2", "invalid repetition bounds"),
+            ("3B .{3,2}", "invalid repetition bounds"),
+            ("3B .{,2}", "invalid repetition bounds"),
+            ("3B [9-0]", "invalid range in bracket expression"),
+            ("3B [0-9", "unexpected end of pattern"),
+        ] {
+            assert_eq!(Pattern::parse(pattern), Err(message), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn parse_list() {
+        let list: AtrList = "# Comment\n\
+                             \n\
+                             3B 00\n\
+                             \tFirst card\n\
+                             \tFirst card, second line\n\
+                             3B ..\n  Any card\n"
+            .parse()
+            .unwrap();
+        assert_eq!(list.entries().len(), 2);
+        assert_eq!(list.entries()[0].pattern(), "3B 00");
+        assert_eq!(
+            list.entries()[0].descriptions(),
+            ["First card", "First card, second line"]
+        );
+        assert_eq!(list.entries()[1].pattern(), "3B ..");
+        assert!(list.entries()[1].matches(&[0x3B, 0x10]));
+
+        assert_eq!(
+            list.identify(&[0x3B, 0x00]),
+            ["First card", "First card, second line", "Any card"]
+        );
+        assert_eq!(list.identify(&[0x3B, 0x10]), ["Any card"]);
+        assert_eq!(list.identify(&[0x3F, 0x00]), Vec::<&str>::new());
+        assert_eq!(list.matches(&[0x3B, 0x00]).count(), 2);
+
+        let mut extended = AtrList::default();
+        extended.extend(list.clone());
+        assert_eq!(extended, list);
+
+        let err = "\tNo pattern\n".parse::<AtrList>().unwrap_err();
+        assert_eq!(err.line(), 1);
+        assert_eq!(
+            err.to_string(),
+            "invalid ATR list at line 1: description without an ATR pattern"
+        );
+        let err = "3B 00\n\tCard\n3B (\n".parse::<AtrList>().unwrap_err();
+        assert_eq!(err.line(), 3);
+    }
+
+    #[test]
+    fn embedded() {
+        let list = AtrList::embedded();
+        let mifare_classic = [
+            0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F, 0x0C, 0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x6A,
+        ];
+        assert_eq!(
+            list.identify(&mifare_classic),
+            [
+                "MIFARE Classic 1K (PC/SC contactless storage card)",
+                "Contactless storage card, identified by a PC/SC Part 3 ATR",
+            ]
+        );
+        assert!(list.identify(&[0x3B, 0x00]).is_empty());
+    }
+
+    #[test]
+    fn load_latin1() {
+        let path = std::env::temp_dir().join(format!("pcsc-atr-list-{}.txt", std::process::id()));
+        fs::write(&path, b"3B 00\n\tCarte \xE0 puce\n").unwrap();
+        let list = AtrList::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(list.unwrap().identify(&[0x3B, 0x00]), ["Carte \u{E0} puce"]);
+
+        let path = std::env::temp_dir().join(format!("pcsc-atr-list-invalid-{}.txt", std::process::id()));
+        fs::write(&path, b"3B (\n").unwrap();
+        let err = AtrList::load(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
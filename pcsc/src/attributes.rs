@@ -0,0 +1,481 @@
+//! Decoded reader attributes.
+//!
+//! `Card::get_attribute()` returns the raw bytes of an attribute. This
+//! module adds getters which decode them, per PC/SC Part 3: strings,
+//! little-endian integers, and structured values such as
+//! [`IfdVersion`](struct.IfdVersion.html) and
+//! [`ChannelId`](struct.ChannelId.html).
+//! `Card::reader_capabilities()` gathers them all in a
+//! [`ReaderCapabilities`](struct.ReaderCapabilities.html).
+//!
+//! ```no_run
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//! println!("{}", card.vendor_name().unwrap());
+//! println!("{:#?}", card.reader_capabilities().unwrap());
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use bitflags::bitflags;
+
+use crate::{ffi, Attribute, Card, Error, Protocol};
+
+/// The version of the reader's firmware, from
+/// `Attribute::VendorIfdVersion`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IfdVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub build: u16,
+}
+
+impl IfdVersion {
+    /// Decode the version from its DWORD encoding, `0xMMmmbbbb`.
+    pub fn from_u32(value: u32) -> IfdVersion {
+        IfdVersion {
+            major: (value >> 24) as u8,
+            minor: (value >> 16) as u8,
+            build: value as u16,
+        }
+    }
+}
+
+impl fmt::Display for IfdVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
+
+/// The type of channel through which a reader is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelType {
+    Serial,
+    Parallel,
+    Ps2Keyboard,
+    Scsi,
+    Ide,
+    Usb,
+    /// A vendor-defined channel type, from 0xF0 to 0xFF.
+    Vendor(u16),
+    /// Another channel type.
+    Other(u16),
+}
+
+/// The channel through which a reader is connected, from
+/// `Attribute::ChannelId`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChannelId {
+    /// The type of channel.
+    pub channel_type: ChannelType,
+    /// The channel number, whose meaning depends on the type; for example
+    /// the port number of a serial reader.
+    pub number: u16,
+}
+
+impl ChannelId {
+    /// Decode the channel from its DWORD encoding, `0xDDDDCCCC`, with the
+    /// channel type in `DDDD` and the channel number in `CCCC`.
+    pub fn from_u32(value: u32) -> ChannelId {
+        let raw_type = (value >> 16) as u16;
+        let channel_type = match raw_type {
+            0x01 => ChannelType::Serial,
+            0x02 => ChannelType::Parallel,
+            0x04 => ChannelType::Ps2Keyboard,
+            0x08 => ChannelType::Scsi,
+            0x10 => ChannelType::Ide,
+            0x20 => ChannelType::Usb,
+            0xF0..=0xFF => ChannelType::Vendor(raw_type),
+            _ => ChannelType::Other(raw_type),
+        };
+        ChannelId {
+            channel_type,
+            number: value as u16,
+        }
+    }
+}
+
+/// The presence of a card in the reader, from `Attribute::IccPresence`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IccPresence {
+    /// No card is present.
+    NotPresent,
+    /// A card is present.
+    Present,
+    /// A card is present and swallowed.
+    Swallowed,
+    /// The card was confiscated.
+    Confiscated,
+}
+
+/// The state of the card contacts, from `Attribute::IccInterfaceStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IccInterfaceStatus {
+    /// The contacts are inactive.
+    Inactive,
+    /// The contacts are active.
+    Active,
+}
+
+bitflags! {
+    /// The mechanical characteristics of a reader, from
+    /// `Attribute::Characteristics`.
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+    pub struct Characteristics: u32 {
+        const SWALLOWING = 0x0000_0001;
+        const EJECTION = 0x0000_0002;
+        const CAPTURE = 0x0000_0004;
+    }
+}
+
+/// A snapshot of the attributes of a reader and its current card.
+///
+/// Attributes which the reader does not support are `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ReaderCapabilities {
+    pub vendor_name: Option<String>,
+    pub vendor_ifd_type: Option<String>,
+    pub vendor_ifd_version: Option<IfdVersion>,
+    pub vendor_ifd_serial_no: Option<String>,
+    pub channel_id: Option<ChannelId>,
+    pub characteristics: Option<Characteristics>,
+    /// The default clock frequency, in kHz.
+    pub default_clock: Option<u32>,
+    /// The maximum clock frequency, in kHz.
+    pub max_clock: Option<u32>,
+    /// The default data rate, in bps.
+    pub default_data_rate: Option<u32>,
+    /// The maximum data rate, in bps.
+    pub max_data_rate: Option<u32>,
+    /// The maximum IFSD supported.
+    pub max_ifsd: Option<u32>,
+    pub current_protocol: Option<Protocol>,
+    /// The current clock frequency, in kHz.
+    pub current_clock: Option<u32>,
+    /// The current clock conversion factor F.
+    pub current_f: Option<u32>,
+    /// The current bit rate conversion factor D.
+    pub current_d: Option<u32>,
+    /// The current guard time factor N.
+    pub current_n: Option<u32>,
+    /// The current work waiting time integer W, for T=0.
+    pub current_w: Option<u32>,
+    /// The current IFSC, for T=1.
+    pub current_ifsc: Option<u32>,
+    /// The current IFSD, for T=1.
+    pub current_ifsd: Option<u32>,
+    /// The current block waiting time, for T=1.
+    pub current_bwt: Option<u32>,
+    /// The current character waiting time, for T=1.
+    pub current_cwt: Option<u32>,
+    pub icc_presence: Option<IccPresence>,
+    pub icc_interface_status: Option<IccInterfaceStatus>,
+}
+
+// Decode a little-endian integer attribute. Readers return a DWORD, of 4
+// or 8 bytes depending on the platform, or a single byte.
+fn decode_u32(bytes: &[u8]) -> Result<u32, Error> {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return Err(Error::InvalidValue);
+    }
+    let value = bytes
+        .iter()
+        .rev()
+        .fold(0u64, |value, &byte| value << 8 | u64::from(byte));
+    u32::try_from(value).map_err(|_| Error::InvalidValue)
+}
+
+// Decode a string attribute, without its trailing NULs.
+fn decode_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1);
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+// Turn the errors of readers which do not support an attribute into None.
+fn optional<T>(result: Result<T, Error>) -> Result<Option<T>, Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(Error::UnsupportedFeature) | Err(Error::InvalidParameter) | Err(Error::InvalidValue) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+impl Card {
+    /// Get an integer attribute, such as `Attribute::CurrentClk`.
+    ///
+    /// The attribute data is decoded as a little-endian integer. If it
+    /// does not fit in a `u32`, `Error::InvalidValue` is returned.
+    pub fn get_attribute_u32(&self, attribute: Attribute) -> Result<u32, Error> {
+        decode_u32(&self.get_attribute_owned(attribute)?)
+    }
+
+    /// Get a string attribute, such as `Attribute::VendorName`.
+    ///
+    /// Trailing NUL characters are removed, and invalid UTF-8 is replaced.
+    pub fn get_attribute_string(&self, attribute: Attribute) -> Result<String, Error> {
+        Ok(decode_string(&self.get_attribute_owned(attribute)?))
+    }
+
+    /// The name of the reader's vendor.
+    pub fn vendor_name(&self) -> Result<String, Error> {
+        self.get_attribute_string(Attribute::VendorName)
+    }
+
+    /// The vendor-defined type of the reader.
+    pub fn vendor_ifd_type(&self) -> Result<String, Error> {
+        self.get_attribute_string(Attribute::VendorIfdType)
+    }
+
+    /// The version of the reader's firmware.
+    pub fn vendor_ifd_version(&self) -> Result<IfdVersion, Error> {
+        self.get_attribute_u32(Attribute::VendorIfdVersion)
+            .map(IfdVersion::from_u32)
+    }
+
+    /// The serial number of the reader.
+    pub fn vendor_ifd_serial_no(&self) -> Result<String, Error> {
+        self.get_attribute_string(Attribute::VendorIfdSerialNo)
+    }
+
+    /// The channel through which the reader is connected.
+    pub fn channel_id(&self) -> Result<ChannelId, Error> {
+        self.get_attribute_u32(Attribute::ChannelId).map(ChannelId::from_u32)
+    }
+
+    /// The mechanical characteristics of the reader.
+    pub fn characteristics(&self) -> Result<Characteristics, Error> {
+        self.get_attribute_u32(Attribute::Characteristics)
+            .map(Characteristics::from_bits_retain)
+    }
+
+    /// The protocol in use with the card, if any.
+    ///
+    /// Unlike `Card::status2()`, this reads `Attribute::CurrentProtocolType`
+    /// from the reader.
+    pub fn current_protocol(&self) -> Result<Option<Protocol>, Error> {
+        match self.get_attribute_u32(Attribute::CurrentProtocolType)? {
+            raw if raw == ffi::SCARD_PROTOCOL_UNDEFINED as u32 => Ok(None),
+            raw if raw == ffi::SCARD_PROTOCOL_T0 as u32 => Ok(Some(Protocol::T0)),
+            raw if raw == ffi::SCARD_PROTOCOL_T1 as u32 => Ok(Some(Protocol::T1)),
+            raw if raw == ffi::SCARD_PROTOCOL_RAW as u32 => Ok(Some(Protocol::RAW)),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+
+    /// The presence of a card in the reader.
+    pub fn icc_presence(&self) -> Result<IccPresence, Error> {
+        match self.get_attribute_u32(Attribute::IccPresence)? {
+            0 => Ok(IccPresence::NotPresent),
+            1 => Ok(IccPresence::Present),
+            2 => Ok(IccPresence::Swallowed),
+            4 => Ok(IccPresence::Confiscated),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+
+    /// The state of the card contacts.
+    pub fn icc_interface_status(&self) -> Result<IccInterfaceStatus, Error> {
+        match self.get_attribute_u32(Attribute::IccInterfaceStatus)? {
+            0 => Ok(IccInterfaceStatus::Inactive),
+            _ => Ok(IccInterfaceStatus::Active),
+        }
+    }
+
+    /// Get all the decoded attributes of the reader and its current card.
+    ///
+    /// Attributes which the reader does not support, or returns in an
+    /// invalid format, are left as `None`. Other errors, such as
+    /// `Error::RemovedCard`, are returned.
+    pub fn reader_capabilities(&self) -> Result<ReaderCapabilities, Error> {
+        let u32_attribute = |attribute| optional(self.get_attribute_u32(attribute));
+        Ok(ReaderCapabilities {
+            vendor_name: optional(self.vendor_name())?,
+            vendor_ifd_type: optional(self.vendor_ifd_type())?,
+            vendor_ifd_version: optional(self.vendor_ifd_version())?,
+            vendor_ifd_serial_no: optional(self.vendor_ifd_serial_no())?,
+            channel_id: optional(self.channel_id())?,
+            characteristics: optional(self.characteristics())?,
+            default_clock: u32_attribute(Attribute::DefaultClk)?,
+            max_clock: u32_attribute(Attribute::MaxClk)?,
+            default_data_rate: u32_attribute(Attribute::DefaultDataRate)?,
+            max_data_rate: u32_attribute(Attribute::MaxDataRate)?,
+            max_ifsd: u32_attribute(Attribute::MaxIfsd)?,
+            current_protocol: optional(self.current_protocol())?.and_then(|protocol| protocol),
+            current_clock: u32_attribute(Attribute::CurrentClk)?,
+            current_f: u32_attribute(Attribute::CurrentF)?,
+            current_d: u32_attribute(Attribute::CurrentD)?,
+            current_n: u32_attribute(Attribute::CurrentN)?,
+            current_w: u32_attribute(Attribute::CurrentW)?,
+            current_ifsc: u32_attribute(Attribute::CurrentIfsc)?,
+            current_ifsd: u32_attribute(Attribute::CurrentIfsd)?,
+            current_bwt: u32_attribute(Attribute::CurrentBwt)?,
+            current_cwt: u32_attribute(Attribute::CurrentCwt)?,
+            icc_presence: optional(self.icc_presence())?,
+            icc_interface_status: optional(self.icc_interface_status())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, VirtualCard};
+    use crate::{Context, Protocols, Scope, ShareMode};
+
+    const READER: &str = "Virtual Reader 00 00";
+
+    fn connect() -> (Simulator, Card) {
+        let sim = Simulator::new();
+        sim.add_reader(READER).unwrap();
+        let card = VirtualCard::new(&[0x3B, 0x00], |_command: &[u8]| Ok(vec![0x90, 0x00]));
+        sim.insert_card(READER, card).unwrap();
+        let ctx = Context::establish_with_backend(sim.clone(), Scope::User).unwrap();
+        let readers = ctx.list_readers_owned().unwrap();
+        let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+        (sim, card)
+    }
+
+    #[test]
+    fn decode_integers() {
+        assert_eq!(decode_u32(&[0x12]), Ok(0x12));
+        assert_eq!(decode_u32(&[0x34, 0x12]), Ok(0x1234));
+        assert_eq!(decode_u32(&[0x56, 0x34, 0x12]), Ok(0x12_3456));
+        assert_eq!(decode_u32(&[0x78, 0x56, 0x34, 0x12]), Ok(0x1234_5678));
+        assert_eq!(decode_u32(&[0x78, 0x56, 0x34, 0x12, 0x00]), Ok(0x1234_5678));
+        assert_eq!(decode_u32(&[0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0]), Ok(0x1234_5678));
+        assert_eq!(decode_u32(&[0xFF; 4]), Ok(u32::MAX));
+        assert_eq!(decode_u32(&[0, 0, 0, 0, 1]), Err(Error::InvalidValue));
+        assert_eq!(decode_u32(&[0, 0, 0, 0, 0, 0, 0, 1]), Err(Error::InvalidValue));
+        assert_eq!(decode_u32(&[0; 9]), Err(Error::InvalidValue));
+        assert_eq!(decode_u32(&[]), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn decode_strings() {
+        assert_eq!(decode_string(b"ACS\0"), "ACS");
+        assert_eq!(decode_string(b"ACS\0\0\0"), "ACS");
+        assert_eq!(decode_string(b"ACS"), "ACS");
+        assert_eq!(decode_string(b"A\0CS\0"), "A\0CS");
+        assert_eq!(decode_string(b"\0\0"), "");
+        assert_eq!(decode_string(b""), "");
+        assert_eq!(decode_string(b"\xFFACS\0"), "\u{FFFD}ACS");
+    }
+
+    #[test]
+    fn decode_values() {
+        let version = IfdVersion::from_u32(0x0203_0104);
+        assert_eq!(
+            version,
+            IfdVersion {
+                major: 2,
+                minor: 3,
+                build: 0x0104,
+            }
+        );
+        assert_eq!(version.to_string(), "2.3.260");
+
+        let cases = [
+            (0x0001_0002, ChannelType::Serial, 2),
+            (0x0002_0000, ChannelType::Parallel, 0),
+            (0x0004_0000, ChannelType::Ps2Keyboard, 0),
+            (0x0008_0001, ChannelType::Scsi, 1),
+            (0x0010_0000, ChannelType::Ide, 0),
+            (0x0020_0003, ChannelType::Usb, 3),
+            (0x00F0_0001, ChannelType::Vendor(0xF0), 1),
+            (0x00FF_0001, ChannelType::Vendor(0xFF), 1),
+            (0x0100_ABCD, ChannelType::Other(0x100), 0xABCD),
+        ];
+        for &(value, channel_type, number) in &cases {
+            assert_eq!(ChannelId::from_u32(value), ChannelId { channel_type, number });
+        }
+    }
+
+    #[test]
+    fn getters() {
+        let (sim, card) = connect();
+        let set = |attribute, value: &[u8]| sim.set_reader_attribute(READER, attribute, value).unwrap();
+        set(Attribute::VendorName, b"Virtual\0");
+        set(Attribute::VendorIfdVersion, &[0x04, 0x01, 0x03, 0x02]);
+        set(Attribute::ChannelId, &[0x02, 0x00, 0x20, 0x00]);
+        set(Attribute::Characteristics, &[0x05, 0x00, 0x00, 0x00]);
+        set(Attribute::CurrentClk, &[0xA0, 0x0F]);
+
+        assert_eq!(card.vendor_name(), Ok("Virtual".to_owned()));
+        assert_eq!(
+            card.vendor_ifd_version(),
+            Ok(IfdVersion {
+                major: 2,
+                minor: 3,
+                build: 0x0104,
+            })
+        );
+        assert_eq!(
+            card.channel_id(),
+            Ok(ChannelId {
+                channel_type: ChannelType::Usb,
+                number: 2,
+            })
+        );
+        assert_eq!(
+            card.characteristics(),
+            Ok(Characteristics::SWALLOWING | Characteristics::CAPTURE)
+        );
+        assert_eq!(card.get_attribute_u32(Attribute::CurrentClk), Ok(4000));
+        assert_eq!(card.current_protocol(), Ok(Some(Protocol::T1)));
+        assert_eq!(card.vendor_ifd_type(), Err(Error::UnsupportedFeature));
+
+        let presences = [
+            (0, IccPresence::NotPresent),
+            (1, IccPresence::Present),
+            (2, IccPresence::Swallowed),
+            (4, IccPresence::Confiscated),
+        ];
+        for &(value, presence) in &presences {
+            set(Attribute::IccPresence, &[value]);
+            assert_eq!(card.icc_presence(), Ok(presence));
+        }
+        set(Attribute::IccPresence, &[3]);
+        assert_eq!(card.icc_presence(), Err(Error::InvalidValue));
+
+        set(Attribute::IccInterfaceStatus, &[0]);
+        assert_eq!(card.icc_interface_status(), Ok(IccInterfaceStatus::Inactive));
+        set(Attribute::IccInterfaceStatus, &[1]);
+        assert_eq!(card.icc_interface_status(), Ok(IccInterfaceStatus::Active));
+    }
+
+    #[test]
+    fn reader_capabilities() {
+        assert_eq!(optional(Ok(1)), Ok(Some(1)));
+        assert_eq!(optional::<u32>(Err(Error::UnsupportedFeature)), Ok(None));
+        assert_eq!(optional::<u32>(Err(Error::InvalidParameter)), Ok(None));
+        assert_eq!(optional::<u32>(Err(Error::InvalidValue)), Ok(None));
+        assert_eq!(optional::<u32>(Err(Error::RemovedCard)), Err(Error::RemovedCard));
+
+        let (sim, card) = connect();
+        let set = |attribute, value: &[u8]| sim.set_reader_attribute(READER, attribute, value).unwrap();
+        set(Attribute::VendorName, b"Virtual\0");
+        set(Attribute::MaxClk, &[0x40, 0x1F, 0x00, 0x00]);
+        // Invalid values are left out.
+        set(Attribute::DefaultClk, &[0x00; 9]);
+        set(Attribute::IccPresence, &[3]);
+        set(Attribute::IccInterfaceStatus, &[1]);
+
+        assert_eq!(
+            card.reader_capabilities(),
+            Ok(ReaderCapabilities {
+                vendor_name: Some("Virtual".to_owned()),
+                max_clock: Some(8000),
+                current_protocol: Some(Protocol::T1),
+                icc_interface_status: Some(IccInterfaceStatus::Active),
+                ..Default::default()
+            })
+        );
+
+        sim.remove_card(READER).unwrap();
+        assert_eq!(card.reader_capabilities(), Err(Error::RemovedCard));
+    }
+}
@@ -0,0 +1,512 @@
+//! Pluggable PC/SC implementations.
+//!
+//! `Context`, `Card` and `Transaction` do not call the PC/SC C API
+//! directly; they dispatch through a [`Backend`](trait.Backend.html).
+//! The default backend, [`SystemBackend`](struct.SystemBackend.html),
+//! calls into the platform's PC/SC implementation (see the crate
+//! documentation). Other backends can be used with
+//! `Context::establish_with_backend`, for example to run code against
+//! simulated readers and cards, without a running PC/SC service.
+//!
+//! The `Backend` trait mirrors the C API closely: contexts and cards are
+//! identified by opaque handles which the backend hands out, and the
+//! semantics of each method follow the corresponding `SCard*` function.
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::ptr::{null, null_mut};
+use std::time::Duration;
+
+use crate::{ffi, Attribute, Disposition, Error, Protocol, Protocols, ReaderState, Scope, ShareMode, Status};
+use ffi::{DWORD, LONG};
+
+/// The result of a `Backend::status` call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawStatus {
+    /// Length of the reader names multi-string, including all terminators.
+    pub names_len: usize,
+    /// Current status of the card.
+    pub status: Status,
+    /// Current protocol of the card, if any.
+    pub protocol: Option<Protocol>,
+    /// Length of the ATR.
+    pub atr_len: usize,
+}
+
+/// An implementation of the PC/SC API.
+///
+/// Each method corresponds to an `SCard*` function, and should follow its
+/// documented semantics, including error codes. Where the C API accepts
+/// a null buffer to query the needed buffer length, the corresponding
+/// method takes an `Option` buffer, and returns the needed (or written)
+/// length.
+///
+/// Handles are allocated by the backend; `Context` and `Card` only pass
+/// them back.
+///
+/// Backends must be unwind safe, so that `Context` and `Card` stay
+/// `UnwindSafe` and `RefUnwindSafe`.
+pub trait Backend: Send + Sync + UnwindSafe + RefUnwindSafe {
+    /// Wraps `SCardEstablishContext`.
+    fn establish(&self, scope: Scope) -> Result<ffi::SCARDCONTEXT, Error>;
+
+    /// Wraps `SCardReleaseContext`.
+    fn release(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error>;
+
+    /// Wraps `SCardIsValidContext`.
+    fn is_valid(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error>;
+
+    /// Wraps `SCardCancel`.
+    fn cancel(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error>;
+
+    /// Wraps `SCardListReaders`.
+    ///
+    /// The reader names are written to `buffer` as a multi-string. If
+    /// `buffer` is `None`, only the needed length is returned.
+    fn list_readers(&self, context: ffi::SCARDCONTEXT, buffer: Option<&mut [u8]>) -> Result<usize, Error>;
+
+    /// Wraps `SCardGetStatusChange`.
+    ///
+    /// A `timeout` of `None` means to wait indefinitely.
+    fn get_status_change(
+        &self,
+        context: ffi::SCARDCONTEXT,
+        timeout: Option<Duration>,
+        readers: &mut [ReaderState],
+    ) -> Result<(), Error>;
+
+    /// Wraps `SCardConnect`.
+    ///
+    /// Returns the card handle and the active protocol, if any.
+    fn connect(
+        &self,
+        context: ffi::SCARDCONTEXT,
+        reader: &CStr,
+        share_mode: ShareMode,
+        preferred_protocols: Protocols,
+    ) -> Result<(ffi::SCARDHANDLE, Option<Protocol>), Error>;
+
+    /// Wraps `SCardReconnect`.
+    ///
+    /// Returns the new active protocol, if any.
+    fn reconnect(
+        &self,
+        card: ffi::SCARDHANDLE,
+        share_mode: ShareMode,
+        preferred_protocols: Protocols,
+        initialization: Disposition,
+    ) -> Result<Option<Protocol>, Error>;
+
+    /// Wraps `SCardDisconnect`.
+    fn disconnect(&self, card: ffi::SCARDHANDLE, disposition: Disposition) -> Result<(), Error>;
+
+    /// Wraps `SCardBeginTransaction`.
+    fn begin_transaction(&self, card: ffi::SCARDHANDLE) -> Result<(), Error>;
+
+    /// Wraps `SCardEndTransaction`.
+    fn end_transaction(&self, card: ffi::SCARDHANDLE, disposition: Disposition) -> Result<(), Error>;
+
+    /// Wraps `SCardStatus`.
+    ///
+    /// If a buffer is `None`, only the needed length is returned for it.
+    fn status(
+        &self,
+        card: ffi::SCARDHANDLE,
+        names_buffer: Option<&mut [u8]>,
+        atr_buffer: Option<&mut [u8]>,
+    ) -> Result<RawStatus, Error>;
+
+    /// Wraps `SCardGetAttrib`.
+    ///
+    /// If `buffer` is `None`, only the needed length is returned.
+    fn get_attribute(
+        &self,
+        card: ffi::SCARDHANDLE,
+        attribute: Attribute,
+        buffer: Option<&mut [u8]>,
+    ) -> Result<usize, Error>;
+
+    /// Wraps `SCardSetAttrib`.
+    fn set_attribute(&self, card: ffi::SCARDHANDLE, attribute: Attribute, attribute_data: &[u8]) -> Result<(), Error>;
+
+    /// Wraps `SCardTransmit`.
+    ///
+    /// Returns the length of the response written to `receive_buffer`. On
+    /// error, the `usize` is the length reported by the implementation,
+    /// which is the required length for `Error::InsufficientBuffer`.
+    fn transmit(
+        &self,
+        card: ffi::SCARDHANDLE,
+        protocol: Protocol,
+        send_buffer: &[u8],
+        receive_buffer: &mut [u8],
+    ) -> Result<usize, (Error, usize)>;
+
+    /// Wraps `SCardControl`.
+    ///
+    /// Returns the length of the response written to `receive_buffer`.
+    fn control(
+        &self,
+        card: ffi::SCARDHANDLE,
+        control_code: DWORD,
+        send_buffer: &[u8],
+        receive_buffer: &mut [u8],
+    ) -> Result<usize, Error>;
+}
+
+// We use these instead of std::mem::uninitialized -- variables which are
+// set to this are always overridden and the dummy values are never exposed.
+const DUMMY_LONG: LONG = -1;
+const DUMMY_DWORD: DWORD = 0xdead_beef;
+
+// Note on potentially problematic casts (clippy lints `cast-sign-loss`,
+// `cast-possible-truncation`): from my analysis they are all OK, for
+// both 32bit and 64bit DWORD/LONG. But it is sketchy.
+
+macro_rules! try_pcsc {
+    ($e:expr) => {
+        match $e {
+            ffi::SCARD_S_SUCCESS => (),
+            err => return Err(Error::from_raw(err)),
+        }
+    };
+}
+
+// For some reason, linking in windows fails if we put these directly
+// in statics. This is why we have this function instead of the
+// SCARD_PCI_* defines from the C API.
+fn get_protocol_pci(protocol: Protocol) -> &'static ffi::SCARD_IO_REQUEST {
+    unsafe {
+        match protocol {
+            Protocol::T0 => &ffi::g_rgSCardT0Pci,
+            Protocol::T1 => &ffi::g_rgSCardT1Pci,
+            Protocol::RAW => &ffi::g_rgSCardRawPci,
+        }
+    }
+}
+
+fn buffer_ptr(buffer: &mut Option<&mut [u8]>) -> (*mut u8, DWORD) {
+    match buffer {
+        Some(buffer) => {
+            assert!(buffer.len() <= u32::MAX as usize);
+            (buffer.as_mut_ptr(), buffer.len() as DWORD)
+        }
+        None => (null_mut(), DUMMY_DWORD),
+    }
+}
+
+/// The platform's PC/SC implementation.
+///
+/// This is the backend used by `Context::establish`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemBackend;
+
+impl Backend for SystemBackend {
+    fn establish(&self, scope: Scope) -> Result<ffi::SCARDCONTEXT, Error> {
+        unsafe {
+            let mut handle: ffi::SCARDCONTEXT = DUMMY_LONG as ffi::SCARDCONTEXT;
+
+            try_pcsc!(ffi::SCardEstablishContext(
+                scope.into_raw(),
+                null(),
+                null(),
+                &mut handle,
+            ));
+
+            Ok(handle)
+        }
+    }
+
+    fn release(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error> {
+        unsafe {
+            try_pcsc!(ffi::SCardReleaseContext(context));
+
+            Ok(())
+        }
+    }
+
+    fn is_valid(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error> {
+        unsafe {
+            try_pcsc!(ffi::SCardIsValidContext(context));
+
+            Ok(())
+        }
+    }
+
+    fn cancel(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error> {
+        unsafe {
+            try_pcsc!(ffi::SCardCancel(context));
+
+            Ok(())
+        }
+    }
+
+    fn list_readers(&self, context: ffi::SCARDCONTEXT, mut buffer: Option<&mut [u8]>) -> Result<usize, Error> {
+        let (bufptr, mut buflen) = buffer_ptr(&mut buffer);
+
+        unsafe {
+            try_pcsc!(ffi::SCardListReaders(
+                context,
+                null(),
+                bufptr as *mut c_char,
+                &mut buflen,
+            ));
+
+            Ok(buflen as usize)
+        }
+    }
+
+    fn get_status_change(
+        &self,
+        context: ffi::SCARDCONTEXT,
+        timeout: Option<Duration>,
+        readers: &mut [ReaderState],
+    ) -> Result<(), Error> {
+        let timeout_ms = match timeout {
+            Some(duration) => {
+                let timeout_ms_u64 = duration
+                    .as_secs()
+                    .saturating_mul(1000)
+                    .saturating_add(u64::from(duration.subsec_nanos()) / 1_000_000);
+                std::cmp::min(ffi::INFINITE, timeout_ms_u64 as DWORD)
+            }
+            None => ffi::INFINITE,
+        };
+
+        unsafe {
+            assert!(readers.len() <= u32::MAX as usize);
+
+            try_pcsc!(ffi::SCardGetStatusChange(
+                context,
+                timeout_ms,
+                readers.as_mut_ptr() as *mut ffi::SCARD_READERSTATE,
+                readers.len() as DWORD,
+            ));
+
+            Ok(())
+        }
+    }
+
+    fn connect(
+        &self,
+        context: ffi::SCARDCONTEXT,
+        reader: &CStr,
+        share_mode: ShareMode,
+        preferred_protocols: Protocols,
+    ) -> Result<(ffi::SCARDHANDLE, Option<Protocol>), Error> {
+        unsafe {
+            let mut handle: ffi::SCARDHANDLE = DUMMY_LONG as ffi::SCARDHANDLE;
+            let mut raw_active_protocol: DWORD = DUMMY_DWORD;
+
+            try_pcsc!(ffi::SCardConnect(
+                context,
+                reader.as_ptr(),
+                share_mode.into_raw(),
+                preferred_protocols.bits(),
+                &mut handle,
+                &mut raw_active_protocol,
+            ));
+
+            Ok((handle, Protocol::from_raw(raw_active_protocol)))
+        }
+    }
+
+    fn reconnect(
+        &self,
+        card: ffi::SCARDHANDLE,
+        share_mode: ShareMode,
+        preferred_protocols: Protocols,
+        initialization: Disposition,
+    ) -> Result<Option<Protocol>, Error> {
+        unsafe {
+            let mut raw_active_protocol: DWORD = DUMMY_DWORD;
+
+            try_pcsc!(ffi::SCardReconnect(
+                card,
+                share_mode.into_raw(),
+                preferred_protocols.bits(),
+                initialization.into_raw(),
+                &mut raw_active_protocol,
+            ));
+
+            Ok(Protocol::from_raw(raw_active_protocol))
+        }
+    }
+
+    fn disconnect(&self, card: ffi::SCARDHANDLE, disposition: Disposition) -> Result<(), Error> {
+        unsafe {
+            try_pcsc!(ffi::SCardDisconnect(card, disposition.into_raw()));
+
+            Ok(())
+        }
+    }
+
+    fn begin_transaction(&self, card: ffi::SCARDHANDLE) -> Result<(), Error> {
+        unsafe {
+            try_pcsc!(ffi::SCardBeginTransaction(card));
+
+            Ok(())
+        }
+    }
+
+    fn end_transaction(&self, card: ffi::SCARDHANDLE, disposition: Disposition) -> Result<(), Error> {
+        unsafe {
+            try_pcsc!(ffi::SCardEndTransaction(card, disposition.into_raw()));
+
+            Ok(())
+        }
+    }
+
+    fn status(
+        &self,
+        card: ffi::SCARDHANDLE,
+        mut names_buffer: Option<&mut [u8]>,
+        mut atr_buffer: Option<&mut [u8]>,
+    ) -> Result<RawStatus, Error> {
+        let (names_ptr, mut names_len) = buffer_ptr(&mut names_buffer);
+        let (atr_ptr, mut atr_len) = buffer_ptr(&mut atr_buffer);
+
+        unsafe {
+            let mut raw_state: DWORD = DUMMY_DWORD;
+            let mut raw_protocol: DWORD = DUMMY_DWORD;
+
+            try_pcsc!(ffi::SCardStatus(
+                card,
+                names_ptr as *mut c_char,
+                &mut names_len,
+                &mut raw_state,
+                &mut raw_protocol,
+                atr_ptr,
+                &mut atr_len,
+            ));
+
+            Ok(RawStatus {
+                names_len: names_len as usize,
+                status: Status::from_raw(raw_state),
+                protocol: Protocol::from_raw(raw_protocol),
+                atr_len: atr_len as usize,
+            })
+        }
+    }
+
+    fn get_attribute(
+        &self,
+        card: ffi::SCARDHANDLE,
+        attribute: Attribute,
+        mut buffer: Option<&mut [u8]>,
+    ) -> Result<usize, Error> {
+        let (bufptr, mut attribute_len) = buffer_ptr(&mut buffer);
+
+        unsafe {
+            try_pcsc!(ffi::SCardGetAttrib(
+                card,
+                attribute.into_raw(),
+                bufptr,
+                &mut attribute_len,
+            ));
+
+            Ok(attribute_len as usize)
+        }
+    }
+
+    fn set_attribute(&self, card: ffi::SCARDHANDLE, attribute: Attribute, attribute_data: &[u8]) -> Result<(), Error> {
+        unsafe {
+            assert!(attribute_data.len() <= u32::MAX as usize);
+
+            try_pcsc!(ffi::SCardSetAttrib(
+                card,
+                attribute.into_raw(),
+                attribute_data.as_ptr(),
+                attribute_data.len() as DWORD,
+            ));
+
+            Ok(())
+        }
+    }
+
+    fn transmit(
+        &self,
+        card: ffi::SCARDHANDLE,
+        protocol: Protocol,
+        send_buffer: &[u8],
+        receive_buffer: &mut [u8],
+    ) -> Result<usize, (Error, usize)> {
+        let send_pci = get_protocol_pci(protocol);
+        let recv_pci = null_mut();
+        assert!(receive_buffer.len() <= u32::MAX as usize);
+        let mut receive_len = receive_buffer.len() as DWORD;
+
+        unsafe {
+            assert!(send_buffer.len() <= u32::MAX as usize);
+
+            let r = ffi::SCardTransmit(
+                card,
+                send_pci,
+                send_buffer.as_ptr(),
+                send_buffer.len() as DWORD,
+                recv_pci,
+                receive_buffer.as_mut_ptr(),
+                &mut receive_len,
+            );
+
+            match r {
+                ffi::SCARD_S_SUCCESS => (),
+                err => return Err((Error::from_raw(err), receive_len as usize)),
+            }
+
+            Ok(receive_len as usize)
+        }
+    }
+
+    fn control(
+        &self,
+        card: ffi::SCARDHANDLE,
+        control_code: DWORD,
+        send_buffer: &[u8],
+        receive_buffer: &mut [u8],
+    ) -> Result<usize, Error> {
+        let mut receive_len: DWORD = DUMMY_DWORD;
+
+        unsafe {
+            assert!(send_buffer.len() <= u32::MAX as usize);
+            assert!(receive_buffer.len() <= u32::MAX as usize);
+
+            try_pcsc!(ffi::SCardControl(
+                card,
+                control_code,
+                send_buffer.as_ptr(),
+                send_buffer.len() as DWORD,
+                receive_buffer.as_mut_ptr(),
+                receive_buffer.len() as DWORD,
+                &mut receive_len,
+            ));
+
+            Ok(receive_len as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{RefUnwindSafe, UnwindSafe};
+
+    use crate::{Card, Context, Transaction};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    fn assert_unwind_safe<T: UnwindSafe + RefUnwindSafe>() {}
+
+    fn assert_ref_unwind_safe<T: RefUnwindSafe>() {}
+
+    #[test]
+    fn auto_traits() {
+        assert_send_sync::<Context>();
+        assert_send_sync::<Card>();
+        assert_send_sync::<Transaction<'static>>();
+        assert_unwind_safe::<Context>();
+        assert_unwind_safe::<Card>();
+        // A transaction holds a `&mut Card`, so it is only `RefUnwindSafe`.
+        assert_ref_unwind_safe::<Transaction<'static>>();
+    }
+}
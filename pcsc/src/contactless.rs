@@ -0,0 +1,432 @@
+//! Decoding of the ATRs which PC/SC readers build for contactless cards.
+//!
+//! Contactless cards have no ATR; per PC/SC Part 3, the reader builds one.
+//! For storage cards (such as MIFARE Classic or ICODE), the historical
+//! bytes identify the card standard and the card name. For ISO 14443-4
+//! cards, they carry the historical bytes of the ATS (type A) or the
+//! application data and protocol information of the ATQB (type B).
+//!
+//! [`ContactlessAtr`](struct.ContactlessAtr.html) decodes these ATRs, and
+//! [`ContactlessCardType`](enum.ContactlessCardType.html) tells which
+//! command set a card expects.
+//!
+//! ```
+//! use pcsc::contactless::{ContactlessAtr, ContactlessCardType};
+//!
+//! let atr = ContactlessAtr::parse(&[
+//!     0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F, 0x0C, 0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00, 0x00,
+//!     0x00, 0x00, 0x6A,
+//! ])
+//! .unwrap();
+//! assert_eq!(atr.card_type(), ContactlessCardType::MifareClassic1K);
+//! ```
+
+use std::fmt;
+
+use crate::atr::Atr;
+use crate::{Card, Error};
+
+// The start of the historical bytes of a storage card: the category
+// indicator, the application identifier tag and length, and the RID of
+// the PC/SC Workgroup.
+const STORAGE_CARD_PREFIX: [u8; 8] = [0x80, 0x4F, 0x0C, 0xA0, 0x00, 0x00, 0x03, 0x06];
+
+/// The card standard of a contactless storage card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContactlessStandard {
+    /// ISO 14443 type A (parts 1 to 3).
+    Iso14443A,
+    /// ISO 14443 type B (parts 1 to 3).
+    Iso14443B,
+    /// ISO 15693 (parts 1 to 4).
+    Iso15693,
+    /// FeliCa.
+    Felica,
+    /// A low frequency contactless card.
+    LowFrequency,
+    /// Another standard, with the standard byte (SS).
+    Other(u8),
+}
+
+impl ContactlessStandard {
+    /// Decode a standard byte (SS).
+    pub fn from_byte(ss: u8) -> ContactlessStandard {
+        match ss {
+            0x01..=0x03 => ContactlessStandard::Iso14443A,
+            0x05..=0x07 => ContactlessStandard::Iso14443B,
+            0x09..=0x0C => ContactlessStandard::Iso15693,
+            0x11 => ContactlessStandard::Felica,
+            0x40 => ContactlessStandard::LowFrequency,
+            ss => ContactlessStandard::Other(ss),
+        }
+    }
+}
+
+/// The type of a contactless card, which determines its command set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContactlessCardType {
+    /// MIFARE Classic 1K.
+    MifareClassic1K,
+    /// MIFARE Classic 4K.
+    MifareClassic4K,
+    /// MIFARE Mini.
+    MifareMini,
+    /// MIFARE Ultralight, or a compatible NFC Forum Type 2 tag.
+    MifareUltralight,
+    /// MIFARE Ultralight C.
+    MifareUltralightC,
+    /// MIFARE Plus, in security level 1 or 2.
+    MifarePlus,
+    /// Innovision Topaz or Jewel (NFC Forum Type 1 tag).
+    Topaz,
+    /// FeliCa (NFC Forum Type 3 tag).
+    Felica,
+    /// An ISO 15693 vicinity card, such as NXP ICODE.
+    Iso15693,
+    /// HID iCLASS / PicoPass.
+    PicoPass,
+    /// An ISO 14443-4 card, which accepts APDUs (NFC Forum Type 4 tags,
+    /// MIFARE DESFire, contactless smart cards).
+    Iso14443_4,
+    /// Another storage card, with its standard byte and card name.
+    OtherStorageCard {
+        /// The standard byte (SS).
+        standard: u8,
+        /// The card name (C0 C1).
+        name: u16,
+    },
+}
+
+impl ContactlessCardType {
+    /// The type of a storage card, from its standard byte (SS) and card
+    /// name (C0 C1).
+    pub fn from_storage_card(standard: u8, name: u16) -> ContactlessCardType {
+        match name {
+            0x0001 => ContactlessCardType::MifareClassic1K,
+            0x0002 => ContactlessCardType::MifareClassic4K,
+            0x0003 => ContactlessCardType::MifareUltralight,
+            0x0026 => ContactlessCardType::MifareMini,
+            0x003A => ContactlessCardType::MifareUltralightC,
+            0x0036..=0x0039 => ContactlessCardType::MifarePlus,
+            0x002F | 0x0030 | 0xF004 => ContactlessCardType::Topaz,
+            0x003B | 0xF011 | 0xF012 => ContactlessCardType::Felica,
+            0x0017..=0x0020 => ContactlessCardType::PicoPass,
+            _ => match ContactlessStandard::from_byte(standard) {
+                ContactlessStandard::Iso15693 => ContactlessCardType::Iso15693,
+                ContactlessStandard::Felica => ContactlessCardType::Felica,
+                _ => ContactlessCardType::OtherStorageCard { standard, name },
+            },
+        }
+    }
+
+    /// Whether the card accepts ISO 7816-4 APDUs directly.
+    ///
+    /// Storage cards are accessed with the pseudo-APDUs of PC/SC Part 3
+    /// instead, which the reader translates.
+    pub fn is_iso14443_4(&self) -> bool {
+        *self == ContactlessCardType::Iso14443_4
+    }
+}
+
+impl fmt::Display for ContactlessCardType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ContactlessCardType::MifareClassic1K => f.write_str("MIFARE Classic 1K"),
+            ContactlessCardType::MifareClassic4K => f.write_str("MIFARE Classic 4K"),
+            ContactlessCardType::MifareMini => f.write_str("MIFARE Mini"),
+            ContactlessCardType::MifareUltralight => f.write_str("MIFARE Ultralight"),
+            ContactlessCardType::MifareUltralightC => f.write_str("MIFARE Ultralight C"),
+            ContactlessCardType::MifarePlus => f.write_str("MIFARE Plus"),
+            ContactlessCardType::Topaz => f.write_str("Topaz"),
+            ContactlessCardType::Felica => f.write_str("FeliCa"),
+            ContactlessCardType::Iso15693 => f.write_str("ISO 15693 card"),
+            ContactlessCardType::PicoPass => f.write_str("PicoPass"),
+            ContactlessCardType::Iso14443_4 => f.write_str("ISO 14443-4 card"),
+            ContactlessCardType::OtherStorageCard { standard, name } => {
+                write!(f, "storage card (standard {:02X}, name {:04X})", standard, name)
+            }
+        }
+    }
+}
+
+/// The ATQB information in the ATR of an ISO 14443-4 type B card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AtqbInfo {
+    /// The application data of the ATQB.
+    pub application_data: [u8; 4],
+    /// The protocol information of the ATQB.
+    pub protocol_info: [u8; 3],
+    /// The maximum buffer length index (MBLI) of the ATTRIB response.
+    pub mbli: u8,
+}
+
+/// A decoded ATR of a contactless card, per PC/SC Part 3.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContactlessAtr {
+    card_type: ContactlessCardType,
+    storage_card: Option<(u8, u16)>,
+    historical_bytes: Vec<u8>,
+}
+
+impl ContactlessAtr {
+    /// Decode an ATR built by a contactless reader.
+    ///
+    /// Returns `None` if the ATR is not of the form specified by PC/SC
+    /// Part 3, for example for contact cards.
+    pub fn parse(atr: &[u8]) -> Option<ContactlessAtr> {
+        Atr::parse(atr).ok().and_then(|atr| ContactlessAtr::from_atr(&atr))
+    }
+
+    /// Decode a parsed ATR built by a contactless reader.
+    ///
+    /// Returns `None` if the ATR is not of the form specified by PC/SC
+    /// Part 3.
+    pub fn from_atr(atr: &Atr) -> Option<ContactlessAtr> {
+        // 3B 8n 80 01: only TD1 and TD2, offering T=0 and T=1, with n
+        // historical bytes and TCK.
+        let interface_bytes = atr.interface_bytes();
+        let is_contactless = atr.as_bytes()[0] == 0x3B
+            && atr.t0() >> 4 == 0x8
+            && interface_bytes.len() == 3
+            && interface_bytes[0].td == Some(0x80)
+            && interface_bytes[1].td == Some(0x01)
+            && interface_bytes[2] == Default::default()
+            && interface_bytes[0].ta.is_none()
+            && interface_bytes[0].tb.is_none()
+            && interface_bytes[0].tc.is_none();
+        if !is_contactless {
+            return None;
+        }
+
+        let historical_bytes = atr.historical_bytes();
+        let storage_card = match *historical_bytes {
+            [_, _, _, _, _, _, _, _, ss, c0, c1, _, _, _, _] if historical_bytes.starts_with(&STORAGE_CARD_PREFIX) => {
+                Some((ss, u16::from_be_bytes([c0, c1])))
+            }
+            _ => None,
+        };
+        let card_type = match storage_card {
+            Some((standard, name)) => ContactlessCardType::from_storage_card(standard, name),
+            None => ContactlessCardType::Iso14443_4,
+        };
+        Some(ContactlessAtr {
+            card_type,
+            storage_card,
+            historical_bytes: historical_bytes.to_vec(),
+        })
+    }
+
+    /// The type of the card.
+    pub fn card_type(&self) -> ContactlessCardType {
+        self.card_type
+    }
+
+    /// The card standard, for storage cards.
+    pub fn standard(&self) -> Option<ContactlessStandard> {
+        self.storage_card.map(|(ss, _)| ContactlessStandard::from_byte(ss))
+    }
+
+    /// The standard byte (SS), for storage cards.
+    pub fn standard_byte(&self) -> Option<u8> {
+        self.storage_card.map(|(ss, _)| ss)
+    }
+
+    /// The card name (C0 C1), for storage cards.
+    pub fn card_name(&self) -> Option<u16> {
+        self.storage_card.map(|(_, name)| name)
+    }
+
+    /// The historical bytes of the ATR.
+    ///
+    /// For an ISO 14443-4 type A card, these are the historical bytes of
+    /// its ATS.
+    pub fn historical_bytes(&self) -> &[u8] {
+        &self.historical_bytes
+    }
+
+    /// The ATQB information, for ISO 14443-4 cards whose ATR has the form
+    /// used for type B cards.
+    ///
+    /// ## Note
+    ///
+    /// The ATR does not tell the type of an ISO 14443-4 card. A type A
+    /// card with 8 historical bytes, the last one ending with a 0 nibble,
+    /// also has this form.
+    pub fn atqb(&self) -> Option<AtqbInfo> {
+        if self.card_type != ContactlessCardType::Iso14443_4 {
+            return None;
+        }
+        match *self.historical_bytes {
+            [a0, a1, a2, a3, p0, p1, p2, mbli] if mbli & 0x0F == 0 => Some(AtqbInfo {
+                application_data: [a0, a1, a2, a3],
+                protocol_info: [p0, p1, p2],
+                mbli: mbli >> 4,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Card {
+    /// The type of the contactless card, from its ATR.
+    ///
+    /// Returns `None` if the ATR is not one built by a contactless reader
+    /// per PC/SC Part 3, for example for contact cards.
+    pub fn contactless_card_type(&self) -> Result<Option<ContactlessCardType>, Error> {
+        let status = self.status2_owned()?;
+        Ok(ContactlessAtr::parse(status.atr()).map(|atr| atr.card_type()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, VirtualCard};
+    use crate::{Context, Protocols, Scope, ShareMode};
+
+    const MIFARE_CLASSIC_1K: &[u8] = &[
+        0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F, 0x0C, 0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x6A,
+    ];
+
+    // Build a contactless ATR with the given historical bytes.
+    fn build_atr(historical_bytes: &[u8]) -> Vec<u8> {
+        let mut atr = vec![0x3B, 0x80 | historical_bytes.len() as u8, 0x80, 0x01];
+        atr.extend_from_slice(historical_bytes);
+        let tck = atr[1..].iter().fold(0, |tck, byte| tck ^ byte);
+        atr.push(tck);
+        atr
+    }
+
+    fn storage_card_atr(standard: u8, name: u16) -> Vec<u8> {
+        let mut historical_bytes = STORAGE_CARD_PREFIX.to_vec();
+        historical_bytes.push(standard);
+        historical_bytes.extend_from_slice(&name.to_be_bytes());
+        historical_bytes.extend_from_slice(&[0x00; 4]);
+        build_atr(&historical_bytes)
+    }
+
+    #[test]
+    fn storage_cards() {
+        assert_eq!(storage_card_atr(0x03, 0x0001), MIFARE_CLASSIC_1K);
+
+        let cases = [
+            (
+                0x03,
+                0x0001,
+                ContactlessStandard::Iso14443A,
+                ContactlessCardType::MifareClassic1K,
+            ),
+            (
+                0x03,
+                0x0002,
+                ContactlessStandard::Iso14443A,
+                ContactlessCardType::MifareClassic4K,
+            ),
+            (
+                0x03,
+                0x0003,
+                ContactlessStandard::Iso14443A,
+                ContactlessCardType::MifareUltralight,
+            ),
+            (0x11, 0x003B, ContactlessStandard::Felica, ContactlessCardType::Felica),
+            (0x11, 0x0000, ContactlessStandard::Felica, ContactlessCardType::Felica),
+            (
+                0x0B,
+                0x0000,
+                ContactlessStandard::Iso15693,
+                ContactlessCardType::Iso15693,
+            ),
+            (
+                0x40,
+                0x1234,
+                ContactlessStandard::LowFrequency,
+                ContactlessCardType::OtherStorageCard {
+                    standard: 0x40,
+                    name: 0x1234,
+                },
+            ),
+        ];
+        for &(standard, name, contactless_standard, card_type) in &cases {
+            let atr = ContactlessAtr::parse(&storage_card_atr(standard, name)).unwrap();
+            assert_eq!(atr.card_type(), card_type);
+            assert!(!atr.card_type().is_iso14443_4());
+            assert_eq!(atr.standard_byte(), Some(standard));
+            assert_eq!(atr.standard(), Some(contactless_standard));
+            assert_eq!(atr.card_name(), Some(name));
+            assert_eq!(atr.atqb(), None);
+        }
+    }
+
+    #[test]
+    fn iso14443_4() {
+        // The ATS historical bytes of a MIFARE DESFire.
+        let atr = ContactlessAtr::parse(&[0x3B, 0x81, 0x80, 0x01, 0x80, 0x80]).unwrap();
+        assert_eq!(atr.card_type(), ContactlessCardType::Iso14443_4);
+        assert!(atr.card_type().is_iso14443_4());
+        assert_eq!(atr.historical_bytes(), [0x80]);
+        assert_eq!(atr.standard(), None);
+        assert_eq!(atr.card_name(), None);
+        assert_eq!(atr.atqb(), None);
+
+        // No historical bytes.
+        let atr = ContactlessAtr::parse(&[0x3B, 0x80, 0x80, 0x01, 0x01]).unwrap();
+        assert_eq!(atr.card_type(), ContactlessCardType::Iso14443_4);
+        assert_eq!(atr.historical_bytes(), []);
+
+        // The ATQB of a type B card.
+        let atqb = [0x12, 0x34, 0x56, 0x78, 0x00, 0x81, 0x71, 0x50];
+        let atr = ContactlessAtr::parse(&build_atr(&atqb)).unwrap();
+        assert_eq!(atr.card_type(), ContactlessCardType::Iso14443_4);
+        assert_eq!(
+            atr.atqb(),
+            Some(AtqbInfo {
+                application_data: [0x12, 0x34, 0x56, 0x78],
+                protocol_info: [0x00, 0x81, 0x71],
+                mbli: 5,
+            })
+        );
+
+        // The last byte of an ATQB has a zero low nibble.
+        let mut ats = atqb;
+        ats[7] = 0x51;
+        assert_eq!(ContactlessAtr::parse(&build_atr(&ats)).unwrap().atqb(), None);
+    }
+
+    #[test]
+    fn contact_atrs() {
+        assert_eq!(ContactlessAtr::parse(&[0x3B, 0x00]), None);
+        let contact = Atr::parse(&[0x3B, 0x02, 0x14, 0x50]).unwrap();
+        assert_eq!(ContactlessAtr::from_atr(&contact), None);
+        // YubiKey 4.
+        let yubikey = [
+            0x3B, 0xF8, 0x13, 0x00, 0x00, 0x81, 0x31, 0xFE, 0x15, 0x59, 0x75, 0x62, 0x69, 0x6B, 0x65, 0x79, 0x34, 0xD4,
+        ];
+        assert_eq!(ContactlessAtr::parse(&yubikey), None);
+        // Inverse convention.
+        let mut inverse = MIFARE_CLASSIC_1K.to_vec();
+        inverse[0] = 0x3F;
+        assert_eq!(ContactlessAtr::parse(&inverse), None);
+        // Offering only T=0 in TD2.
+        let t0_only = Atr::parse(&[0x3B, 0x80, 0x80, 0x00]).unwrap();
+        assert_eq!(ContactlessAtr::from_atr(&t0_only), None);
+        // Not an ATR.
+        assert_eq!(ContactlessAtr::parse(&[]), None);
+    }
+
+    #[test]
+    fn card_type() {
+        let sim = Simulator::new();
+        sim.add_reader("Virtual Reader 00 00").unwrap();
+        let card = VirtualCard::new(MIFARE_CLASSIC_1K, |_command: &[u8]| Ok(vec![0x90, 0x00]));
+        sim.insert_card("Virtual Reader 00 00", card).unwrap();
+        let ctx = Context::establish_with_backend(sim.clone(), Scope::User).unwrap();
+        let readers = ctx.list_readers_owned().unwrap();
+        let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+        assert_eq!(
+            card.contactless_card_type(),
+            Ok(Some(ContactlessCardType::MifareClassic1K))
+        );
+        assert_eq!(ContactlessCardType::MifareClassic1K.to_string(), "MIFARE Classic 1K");
+    }
+}
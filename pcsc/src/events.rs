@@ -0,0 +1,438 @@
+//! A stream of reader and card events.
+//!
+//! [`ReaderEvents`](struct.ReaderEvents.html) watches all readers of a
+//! `Context` with a [`ReaderMonitor`](../monitor/struct.ReaderMonitor.html)
+//! on a background thread, and yields typed
+//! [`ReaderEvent`](enum.ReaderEvent.html)s.
+//!
+//! The stream is runtime-agnostic: events are awaited with
+//! `ReaderEvents::next_event()`. With the `stream` feature, `ReaderEvents`
+//! also implements `futures_core::Stream`. With the `tokio` feature,
+//! `ReaderEvents::spawn_tokio()` runs the watcher on tokio's blocking
+//! thread pool instead of a dedicated thread.
+//!
+//! ```no_run
+//! use pcsc::events::ReaderEvents;
+//! use pcsc::*;
+//!
+//! async fn watch() {
+//!     let ctx = Context::establish(Scope::User).unwrap();
+//!     let mut events = ReaderEvents::new(&ctx);
+//!     while let Some(event) = events.next_event().await {
+//!         println!("{:?}", event.unwrap());
+//!     }
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::monitor::ReaderMonitor;
+use crate::{Context, Error};
+
+// How long dropping `ReaderEvents` waits for the watcher to stop.
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How often dropping `ReaderEvents` cancels the watcher's blocking call.
+const CANCEL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An event of a reader or the card in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReaderEvent {
+    /// A reader was connected.
+    ReaderAdded { reader: CString },
+    /// A reader was disconnected.
+    ReaderRemoved { reader: CString },
+    /// A card was inserted into a reader.
+    ///
+    /// The ATR is empty if the card was removed again before its state
+    /// could be read.
+    CardInserted { reader: CString, atr: Vec<u8> },
+    /// The card was removed from a reader.
+    CardRemoved { reader: CString },
+    /// An unresponsive card was inserted into a reader.
+    CardMuted { reader: CString },
+    /// Another application connected to the card in a reader.
+    ReaderInUse { reader: CString },
+}
+
+impl ReaderEvent {
+    /// The name of the reader the event happened in.
+    pub fn reader(&self) -> &CString {
+        match *self {
+            ReaderEvent::ReaderAdded { ref reader }
+            | ReaderEvent::ReaderRemoved { ref reader }
+            | ReaderEvent::CardInserted { ref reader, .. }
+            | ReaderEvent::CardRemoved { ref reader }
+            | ReaderEvent::CardMuted { ref reader }
+            | ReaderEvent::ReaderInUse { ref reader } => reader,
+        }
+    }
+}
+
+struct Queue {
+    events: VecDeque<Result<ReaderEvent, Error>>,
+    waker: Option<Waker>,
+    stopping: bool,
+    stopped: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn push(&self, events: Vec<Result<ReaderEvent, Error>>) {
+        if events.is_empty() {
+            return;
+        }
+        let mut queue = self.lock();
+        queue.events.extend(events);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn stopping(&self) -> bool {
+        self.lock().stopping
+    }
+}
+
+/// A stream of `ReaderEvent`s.
+///
+/// When the stream is created, a `ReaderAdded` event is yielded for each
+/// connected reader, followed by a `CardInserted` event for each inserted
+/// card.
+///
+/// If watching fails, the error is yielded and the stream ends.
+///
+/// ## Note
+///
+/// The events are watched on a new context, established with the same
+/// backend and scope as the given one. Dropping the stream cancels the
+/// blocking call on that context, without affecting calls on the given
+/// context.
+pub struct ReaderEvents {
+    shared: Arc<Shared>,
+    // The watcher's context, if it could be established.
+    context: Option<Context>,
+}
+
+impl ReaderEvents {
+    /// Watch the readers of `context` on a new background thread.
+    pub fn new(context: &Context) -> ReaderEvents {
+        let (events, watcher) = ReaderEvents::with_watcher(context);
+        thread::spawn(watcher);
+        events
+    }
+
+    /// Watch the readers of `context` on tokio's blocking thread pool.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if called outside of a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn spawn_tokio(context: &Context) -> ReaderEvents {
+        let (events, watcher) = ReaderEvents::with_watcher(context);
+        tokio::task::spawn_blocking(watcher);
+        events
+    }
+
+    fn with_watcher(context: &Context) -> (ReaderEvents, impl FnOnce() + Send + 'static) {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                events: VecDeque::new(),
+                waker: None,
+                stopping: false,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let context = context.establish_sibling();
+        let watcher = {
+            let shared = Arc::clone(&shared);
+            let context = context.clone();
+            move || {
+                match context {
+                    Ok(ref context) => watch(context, &shared),
+                    Err(err) => shared.push(vec![Err(err)]),
+                }
+                let mut queue = shared.lock();
+                queue.stopped = true;
+                if let Some(waker) = queue.waker.take() {
+                    waker.wake();
+                }
+                shared.changed.notify_all();
+            }
+        };
+        let events = ReaderEvents {
+            shared,
+            context: context.ok(),
+        };
+        (events, watcher)
+    }
+
+    /// Wait for the next event.
+    ///
+    /// Resolves to `None` when the stream has ended.
+    pub fn next_event(&mut self) -> Next<'_> {
+        Next { events: self }
+    }
+
+    /// Poll for the next event.
+    ///
+    /// Returns `Poll::Ready(None)` when the stream has ended.
+    pub fn poll_event(&mut self, cx: &mut TaskContext<'_>) -> Poll<Option<Result<ReaderEvent, Error>>> {
+        let mut queue = self.shared.lock();
+        if let Some(event) = queue.events.pop_front() {
+            Poll::Ready(Some(event))
+        } else if queue.stopped {
+            Poll::Ready(None)
+        } else {
+            queue.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for ReaderEvents {
+    fn drop(&mut self) {
+        let context = match self.context {
+            Some(ref context) => context,
+            None => return,
+        };
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        let mut queue = self.shared.lock();
+        queue.stopping = true;
+        // A cancel only affects a call in progress, so keep cancelling
+        // until the watcher notices it should stop. If it does not stop in
+        // time, it is left to exit on its own after its current call.
+        while !queue.stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            drop(queue);
+            let _err = context.cancel();
+            queue = self.shared.lock();
+            if !queue.stopped {
+                let timeout = (deadline - now).min(CANCEL_INTERVAL);
+                queue = self
+                    .shared
+                    .changed
+                    .wait_timeout(queue, timeout)
+                    .unwrap_or_else(|err| err.into_inner())
+                    .0;
+            }
+        }
+    }
+}
+
+/// Future returned by `ReaderEvents::next_event()`.
+pub struct Next<'a> {
+    events: &'a mut ReaderEvents,
+}
+
+impl<'a> Future for Next<'a> {
+    type Output = Option<Result<ReaderEvent, Error>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        self.events.poll_event(cx)
+    }
+}
+
+#[cfg(feature = "stream")]
+impl futures_core::Stream for ReaderEvents {
+    type Item = Result<ReaderEvent, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_event(cx)
+    }
+}
+
+fn watch(ctx: &Context, shared: &Shared) {
+    let mut monitor = ReaderMonitor::new(ctx);
+    while !shared.stopping() {
+        match monitor.poll(None) {
+            Ok(events) => shared.push(events.into_iter().map(Ok).collect()),
+            Err(Error::Cancelled) => {}
+            Err(err) => {
+                shared.push(vec![Err(err)]);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, VirtualCard};
+    use crate::{ReaderState, Scope, State};
+    use std::task::Wake;
+    use std::time::Instant;
+
+    const READER: &str = "Virtual Reader 00 00";
+    const ATR: &[u8] = &[0x3B, 0x00];
+
+    fn card() -> VirtualCard {
+        VirtualCard::new(ATR, |_command: &[u8]| Ok(vec![0x90, 0x00]))
+    }
+
+    fn reader() -> CString {
+        CString::new(READER).unwrap()
+    }
+
+    fn setup() -> (Simulator, Context) {
+        let sim = Simulator::new();
+        sim.add_reader(READER).unwrap();
+        sim.insert_card(READER, card()).unwrap();
+        let ctx = Context::establish_with_backend(sim.clone(), Scope::User).unwrap();
+        (sim, ctx)
+    }
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Drive `poll` to completion on the current thread.
+    fn wait<T>(mut poll: impl FnMut(&mut TaskContext<'_>) -> Poll<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = TaskContext::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = poll(&mut cx) {
+                return output;
+            }
+            let now = Instant::now();
+            assert!(now < deadline, "timed out waiting for an event");
+            thread::park_timeout(deadline - now);
+        }
+    }
+
+    fn next(events: &mut ReaderEvents) -> ReaderEvent {
+        wait(|cx| Pin::new(&mut events.next_event()).poll(cx)).unwrap().unwrap()
+    }
+
+    fn assert_idle(events: &mut ReaderEvents) {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = TaskContext::from_waker(&waker);
+        assert_eq!(events.poll_event(&mut cx), Poll::Pending);
+    }
+
+    // Check the events of inserting and removing cards and readers.
+    fn check_events(sim: &Simulator, next: &mut dyn FnMut() -> ReaderEvent) {
+        assert_eq!(next(), ReaderEvent::ReaderAdded { reader: reader() });
+        assert_eq!(
+            next(),
+            ReaderEvent::CardInserted {
+                reader: reader(),
+                atr: ATR.to_vec()
+            }
+        );
+
+        sim.remove_card(READER).unwrap();
+        assert_eq!(next(), ReaderEvent::CardRemoved { reader: reader() });
+
+        sim.insert_card(READER, VirtualCard::mute()).unwrap();
+        assert_eq!(next(), ReaderEvent::CardMuted { reader: reader() });
+
+        let other = CString::new("Virtual Reader 01 00").unwrap();
+        sim.add_reader("Virtual Reader 01 00").unwrap();
+        assert_eq!(next(), ReaderEvent::ReaderAdded { reader: other.clone() });
+        sim.insert_card("Virtual Reader 01 00", card()).unwrap();
+        assert_eq!(
+            next(),
+            ReaderEvent::CardInserted {
+                reader: other,
+                atr: ATR.to_vec()
+            }
+        );
+
+        sim.remove_reader(READER).unwrap();
+        assert_eq!(next(), ReaderEvent::ReaderRemoved { reader: reader() });
+    }
+
+    #[test]
+    fn events() {
+        let (sim, ctx) = setup();
+        let mut events = ReaderEvents::new(&ctx);
+        check_events(&sim, &mut || next(&mut events));
+        assert_idle(&mut events);
+    }
+
+    #[test]
+    fn poll_event() {
+        let (sim, ctx) = setup();
+        let mut events = ReaderEvents::new(&ctx);
+        let mut next = || wait(|cx| events.poll_event(cx)).unwrap().unwrap();
+        check_events(&sim, &mut next);
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn stream() {
+        use futures_core::Stream;
+
+        let (sim, ctx) = setup();
+        let mut events = ReaderEvents::new(&ctx);
+        let mut next = || wait(|cx| Pin::new(&mut events).poll_next(cx)).unwrap().unwrap();
+        check_events(&sim, &mut next);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn spawn_tokio() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let (sim, ctx) = setup();
+        let mut events = {
+            let _guard = runtime.enter();
+            ReaderEvents::spawn_tokio(&ctx)
+        };
+        check_events(&sim, &mut || runtime.block_on(events.next_event()).unwrap().unwrap());
+        drop(events);
+    }
+
+    #[test]
+    fn drop_cancels() {
+        let (sim, ctx) = setup();
+        let mut events = ReaderEvents::new(&ctx);
+        next(&mut events);
+        next(&mut events);
+        let shared = Arc::clone(&events.shared);
+
+        // A blocking call on the given context is not cancelled.
+        let waiter = {
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                let mut reader_states = vec![ReaderState::new(reader(), State::PRESENT)];
+                ctx.get_status_change(Some(Duration::from_secs(5)), &mut reader_states)
+            })
+        };
+
+        // Give the watcher time to block in get_status_change().
+        thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        drop(events);
+        assert!(start.elapsed() < STOP_TIMEOUT);
+        assert!(shared.lock().stopped);
+
+        sim.remove_card(READER).unwrap();
+        assert_eq!(waiter.join().unwrap(), Ok(()));
+    }
+}
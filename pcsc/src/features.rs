@@ -0,0 +1,477 @@
+//! Discovery of reader features, per PC/SC Part 10.
+//!
+//! Readers announce the features they support beyond APDU exchange, such
+//! as PIN entry on a pinpad, in reply to the `CM_IOCTL_GET_FEATURE_REQUEST`
+//! control command. Each feature is then used through its own control
+//! code with `Card::control()`.
+//!
+//! ```no_run
+//! use pcsc::features::Feature;
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Direct, Protocols::UNDEFINED).unwrap();
+//! let features = card.features().unwrap();
+//! if let Some(control_code) = features.get(Feature::VerifyPinDirect) {
+//!     println!("The reader has a pinpad, VERIFY_PIN_DIRECT is {:#X}", control_code);
+//! }
+//! ```
+//!
+//! Readers with the `Feature::GetTlvProperties` feature describe
+//! themselves with [`TlvProperties`](struct.TlvProperties.html), which
+//! `Card::tlv_properties()` returns.
+
+use std::collections::BTreeMap;
+
+use bitflags::bitflags;
+
+use crate::ffi::DWORD;
+use crate::pin::EntryValidation;
+use crate::{ctl_code, Card, Error, MAX_BUFFER_SIZE};
+
+/// The control code of the GET_FEATURE_REQUEST command.
+pub const CM_IOCTL_GET_FEATURE_REQUEST: DWORD = ctl_code(3400);
+
+/// A reader feature, identified by its tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Feature {
+    VerifyPinStart,
+    VerifyPinFinish,
+    ModifyPinStart,
+    ModifyPinFinish,
+    GetKeyPressed,
+    VerifyPinDirect,
+    ModifyPinDirect,
+    MctReaderDirect,
+    MctUniversal,
+    IfdPinProperties,
+    Abort,
+    SetSpeMessage,
+    VerifyPinDirectAppId,
+    ModifyPinDirectAppId,
+    WriteDisplay,
+    GetKey,
+    IfdDisplayProperties,
+    GetTlvProperties,
+    CcidEscCommand,
+    ExecutePace,
+    /// A feature not known to this crate, with its tag.
+    Other(u8),
+}
+
+impl Feature {
+    /// The feature with the given tag.
+    pub fn from_tag(tag: u8) -> Feature {
+        match tag {
+            0x01 => Feature::VerifyPinStart,
+            0x02 => Feature::VerifyPinFinish,
+            0x03 => Feature::ModifyPinStart,
+            0x04 => Feature::ModifyPinFinish,
+            0x05 => Feature::GetKeyPressed,
+            0x06 => Feature::VerifyPinDirect,
+            0x07 => Feature::ModifyPinDirect,
+            0x08 => Feature::MctReaderDirect,
+            0x09 => Feature::MctUniversal,
+            0x0A => Feature::IfdPinProperties,
+            0x0B => Feature::Abort,
+            0x0C => Feature::SetSpeMessage,
+            0x0D => Feature::VerifyPinDirectAppId,
+            0x0E => Feature::ModifyPinDirectAppId,
+            0x0F => Feature::WriteDisplay,
+            0x10 => Feature::GetKey,
+            0x11 => Feature::IfdDisplayProperties,
+            0x12 => Feature::GetTlvProperties,
+            0x13 => Feature::CcidEscCommand,
+            0x20 => Feature::ExecutePace,
+            tag => Feature::Other(tag),
+        }
+    }
+
+    /// The tag of the feature.
+    pub fn tag(self) -> u8 {
+        match self {
+            Feature::VerifyPinStart => 0x01,
+            Feature::VerifyPinFinish => 0x02,
+            Feature::ModifyPinStart => 0x03,
+            Feature::ModifyPinFinish => 0x04,
+            Feature::GetKeyPressed => 0x05,
+            Feature::VerifyPinDirect => 0x06,
+            Feature::ModifyPinDirect => 0x07,
+            Feature::MctReaderDirect => 0x08,
+            Feature::MctUniversal => 0x09,
+            Feature::IfdPinProperties => 0x0A,
+            Feature::Abort => 0x0B,
+            Feature::SetSpeMessage => 0x0C,
+            Feature::VerifyPinDirectAppId => 0x0D,
+            Feature::ModifyPinDirectAppId => 0x0E,
+            Feature::WriteDisplay => 0x0F,
+            Feature::GetKey => 0x10,
+            Feature::IfdDisplayProperties => 0x11,
+            Feature::GetTlvProperties => 0x12,
+            Feature::CcidEscCommand => 0x13,
+            Feature::ExecutePace => 0x20,
+            Feature::Other(tag) => tag,
+        }
+    }
+}
+
+/// The features supported by a reader, with their control codes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Features {
+    control_codes: BTreeMap<Feature, DWORD>,
+}
+
+impl Features {
+    /// Parse the reply to `CM_IOCTL_GET_FEATURE_REQUEST`.
+    ///
+    /// The reply is a list of TLV structures, each with the tag of a
+    /// feature, the length 4, and the control code of the feature in
+    /// big-endian byte order. If the reply is malformed,
+    /// `Error::InvalidValue` is returned.
+    pub fn parse(reply: &[u8]) -> Result<Features, Error> {
+        let mut control_codes = BTreeMap::new();
+        for tlv in reply.chunks(6) {
+            match *tlv {
+                [tag, 4, b0, b1, b2, b3] => {
+                    let control_code = u32::from_be_bytes([b0, b1, b2, b3]);
+                    control_codes.insert(Feature::from_tag(tag), DWORD::from(control_code));
+                }
+                _ => return Err(Error::InvalidValue),
+            }
+        }
+        Ok(Features { control_codes })
+    }
+
+    /// The control code of a feature, if supported.
+    ///
+    /// The control code is passed as is to `Card::control()`; it is not an
+    /// input to `ctl_code()`.
+    pub fn get(&self, feature: Feature) -> Option<DWORD> {
+        self.control_codes.get(&feature).copied()
+    }
+
+    /// Whether a feature is supported.
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.control_codes.contains_key(&feature)
+    }
+
+    /// The supported features and their control codes, ordered by
+    /// feature.
+    pub fn iter(&self) -> impl Iterator<Item = (Feature, DWORD)> + '_ {
+        self.control_codes
+            .iter()
+            .map(|(&feature, &control_code)| (feature, control_code))
+    }
+
+    /// The number of supported features.
+    pub fn len(&self) -> usize {
+        self.control_codes.len()
+    }
+
+    /// Whether no features are supported.
+    pub fn is_empty(&self) -> bool {
+        self.control_codes.is_empty()
+    }
+}
+
+impl Card {
+    /// Get the features supported by the reader.
+    ///
+    /// This sends `CM_IOCTL_GET_FEATURE_REQUEST`, which works both when
+    /// connected to a card and when connected to the reader directly
+    /// (`ShareMode::Direct`). Readers which do not support the request
+    /// return an error, such as `Error::UnsupportedFeature`.
+    pub fn features(&self) -> Result<Features, Error> {
+        let mut receive_buffer = [0; MAX_BUFFER_SIZE];
+        let reply = self.control(CM_IOCTL_GET_FEATURE_REQUEST, &[], &mut receive_buffer)?;
+        Features::parse(reply)
+    }
+
+    /// Get the TLV properties of the reader.
+    ///
+    /// This discovers the reader's `Feature::GetTlvProperties` control
+    /// code, and sends the request to it. If the reader has no such
+    /// feature, `Error::UnsupportedFeature` is returned.
+    pub fn tlv_properties(&self) -> Result<TlvProperties, Error> {
+        let control_code = self
+            .features()?
+            .get(Feature::GetTlvProperties)
+            .ok_or(Error::UnsupportedFeature)?;
+        let mut receive_buffer = [0; MAX_BUFFER_SIZE];
+        let reply = self.control(control_code, &[], &mut receive_buffer)?;
+        TlvProperties::parse(reply)
+    }
+}
+
+/// The layout of a reader's display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LcdLayout {
+    /// The number of lines; 0 if the reader has no display.
+    pub lines: u8,
+    /// The number of characters per line.
+    pub characters: u8,
+}
+
+bitflags! {
+    /// How a reader accepts pseudo-APDUs (PPDUs).
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+    pub struct PpduSupport: u8 {
+        /// PPDUs are sent with `Card::control()` and
+        /// `Feature::CcidEscCommand`.
+        const CONTROL = 0x01;
+        /// PPDUs are sent with `Card::transmit()`.
+        const TRANSMIT = 0x02;
+    }
+}
+
+/// The properties of a reader, from the `Feature::GetTlvProperties`
+/// feature.
+///
+/// Properties which the reader does not report are `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct TlvProperties {
+    /// The layout of the display (`wLcdLayout`).
+    pub lcd_layout: Option<LcdLayout>,
+    /// The default conditions which end a PIN entry
+    /// (`bEntryValidationCondition`).
+    pub entry_validation: Option<EntryValidation>,
+    /// The default timeout after the first key press, in seconds
+    /// (`bTimeOut2`).
+    pub timeout2: Option<u8>,
+    /// The maximum number of characters of the display
+    /// (`wLcdMaxCharacters`).
+    pub lcd_max_characters: Option<u16>,
+    /// The maximum number of lines of the display (`wLcdMaxLines`).
+    pub lcd_max_lines: Option<u16>,
+    /// The minimum PIN length (`bMinPINSize`).
+    pub min_pin_size: Option<u8>,
+    /// The maximum PIN length (`bMaxPINSize`).
+    pub max_pin_size: Option<u8>,
+    /// The firmware identification (`sFirmwareID`).
+    pub firmware_id: Option<String>,
+    /// How the reader accepts pseudo-APDUs (`bPPDUSupport`).
+    pub ppdu_support: Option<PpduSupport>,
+    /// The maximum size of the data of an APDU (`dwMaxAPDUDataSize`); 0
+    /// if the reader only supports short APDUs.
+    pub max_apdu_data_size: Option<u32>,
+    /// The USB vendor ID (`wIdVendor`).
+    pub vendor_id: Option<u16>,
+    /// The USB product ID (`wIdProduct`).
+    pub product_id: Option<u16>,
+}
+
+impl TlvProperties {
+    /// Parse the reply to the `Feature::GetTlvProperties` request.
+    ///
+    /// The reply is a list of TLV structures, each with a one byte tag, a
+    /// one byte length, and a value in little-endian byte order. Unknown
+    /// tags are ignored. If the reply is malformed, `Error::InvalidValue`
+    /// is returned.
+    pub fn parse(reply: &[u8]) -> Result<TlvProperties, Error> {
+        let mut properties = TlvProperties::default();
+        let mut rest = reply;
+        while let [tag, len, ref tail @ ..] = *rest {
+            let len = len as usize;
+            if tail.len() < len {
+                return Err(Error::InvalidValue);
+            }
+            let (value, tail) = tail.split_at(len);
+            rest = tail;
+
+            let byte = || match *value {
+                [byte] => Ok(byte),
+                _ => Err(Error::InvalidValue),
+            };
+            let word = || match *value {
+                [b0, b1] => Ok(u16::from_le_bytes([b0, b1])),
+                _ => Err(Error::InvalidValue),
+            };
+            match tag {
+                0x01 => {
+                    let layout = word()?;
+                    properties.lcd_layout = Some(LcdLayout {
+                        lines: (layout >> 8) as u8,
+                        characters: layout as u8,
+                    });
+                }
+                0x02 => properties.entry_validation = Some(EntryValidation::from_bits_retain(byte()?)),
+                0x03 => properties.timeout2 = Some(byte()?),
+                0x04 => properties.lcd_max_characters = Some(word()?),
+                0x05 => properties.lcd_max_lines = Some(word()?),
+                0x06 => properties.min_pin_size = Some(byte()?),
+                0x07 => properties.max_pin_size = Some(byte()?),
+                0x08 => properties.firmware_id = Some(String::from_utf8_lossy(value).into_owned()),
+                0x09 => properties.ppdu_support = Some(PpduSupport::from_bits_retain(byte()?)),
+                0x0A => match *value {
+                    [b0, b1, b2, b3] => properties.max_apdu_data_size = Some(u32::from_le_bytes([b0, b1, b2, b3])),
+                    _ => return Err(Error::InvalidValue),
+                },
+                0x0B => properties.vendor_id = Some(word()?),
+                0x0C => properties.product_id = Some(word()?),
+                _ => {}
+            }
+        }
+        if !rest.is_empty() {
+            return Err(Error::InvalidValue);
+        }
+        Ok(properties)
+    }
+
+    /// Whether the reader supports extended length APDUs.
+    pub fn extended_apdu(&self) -> bool {
+        self.max_apdu_data_size.map_or(false, |size| size > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::test_reader;
+
+    // The reply of a pinpad reader to GET_FEATURE_REQUEST.
+    const FEATURES: &[u8] = &[
+        0x06, 0x04, 0x42, 0x33, 0x00, 0x06, // VERIFY_PIN_DIRECT
+        0x07, 0x04, 0x42, 0x33, 0x00, 0x07, // MODIFY_PIN_DIRECT
+        0x12, 0x04, 0x42, 0x33, 0x00, 0x12, // GET_TLV_PROPERTIES
+        0x30, 0x04, 0x42, 0x33, 0x00, 0x30, // Unknown
+    ];
+
+    // The reply of a pinpad reader with a display to GET_TLV_PROPERTIES.
+    const TLV_PROPERTIES: &[u8] = &[
+        0x01, 0x02, 0x10, 0x02, // wLcdLayout
+        0x02, 0x01, 0x02, // bEntryValidationCondition
+        0x03, 0x01, 0x0F, // bTimeOut2
+        0x04, 0x02, 0x10, 0x00, // wLcdMaxCharacters
+        0x05, 0x02, 0x02, 0x00, // wLcdMaxLines
+        0x06, 0x01, 0x04, // bMinPINSize
+        0x07, 0x01, 0x08, // bMaxPINSize
+        0x08, 0x06, b'V', b'1', b'.', b'2', b'.', b'3', // sFirmwareID
+        0x09, 0x01, 0x01, // bPPDUSupport
+        0x0A, 0x04, 0x00, 0x01, 0x00, 0x00, // dwMaxAPDUDataSize
+        0x0B, 0x02, 0xE6, 0x08, // wIdVendor
+        0x0C, 0x02, 0x30, 0x34, // wIdProduct
+        0x80, 0x01, 0x00, // Unknown
+    ];
+
+    #[test]
+    fn feature_tags() {
+        for tag in 0..=0xFF {
+            assert_eq!(Feature::from_tag(tag).tag(), tag);
+        }
+        assert_eq!(Feature::from_tag(0x06), Feature::VerifyPinDirect);
+        assert_eq!(Feature::from_tag(0x20), Feature::ExecutePace);
+        assert_eq!(Feature::from_tag(0x14), Feature::Other(0x14));
+    }
+
+    #[test]
+    fn parse_features() {
+        let features = Features::parse(FEATURES).unwrap();
+        assert_eq!(features.len(), 4);
+        assert!(features.contains(Feature::VerifyPinDirect));
+        assert!(!features.contains(Feature::VerifyPinStart));
+        assert_eq!(features.get(Feature::ModifyPinDirect), Some(0x4233_0007));
+        assert_eq!(features.get(Feature::Other(0x30)), Some(0x4233_0030));
+        assert_eq!(features.get(Feature::Abort), None);
+        assert_eq!(
+            features.iter().collect::<Vec<_>>(),
+            [
+                (Feature::VerifyPinDirect, 0x4233_0006),
+                (Feature::ModifyPinDirect, 0x4233_0007),
+                (Feature::GetTlvProperties, 0x4233_0012),
+                (Feature::Other(0x30), 0x4233_0030),
+            ]
+        );
+
+        let features = Features::parse(&[]).unwrap();
+        assert!(features.is_empty());
+
+        assert_eq!(
+            Features::parse(&FEATURES[..FEATURES.len() - 1]),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            Features::parse(&[0x06, 0x02, 0x42, 0x33, 0x00, 0x06]),
+            Err(Error::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn card_features() {
+        let card = test_reader(|control_code, command: &[u8]| {
+            assert_eq!(control_code, CM_IOCTL_GET_FEATURE_REQUEST);
+            assert_eq!(command, []);
+            Ok(FEATURES.to_vec())
+        });
+        let features = card.features().unwrap();
+        assert_eq!(features.get(Feature::VerifyPinDirect), Some(0x4233_0006));
+
+        let card = test_reader(|_, _: &[u8]| Err(Error::UnsupportedFeature));
+        assert_eq!(card.features(), Err(Error::UnsupportedFeature));
+    }
+
+    #[test]
+    fn parse_tlv_properties() {
+        let properties = TlvProperties::parse(TLV_PROPERTIES).unwrap();
+        assert_eq!(
+            properties,
+            TlvProperties {
+                lcd_layout: Some(LcdLayout {
+                    lines: 2,
+                    characters: 16,
+                }),
+                entry_validation: Some(EntryValidation::KEY_PRESSED),
+                timeout2: Some(15),
+                lcd_max_characters: Some(16),
+                lcd_max_lines: Some(2),
+                min_pin_size: Some(4),
+                max_pin_size: Some(8),
+                firmware_id: Some("V1.2.3".to_owned()),
+                ppdu_support: Some(PpduSupport::CONTROL),
+                max_apdu_data_size: Some(256),
+                vendor_id: Some(0x08E6),
+                product_id: Some(0x3430),
+            }
+        );
+        assert!(properties.extended_apdu());
+
+        let properties = TlvProperties::parse(&[0x0A, 0x04, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(properties.max_apdu_data_size, Some(0));
+        assert!(!properties.extended_apdu());
+
+        let properties = TlvProperties::parse(&[]).unwrap();
+        assert_eq!(properties, TlvProperties::default());
+        assert!(!properties.extended_apdu());
+
+        for reply in [
+            // Truncated value.
+            &[0x0B, 0x02, 0xE6][..],
+            // Truncated header.
+            &[0x0B],
+            // Wrong lengths.
+            &[0x03, 0x02, 0x0F, 0x00],
+            &[0x0B, 0x01, 0xE6],
+            &[0x0A, 0x02, 0x00, 0x01],
+        ] {
+            assert_eq!(TlvProperties::parse(reply), Err(Error::InvalidValue), "{:02X?}", reply);
+        }
+    }
+
+    #[test]
+    fn card_tlv_properties() {
+        let card = test_reader(|control_code, _: &[u8]| match control_code {
+            CM_IOCTL_GET_FEATURE_REQUEST => Ok(FEATURES.to_vec()),
+            0x4233_0012 => Ok(TLV_PROPERTIES.to_vec()),
+            _ => Err(Error::UnsupportedFeature),
+        });
+        let properties = card.tlv_properties().unwrap();
+        assert_eq!(properties.vendor_id, Some(0x08E6));
+
+        // The reader does not have the feature.
+        let card = test_reader(|control_code, _: &[u8]| match control_code {
+            CM_IOCTL_GET_FEATURE_REQUEST => Ok(FEATURES[..12].to_vec()),
+            _ => panic!("unexpected control code {:#X}", control_code),
+        });
+        assert_eq!(card.tlv_properties(), Err(Error::UnsupportedFeature));
+    }
+}
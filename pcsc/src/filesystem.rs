@@ -0,0 +1,1715 @@
+//! Accessing the files of ISO 7816-4 cards.
+//!
+//! ISO 7816-4 cards organize their data in dedicated files (DFs), such as
+//! the master file (3F00) and applications, which contain elementary
+//! files (EFs). Transparent EFs are read and written as a sequence of
+//! bytes at an offset; record EFs as a sequence of records, numbered from
+//! 1.
+//!
+//! [`FileSystem`](struct.FileSystem.html) selects files by file
+//! identifier, path or DF name, and returns their
+//! [`FileControl`](struct.FileControl.html) parameters. It reads and
+//! writes transparent EFs in chunks, using the odd instruction bytes for
+//! offsets over 32767, and reads and appends records. EFs can also be
+//! referenced by their short EF identifier, without selecting them first.
+//! [`EfStream`](struct.EfStream.html) gives access to a transparent EF
+//! through `std::io::Read`, `Write` and `Seek`.
+//!
+//! ```no_run
+//! use pcsc::filesystem::FileSystem;
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//!
+//! let fs = FileSystem::new(&card);
+//! let control = fs.select_path(&[0x3F00, 0x5015]).unwrap();
+//! println!("{:?}", control.descriptor);
+//! let certificate = fs.read_file(0x4401).unwrap();
+//! println!("{} bytes", certificate.len());
+//! ```
+
+use std::error;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use crate::apdu::{
+    ApduError, Command, Response, StatusWord, MAX_EXTENDED_DATA, MAX_EXTENDED_LE, MAX_SHORT_DATA, MAX_SHORT_LE,
+};
+use crate::tlv::{self, Tag, Tlv};
+use crate::{Card, Error};
+
+/// The file identifier of the master file.
+pub const MASTER_FILE_ID: u16 = 0x3F00;
+
+// READ BINARY and UPDATE BINARY with the even instruction byte address at
+// most 15 bits of offset, or 8 bits with a short EF identifier.
+const MAX_OFFSET: usize = 0x7FFF;
+const MAX_SFI_OFFSET: usize = 0xFF;
+
+// The most bytes which the offset and discretionary data objects of the
+// odd instruction bytes add around the data: 54 with an offset of up to 8
+// bytes, and 53 with a length of up to 5 bytes.
+const ODD_INS_OVERHEAD: usize = 2 + 8 + 1 + 5;
+
+// Record numbers 1 to 254; FF is reserved.
+const MAX_RECORD_NUMBER: u8 = 0xFE;
+
+const TAG_FCP: Tag = Tag::new(0x62);
+const TAG_FMD: Tag = Tag::new(0x64);
+const TAG_FCI: Tag = Tag::new(0x6F);
+const TAG_DATA_SIZE: Tag = Tag::new(0x80);
+const TAG_TOTAL_SIZE: Tag = Tag::new(0x81);
+const TAG_DESCRIPTOR: Tag = Tag::new(0x82);
+const TAG_FILE_ID: Tag = Tag::new(0x83);
+const TAG_DF_NAME: Tag = Tag::new(0x84);
+const TAG_SHORT_FILE_ID: Tag = Tag::new(0x88);
+const TAG_LIFE_CYCLE: Tag = Tag::new(0x8A);
+const TAG_SECURITY_PROPRIETARY: Tag = Tag::new(0x86);
+const TAG_SECURITY_REFERENCED: Tag = Tag::new(0x8B);
+const TAG_SECURITY_COMPACT: Tag = Tag::new(0x8C);
+const TAG_SECURITY_DATA_OBJECTS: Tag = Tag::new(0xA0);
+const TAG_SECURITY_PROPRIETARY_STATE: Tag = Tag::new(0xA1);
+const TAG_SECURITY_EXPANDED: Tag = Tag::new(0xAB);
+const TAG_DISCRETIONARY_DATA: Tag = Tag::new(0x53);
+const TAG_OFFSET: Tag = Tag::new(0x54);
+
+/// An error accessing the files of a card.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSystemError {
+    /// The PC/SC call failed.
+    Pcsc(Error),
+    /// The card returned a status word which does not indicate success.
+    Status(StatusWord),
+    /// The response is shorter than a status word, or the response data
+    /// of an odd instruction byte is not a discretionary data object.
+    MalformedResponse,
+    /// The file control parameters or information returned by SELECT are
+    /// not valid.
+    InvalidFileControl,
+    /// The short EF identifier is not in the range 1 to 30.
+    InvalidShortFileId(u8),
+    /// The file is not a transparent EF.
+    NotTransparent,
+}
+
+impl fmt::Display for FileSystemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileSystemError::Pcsc(ref err) => write!(f, "{}", err),
+            FileSystemError::Status(sw) => write!(f, "{}", sw),
+            FileSystemError::MalformedResponse => f.write_str("malformed response"),
+            FileSystemError::InvalidFileControl => f.write_str("invalid file control information"),
+            FileSystemError::InvalidShortFileId(sfi) => write!(f, "invalid short EF identifier {}", sfi),
+            FileSystemError::NotTransparent => f.write_str("not a transparent EF"),
+        }
+    }
+}
+
+impl error::Error for FileSystemError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            FileSystemError::Pcsc(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for FileSystemError {
+    fn from(err: Error) -> FileSystemError {
+        FileSystemError::Pcsc(err)
+    }
+}
+
+impl From<FileSystemError> for io::Error {
+    /// Wrap the error in an `io::Error`, with the closest `ErrorKind` for
+    /// the PC/SC error or the status word, or `ErrorKind::Other` if there
+    /// is none.
+    fn from(err: FileSystemError) -> io::Error {
+        let kind = match err {
+            FileSystemError::Pcsc(err) => return err.into(),
+            FileSystemError::Status(status) => match status {
+                StatusWord::FileNotFound | StatusWord::RecordNotFound | StatusWord::ReferencedDataNotFound => {
+                    ErrorKind::NotFound
+                }
+                StatusWord::CommandNotAllowed
+                | StatusWord::SecurityStatusNotSatisfied
+                | StatusWord::AuthenticationBlocked
+                | StatusWord::ReferenceDataNotUsable
+                | StatusWord::ConditionsNotSatisfied => ErrorKind::PermissionDenied,
+                StatusWord::WrongP1P2
+                | StatusWord::IncorrectP1P2
+                | StatusWord::WrongLength
+                | StatusWord::IncompatibleFileStructure
+                | StatusWord::NoCurrentEf => ErrorKind::InvalidInput,
+                StatusWord::ClaFunctionsNotSupported
+                | StatusWord::SecureMessagingNotSupported
+                | StatusWord::FunctionNotSupported
+                | StatusWord::InsNotSupported
+                | StatusWord::ClaNotSupported => ErrorKind::Unsupported,
+                StatusWord::CorruptedData => ErrorKind::InvalidData,
+                _ => ErrorKind::Other,
+            },
+            FileSystemError::MalformedResponse | FileSystemError::InvalidFileControl => ErrorKind::InvalidData,
+            FileSystemError::InvalidShortFileId(_) | FileSystemError::NotTransparent => ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// The type of a file, from its file descriptor byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    /// A working EF, which holds data for the outside world.
+    WorkingEf,
+    /// An internal EF, which holds data for the card itself, such as keys.
+    InternalEf,
+    /// A proprietary type of EF, with the given type bits (2 to 6).
+    ProprietaryEf(u8),
+    /// A DF.
+    Df,
+    /// A file descriptor byte with reserved values.
+    Other(u8),
+}
+
+/// The structure of an EF, from its file descriptor byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileStructure {
+    /// No information given, for example for DFs.
+    NoInformation,
+    /// A transparent EF.
+    Transparent,
+    /// Records of a fixed size.
+    LinearFixed,
+    /// Records of a fixed size, holding SIMPLE-TLV data objects.
+    LinearFixedTlv,
+    /// Records of variable sizes.
+    LinearVariable,
+    /// Records of variable sizes, holding SIMPLE-TLV data objects.
+    LinearVariableTlv,
+    /// Records of a fixed size, in a ring.
+    Cyclic,
+    /// Records of a fixed size in a ring, holding SIMPLE-TLV data objects.
+    CyclicTlv,
+    /// BER-TLV data objects, accessed with GET DATA and PUT DATA.
+    BerTlv,
+    /// SIMPLE-TLV data objects, accessed with GET DATA and PUT DATA.
+    SimpleTlv,
+}
+
+impl FileStructure {
+    fn from_bits(bits: u8) -> FileStructure {
+        match bits & 0x07 {
+            0 => FileStructure::NoInformation,
+            1 => FileStructure::Transparent,
+            2 => FileStructure::LinearFixed,
+            3 => FileStructure::LinearFixedTlv,
+            4 => FileStructure::LinearVariable,
+            5 => FileStructure::LinearVariableTlv,
+            6 => FileStructure::Cyclic,
+            _ => FileStructure::CyclicTlv,
+        }
+    }
+
+    /// Whether the EF is made of records.
+    pub fn is_record(self) -> bool {
+        matches!(
+            self,
+            FileStructure::LinearFixed
+                | FileStructure::LinearFixedTlv
+                | FileStructure::LinearVariable
+                | FileStructure::LinearVariableTlv
+                | FileStructure::Cyclic
+                | FileStructure::CyclicTlv
+        )
+    }
+}
+
+/// The file descriptor data object (82) of the file control parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileDescriptor {
+    /// The file descriptor byte.
+    pub descriptor: u8,
+    /// Whether the file supports concurrent access on several logical
+    /// channels.
+    pub shareable: bool,
+    /// The type of the file.
+    pub file_type: FileType,
+    /// The structure of the file.
+    pub structure: FileStructure,
+    /// The data coding byte.
+    pub data_coding: Option<u8>,
+    /// The maximum record size of a record EF.
+    pub max_record_size: Option<u16>,
+    /// The number of records of a record EF.
+    pub record_count: Option<u16>,
+}
+
+impl FileDescriptor {
+    /// Decode the value of a file descriptor data object.
+    pub fn parse(bytes: &[u8]) -> Result<FileDescriptor, FileSystemError> {
+        let (descriptor, data_coding, max_record_size, record_count) = match *bytes {
+            [descriptor] => (descriptor, None, None, None),
+            [descriptor, coding] => (descriptor, Some(coding), None, None),
+            [descriptor, coding, size] => (descriptor, Some(coding), Some(u16::from(size)), None),
+            [descriptor, coding, size_high, size_low] => (
+                descriptor,
+                Some(coding),
+                Some(u16::from_be_bytes([size_high, size_low])),
+                None,
+            ),
+            [descriptor, coding, size_high, size_low, count] => (
+                descriptor,
+                Some(coding),
+                Some(u16::from_be_bytes([size_high, size_low])),
+                Some(u16::from(count)),
+            ),
+            [descriptor, coding, size_high, size_low, count_high, count_low] => (
+                descriptor,
+                Some(coding),
+                Some(u16::from_be_bytes([size_high, size_low])),
+                Some(u16::from_be_bytes([count_high, count_low])),
+            ),
+            _ => return Err(FileSystemError::InvalidFileControl),
+        };
+        // Bit 8 is reserved, bit 7 is the shareable bit, bits 6 to 4 are
+        // the type and bits 3 to 1 the structure.
+        let (file_type, structure) = match (descriptor & 0x80, (descriptor >> 3) & 0x07, descriptor & 0x07) {
+            (0x00, 0x07, 0x00) => (FileType::Df, FileStructure::NoInformation),
+            (0x00, 0x07, 0x01) => (FileType::WorkingEf, FileStructure::BerTlv),
+            (0x00, 0x07, 0x02) => (FileType::WorkingEf, FileStructure::SimpleTlv),
+            (0x00, 0x00, bits) => (FileType::WorkingEf, FileStructure::from_bits(bits)),
+            (0x00, 0x01, bits) => (FileType::InternalEf, FileStructure::from_bits(bits)),
+            (0x00, bits @ 0x02..=0x06, structure) => {
+                (FileType::ProprietaryEf(bits), FileStructure::from_bits(structure))
+            }
+            _ => (FileType::Other(descriptor), FileStructure::NoInformation),
+        };
+        Ok(FileDescriptor {
+            descriptor,
+            shareable: descriptor & 0x40 != 0,
+            file_type,
+            structure,
+            data_coding,
+            max_record_size,
+            record_count,
+        })
+    }
+}
+
+/// The life cycle status of a file (8A).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LifeCycle {
+    /// 00: No information given.
+    NoInformation,
+    /// 01: Creation state.
+    Creation,
+    /// 03: Initialisation state.
+    Initialisation,
+    /// 05 or 07: Operational state, activated.
+    Activated,
+    /// 04 or 06: Operational state, deactivated.
+    Deactivated,
+    /// 0C to 0F: Termination state.
+    Terminated,
+    /// A reserved or proprietary value.
+    Other(u8),
+}
+
+impl From<u8> for LifeCycle {
+    fn from(byte: u8) -> LifeCycle {
+        match byte {
+            0x00 => LifeCycle::NoInformation,
+            0x01 => LifeCycle::Creation,
+            0x03 => LifeCycle::Initialisation,
+            0x05 | 0x07 => LifeCycle::Activated,
+            0x04 | 0x06 => LifeCycle::Deactivated,
+            0x0C..=0x0F => LifeCycle::Terminated,
+            byte => LifeCycle::Other(byte),
+        }
+    }
+}
+
+/// A security attribute of a file, giving its access conditions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SecurityAttribute {
+    /// 8C: The compact format. The access mode byte tells which commands
+    /// the conditions apply to; there is one security condition byte for
+    /// each of its bits 7 to 1 which is set, from bit 7 down.
+    Compact {
+        /// The access mode byte.
+        access_mode: u8,
+        /// The security condition bytes.
+        conditions: Vec<u8>,
+    },
+    /// 8B: A reference to records of an EF.ARR, which hold the expanded
+    /// format.
+    Referenced(Vec<u8>),
+    /// AB: The expanded format.
+    Expanded(Vec<u8>),
+    /// 86, A0 or A1: Another format, with its tag.
+    Other(Tag, Vec<u8>),
+}
+
+/// The file control parameters of a file, as returned by SELECT.
+///
+/// SELECT returns an FCP template (62), an FCI template (6F) or an FMD
+/// template (64); their data objects are decoded into the fields, and
+/// the fields of data objects which are missing are `None`. Data objects
+/// without a field, such as proprietary information (85 or A5), are kept
+/// in `other`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FileControl {
+    /// The number of data bytes of the file (80).
+    pub size: Option<usize>,
+    /// The number of bytes allocated to the file, including structural
+    /// information (81).
+    pub total_size: Option<usize>,
+    /// The file descriptor (82).
+    pub descriptor: Option<FileDescriptor>,
+    /// The file identifier (83).
+    pub file_id: Option<u16>,
+    /// The DF name, such as the AID of an application (84).
+    pub df_name: Option<Vec<u8>>,
+    /// The short EF identifier (88). `None` also if the data object is
+    /// empty, which means that the EF has no short EF identifier.
+    pub short_file_id: Option<u8>,
+    /// The life cycle status (8A).
+    pub life_cycle: Option<LifeCycle>,
+    /// The security attributes.
+    pub security_attributes: Vec<SecurityAttribute>,
+    /// The other data objects, with their tags.
+    pub other: Vec<(Tag, Vec<u8>)>,
+}
+
+impl FileControl {
+    /// Decode the response data of SELECT.
+    ///
+    /// Empty response data gives empty file control parameters.
+    pub fn parse(bytes: &[u8]) -> Result<FileControl, FileSystemError> {
+        let mut control = FileControl::default();
+        if bytes.is_empty() {
+            return Ok(control);
+        }
+        let (template, _) = Tlv::parse(bytes).map_err(|_| FileSystemError::InvalidFileControl)?;
+        if ![TAG_FCP, TAG_FCI, TAG_FMD].contains(&template.tag()) {
+            return Err(FileSystemError::InvalidFileControl);
+        }
+        for object in template.children() {
+            let object = object.map_err(|_| FileSystemError::InvalidFileControl)?;
+            let value = object.value();
+            match object.tag() {
+                TAG_DATA_SIZE => control.size = Some(parse_size(value)?),
+                TAG_TOTAL_SIZE => control.total_size = Some(parse_size(value)?),
+                TAG_DESCRIPTOR => control.descriptor = Some(FileDescriptor::parse(value)?),
+                TAG_FILE_ID => match *value {
+                    [high, low] => control.file_id = Some(u16::from_be_bytes([high, low])),
+                    _ => return Err(FileSystemError::InvalidFileControl),
+                },
+                TAG_DF_NAME => control.df_name = Some(value.to_vec()),
+                TAG_SHORT_FILE_ID => match *value {
+                    [] => control.short_file_id = None,
+                    [sfi] => control.short_file_id = Some(sfi >> 3),
+                    _ => return Err(FileSystemError::InvalidFileControl),
+                },
+                TAG_LIFE_CYCLE => match *value {
+                    [status] => control.life_cycle = Some(LifeCycle::from(status)),
+                    _ => return Err(FileSystemError::InvalidFileControl),
+                },
+                TAG_SECURITY_COMPACT => match *value {
+                    [access_mode, ref conditions @ ..] => {
+                        control.security_attributes.push(SecurityAttribute::Compact {
+                            access_mode,
+                            conditions: conditions.to_vec(),
+                        })
+                    }
+                    _ => return Err(FileSystemError::InvalidFileControl),
+                },
+                TAG_SECURITY_REFERENCED => control
+                    .security_attributes
+                    .push(SecurityAttribute::Referenced(value.to_vec())),
+                TAG_SECURITY_EXPANDED => control
+                    .security_attributes
+                    .push(SecurityAttribute::Expanded(value.to_vec())),
+                tag @ (TAG_SECURITY_PROPRIETARY | TAG_SECURITY_DATA_OBJECTS | TAG_SECURITY_PROPRIETARY_STATE) => {
+                    control
+                        .security_attributes
+                        .push(SecurityAttribute::Other(tag, value.to_vec()))
+                }
+                tag => control.other.push((tag, value.to_vec())),
+            }
+        }
+        Ok(control)
+    }
+}
+
+fn parse_size(value: &[u8]) -> Result<usize, FileSystemError> {
+    if value.is_empty() || value.len() > 4 {
+        return Err(FileSystemError::InvalidFileControl);
+    }
+    Ok(value.iter().fold(0usize, |size, &byte| size << 8 | byte as usize))
+}
+
+/// Access to the files of a card.
+///
+/// The commands are sent with `Card::exchange()`, so the card's
+/// `ApduOptions` apply. A `Transaction` can be used as the card, to keep
+/// the selected file while accessing it.
+///
+/// READ BINARY responses are limited to `max_le()` bytes, and UPDATE
+/// BINARY and APPEND RECORD data to `max_lc()` bytes. By default, these
+/// are the limits of short APDUs, or of extended APDUs if the
+/// `ApduOptions` declare extended length support, with responses also
+/// limited to the `ApduOptions`' maximum response length.
+pub struct FileSystem<'card> {
+    card: &'card Card,
+    max_le: usize,
+    max_lc: usize,
+}
+
+impl<'card> FileSystem<'card> {
+    /// Access the files of `card`.
+    pub fn new(card: &'card Card) -> FileSystem<'card> {
+        let options = card.apdu_options();
+        let (max_le, max_lc) = if options.extended_length() {
+            (MAX_EXTENDED_LE.min(options.max_response_len()), MAX_EXTENDED_DATA)
+        } else {
+            (MAX_SHORT_LE.min(options.max_response_len()), MAX_SHORT_DATA)
+        };
+        FileSystem { card, max_le, max_lc }
+    }
+
+    /// Limit the response data of READ BINARY and READ RECORD to `max_le`
+    /// bytes.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `max_le` is 0 or over `MAX_EXTENDED_LE`.
+    pub fn with_max_le(mut self, max_le: usize) -> FileSystem<'card> {
+        assert!((1..=MAX_EXTENDED_LE).contains(&max_le), "invalid max_le {}", max_le);
+        self.max_le = max_le;
+        self
+    }
+
+    /// Limit the command data of UPDATE BINARY and APPEND RECORD to
+    /// `max_lc` bytes.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `max_lc` is 0 or over `MAX_EXTENDED_DATA`.
+    pub fn with_max_lc(mut self, max_lc: usize) -> FileSystem<'card> {
+        assert!((1..=MAX_EXTENDED_DATA).contains(&max_lc), "invalid max_lc {}", max_lc);
+        self.max_lc = max_lc;
+        self
+    }
+
+    /// The card.
+    pub fn card(&self) -> &'card Card {
+        self.card
+    }
+
+    /// The maximum response data length of READ BINARY and READ RECORD.
+    pub fn max_le(&self) -> usize {
+        self.max_le
+    }
+
+    /// The maximum command data length of UPDATE BINARY and APPEND
+    /// RECORD.
+    pub fn max_lc(&self) -> usize {
+        self.max_lc
+    }
+
+    /// Select a file by its file identifier, and return its file control
+    /// parameters.
+    pub fn select_file_id(&self, file_id: u16) -> Result<FileControl, FileSystemError> {
+        self.select(0x00, 0x04, &file_id.to_be_bytes())
+    }
+
+    /// Select a file by its path from the master file, and return its file
+    /// control parameters.
+    ///
+    /// The path may start with the master file identifier 3F00. An empty
+    /// path selects the master file.
+    pub fn select_path(&self, path: &[u16]) -> Result<FileControl, FileSystemError> {
+        let path = match *path {
+            [MASTER_FILE_ID, ref rest @ ..] => rest,
+            ref path => path,
+        };
+        if path.is_empty() {
+            return self.select_file_id(MASTER_FILE_ID);
+        }
+        let data: Vec<u8> = path.iter().flat_map(|file_id| file_id.to_be_bytes()).collect();
+        self.select(0x08, 0x04, &data)
+    }
+
+    /// Select a DF by its name, such as the AID of an application, and
+    /// return its file control information.
+    pub fn select_df_name(&self, name: &[u8]) -> Result<FileControl, FileSystemError> {
+        self.select(0x04, 0x00, name)
+    }
+
+    fn select(&self, p1: u8, p2: u8, data: &[u8]) -> Result<FileControl, FileSystemError> {
+        let command = Command::new(0x00, 0xA4, p1, p2).with_data(data).with_le(MAX_SHORT_LE);
+        FileControl::parse(&self.exchange(&command)?)
+    }
+
+    /// Read up to `len` bytes of the current EF, at `offset`.
+    ///
+    /// The data is read in chunks of at most `max_le()` bytes. Fewer bytes
+    /// are returned if the end of the file is reached.
+    pub fn read_binary(&self, offset: usize, len: usize) -> Result<Vec<u8>, FileSystemError> {
+        self.read(None, offset, Some(len))
+    }
+
+    /// Read up to `len` bytes of the EF with the short EF identifier
+    /// `sfi`, at `offset`, and make it the current EF.
+    pub fn read_binary_sfi(&self, sfi: u8, offset: usize, len: usize) -> Result<Vec<u8>, FileSystemError> {
+        self.read(Some(check_sfi(sfi)?), offset, Some(len))
+    }
+
+    /// Read the current EF, from `offset` to its end.
+    pub fn read_to_end(&self, offset: usize) -> Result<Vec<u8>, FileSystemError> {
+        self.read(None, offset, None)
+    }
+
+    /// Select the EF with the file identifier `file_id`, and read all of
+    /// it.
+    ///
+    /// If the file control parameters give the size of the file, exactly
+    /// that many bytes are read; otherwise the file is read until its end.
+    pub fn read_file(&self, file_id: u16) -> Result<Vec<u8>, FileSystemError> {
+        let control = self.select_file_id(file_id)?;
+        self.read(None, 0, control.size)
+    }
+
+    /// Read all of the EF with the short EF identifier `sfi`, and make it
+    /// the current EF.
+    pub fn read_file_sfi(&self, sfi: u8) -> Result<Vec<u8>, FileSystemError> {
+        self.read(Some(check_sfi(sfi)?), 0, None)
+    }
+
+    // Read `len` bytes, or until the end of the file if `len` is `None`.
+    fn read(&self, sfi: Option<u8>, offset: usize, len: Option<usize>) -> Result<Vec<u8>, FileSystemError> {
+        let mut data = Vec::new();
+        loop {
+            let remaining = match len {
+                Some(len) if data.len() >= len => break,
+                Some(len) => len - data.len(),
+                None => usize::MAX,
+            };
+            let requested = remaining.min(self.read_chunk_len(sfi, offset + data.len()));
+            let (chunk, end) = match self.read_chunk(sfi, offset + data.len(), requested) {
+                Ok(result) => result,
+                // Reading at the end of a file whose size is a multiple of
+                // the chunk size.
+                Err(FileSystemError::Status(StatusWord::WrongP1P2)) if len.is_none() && !data.is_empty() => break,
+                Err(err) => return Err(err),
+            };
+            let short = chunk.len() < requested;
+            data.extend_from_slice(&chunk);
+            if end || short {
+                break;
+            }
+        }
+        if let Some(len) = len {
+            data.truncate(len);
+        }
+        Ok(data)
+    }
+
+    // The most bytes which a single READ BINARY at `offset` returns.
+    fn read_chunk_len(&self, sfi: Option<u8>, offset: usize) -> usize {
+        if even_offset(sfi, offset) {
+            self.max_le
+        } else {
+            self.max_le.saturating_sub(ODD_INS_OVERHEAD).max(1)
+        }
+    }
+
+    // Send a single READ BINARY for at most `read_chunk_len()` bytes, and return
+    // the data and whether the end of the file was reached.
+    fn read_chunk(&self, sfi: Option<u8>, offset: usize, len: usize) -> Result<(Vec<u8>, bool), FileSystemError> {
+        let command = if even_offset(sfi, offset) {
+            let [p1, p2] = match sfi {
+                Some(sfi) => [0x80 | sfi, offset as u8],
+                None => (offset as u16).to_be_bytes(),
+            };
+            Command::new(0x00, 0xB0, p1, p2).with_le(len)
+        } else {
+            let mut data = Vec::new();
+            tlv::encode(TAG_OFFSET, &encode_offset(offset), &mut data);
+            Command::new(0x00, 0xB1, 0x00, sfi.unwrap_or(0))
+                .with_data(&data)
+                .with_le((len + ODD_INS_OVERHEAD).min(self.max_le))
+        };
+        let response = self.exchange_response(&command)?;
+        let end = match response.status() {
+            StatusWord::Success => false,
+            StatusWord::EndOfFile => true,
+            status => return Err(FileSystemError::Status(status)),
+        };
+        let data = if command.ins() == 0xB1 {
+            match tlv::parse(response.data()).find_tag(TAG_DISCRETIONARY_DATA) {
+                Ok(Some(object)) => object.value().to_vec(),
+                Ok(None) if response.data().is_empty() => Vec::new(),
+                _ => return Err(FileSystemError::MalformedResponse),
+            }
+        } else {
+            response.into_data()
+        };
+        Ok((data, end))
+    }
+
+    /// Write `data` to the current EF, at `offset`.
+    ///
+    /// The data is written in chunks of at most `max_lc()` bytes.
+    pub fn update_binary(&self, offset: usize, data: &[u8]) -> Result<(), FileSystemError> {
+        self.update(None, offset, data)
+    }
+
+    /// Write `data` to the EF with the short EF identifier `sfi`, at
+    /// `offset`, and make it the current EF.
+    pub fn update_binary_sfi(&self, sfi: u8, offset: usize, data: &[u8]) -> Result<(), FileSystemError> {
+        self.update(Some(check_sfi(sfi)?), offset, data)
+    }
+
+    fn update(&self, sfi: Option<u8>, offset: usize, data: &[u8]) -> Result<(), FileSystemError> {
+        let mut written = 0;
+        while written < data.len() {
+            let len = (data.len() - written).min(self.write_chunk_len(sfi, offset + written));
+            self.update_chunk(sfi, offset + written, &data[written..written + len])?;
+            written += len;
+        }
+        Ok(())
+    }
+
+    // The most bytes which a single UPDATE BINARY at `offset` writes.
+    fn write_chunk_len(&self, sfi: Option<u8>, offset: usize) -> usize {
+        if even_offset(sfi, offset) {
+            self.max_lc
+        } else {
+            self.max_lc.saturating_sub(ODD_INS_OVERHEAD).max(1)
+        }
+    }
+
+    // Send a single UPDATE BINARY for at most `write_chunk_len()` bytes.
+    fn update_chunk(&self, sfi: Option<u8>, offset: usize, data: &[u8]) -> Result<(), FileSystemError> {
+        let command = if even_offset(sfi, offset) {
+            let [p1, p2] = match sfi {
+                Some(sfi) => [0x80 | sfi, offset as u8],
+                None => (offset as u16).to_be_bytes(),
+            };
+            Command::new(0x00, 0xD6, p1, p2).with_data(data)
+        } else {
+            let mut command_data = Vec::new();
+            tlv::encode(TAG_OFFSET, &encode_offset(offset), &mut command_data);
+            tlv::encode(TAG_DISCRETIONARY_DATA, data, &mut command_data);
+            Command::new(0x00, 0xD7, 0x00, sfi.unwrap_or(0)).with_data(&command_data)
+        };
+        self.exchange(&command).map(drop)
+    }
+
+    /// Read the record `number` of the current EF.
+    ///
+    /// Records are numbered from 1; record 0 is the current record.
+    pub fn read_record(&self, number: u8) -> Result<Vec<u8>, FileSystemError> {
+        self.read_record_p2(number, 0x04)
+    }
+
+    /// Read the record `number` of the EF with the short EF identifier
+    /// `sfi`, and make it the current EF.
+    pub fn read_record_sfi(&self, sfi: u8, number: u8) -> Result<Vec<u8>, FileSystemError> {
+        self.read_record_p2(number, check_sfi(sfi)? << 3 | 0x04)
+    }
+
+    /// Read all records of the current EF, from record 1 until the card
+    /// answers that the record is not found.
+    pub fn read_records(&self) -> Result<Vec<Vec<u8>>, FileSystemError> {
+        self.read_records_p2(0x04)
+    }
+
+    /// Read all records of the EF with the short EF identifier `sfi`, and
+    /// make it the current EF.
+    pub fn read_records_sfi(&self, sfi: u8) -> Result<Vec<Vec<u8>>, FileSystemError> {
+        self.read_records_p2(check_sfi(sfi)? << 3 | 0x04)
+    }
+
+    fn read_record_p2(&self, number: u8, p2: u8) -> Result<Vec<u8>, FileSystemError> {
+        let command = Command::new(0x00, 0xB2, number, p2).with_le(self.max_le);
+        let response = self.exchange_response(&command)?;
+        match response.status() {
+            // The record is shorter than Le.
+            StatusWord::Success | StatusWord::EndOfFile => Ok(response.into_data()),
+            status => Err(FileSystemError::Status(status)),
+        }
+    }
+
+    fn read_records_p2(&self, p2: u8) -> Result<Vec<Vec<u8>>, FileSystemError> {
+        let mut records = Vec::new();
+        for number in 1..=MAX_RECORD_NUMBER {
+            match self.read_record_p2(number, p2) {
+                Ok(record) => records.push(record),
+                Err(FileSystemError::Status(StatusWord::RecordNotFound)) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Append a record to the current EF.
+    pub fn append_record(&self, data: &[u8]) -> Result<(), FileSystemError> {
+        self.append_record_p2(0x00, data)
+    }
+
+    /// Append a record to the EF with the short EF identifier `sfi`, and
+    /// make it the current EF.
+    pub fn append_record_sfi(&self, sfi: u8, data: &[u8]) -> Result<(), FileSystemError> {
+        self.append_record_p2(check_sfi(sfi)? << 3, data)
+    }
+
+    fn append_record_p2(&self, p2: u8, data: &[u8]) -> Result<(), FileSystemError> {
+        if data.len() > self.max_lc {
+            return Err(FileSystemError::Pcsc(Error::InvalidParameter));
+        }
+        let command = Command::new(0x00, 0xE2, 0x00, p2).with_data(data);
+        self.exchange(&command).map(drop)
+    }
+
+    fn exchange_response(&self, command: &Command) -> Result<Response, FileSystemError> {
+        match self.card.exchange(command) {
+            Ok(response) => Ok(response),
+            Err(ApduError::Pcsc(err)) => Err(FileSystemError::Pcsc(err)),
+            Err(ApduError::InvalidCommand(_)) => Err(FileSystemError::Pcsc(Error::InvalidParameter)),
+            Err(_) => Err(FileSystemError::MalformedResponse),
+        }
+    }
+
+    fn exchange(&self, command: &Command) -> Result<Vec<u8>, FileSystemError> {
+        let response = self.exchange_response(command)?;
+        if response.status() == StatusWord::Success {
+            Ok(response.into_data())
+        } else {
+            Err(FileSystemError::Status(response.status()))
+        }
+    }
+}
+
+/// A transparent EF, as a stream of bytes.
+///
+/// `EfStream` implements `Read`, `Write` and `Seek` with READ BINARY and
+/// UPDATE BINARY, so that the EF can be passed to code which takes a
+/// reader or a writer, such as a certificate parser. Each `read()` and
+/// `write()` sends a single command, of at most `FileSystem::max_le()` or
+/// `FileSystem::max_lc()` bytes, so wrap the stream in a `BufReader` for
+/// many small reads.
+///
+/// If the file control parameters give the size of the EF, reads stop at
+/// its end, writes past it write nothing, and `SeekFrom::End` is relative
+/// to it. Otherwise, reads stop when the card reports the end of the file,
+/// and seeking from the end fails with `ErrorKind::Unsupported`.
+///
+/// Errors are returned as `io::Error`s which wrap the `pcsc::Error` or the
+/// `FileSystemError`.
+///
+/// ## Note
+///
+/// The EF is selected only once, when the stream is opened. Use a
+/// `Transaction` as the card, so that other applications do not select
+/// other files in between.
+///
+/// ```no_run
+/// use std::io::{Read, Seek, SeekFrom};
+///
+/// use pcsc::filesystem::EfStream;
+/// use pcsc::*;
+///
+/// let ctx = Context::establish(Scope::User).unwrap();
+/// let readers = ctx.list_readers_owned().unwrap();
+/// let mut card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+/// let tx = card.transaction().unwrap();
+///
+/// let mut stream = EfStream::open(&tx, 0x4401).unwrap();
+/// let mut header = [0; 4];
+/// stream.read_exact(&mut header).unwrap();
+/// stream.seek(SeekFrom::Start(0)).unwrap();
+/// let mut certificate = Vec::new();
+/// stream.read_to_end(&mut certificate).unwrap();
+/// ```
+pub struct EfStream<'card> {
+    file_system: FileSystem<'card>,
+    file_control: FileControl,
+    position: u64,
+}
+
+impl<'card> EfStream<'card> {
+    /// Select the EF with the file identifier `file_id` of `card`.
+    pub fn open(card: &'card Card, file_id: u16) -> Result<EfStream<'card>, FileSystemError> {
+        let file_system = FileSystem::new(card);
+        let file_control = file_system.select_file_id(file_id)?;
+        EfStream::new(file_system, file_control)
+    }
+
+    /// Select the EF of `card` with the path `path` from the master file.
+    pub fn open_path(card: &'card Card, path: &[u16]) -> Result<EfStream<'card>, FileSystemError> {
+        let file_system = FileSystem::new(card);
+        let file_control = file_system.select_path(path)?;
+        EfStream::new(file_system, file_control)
+    }
+
+    /// Access the current EF of `file_system`, with the file control
+    /// parameters returned when it was selected.
+    ///
+    /// Fails with `FileSystemError::NotTransparent` if the file descriptor
+    /// gives another structure.
+    pub fn new(file_system: FileSystem<'card>, file_control: FileControl) -> Result<EfStream<'card>, FileSystemError> {
+        if let Some(descriptor) = file_control.descriptor {
+            let transparent = matches!(
+                descriptor.structure,
+                FileStructure::Transparent | FileStructure::NoInformation
+            );
+            if descriptor.file_type == FileType::Df || !transparent {
+                return Err(FileSystemError::NotTransparent);
+            }
+        }
+        Ok(EfStream {
+            file_system,
+            file_control,
+            position: 0,
+        })
+    }
+
+    /// The file system of the EF.
+    pub fn file_system(&self) -> &FileSystem<'card> {
+        &self.file_system
+    }
+
+    /// The file control parameters of the EF.
+    pub fn file_control(&self) -> &FileControl {
+        &self.file_control
+    }
+
+    // The position as an offset, and the most bytes which can be accessed
+    // from it.
+    fn offset(&self) -> (usize, usize) {
+        let offset = match usize::try_from(self.position) {
+            Ok(offset) => offset,
+            Err(_) => return (usize::MAX, 0),
+        };
+        let available = match self.file_control.size {
+            Some(size) => size.saturating_sub(offset),
+            None => usize::MAX,
+        };
+        (offset, available)
+    }
+}
+
+impl<'card> Read for EfStream<'card> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (offset, available) = self.offset();
+        let len = buf
+            .len()
+            .min(available)
+            .min(self.file_system.read_chunk_len(None, offset));
+        if len == 0 {
+            return Ok(0);
+        }
+        let data = match self.file_system.read_chunk(None, offset, len) {
+            Ok((data, _)) => data,
+            // Reading at the end of an EF of unknown size.
+            Err(FileSystemError::Status(StatusWord::WrongP1P2)) if self.file_control.size.is_none() => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        let len = data.len().min(len);
+        buf[..len].copy_from_slice(&data[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<'card> Write for EfStream<'card> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (offset, available) = self.offset();
+        let len = buf
+            .len()
+            .min(available)
+            .min(self.file_system.write_chunk_len(None, offset));
+        if len == 0 {
+            return Ok(0);
+        }
+        self.file_system.update_chunk(None, offset, &buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'card> Seek for EfStream<'card> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => add_signed(self.position, delta),
+            SeekFrom::End(delta) => match self.file_control.size {
+                Some(size) => add_signed(size as u64, delta),
+                None => return Err(io::Error::new(ErrorKind::Unsupported, "size of the EF unknown")),
+            },
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+fn add_signed(position: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        position.checked_add(delta as u64)
+    } else {
+        position.checked_sub(delta.unsigned_abs())
+    }
+}
+
+fn check_sfi(sfi: u8) -> Result<u8, FileSystemError> {
+    if (1..=30).contains(&sfi) {
+        Ok(sfi)
+    } else {
+        Err(FileSystemError::InvalidShortFileId(sfi))
+    }
+}
+
+// Whether the even instruction byte can address `offset`.
+fn even_offset(sfi: Option<u8>, offset: usize) -> bool {
+    match sfi {
+        Some(_) => offset <= MAX_SFI_OFFSET,
+        None => offset <= MAX_OFFSET,
+    }
+}
+
+// The offset for the offset data object, without leading zero bytes.
+fn encode_offset(offset: usize) -> Vec<u8> {
+    let bytes = (offset as u64).to_be_bytes();
+    let start = bytes.iter().position(|&byte| byte != 0).unwrap_or(bytes.len() - 1);
+    bytes[start..].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apdu::ApduOptions;
+    use crate::simulator::{test_card, Commands};
+    use std::sync::{Arc, Mutex};
+
+    const AID: &[u8] = &[0xA0, 0x00, 0x00, 0x00, 0x01];
+
+    enum Contents {
+        Df,
+        Binary(Vec<u8>),
+        Records(Vec<Vec<u8>>),
+    }
+
+    struct File {
+        id: u16,
+        sfi: u8,
+        name: Option<&'static [u8]>,
+        // Whether the FCP gives the size of a transparent EF.
+        sized: bool,
+        contents: Contents,
+    }
+
+    // A card with a few files, answering SELECT, READ BINARY, UPDATE
+    // BINARY, READ RECORD and APPEND RECORD.
+    struct TestCard {
+        files: Vec<File>,
+        current: Option<usize>,
+    }
+
+    fn binary(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7) as u8).collect()
+    }
+
+    impl TestCard {
+        fn new() -> TestCard {
+            let file = |id, sfi, sized, contents| File {
+                id,
+                sfi,
+                name: None,
+                sized,
+                contents,
+            };
+            TestCard {
+                files: vec![
+                    file(MASTER_FILE_ID, 0, false, Contents::Df),
+                    File {
+                        name: Some(AID),
+                        ..file(0x5000, 0, false, Contents::Df)
+                    },
+                    file(0x0101, 1, true, Contents::Binary(binary(600))),
+                    file(0x0102, 2, false, Contents::Binary(binary(512))),
+                    file(0x0103, 3, true, Contents::Binary(binary(0x8000 + 100))),
+                    file(
+                        0x0201,
+                        4,
+                        false,
+                        Contents::Records(vec![b"AB".to_vec(), b"CDE".to_vec()]),
+                    ),
+                ],
+                current: None,
+            }
+        }
+
+        fn binary(&self, id: u16) -> &[u8] {
+            match self.files.iter().find(|file| file.id == id).unwrap().contents {
+                Contents::Binary(ref data) => data,
+                _ => panic!("not a transparent EF"),
+            }
+        }
+
+        fn file_control(&self, index: usize) -> Vec<u8> {
+            let file = &self.files[index];
+            let mut value = Vec::new();
+            match file.contents {
+                Contents::Df => tlv::encode(TAG_DESCRIPTOR, &[0x38], &mut value),
+                Contents::Binary(ref data) => {
+                    if file.sized {
+                        tlv::encode(TAG_DATA_SIZE, &(data.len() as u16).to_be_bytes(), &mut value);
+                    }
+                    tlv::encode(TAG_DESCRIPTOR, &[0x01], &mut value);
+                }
+                Contents::Records(_) => tlv::encode(TAG_DESCRIPTOR, &[0x04, 0x21, 0x00, 0x10], &mut value),
+            }
+            tlv::encode(TAG_FILE_ID, &file.id.to_be_bytes(), &mut value);
+            if let Some(name) = file.name {
+                tlv::encode(TAG_DF_NAME, name, &mut value);
+            }
+            if file.sfi != 0 {
+                tlv::encode(TAG_SHORT_FILE_ID, &[file.sfi << 3], &mut value);
+            }
+            tlv::encode(TAG_LIFE_CYCLE, &[0x05], &mut value);
+            let mut bytes = Vec::new();
+            let template = if file.name.is_some() { TAG_FCI } else { TAG_FCP };
+            tlv::encode(template, &value, &mut bytes);
+            bytes
+        }
+
+        // The contents of the file with the short EF identifier `sfi`,
+        // making it the current file, or of the current file for 0.
+        fn contents(&mut self, sfi: u8) -> Result<&mut Contents, StatusWord> {
+            if sfi != 0 {
+                let index = self.files.iter().position(|file| file.sfi == sfi);
+                self.current = Some(index.ok_or(StatusWord::FileNotFound)?);
+            }
+            let index = self.current.ok_or(StatusWord::NoCurrentEf)?;
+            Ok(&mut self.files[index].contents)
+        }
+
+        fn binary_contents(&mut self, sfi: u8) -> Result<&mut Vec<u8>, StatusWord> {
+            match *self.contents(sfi)? {
+                Contents::Binary(ref mut data) => Ok(data),
+                _ => Err(StatusWord::IncompatibleFileStructure),
+            }
+        }
+
+        fn respond(&mut self, command: &Command) -> Vec<u8> {
+            let (mut data, status) = match self.process(command) {
+                Ok(response) => response,
+                Err(status) => (Vec::new(), status),
+            };
+            data.extend_from_slice(&status.to_bytes());
+            data
+        }
+
+        fn process(&mut self, command: &Command) -> Result<(Vec<u8>, StatusWord), StatusWord> {
+            let (p1, p2, data) = (command.p1(), command.p2(), command.data());
+            let le = command.le().unwrap_or(0);
+            match command.ins() {
+                0xA4 => {
+                    let index = match (p1, data) {
+                        (0x00, &[high, low]) | (0x08, &[.., high, low]) => {
+                            let id = u16::from_be_bytes([high, low]);
+                            self.files.iter().position(|file| file.id == id)
+                        }
+                        (0x04, name) => self.files.iter().position(|file| file.name == Some(name)),
+                        _ => return Err(StatusWord::IncorrectP1P2),
+                    };
+                    let index = index.ok_or(StatusWord::FileNotFound)?;
+                    self.current = Some(index);
+                    Ok((self.file_control(index), StatusWord::Success))
+                }
+                0xB0 | 0xD6 => {
+                    let (sfi, offset) = if p1 & 0x80 != 0 {
+                        (p1 & 0x1F, p2 as usize)
+                    } else {
+                        (0, u16::from_be_bytes([p1, p2]) as usize)
+                    };
+                    let contents = self.binary_contents(sfi)?;
+                    if command.ins() == 0xB0 {
+                        read(contents, offset, le)
+                    } else {
+                        write(contents, offset, data)
+                    }
+                }
+                0xB1 | 0xD7 => {
+                    let objects: Vec<Tlv> = tlv::parse(data)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| StatusWord::IncorrectData)?;
+                    let value = |tag| {
+                        objects
+                            .iter()
+                            .find(|object| object.tag() == tag)
+                            .map(|object| object.value())
+                            .ok_or(StatusWord::IncorrectData)
+                    };
+                    let offset = value(TAG_OFFSET)?
+                        .iter()
+                        .fold(0, |offset, &byte| offset << 8 | byte as usize);
+                    let contents = self.binary_contents(p2)?;
+                    if command.ins() == 0xB1 {
+                        // The discretionary data object adds up to 4 bytes.
+                        let (data, status) = read(contents, offset, le - 4)?;
+                        let mut response = Vec::new();
+                        tlv::encode(TAG_DISCRETIONARY_DATA, &data, &mut response);
+                        Ok((response, status))
+                    } else {
+                        write(contents, offset, value(TAG_DISCRETIONARY_DATA)?)
+                    }
+                }
+                0xB2 | 0xE2 => {
+                    let records = match *self.contents(p2 >> 3)? {
+                        Contents::Records(ref mut records) => records,
+                        _ => return Err(StatusWord::IncompatibleFileStructure),
+                    };
+                    if command.ins() == 0xE2 {
+                        records.push(data.to_vec());
+                        return Ok((Vec::new(), StatusWord::Success));
+                    }
+                    assert_eq!(p2 & 0x07, 0x04);
+                    let record = records.get((p1 as usize).wrapping_sub(1));
+                    let record = record.ok_or(StatusWord::RecordNotFound)?;
+                    Ok((record[..record.len().min(le)].to_vec(), StatusWord::Success))
+                }
+                _ => Err(StatusWord::InsNotSupported),
+            }
+        }
+    }
+
+    fn read(contents: &[u8], offset: usize, le: usize) -> Result<(Vec<u8>, StatusWord), StatusWord> {
+        if offset >= contents.len() {
+            return Err(StatusWord::WrongP1P2);
+        }
+        let data = &contents[offset..contents.len().min(offset + le)];
+        let status = if data.len() < le {
+            StatusWord::EndOfFile
+        } else {
+            StatusWord::Success
+        };
+        Ok((data.to_vec(), status))
+    }
+
+    fn write(contents: &mut [u8], offset: usize, data: &[u8]) -> Result<(Vec<u8>, StatusWord), StatusWord> {
+        if offset + data.len() > contents.len() {
+            return Err(StatusWord::WrongP1P2);
+        }
+        contents[offset..offset + data.len()].copy_from_slice(data);
+        Ok((Vec::new(), StatusWord::Success))
+    }
+
+    // Connects to a `TestCard`, recording the commands it receives.
+    fn connect() -> (Card, Arc<Mutex<TestCard>>, Commands) {
+        let state = Arc::new(Mutex::new(TestCard::new()));
+        let card_state = state.clone();
+        let (card, commands) = test_card(move |bytes| {
+            let command = Command::parse(bytes).unwrap();
+            card_state.lock().unwrap().respond(&command)
+        });
+        (card, state, commands)
+    }
+
+    fn take(commands: &Commands) -> Vec<Command> {
+        let commands = std::mem::take(&mut *commands.lock().unwrap());
+        commands.iter().map(|bytes| Command::parse(bytes).unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_file_descriptor() {
+        let descriptor = FileDescriptor::parse(&[0x01]).unwrap();
+        assert_eq!(
+            descriptor,
+            FileDescriptor {
+                descriptor: 0x01,
+                shareable: false,
+                file_type: FileType::WorkingEf,
+                structure: FileStructure::Transparent,
+                data_coding: None,
+                max_record_size: None,
+                record_count: None,
+            }
+        );
+
+        let descriptor = FileDescriptor::parse(&[0x78, 0x21]).unwrap();
+        assert!(descriptor.shareable);
+        assert_eq!(descriptor.file_type, FileType::Df);
+        assert_eq!(descriptor.structure, FileStructure::NoInformation);
+        assert_eq!(descriptor.data_coding, Some(0x21));
+
+        let descriptor = FileDescriptor::parse(&[0x02, 0x21, 0x10, 0x05]).unwrap();
+        assert_eq!(descriptor.structure, FileStructure::LinearFixed);
+        assert!(descriptor.structure.is_record());
+        assert_eq!(descriptor.max_record_size, Some(0x1005));
+        assert_eq!(descriptor.record_count, None);
+
+        let descriptor = FileDescriptor::parse(&[0x06, 0x21, 0x10]).unwrap();
+        assert_eq!(descriptor.structure, FileStructure::Cyclic);
+        assert_eq!(descriptor.max_record_size, Some(0x10));
+        let descriptor = FileDescriptor::parse(&[0x02, 0x21, 0x00, 0x10, 0x05]).unwrap();
+        assert_eq!(descriptor.max_record_size, Some(0x10));
+        assert_eq!(descriptor.record_count, Some(5));
+        let descriptor = FileDescriptor::parse(&[0x02, 0x21, 0x00, 0x10, 0x01, 0x00]).unwrap();
+        assert_eq!(descriptor.record_count, Some(0x100));
+
+        for (byte, file_type, structure) in [
+            (0x0C, FileType::InternalEf, FileStructure::LinearVariable),
+            (0x11, FileType::ProprietaryEf(2), FileStructure::Transparent),
+            (0x39, FileType::WorkingEf, FileStructure::BerTlv),
+            (0x3A, FileType::WorkingEf, FileStructure::SimpleTlv),
+            (0x07, FileType::WorkingEf, FileStructure::CyclicTlv),
+            (0x81, FileType::Other(0x81), FileStructure::NoInformation),
+        ] {
+            let descriptor = FileDescriptor::parse(&[byte]).unwrap();
+            assert_eq!((descriptor.file_type, descriptor.structure), (file_type, structure));
+        }
+        assert!(!FileStructure::Transparent.is_record());
+
+        assert_eq!(FileDescriptor::parse(&[]), Err(FileSystemError::InvalidFileControl));
+        assert_eq!(
+            FileDescriptor::parse(&[0x02, 0x21, 0x00, 0x10, 0x00, 0x01, 0x00]),
+            Err(FileSystemError::InvalidFileControl)
+        );
+    }
+
+    #[test]
+    fn parse_file_control() {
+        let fcp = [
+            0x62, 0x24, // FCP template
+            0x80, 0x02, 0x02, 0x58, // Size
+            0x81, 0x03, 0x01, 0x00, 0x00, // Total size
+            0x82, 0x01, 0x01, // Descriptor
+            0x83, 0x02, 0x01, 0x01, // File identifier
+            0x88, 0x01, 0x08, // Short EF identifier
+            0x8A, 0x01, 0x05, // Life cycle
+            0x8C, 0x03, 0x03, 0x00, 0xFF, // Compact security attributes
+            0x8B, 0x03, 0x2F, 0x06, 0x01, // Referenced security attributes
+            0xA5, 0x02, 0x01, 0x02, // Proprietary information
+        ];
+        let control = FileControl::parse(&fcp).unwrap();
+        assert_eq!(
+            control,
+            FileControl {
+                size: Some(600),
+                total_size: Some(0x10000),
+                descriptor: Some(FileDescriptor::parse(&[0x01]).unwrap()),
+                file_id: Some(0x0101),
+                df_name: None,
+                short_file_id: Some(1),
+                life_cycle: Some(LifeCycle::Activated),
+                security_attributes: vec![
+                    SecurityAttribute::Compact {
+                        access_mode: 0x03,
+                        conditions: vec![0x00, 0xFF],
+                    },
+                    SecurityAttribute::Referenced(vec![0x2F, 0x06, 0x01]),
+                ],
+                other: vec![(Tag::new(0xA5), vec![0x01, 0x02])],
+            }
+        );
+
+        // FCI template, with an empty short EF identifier and other
+        // security attributes.
+        let fci = [
+            0x6F, 0x10, 0x84, 0x05, 0xA0, 0x00, 0x00, 0x00, 0x01, 0x88, 0x00, 0xAB, 0x02, 0x80, 0x01, 0x86, 0x01, 0x00,
+        ];
+        let control = FileControl::parse(&fci).unwrap();
+        assert_eq!(control.df_name.as_deref(), Some(AID));
+        assert_eq!(control.short_file_id, None);
+        assert_eq!(
+            control.security_attributes,
+            [
+                SecurityAttribute::Expanded(vec![0x80, 0x01]),
+                SecurityAttribute::Other(Tag::new(0x86), vec![0x00]),
+            ]
+        );
+
+        let control = FileControl::parse(&[0x64, 0x03, 0x53, 0x01, 0x00]).unwrap();
+        assert_eq!(control.other, [(Tag::new(0x53), vec![0x00])]);
+        assert_eq!(FileControl::parse(&[]).unwrap(), FileControl::default());
+
+        for bytes in [
+            // Not a template.
+            &[0x70, 0x00][..],
+            // Truncated.
+            &[0x62, 0x04, 0x80, 0x02, 0x00],
+            // Invalid values.
+            &[0x62, 0x03, 0x83, 0x01, 0x01],
+            &[0x62, 0x07, 0x80, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00],
+            &[0x62, 0x02, 0x80, 0x00],
+            &[0x62, 0x04, 0x88, 0x02, 0x08, 0x00],
+            &[0x62, 0x02, 0x8A, 0x00],
+            &[0x62, 0x02, 0x8C, 0x00],
+            &[0x62, 0x02, 0x82, 0x00],
+        ] {
+            assert_eq!(
+                FileControl::parse(bytes),
+                Err(FileSystemError::InvalidFileControl),
+                "{:02X?}",
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn life_cycle() {
+        assert_eq!(LifeCycle::from(0x00), LifeCycle::NoInformation);
+        assert_eq!(LifeCycle::from(0x01), LifeCycle::Creation);
+        assert_eq!(LifeCycle::from(0x03), LifeCycle::Initialisation);
+        assert_eq!(LifeCycle::from(0x07), LifeCycle::Activated);
+        assert_eq!(LifeCycle::from(0x04), LifeCycle::Deactivated);
+        assert_eq!(LifeCycle::from(0x0D), LifeCycle::Terminated);
+        assert_eq!(LifeCycle::from(0x80), LifeCycle::Other(0x80));
+    }
+
+    #[test]
+    fn io_errors() {
+        for (err, kind) in [
+            (FileSystemError::Status(StatusWord::FileNotFound), ErrorKind::NotFound),
+            (
+                FileSystemError::Status(StatusWord::SecurityStatusNotSatisfied),
+                ErrorKind::PermissionDenied,
+            ),
+            (FileSystemError::Status(StatusWord::WrongP1P2), ErrorKind::InvalidInput),
+            (
+                FileSystemError::Status(StatusWord::InsNotSupported),
+                ErrorKind::Unsupported,
+            ),
+            (FileSystemError::Status(StatusWord::MemoryFailure), ErrorKind::Other),
+            (FileSystemError::InvalidFileControl, ErrorKind::InvalidData),
+            (FileSystemError::InvalidShortFileId(0), ErrorKind::InvalidInput),
+        ] {
+            let message = err.to_string();
+            let err = io::Error::from(err);
+            assert_eq!(err.kind(), kind);
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn select() {
+        let (card, _, commands) = connect();
+        let fs = FileSystem::new(&card);
+        assert_eq!((fs.max_le(), fs.max_lc()), (MAX_SHORT_LE, MAX_SHORT_DATA));
+
+        let control = fs.select_path(&[MASTER_FILE_ID, 0x0101]).unwrap();
+        assert_eq!(control.file_id, Some(0x0101));
+        assert_eq!(control.size, Some(600));
+        assert_eq!(control.short_file_id, Some(1));
+        assert_eq!(control.life_cycle, Some(LifeCycle::Activated));
+        assert_eq!(control.descriptor.unwrap().structure, FileStructure::Transparent);
+
+        let control = fs.select_path(&[MASTER_FILE_ID]).unwrap();
+        assert_eq!(control.descriptor.unwrap().file_type, FileType::Df);
+
+        let control = fs.select_df_name(AID).unwrap();
+        assert_eq!(control.file_id, Some(0x5000));
+        assert_eq!(control.df_name.as_deref(), Some(AID));
+
+        assert_eq!(
+            fs.select_file_id(0x0999),
+            Err(FileSystemError::Status(StatusWord::FileNotFound))
+        );
+
+        let select = |p1, p2, data: &[u8]| Command::new(0x00, 0xA4, p1, p2).with_data(data).with_le(MAX_SHORT_LE);
+        assert_eq!(
+            take(&commands),
+            [
+                select(0x08, 0x04, &[0x01, 0x01]),
+                select(0x00, 0x04, &[0x3F, 0x00]),
+                select(0x04, 0x00, AID),
+                select(0x00, 0x04, &[0x09, 0x99]),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_binary() {
+        let (card, state, commands) = connect();
+        let fs = FileSystem::new(&card);
+        let read = |p1, p2, le| Command::new(0x00, 0xB0, p1, p2).with_le(le);
+
+        // The size is known from the FCP.
+        let data = fs.read_file(0x0101).unwrap();
+        assert_eq!(data, state.lock().unwrap().binary(0x0101));
+        assert_eq!(
+            take(&commands)[1..],
+            [read(0x00, 0x00, 256), read(0x01, 0x00, 256), read(0x02, 0x00, 88)]
+        );
+
+        let fs = fs.with_max_le(100);
+        let data = fs.read_binary(10, 250).unwrap();
+        assert_eq!(data, state.lock().unwrap().binary(0x0101)[10..260]);
+        assert_eq!(
+            take(&commands),
+            [read(0x00, 0x0A, 100), read(0x00, 0x6E, 100), read(0x00, 0xD2, 50)]
+        );
+
+        // Reading past the end returns fewer bytes.
+        let data = fs.read_binary(550, 100).unwrap();
+        assert_eq!(data.len(), 50);
+        take(&commands);
+
+        // The size is unknown, and a multiple of the chunk size.
+        let fs = FileSystem::new(&card);
+        let data = fs.read_file(0x0102).unwrap();
+        assert_eq!(data, state.lock().unwrap().binary(0x0102));
+        assert_eq!(
+            take(&commands)[1..],
+            [read(0x00, 0x00, 256), read(0x01, 0x00, 256), read(0x02, 0x00, 256)]
+        );
+        assert_eq!(fs.read_to_end(500).unwrap().len(), 12);
+        take(&commands);
+
+        // With a short EF identifier, offsets over 255 use the odd
+        // instruction byte.
+        let data = fs.read_file_sfi(2).unwrap();
+        assert_eq!(data, state.lock().unwrap().binary(0x0102));
+        assert_eq!(
+            take(&commands),
+            [
+                read(0x82, 0x00, 256),
+                Command::new(0x00, 0xB1, 0x00, 0x02)
+                    .with_data(&[0x54, 0x02, 0x01, 0x00])
+                    .with_le(256),
+                Command::new(0x00, 0xB1, 0x00, 0x02)
+                    .with_data(&[0x54, 0x02, 0x01, 0xFC])
+                    .with_le(256),
+            ]
+        );
+
+        let data = fs.read_binary_sfi(1, 0x10, 4).unwrap();
+        assert_eq!(data, state.lock().unwrap().binary(0x0101)[0x10..0x14]);
+        assert_eq!(take(&commands).last(), Some(&read(0x81, 0x10, 4)));
+
+        for sfi in [0, 31] {
+            assert_eq!(
+                fs.read_binary_sfi(sfi, 0, 1),
+                Err(FileSystemError::InvalidShortFileId(sfi))
+            );
+        }
+        fs.select_file_id(0x0201).unwrap();
+        assert_eq!(
+            fs.read_binary(0, 1),
+            Err(FileSystemError::Status(StatusWord::IncompatibleFileStructure))
+        );
+    }
+
+    #[test]
+    fn odd_instruction() {
+        let (card, state, commands) = connect();
+        let fs = FileSystem::new(&card);
+        fs.select_file_id(0x0103).unwrap();
+        take(&commands);
+
+        // Offsets over 32767 use READ BINARY with the odd instruction byte.
+        let data = fs.read_binary(0x8000, 100).unwrap();
+        assert_eq!(data, state.lock().unwrap().binary(0x0103)[0x8000..]);
+        assert_eq!(
+            take(&commands),
+            [Command::new(0x00, 0xB1, 0x00, 0x00)
+                .with_data(&[0x54, 0x02, 0x80, 0x00])
+                .with_le(116)]
+        );
+
+        // Reads across 32767 switch to it.
+        let data = fs.read_binary(0x7F80, 0x100).unwrap();
+        assert_eq!(data, state.lock().unwrap().binary(0x0103)[0x7F80..0x8064]);
+        assert_eq!(take(&commands)[0], Command::new(0x00, 0xB0, 0x7F, 0x80).with_le(256));
+
+        fs.update_binary(0x800A, &[0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(state.lock().unwrap().binary(0x0103)[0x800A..0x800D], [0x01, 0x02, 0x03]);
+        assert_eq!(
+            take(&commands),
+            [Command::new(0x00, 0xD7, 0x00, 0x00).with_data(&[0x54, 0x02, 0x80, 0x0A, 0x53, 0x03, 0x01, 0x02, 0x03])]
+        );
+
+        // With a short EF identifier, offsets over 255 do.
+        let data = fs.read_binary_sfi(1, 0x100, 8).unwrap();
+        assert_eq!(data, state.lock().unwrap().binary(0x0101)[0x100..0x108]);
+        assert_eq!(
+            take(&commands),
+            [Command::new(0x00, 0xB1, 0x00, 0x01)
+                .with_data(&[0x54, 0x02, 0x01, 0x00])
+                .with_le(24)]
+        );
+        fs.update_binary_sfi(1, 0x100, &[0xFF]).unwrap();
+        assert_eq!(state.lock().unwrap().binary(0x0101)[0x100], 0xFF);
+    }
+
+    #[test]
+    fn update_binary() {
+        let (card, state, commands) = connect();
+        let fs = FileSystem::new(&card);
+        fs.select_file_id(0x0101).unwrap();
+        take(&commands);
+
+        let data = vec![0x55; 300];
+        fs.update_binary(0x10, &data).unwrap();
+        assert_eq!(state.lock().unwrap().binary(0x0101)[0x10..0x10 + 300], data[..]);
+        assert_eq!(
+            take(&commands),
+            [
+                Command::new(0x00, 0xD6, 0x00, 0x10).with_data(&data[..255]),
+                Command::new(0x00, 0xD6, 0x01, 0x0F).with_data(&data[255..]),
+            ]
+        );
+
+        let fs = fs.with_max_lc(16);
+        fs.update_binary_sfi(2, 0, &data[..20]).unwrap();
+        assert_eq!(state.lock().unwrap().binary(0x0102)[..20], data[..20]);
+        assert_eq!(
+            take(&commands),
+            [
+                Command::new(0x00, 0xD6, 0x82, 0x00).with_data(&data[..16]),
+                Command::new(0x00, 0xD6, 0x82, 0x10).with_data(&data[16..20]),
+            ]
+        );
+
+        assert_eq!(
+            fs.update_binary(600, &[0x00]),
+            Err(FileSystemError::Status(StatusWord::WrongP1P2))
+        );
+    }
+
+    #[test]
+    fn records() {
+        let (card, _, commands) = connect();
+        let fs = FileSystem::new(&card);
+        let control = fs.select_file_id(0x0201).unwrap();
+        let descriptor = control.descriptor.unwrap();
+        assert_eq!(descriptor.structure, FileStructure::LinearVariable);
+        assert_eq!(descriptor.max_record_size, Some(0x10));
+        take(&commands);
+
+        assert_eq!(fs.read_records().unwrap(), [b"AB".to_vec(), b"CDE".to_vec()]);
+        let read = |number, p2| Command::new(0x00, 0xB2, number, p2).with_le(256);
+        assert_eq!(take(&commands), [read(1, 0x04), read(2, 0x04), read(3, 0x04)]);
+
+        assert_eq!(fs.read_record(2).unwrap(), b"CDE");
+        assert_eq!(
+            fs.read_record(3),
+            Err(FileSystemError::Status(StatusWord::RecordNotFound))
+        );
+        take(&commands);
+
+        fs.append_record(b"FG").unwrap();
+        fs.append_record_sfi(4, b"HIJ").unwrap();
+        assert_eq!(
+            take(&commands),
+            [
+                Command::new(0x00, 0xE2, 0x00, 0x00).with_data(b"FG"),
+                Command::new(0x00, 0xE2, 0x00, 0x20).with_data(b"HIJ"),
+            ]
+        );
+        assert_eq!(fs.read_record_sfi(4, 4).unwrap(), b"HIJ");
+        assert_eq!(take(&commands), [read(4, 0x24)]);
+        assert_eq!(fs.read_records_sfi(4).unwrap().len(), 4);
+
+        assert_eq!(
+            fs.append_record(&[0; MAX_SHORT_DATA + 1]),
+            Err(FileSystemError::Pcsc(Error::InvalidParameter))
+        );
+        assert_eq!(fs.read_record_sfi(31, 1), Err(FileSystemError::InvalidShortFileId(31)));
+    }
+
+    #[test]
+    fn ef_stream() {
+        let (card, state, _) = connect();
+        let expected = state.lock().unwrap().binary(0x0101).to_vec();
+
+        let mut stream = EfStream::open(&card, 0x0101).unwrap();
+        assert_eq!(stream.file_control().size, Some(600));
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        assert_eq!(data, expected);
+
+        assert_eq!(stream.seek(SeekFrom::End(-10)).unwrap(), 590);
+        let mut buf = [0; 16];
+        assert_eq!(stream.read(&mut buf).unwrap(), 10);
+        assert_eq!(buf[..10], expected[590..]);
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        assert_eq!(stream.write(&[0x00]).unwrap(), 0);
+
+        assert_eq!(stream.seek(SeekFrom::Start(2)).unwrap(), 2);
+        stream.write_all(&[0xAA, 0xBB]).unwrap();
+        assert_eq!(
+            state.lock().unwrap().binary(0x0101)[..4],
+            [expected[0], expected[1], 0xAA, 0xBB]
+        );
+        assert_eq!(stream.seek(SeekFrom::Current(-4)).unwrap(), 0);
+        let err = stream.seek(SeekFrom::Current(-1)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // The size is unknown.
+        let mut stream = EfStream::open_path(&card, &[MASTER_FILE_ID, 0x0102]).unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 512);
+        let err = stream.seek(SeekFrom::End(0)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+        assert_eq!(
+            EfStream::open(&card, 0x0201).err(),
+            Some(FileSystemError::NotTransparent)
+        );
+        assert_eq!(
+            EfStream::open(&card, MASTER_FILE_ID).err(),
+            Some(FileSystemError::NotTransparent)
+        );
+        let err = io::Error::from(EfStream::open(&card, 0x0999).err().unwrap());
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn ef_stream_max_response_len() {
+        let (mut card, state, commands) = connect();
+        card.set_apdu_options(ApduOptions::new().with_max_response_len(128));
+        let expected = state.lock().unwrap().binary(0x0101).to_vec();
+
+        let mut stream = EfStream::open(&card, 0x0101).unwrap();
+        take(&commands);
+        // Reads larger than the limit are split into READ BINARY commands of at most 128 bytes.
+        let mut data = Vec::new();
+        let mut buf = [0; 256];
+        loop {
+            let len = stream.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            data.extend_from_slice(&buf[..len]);
+        }
+        assert_eq!(data, expected);
+        let reads = take(&commands);
+        assert_eq!(reads.len(), (expected.len() + 127) / 128);
+        for (index, command) in reads.iter().enumerate() {
+            let offset = index * 128;
+            let [p1, p2] = (offset as u16).to_be_bytes();
+            let le = (expected.len() - offset).min(128);
+            assert_eq!(*command, Command::new(0x00, 0xB0, p1, p2).with_le(le));
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// Hex encoding helpers, used by the text formats in this crate.
+
+use std::fmt::Write;
+
+/// Encode bytes as uppercase hex digits, without separators.
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(s, "{:02X}", byte);
+    }
+    s
+}
+
+/// Decode hex digits, ignoring ASCII whitespace.
+///
+/// Returns `None` if `s` contains a non-hex character or an odd number of
+/// digits.
+pub(crate) fn decode(s: &str) -> Option<Vec<u8>> {
+    let digits = s
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace())
+        .map(digit)
+        .collect::<Option<Vec<u8>>>()?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+fn digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
@@ -98,6 +98,17 @@
 //! See [MSDN][8] for more details.
 //!
 //! [8]: https://msdn.microsoft.com/en-us/library/ms953432.aspx#smartcardcspcook_topic2
+//!
+//! ## Backends
+//!
+//! All PC/SC calls go through a [`Backend`](backend/trait.Backend.html).
+//! By default, the platform's PC/SC implementation is used. A different
+//! backend can be passed to `Context::establish_with_backend()`, for
+//! example to test code without a PC/SC service or card reader. See the
+//! [`backend`](backend/index.html) module.
+//!
+//! The [`simulator`](simulator/index.html) module provides a backend with
+//! virtual readers and cards.
 #![allow(deprecated)]
 #![allow(clippy::bad_bit_mask)]
 
@@ -105,7 +116,7 @@ use std::ffi::{CStr, CString};
 use std::mem::{forget, transmute};
 use std::ops::Deref;
 use std::os::raw::c_char;
-use std::ptr::{null, null_mut};
+use std::ptr::null_mut;
 use std::sync::Arc;
 
 use bitflags::bitflags;
@@ -113,14 +124,29 @@ pub use pcsc_sys as ffi;
 
 use ffi::{DWORD, LONG};
 
-// We use these instead of std::mem::uninitialized -- variables which are
-// set to this are always overridden and the dummy values are never exposed.
-const DUMMY_LONG: LONG = -1;
-const DUMMY_DWORD: DWORD = 0xdead_beef;
-
-// Note on potentially problematic casts (clippy lints `cast-sign-loss`,
-// `cast-possible-truncation`): from my analysis they are all OK, for
-// both 32bit and 64bit DWORD/LONG. But it is sketchy.
+pub mod apdu;
+pub mod atr;
+pub mod atr_list;
+pub mod attributes;
+pub mod backend;
+pub mod contactless;
+pub mod events;
+pub mod features;
+pub mod filesystem;
+pub mod monitor;
+pub mod ndef;
+pub mod pin;
+pub mod simulator;
+pub mod storage;
+pub mod tlv;
+pub mod trace;
+pub mod transparent;
+pub mod type2;
+pub mod type4;
+
+mod hex;
+
+pub use backend::{Backend, SystemBackend};
 
 bitflags! {
     /// A mask of the state a card reader.
@@ -375,15 +401,10 @@ pub enum Error {
 }
 
 impl Error {
-    #[allow(clippy::manual_range_contains)]
     fn from_raw(raw: LONG) -> Error {
-        unsafe {
-            // The ranges here are the "blocks" above.
-            if ffi::SCARD_F_INTERNAL_ERROR <= raw && raw <= ffi::SCARD_E_SERVER_TOO_BUSY
-                || ffi::SCARD_W_UNSUPPORTED_CARD <= raw && raw <= ffi::SCARD_W_CACHE_ITEM_TOO_BIG
-            {
-                transmute::<u32, Error>(raw as u32)
-            } else {
+        match Error::try_from_raw(raw) {
+            Some(err) => err,
+            None => {
                 if cfg!(debug_assertions) {
                     panic!("unknown PCSC error code: {:#x}", raw);
                 }
@@ -394,9 +415,18 @@ impl Error {
         }
     }
 
-    fn into_raw(self) -> LONG {
-        // Note: not using LONG::from() - won't work when LONG is i32.
-        self as u32 as LONG
+    #[allow(clippy::manual_range_contains)]
+    fn try_from_raw(raw: LONG) -> Option<Error> {
+        unsafe {
+            // The ranges here are the "blocks" above.
+            if ffi::SCARD_F_INTERNAL_ERROR <= raw && raw <= ffi::SCARD_E_SERVER_TOO_BUSY
+                || ffi::SCARD_W_UNSUPPORTED_CARD <= raw && raw <= ffi::SCARD_W_CACHE_ITEM_TOO_BIG
+            {
+                Some(transmute::<u32, Error>(raw as u32))
+            } else {
+                None
+            }
+        }
     }
 }
 
@@ -486,13 +516,46 @@ impl std::fmt::Display for Error {
     }
 }
 
-macro_rules! try_pcsc {
-    ($e:expr) => {
-        match $e {
-            ffi::SCARD_S_SUCCESS => (),
-            err => return Err(Error::from_raw(err)),
-        }
-    };
+impl From<Error> for std::io::Error {
+    /// Wrap the error in an `io::Error`, with the closest `ErrorKind`, or
+    /// `ErrorKind::Other` if there is none.
+    fn from(err: Error) -> std::io::Error {
+        use std::io::ErrorKind;
+        let kind = match err {
+            Error::Timeout | Error::WaitedTooLong => ErrorKind::TimedOut,
+            Error::InvalidHandle | Error::InvalidParameter | Error::InvalidValue => ErrorKind::InvalidInput,
+            Error::NoMemory => ErrorKind::OutOfMemory,
+            Error::NoSmartcard
+            | Error::RemovedCard
+            | Error::UnpoweredCard
+            | Error::UnresponsiveCard
+            | Error::ReaderUnavailable
+            | Error::NoReadersAvailable
+            | Error::NoService
+            | Error::ServiceStopped => ErrorKind::NotConnected,
+            Error::ResetCard => ErrorKind::ConnectionReset,
+            Error::UnknownReader
+            | Error::UnknownCard
+            | Error::DirNotFound
+            | Error::FileNotFound
+            | Error::NoDir
+            | Error::NoFile
+            | Error::NoSuchCertificate => ErrorKind::NotFound,
+            Error::NoAccess
+            | Error::SecurityViolation
+            | Error::WrongChv
+            | Error::ChvBlocked
+            | Error::CardNotAuthenticated => ErrorKind::PermissionDenied,
+            Error::UnsupportedFeature
+            | Error::ReaderUnsupported
+            | Error::CardUnsupported
+            | Error::UnsupportedCard
+            | Error::ProtoMismatch => ErrorKind::Unsupported,
+            Error::Eof => ErrorKind::UnexpectedEof,
+            _ => ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err)
+    }
 }
 
 /// Scope of a context.
@@ -627,20 +690,9 @@ pub struct ReaderState {
     inner: ffi::SCARD_READERSTATE,
 }
 
-// For some reason, linking in windows fails if we put these directly
-// in statics. This is why we have this function instead of the
-// SCARD_PCI_* defines from the C API.
-fn get_protocol_pci(protocol: Protocol) -> &'static ffi::SCARD_IO_REQUEST {
-    unsafe {
-        match protocol {
-            Protocol::T0 => &ffi::g_rgSCardT0Pci,
-            Protocol::T1 => &ffi::g_rgSCardT1Pci,
-            Protocol::RAW => &ffi::g_rgSCardRawPci,
-        }
-    }
-}
-
 struct ContextInner {
+    backend: Arc<dyn Backend>,
+    scope: Scope,
     handle: ffi::SCARDCONTEXT,
 }
 
@@ -656,9 +708,10 @@ pub struct Context {
 /// This structure wraps `SCARDHANDLE`.
 pub struct Card {
     // Keeps the context alive.
-    _context: Context,
+    context: Context,
     handle: ffi::SCARDHANDLE,
     active_protocol: Option<Protocol>,
+    apdu_options: apdu::ApduOptions,
 }
 
 /// An exclusive transaction with a card.
@@ -711,20 +764,42 @@ impl Context {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#gaa1b8970169fd4883a6dc4a8f43f19b67
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379479.aspx
     pub fn establish(scope: Scope) -> Result<Context, Error> {
-        unsafe {
-            let mut handle: ffi::SCARDCONTEXT = DUMMY_LONG as ffi::SCARDCONTEXT;
-
-            try_pcsc!(ffi::SCardEstablishContext(
-                scope.into_raw(),
-                null(),
-                null(),
-                &mut handle,
-            ));
-
-            Ok(Context {
-                inner: Arc::new(ContextInner { handle }),
-            })
-        }
+        Context::establish_with_backend(SystemBackend, scope)
+    }
+
+    /// Establish a new context using the given backend.
+    ///
+    /// The context, and all cards connected through it, dispatch to
+    /// `backend` instead of the platform's PC/SC implementation. See the
+    /// [`backend`](backend/index.html) module.
+    pub fn establish_with_backend<B: Backend + 'static>(backend: B, scope: Scope) -> Result<Context, Error> {
+        let handle = backend.establish(scope)?;
+
+        Ok(Context {
+            inner: Arc::new(ContextInner {
+                backend: Arc::new(backend),
+                scope,
+                handle,
+            }),
+        })
+    }
+
+    // Establish a new context, with the same backend and scope.
+    pub(crate) fn establish_sibling(&self) -> Result<Context, Error> {
+        let backend = Arc::clone(&self.inner.backend);
+        let handle = backend.establish(self.inner.scope)?;
+
+        Ok(Context {
+            inner: Arc::new(ContextInner {
+                backend,
+                scope: self.inner.scope,
+                handle,
+            }),
+        })
+    }
+
+    fn backend(&self) -> &dyn Backend {
+        &*self.inner.backend
     }
 
     /// Release the context.
@@ -749,18 +824,19 @@ impl Context {
     pub fn release(self) -> Result<(), (Context, Error)> {
         match Arc::try_unwrap(self.inner) {
             Ok(inner) => {
-                unsafe {
-                    let err = ffi::SCardReleaseContext(inner.handle);
-                    if err != ffi::SCARD_S_SUCCESS {
-                        let context = Context { inner: Arc::new(inner) };
-                        return Err((context, Error::from_raw(err)));
-                    }
+                if let Err(err) = inner.backend.release(inner.handle) {
+                    let context = Context { inner: Arc::new(inner) };
+                    return Err((context, err));
+                }
 
-                    // Skip the drop, we did it "manually".
+                // Skip the drop, we did it "manually".
+                unsafe {
+                    let backend = std::ptr::read(&inner.backend);
                     forget(inner);
-
-                    Ok(())
+                    drop(backend);
                 }
+
+                Ok(())
             }
             Err(arc_inner) => {
                 let context = Context { inner: arc_inner };
@@ -777,11 +853,7 @@ impl Context {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#ga722eb66bcc44d391f700ff9065cc080b
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379788.aspx
     pub fn is_valid(&self) -> Result<(), Error> {
-        unsafe {
-            try_pcsc!(ffi::SCardIsValidContext(self.inner.handle,));
-
-            Ok(())
-        }
+        self.backend().is_valid(self.inner.handle)
     }
 
     /// Cancel any ongoing blocking operation in the Context.
@@ -793,11 +865,7 @@ impl Context {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#gaacbbc0c6d6c0cbbeb4f4debf6fbeeee6
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379470.aspx
     pub fn cancel(&self) -> Result<(), Error> {
-        unsafe {
-            try_pcsc!(ffi::SCardCancel(self.inner.handle,));
-
-            Ok(())
-        }
+        self.backend().cancel(self.inner.handle)
     }
 
     /// List all connected card readers.
@@ -817,37 +885,26 @@ impl Context {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#ga93b07815789b3cf2629d439ecf20f0d9
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379793.aspx
     pub fn list_readers<'buf>(&self, buffer: &'buf mut [u8]) -> Result<ReaderNames<'buf>, Error> {
-        unsafe {
-            assert!(buffer.len() <= std::u32::MAX as usize);
-            let mut buflen = buffer.len() as DWORD;
-            // SCardListReaders treats null specially, to query the needed
-            // buffer length. We don't want the caller to be able to trigger
-            // this here -- should use `list_readers_len` instead. So needs
-            // some special treatment.
-            let bufptr = if buflen == 0 {
-                null_mut()
-            } else {
-                let ptr = buffer.as_mut_ptr() as *mut c_char;
-                assert!(!ptr.is_null());
-                ptr
-            };
-
-            let err = ffi::SCardListReaders(self.inner.handle, null(), bufptr, &mut buflen);
-            if err == Error::NoReadersAvailable.into_raw() {
-                return Ok(ReaderNames { buf: b"\0", pos: 0 });
-            }
-            if err != ffi::SCARD_S_SUCCESS {
-                return Err(Error::from_raw(err));
-            }
-            if bufptr.is_null() {
-                return Err(Error::InsufficientBuffer);
-            }
-
-            Ok(ReaderNames {
-                buf: &buffer[..buflen as usize],
-                pos: 0,
-            })
+        // SCardListReaders treats null specially, to query the needed
+        // buffer length. We don't want the caller to be able to trigger
+        // this here -- should use `list_readers_len` instead. So needs
+        // some special treatment.
+        let query_len = buffer.is_empty();
+        let bufopt = if query_len { None } else { Some(&mut buffer[..]) };
+
+        let buflen = match self.backend().list_readers(self.inner.handle, bufopt) {
+            Ok(buflen) => buflen,
+            Err(Error::NoReadersAvailable) => return Ok(ReaderNames { buf: b"\0", pos: 0 }),
+            Err(err) => return Err(err),
+        };
+        if query_len {
+            return Err(Error::InsufficientBuffer);
         }
+
+        Ok(ReaderNames {
+            buf: &buffer[..buflen],
+            pos: 0,
+        })
     }
 
     /// Get the needed length of a buffer to be passed to `list_readers`.
@@ -857,18 +914,10 @@ impl Context {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#ga93b07815789b3cf2629d439ecf20f0d9
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379793.aspx
     pub fn list_readers_len(&self) -> Result<usize, Error> {
-        unsafe {
-            let mut buflen = DUMMY_DWORD;
-
-            let err = ffi::SCardListReaders(self.inner.handle, null(), null_mut(), &mut buflen);
-            if err == Error::NoReadersAvailable.into_raw() {
-                return Ok(0);
-            }
-            if err != ffi::SCARD_S_SUCCESS {
-                return Err(Error::from_raw(err));
-            }
-
-            Ok(buflen as usize)
+        match self.backend().list_readers(self.inner.handle, None) {
+            Ok(buflen) => Ok(buflen),
+            Err(Error::NoReadersAvailable) => Ok(0),
+            Err(err) => Err(err),
         }
     }
 
@@ -898,27 +947,16 @@ impl Context {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#ga4e515829752e0a8dbc4d630696a8d6a5
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379473.aspx
     pub fn connect(&self, reader: &CStr, share_mode: ShareMode, preferred_protocols: Protocols) -> Result<Card, Error> {
-        unsafe {
-            let mut handle: ffi::SCARDHANDLE = DUMMY_LONG as ffi::SCARDHANDLE;
-            let mut raw_active_protocol: DWORD = DUMMY_DWORD;
-
-            try_pcsc!(ffi::SCardConnect(
-                self.inner.handle,
-                reader.as_ptr(),
-                share_mode.into_raw(),
-                preferred_protocols.bits(),
-                &mut handle,
-                &mut raw_active_protocol,
-            ));
-
-            let active_protocol = Protocol::from_raw(raw_active_protocol);
-
-            Ok(Card {
-                _context: self.clone(),
-                handle,
-                active_protocol,
-            })
-        }
+        let (handle, active_protocol) =
+            self.backend()
+                .connect(self.inner.handle, reader, share_mode, preferred_protocols)?;
+
+        Ok(Card {
+            context: self.clone(),
+            handle,
+            active_protocol,
+            apdu_options: apdu::ApduOptions::default(),
+        })
     }
 
     /// Wait for card and card reader state changes.
@@ -943,39 +981,16 @@ impl Context {
     where
         D: Into<Option<std::time::Duration>>,
     {
-        let timeout_ms = match timeout.into() {
-            Some(duration) => {
-                let timeout_ms_u64 = duration
-                    .as_secs()
-                    .saturating_mul(1000)
-                    .saturating_add(u64::from(duration.subsec_nanos()) / 1_000_000);
-                std::cmp::min(ffi::INFINITE, timeout_ms_u64 as DWORD)
-            }
-            None => ffi::INFINITE,
-        };
-
-        unsafe {
-            assert!(readers.len() <= std::u32::MAX as usize);
-
-            try_pcsc!(ffi::SCardGetStatusChange(
-                self.inner.handle,
-                timeout_ms,
-                readers.as_mut_ptr() as *mut ffi::SCARD_READERSTATE,
-                readers.len() as DWORD,
-            ));
-
-            Ok(())
-        }
+        self.backend()
+            .get_status_change(self.inner.handle, timeout.into(), readers)
     }
 }
 
 impl Drop for ContextInner {
     fn drop(&mut self) {
-        unsafe {
-            // Error is ignored here; to do proper error handling,
-            // release() should be called manually.
-            let _err = ffi::SCardReleaseContext(self.handle);
-        }
+        // Error is ignored here; to do proper error handling,
+        // release() should be called manually.
+        let _err = self.backend.release(self.handle);
     }
 }
 
@@ -1050,6 +1065,29 @@ impl ReaderState {
         // https://stackoverflow.com/a/16467368
         self.inner.dwCurrentState = self.inner.dwEventState;
     }
+
+    /// Set the reported state and card event count.
+    ///
+    /// This is intended for `Backend` implementations of
+    /// `get_status_change()`; it sets what `event_state()` and
+    /// `event_count()` return.
+    pub fn set_event_state(&mut self, event_state: State, event_count: u32) {
+        self.inner.dwEventState = event_state.bits() | (DWORD::from(event_count & 0xFFFF) << 16);
+    }
+
+    /// Set the reported ATR of the card inserted to the reader.
+    ///
+    /// This is intended for `Backend` implementations of
+    /// `get_status_change()`; it sets what `atr()` returns.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `atr` is longer than `MAX_ATR_SIZE`.
+    pub fn set_atr(&mut self, atr: &[u8]) {
+        assert!(atr.len() <= MAX_ATR_SIZE);
+        self.inner.rgbAtr[..atr.len()].copy_from_slice(atr);
+        self.inner.cbAtr = atr.len() as DWORD;
+    }
 }
 
 impl Drop for ReaderState {
@@ -1066,7 +1104,7 @@ unsafe impl Sync for ReaderState {}
 #[derive(Clone, Debug)]
 pub struct CardStatus<'names_buf, 'atr_buf> {
     reader_names: ReaderNames<'names_buf>,
-    state: DWORD,
+    status: Status,
     protocol: Option<Protocol>,
     atr: &'atr_buf [u8],
 }
@@ -1079,7 +1117,7 @@ impl<'names_buf, 'atr_buf> CardStatus<'names_buf, 'atr_buf> {
 
     /// Current status of the smart card in the reader.
     pub fn status(&self) -> Status {
-        Status::from_raw(self.state)
+        self.status
     }
 
     /// Current protocol of the card, if any.
@@ -1119,7 +1157,7 @@ impl<'names_buf, 'atr_buf> CardStatus<'names_buf, 'atr_buf> {
 #[derive(Clone, Debug)]
 pub struct CardStatusOwned {
     reader_names: Vec<CString>,
-    state: DWORD,
+    status: Status,
     protocol: Option<Protocol>,
     atr: Vec<u8>,
 }
@@ -1132,7 +1170,7 @@ impl CardStatusOwned {
 
     /// Current status of the smart card in the reader.
     pub fn status(&self) -> Status {
-        Status::from_raw(self.state)
+        self.status
     }
 
     /// Current protocol of the card, if any.
@@ -1177,12 +1215,10 @@ impl Card {
     ///
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#gaddb835dce01a0da1d6ca02d33ee7d861
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379469.aspx
-    pub fn transaction(&mut self) -> Result<Transaction, Error> {
-        unsafe {
-            try_pcsc!(ffi::SCardBeginTransaction(self.handle,));
+    pub fn transaction(&mut self) -> Result<Transaction<'_>, Error> {
+        self.backend().begin_transaction(self.handle)?;
 
-            Ok(Transaction { card: self })
-        }
+        Ok(Transaction { card: self })
     }
 
     /// Start a new exclusive transaction with the card.
@@ -1203,15 +1239,12 @@ impl Card {
     ///
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#gaddb835dce01a0da1d6ca02d33ee7d861
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379469.aspx
-    pub fn transaction2(&mut self) -> Result<Transaction, (&mut Self, Error)> {
-        unsafe {
-            let err = ffi::SCardBeginTransaction(self.handle);
-            if err != ffi::SCARD_S_SUCCESS {
-                return Err((self, Error::from_raw(err)));
-            }
-
-            Ok(Transaction { card: self })
+    pub fn transaction2(&mut self) -> Result<Transaction<'_>, (&mut Self, Error)> {
+        if let Err(err) = self.backend().begin_transaction(self.handle) {
+            return Err((self, err));
         }
+
+        Ok(Transaction { card: self })
     }
 
     /// Reconnect to the card.
@@ -1226,21 +1259,11 @@ impl Card {
         preferred_protocols: Protocols,
         initialization: Disposition,
     ) -> Result<(), Error> {
-        unsafe {
-            let mut raw_active_protocol: DWORD = DUMMY_DWORD;
-
-            try_pcsc!(ffi::SCardReconnect(
-                self.handle,
-                share_mode.into_raw(),
-                preferred_protocols.bits(),
-                initialization.into_raw(),
-                &mut raw_active_protocol,
-            ));
+        self.active_protocol =
+            self.backend()
+                .reconnect(self.handle, share_mode, preferred_protocols, initialization)?;
 
-            self.active_protocol = Protocol::from_raw(raw_active_protocol);
-
-            Ok(())
-        }
+        Ok(())
     }
 
     /// Disconnect from the card.
@@ -1259,18 +1282,17 @@ impl Card {
     /// function if you want to handle errors or use a different
     /// disposition method.
     pub fn disconnect(mut self, disposition: Disposition) -> Result<(), (Card, Error)> {
-        unsafe {
-            let err = ffi::SCardDisconnect(self.handle, disposition.into_raw());
-            if err != ffi::SCARD_S_SUCCESS {
-                return Err((self, Error::from_raw(err)));
-            }
-
-            // Skip the drop, we did it "manually".
-            std::ptr::drop_in_place(&mut self._context);
-            forget(self);
+        if let Err(err) = self.backend().disconnect(self.handle, disposition) {
+            return Err((self, err));
+        }
 
-            Ok(())
+        // Skip the drop, we did it "manually".
+        unsafe {
+            std::ptr::drop_in_place(&mut self.context);
         }
+        forget(self);
+
+        Ok(())
     }
 
     /// Get current info on the card.
@@ -1290,27 +1312,13 @@ impl Card {
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379803.aspx
     #[deprecated(since = "2.3.0", note = "Use status2() or status2_owned() instead.")]
     pub fn status(&self) -> Result<(Status, Protocol), Error> {
-        unsafe {
-            let mut raw_status: DWORD = DUMMY_DWORD;
-            let mut raw_protocol: DWORD = DUMMY_DWORD;
+        let raw_status = self.backend().status(self.handle, None, None)?;
 
-            try_pcsc!(ffi::SCardStatus(
-                self.handle,
-                null_mut(),
-                null_mut(),
-                &mut raw_status,
-                &mut raw_protocol,
-                null_mut(),
-                null_mut(),
-            ));
+        let protocol = raw_status
+            .protocol
+            .expect("pcsc::Card::status() does not support direct connections; use status2() instead");
 
-            let status = Status::from_raw(raw_status);
-
-            let protocol = Protocol::from_raw(raw_protocol)
-                .expect("pcsc::Card::status() does not support direct connections; use status2() instead");
-
-            Ok((status, protocol))
-        }
+        Ok((raw_status.status, protocol))
     }
 
     /// Get current info on the card.
@@ -1337,34 +1345,19 @@ impl Card {
         names_buffer: &'names_buf mut [u8],
         atr_buffer: &'atr_buf mut [u8],
     ) -> Result<CardStatus<'names_buf, 'atr_buf>, Error> {
-        unsafe {
-            assert!(names_buffer.len() <= std::u32::MAX as usize);
-            let mut names_len: DWORD = names_buffer.len() as DWORD;
-            let mut raw_state: DWORD = DUMMY_DWORD;
-            let mut raw_protocol: DWORD = DUMMY_DWORD;
-            assert!(atr_buffer.len() <= std::u32::MAX as usize);
-            let mut atr_len: DWORD = atr_buffer.len() as DWORD;
-
-            try_pcsc!(ffi::SCardStatus(
-                self.handle,
-                names_buffer.as_mut_ptr() as *mut c_char,
-                &mut names_len,
-                &mut raw_state,
-                &mut raw_protocol,
-                atr_buffer.as_mut_ptr(),
-                &mut atr_len,
-            ));
-
-            Ok(CardStatus {
-                reader_names: ReaderNames {
-                    buf: &names_buffer[..names_len as usize],
-                    pos: 0,
-                },
-                state: raw_state,
-                protocol: Protocol::from_raw(raw_protocol),
-                atr: &atr_buffer[0..atr_len as usize],
-            })
-        }
+        let raw_status = self
+            .backend()
+            .status(self.handle, Some(&mut names_buffer[..]), Some(&mut atr_buffer[..]))?;
+
+        Ok(CardStatus {
+            reader_names: ReaderNames {
+                buf: &names_buffer[..raw_status.names_len],
+                pos: 0,
+            },
+            status: raw_status.status,
+            protocol: raw_status.protocol,
+            atr: &atr_buffer[0..raw_status.atr_len],
+        })
     }
 
     /// Get the needed length of the names buffer (first result) and ATR buffer
@@ -1375,24 +1368,9 @@ impl Card {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#gae49c3c894ad7ac12a5b896bde70d0382
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379803.aspx
     pub fn status2_len(&self) -> Result<(usize, usize), Error> {
-        unsafe {
-            let mut names_len: DWORD = DUMMY_DWORD;
-            let mut raw_state: DWORD = DUMMY_DWORD;
-            let mut raw_protocol: DWORD = DUMMY_DWORD;
-            let mut atr_len: DWORD = DUMMY_DWORD;
-
-            try_pcsc!(ffi::SCardStatus(
-                self.handle,
-                null_mut(),
-                &mut names_len,
-                &mut raw_state,
-                &mut raw_protocol,
-                null_mut(),
-                &mut atr_len,
-            ));
-
-            Ok((names_len as usize, atr_len as usize))
-        }
+        let raw_status = self.backend().status(self.handle, None, None)?;
+
+        Ok((raw_status.names_len, raw_status.atr_len))
     }
 
     /// Get current info on the card, allocating buffers of the required size.
@@ -1408,12 +1386,12 @@ impl Card {
         let mut names_buffer = vec![0u8; names_len];
         let mut atr_buffer = vec![0u8; atr_len];
 
-        let (reader_names, state, protocol, atr_len) = {
+        let (reader_names, status, protocol, atr_len) = {
             let card_status = self.status2(&mut names_buffer, &mut atr_buffer)?;
             let reader_names = card_status.reader_names.map(ToOwned::to_owned).collect();
             (
                 reader_names,
-                card_status.state,
+                card_status.status,
                 card_status.protocol,
                 card_status.atr.len(),
             )
@@ -1423,7 +1401,7 @@ impl Card {
 
         Ok(CardStatusOwned {
             reader_names,
-            state,
+            status,
             protocol,
             atr: atr_buffer,
         })
@@ -1445,33 +1423,19 @@ impl Card {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#gaacfec51917255b7a25b94c5104961602
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379559.aspx
     pub fn get_attribute<'buf>(&self, attribute: Attribute, buffer: &'buf mut [u8]) -> Result<&'buf [u8], Error> {
-        unsafe {
-            assert!(buffer.len() <= std::u32::MAX as usize);
-            let mut attribute_len = buffer.len() as DWORD;
-            // SCardGetAttribtreats null specially, to query the needed
-            // buffer length. We don't want the caller to be able to trigger
-            // this here -- should use `get_attribute_len` instead. So needs
-            // some special treatment.
-            let bufptr = if attribute_len == 0 {
-                null_mut()
-            } else {
-                let ptr = buffer.as_mut_ptr();
-                assert!(!ptr.is_null());
-                ptr
-            };
-
-            try_pcsc!(ffi::SCardGetAttrib(
-                self.handle,
-                attribute.into_raw(),
-                buffer.as_mut_ptr(),
-                &mut attribute_len,
-            ));
-            if bufptr.is_null() && attribute_len > 0 {
-                return Err(Error::InsufficientBuffer);
-            }
-
-            Ok(&buffer[0..attribute_len as usize])
+        // SCardGetAttrib treats null specially, to query the needed
+        // buffer length. We don't want the caller to be able to trigger
+        // this here -- should use `get_attribute_len` instead. So the
+        // buffer is always passed, and an empty buffer needs some special
+        // treatment.
+        let attribute_len = self
+            .backend()
+            .get_attribute(self.handle, attribute, Some(&mut buffer[..]))?;
+        if buffer.is_empty() && attribute_len > 0 {
+            return Err(Error::InsufficientBuffer);
         }
+
+        Ok(&buffer[0..attribute_len])
     }
 
     /// Get the needed length of a buffer to be passed to `get_attribute`.
@@ -1481,18 +1445,7 @@ impl Card {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#gaacfec51917255b7a25b94c5104961602
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379559.aspx
     pub fn get_attribute_len(&self, attribute: Attribute) -> Result<usize, Error> {
-        unsafe {
-            let mut attribute_len = DUMMY_DWORD;
-
-            try_pcsc!(ffi::SCardGetAttrib(
-                self.handle,
-                attribute.into_raw(),
-                null_mut(),
-                &mut attribute_len,
-            ));
-
-            Ok(attribute_len as usize)
-        }
+        self.backend().get_attribute(self.handle, attribute, None)
     }
 
     /// Get an attribute of the card or card reader, allocating a buffer of the required size.
@@ -1521,18 +1474,7 @@ impl Card {
     /// [1]: https://pcsclite.apdu.fr/api/group__API.html#ga060f0038a4ddfd5dd2b8fadf3c3a2e4f
     /// [2]: https://msdn.microsoft.com/en-us/library/aa379801.aspx
     pub fn set_attribute(&self, attribute: Attribute, attribute_data: &[u8]) -> Result<(), Error> {
-        unsafe {
-            assert!(attribute_data.len() <= std::u32::MAX as usize);
-
-            try_pcsc!(ffi::SCardSetAttrib(
-                self.handle,
-                attribute.into_raw(),
-                attribute_data.as_ptr(),
-                attribute_data.len() as DWORD,
-            ));
-
-            Ok(())
-        }
+        self.backend().set_attribute(self.handle, attribute, attribute_data)
     }
 
     /// Transmit an APDU command to the card.
@@ -1591,31 +1533,12 @@ impl Card {
         let active_protocol = self
             .active_protocol
             .expect("pcsc::Card::transmit() does not work with direct connections");
-        let send_pci = get_protocol_pci(active_protocol);
-        let recv_pci = null_mut();
-        assert!(receive_buffer.len() <= std::u32::MAX as usize);
-        let mut receive_len = receive_buffer.len() as DWORD;
 
-        unsafe {
-            assert!(send_buffer.len() <= std::u32::MAX as usize);
-
-            let r = ffi::SCardTransmit(
-                self.handle,
-                send_pci,
-                send_buffer.as_ptr(),
-                send_buffer.len() as DWORD,
-                recv_pci,
-                receive_buffer.as_mut_ptr(),
-                &mut receive_len,
-            );
-
-            match r {
-                ffi::SCARD_S_SUCCESS => (),
-                err => return Err((Error::from_raw(err), receive_len as usize)),
-            }
+        let receive_len = self
+            .backend()
+            .transmit(self.handle, active_protocol, send_buffer, receive_buffer)?;
 
-            Ok(&receive_buffer[0..receive_len as usize])
-        }
+        Ok(&receive_buffer[0..receive_len])
     }
 
     /// Sends a command directly to the reader (driver).
@@ -1644,37 +1567,26 @@ impl Card {
         send_buffer: &[u8],
         receive_buffer: &'buf mut [u8],
     ) -> Result<&'buf [u8], Error> {
-        let mut receive_len: DWORD = DUMMY_DWORD;
+        let receive_len = self
+            .backend()
+            .control(self.handle, control_code, send_buffer, receive_buffer)?;
 
-        unsafe {
-            assert!(send_buffer.len() <= std::u32::MAX as usize);
-            assert!(receive_buffer.len() <= std::u32::MAX as usize);
-
-            try_pcsc!(ffi::SCardControl(
-                self.handle,
-                control_code,
-                send_buffer.as_ptr(),
-                send_buffer.len() as DWORD,
-                receive_buffer.as_mut_ptr(),
-                receive_buffer.len() as DWORD,
-                &mut receive_len,
-            ));
-
-            Ok(&receive_buffer[0..receive_len as usize])
-        }
+        Ok(&receive_buffer[0..receive_len])
+    }
+
+    fn backend(&self) -> &dyn Backend {
+        self.context.backend()
     }
 }
 
 impl Drop for Card {
     fn drop(&mut self) {
-        unsafe {
-            // Error is ignored here; to do proper error handling,
-            // disconnect() should be called manually.
-            //
-            // Disposition is hard-coded to ResetCard here; to use
-            // another method, disconnect() should be called manually.
-            let _err = ffi::SCardDisconnect(self.handle, Disposition::ResetCard.into_raw());
-        }
+        // Error is ignored here; to do proper error handling,
+        // disconnect() should be called manually.
+        //
+        // Disposition is hard-coded to ResetCard here; to use
+        // another method, disconnect() should be called manually.
+        let _err = self.backend().disconnect(self.handle, Disposition::ResetCard);
     }
 }
 
@@ -1700,17 +1612,14 @@ impl<'tx> Transaction<'tx> {
     /// this function if you want to handle errors or use a different
     /// disposition method.
     pub fn end(self, disposition: Disposition) -> Result<(), (Transaction<'tx>, Error)> {
-        unsafe {
-            let err = ffi::SCardEndTransaction(self.card.handle, disposition.into_raw());
-            if err != 0 {
-                return Err((self, Error::from_raw(err)));
-            }
+        if let Err(err) = self.card.backend().end_transaction(self.card.handle, disposition) {
+            return Err((self, err));
+        }
 
-            // Skip the drop, we did it "manually".
-            forget(self);
+        // Skip the drop, we did it "manually".
+        forget(self);
 
-            Ok(())
-        }
+        Ok(())
     }
 
     /// Reconnect to the card.
@@ -1731,14 +1640,15 @@ impl<'tx> Transaction<'tx> {
 
 impl<'tx> Drop for Transaction<'tx> {
     fn drop(&mut self) {
-        unsafe {
-            // Error is ignored here; to do proper error handling,
-            // end() should be called manually.
-            //
-            // Disposition is hard-coded to LeaveCard here; to use
-            // another method, end() should be called manually.
-            let _err = ffi::SCardEndTransaction(self.card.handle, Disposition::LeaveCard.into_raw());
-        }
+        // Error is ignored here; to do proper error handling,
+        // end() should be called manually.
+        //
+        // Disposition is hard-coded to LeaveCard here; to use
+        // another method, end() should be called manually.
+        let _err = self
+            .card
+            .backend()
+            .end_transaction(self.card.handle, Disposition::LeaveCard);
     }
 }
 
@@ -0,0 +1,322 @@
+//! Blocking monitoring of reader and card events.
+//!
+//! A [`ReaderMonitor`](struct.ReaderMonitor.html) encapsulates the
+//! bookkeeping needed to watch all readers with
+//! `Context::get_status_change()`: it tracks the `ReaderState`s, including
+//! the `PNP_NOTIFICATION()` pseudo-reader, adds new readers and removes dead
+//! ones, and turns state changes into typed
+//! [`ReaderEvent`](../events/enum.ReaderEvent.html)s.
+//!
+//! ```no_run
+//! use pcsc::monitor::ReaderMonitor;
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).expect("failed to establish context");
+//! let mut monitor = ReaderMonitor::new(&ctx);
+//! loop {
+//!     for event in monitor.poll(None).expect("failed to poll") {
+//!         println!("{:?}", event);
+//!     }
+//! }
+//! ```
+//!
+//! For an async interface, see the [`events`](../events/index.html) module.
+
+use std::time::{Duration, Instant};
+
+use crate::events::ReaderEvent;
+use crate::{Context, Error, ReaderState, State, PNP_NOTIFICATION};
+
+// How often to rescan the readers, when the PC/SC service does not support
+// PNP_NOTIFICATION.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+fn is_dead(rs: &ReaderState) -> bool {
+    rs.event_state().intersects(State::UNKNOWN | State::IGNORE)
+}
+
+// Derive the events from a reader's previous and new state.
+fn diff_states(rs: &ReaderState, previous_count: u32, events: &mut Vec<ReaderEvent>) {
+    let old = rs.current_state();
+    let new = rs.event_state();
+    let reader = || rs.name().to_owned();
+
+    let mut was_present = old.contains(State::PRESENT);
+    let now_present = new.contains(State::PRESENT);
+    // The event count changes once for each insertion and removal, so a
+    // difference of 2 or more means events were missed in between.
+    let missed = !old.is_empty() && rs.event_count().wrapping_sub(previous_count) & 0xFFFF >= 2;
+    if missed && was_present && now_present {
+        events.push(ReaderEvent::CardRemoved { reader: reader() });
+        was_present = false;
+    } else if missed && !was_present && !now_present {
+        events.push(ReaderEvent::CardInserted {
+            reader: reader(),
+            atr: Vec::new(),
+        });
+        events.push(ReaderEvent::CardRemoved { reader: reader() });
+    }
+
+    if was_present && !now_present {
+        events.push(ReaderEvent::CardRemoved { reader: reader() });
+    } else if now_present {
+        let was_mute = was_present && old.contains(State::MUTE);
+        let was_responsive = was_present && !old.contains(State::MUTE);
+        if new.contains(State::MUTE) {
+            if !was_mute {
+                events.push(ReaderEvent::CardMuted { reader: reader() });
+            }
+        } else if !was_responsive {
+            events.push(ReaderEvent::CardInserted {
+                reader: reader(),
+                atr: rs.atr().to_vec(),
+            });
+        }
+    }
+
+    if new.contains(State::INUSE) && !old.contains(State::INUSE) {
+        events.push(ReaderEvent::ReaderInUse { reader: reader() });
+    }
+}
+
+/// Watches all readers of a context for changes.
+///
+/// The first call to `poll()` reports a `ReaderAdded` event for each
+/// connected reader, followed by a `CardInserted` event for each inserted
+/// card.
+///
+/// If the PC/SC service does not support `PNP_NOTIFICATION()`, the monitor
+/// falls back to rescanning the readers periodically.
+pub struct ReaderMonitor {
+    context: Context,
+    // The first element is always PNP_NOTIFICATION().
+    reader_states: Vec<ReaderState>,
+    previous_counts: Vec<u32>,
+    pnp_supported: bool,
+}
+
+impl ReaderMonitor {
+    /// Create a monitor for the readers of `context`.
+    pub fn new(context: &Context) -> ReaderMonitor {
+        ReaderMonitor {
+            context: context.clone(),
+            reader_states: vec![ReaderState::new(PNP_NOTIFICATION(), State::UNAWARE)],
+            previous_counts: Vec::new(),
+            pnp_supported: true,
+        }
+    }
+
+    /// The context the monitor watches.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// The states of the currently known readers, as of the last `poll()`.
+    pub fn reader_states(&self) -> &[ReaderState] {
+        &self.reader_states[1..]
+    }
+
+    /// Whether the PC/SC service supports `PNP_NOTIFICATION()`.
+    ///
+    /// This is only known after the first `poll()`.
+    pub fn pnp_supported(&self) -> bool {
+        self.pnp_supported
+    }
+
+    /// Wait for reader or card events.
+    ///
+    /// `timeout` is the maximum time to wait; `None` waits indefinitely.
+    /// Returns the events that happened, which is empty if the timeout
+    /// expired first.
+    ///
+    /// `Context::cancel()` interrupts the wait, in which case
+    /// `Error::Cancelled` is returned.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> Result<Vec<ReaderEvent>, Error> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            let mut events = self.update_readers()?;
+
+            // If readers came or went, only pick up the states of the new
+            // readers, without waiting.
+            let wait = if !events.is_empty() {
+                Some(Duration::from_secs(0))
+            } else {
+                let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                match (remaining, self.pnp_supported) {
+                    (Some(remaining), false) if remaining > RESCAN_INTERVAL => Some(RESCAN_INTERVAL),
+                    (None, false) => Some(RESCAN_INTERVAL),
+                    (remaining, _) => remaining,
+                }
+            };
+
+            self.previous_counts.clear();
+            for rs in &mut self.reader_states {
+                self.previous_counts.push(rs.event_count());
+                rs.sync_current_state();
+            }
+
+            match self.context.get_status_change(wait, &mut self.reader_states) {
+                Ok(()) => {
+                    self.diff(&mut events);
+                }
+                Err(Error::Timeout) => {}
+                Err(err) => return Err(err),
+            }
+
+            let expired = deadline.map_or(false, |deadline| Instant::now() >= deadline);
+            if !events.is_empty() || expired {
+                return Ok(events);
+            }
+        }
+    }
+
+    // Remove dead readers and add new ones.
+    fn update_readers(&mut self) -> Result<Vec<ReaderEvent>, Error> {
+        let mut events = Vec::new();
+
+        for rs in &self.reader_states[1..] {
+            if is_dead(rs) {
+                events.push(ReaderEvent::ReaderRemoved {
+                    reader: rs.name().to_owned(),
+                });
+            }
+        }
+        let mut index = 0;
+        self.reader_states.retain(|rs| {
+            index += 1;
+            index == 1 || !is_dead(rs)
+        });
+
+        for name in self.context.list_readers_owned()? {
+            if !self.reader_states.iter().any(|rs| rs.name() == name.as_c_str()) {
+                events.push(ReaderEvent::ReaderAdded { reader: name.clone() });
+                self.reader_states.push(ReaderState::new(name, State::UNAWARE));
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn diff(&mut self, events: &mut Vec<ReaderEvent>) {
+        // Without PNP_NOTIFICATION support, the pseudo-reader is reported
+        // as unknown. It stays in the list, and is not reported again once
+        // its current state is synced.
+        if self.reader_states[0].event_state().contains(State::UNKNOWN) {
+            self.pnp_supported = false;
+        }
+
+        for (rs, &previous_count) in self.reader_states[1..].iter().zip(&self.previous_counts[1..]) {
+            if !is_dead(rs) && rs.event_state().contains(State::CHANGED) {
+                diff_states(rs, previous_count, events);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, VirtualCard};
+    use crate::Scope;
+    use std::ffi::CString;
+
+    const READER: &str = "Virtual Reader 00 00";
+    const ATR: &[u8] = &[0x3B, 0x00];
+
+    fn card() -> VirtualCard {
+        VirtualCard::new(ATR, |_command: &[u8]| Ok(vec![0x90, 0x00]))
+    }
+
+    fn reader() -> CString {
+        CString::new(READER).unwrap()
+    }
+
+    fn poll(monitor: &mut ReaderMonitor) -> Vec<ReaderEvent> {
+        monitor.poll(Some(Duration::from_secs(1))).unwrap()
+    }
+
+    fn assert_idle(monitor: &mut ReaderMonitor) {
+        assert_eq!(monitor.poll(Some(Duration::from_secs(0))).unwrap(), []);
+    }
+
+    #[test]
+    fn events() {
+        let sim = Simulator::new();
+        sim.add_reader(READER).unwrap();
+        sim.insert_card(READER, card()).unwrap();
+        let ctx = Context::establish_with_backend(sim.clone(), Scope::User).unwrap();
+        let mut monitor = ReaderMonitor::new(&ctx);
+
+        assert_eq!(
+            poll(&mut monitor),
+            [
+                ReaderEvent::ReaderAdded { reader: reader() },
+                ReaderEvent::CardInserted {
+                    reader: reader(),
+                    atr: ATR.to_vec()
+                },
+            ]
+        );
+        assert!(monitor.pnp_supported());
+        assert_idle(&mut monitor);
+
+        sim.remove_card(READER).unwrap();
+        assert_eq!(poll(&mut monitor), [ReaderEvent::CardRemoved { reader: reader() }]);
+
+        sim.insert_card(READER, VirtualCard::mute()).unwrap();
+        assert_eq!(poll(&mut monitor), [ReaderEvent::CardMuted { reader: reader() }]);
+
+        sim.add_reader("Virtual Reader 01 00").unwrap();
+        assert_eq!(
+            poll(&mut monitor),
+            [ReaderEvent::ReaderAdded {
+                reader: CString::new("Virtual Reader 01 00").unwrap()
+            }]
+        );
+        assert_idle(&mut monitor);
+
+        sim.remove_reader(READER).unwrap();
+        assert_eq!(poll(&mut monitor), [ReaderEvent::ReaderRemoved { reader: reader() }]);
+        assert_eq!(monitor.reader_states().len(), 1);
+    }
+
+    #[test]
+    fn missed_events() {
+        let sim = Simulator::new();
+        sim.add_reader(READER).unwrap();
+        sim.insert_card(READER, card()).unwrap();
+        let ctx = Context::establish_with_backend(sim.clone(), Scope::User).unwrap();
+        let mut monitor = ReaderMonitor::new(&ctx);
+        poll(&mut monitor);
+
+        // Removed and inserted again.
+        sim.insert_card(READER, card()).unwrap();
+        assert_eq!(
+            poll(&mut monitor),
+            [
+                ReaderEvent::CardRemoved { reader: reader() },
+                ReaderEvent::CardInserted {
+                    reader: reader(),
+                    atr: ATR.to_vec()
+                },
+            ]
+        );
+
+        // Inserted and removed again.
+        sim.remove_card(READER).unwrap();
+        poll(&mut monitor);
+        sim.insert_card(READER, card()).unwrap();
+        sim.remove_card(READER).unwrap();
+        assert_eq!(
+            poll(&mut monitor),
+            [
+                ReaderEvent::CardInserted {
+                    reader: reader(),
+                    atr: Vec::new()
+                },
+                ReaderEvent::CardRemoved { reader: reader() },
+            ]
+        );
+        assert_idle(&mut monitor);
+    }
+}
@@ -0,0 +1,644 @@
+//! NFC Forum NDEF messages and records.
+//!
+//! NFC tags store their data as an NDEF message, a sequence of records
+//! which each have a type name format ([`Tnf`](enum.Tnf.html)), a type, an
+//! optional ID and a payload. [`Message`](struct.Message.html) parses and
+//! serializes messages, reassembling chunked records, and
+//! [`Record`](struct.Record.html) builds and decodes the common record
+//! types: URI, Text, MIME and external types.
+//!
+//! The messages are read from and written to tags with the
+//! [`type4`](../type4/index.html) module.
+//!
+//! ```
+//! use pcsc::ndef::{Message, Record};
+//!
+//! let message = Message::new(vec![
+//!     Record::uri("https://example.com/asset/42"),
+//!     Record::text("Asset 42", "en"),
+//! ]);
+//! let bytes = message.to_bytes();
+//! let parsed = Message::parse(&bytes).unwrap();
+//! assert_eq!(parsed.records()[0].to_uri().unwrap(), "https://example.com/asset/42");
+//! assert_eq!(parsed.records()[1].to_text().unwrap().text, "Asset 42");
+//! ```
+
+use std::error;
+use std::fmt;
+
+/// An error parsing an NDEF message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NdefError {
+    /// A record extends past the end of the data.
+    Truncated,
+    /// The first record does not have the Message Begin flag, or another
+    /// record has it.
+    InvalidMessageBegin,
+    /// The data ends before a record with the Message End flag.
+    MissingMessageEnd,
+    /// There are more bytes after the record with the Message End flag.
+    TrailingBytes(usize),
+    /// The chunks of a chunked record are not valid.
+    InvalidChunk,
+}
+
+impl fmt::Display for NdefError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NdefError::Truncated => f.write_str("truncated NDEF record"),
+            NdefError::InvalidMessageBegin => f.write_str("invalid NDEF Message Begin flag"),
+            NdefError::MissingMessageEnd => f.write_str("missing NDEF Message End flag"),
+            NdefError::TrailingBytes(count) => write!(f, "{} trailing bytes after the NDEF message", count),
+            NdefError::InvalidChunk => f.write_str("invalid NDEF record chunk"),
+        }
+    }
+}
+
+impl error::Error for NdefError {}
+
+// The maximum length of a record type or ID, which have a 1-byte length.
+const MAX_TYPE_LEN: usize = 0xFF;
+
+// The flags of the record header.
+const MB: u8 = 0x80;
+const ME: u8 = 0x40;
+const CF: u8 = 0x20;
+const SR: u8 = 0x10;
+const IL: u8 = 0x08;
+
+/// The type name format of a record, which tells how its type is
+/// interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tnf {
+    /// An empty record, with no type, ID or payload.
+    Empty,
+    /// An NFC Forum well-known type, such as `U` (URI) or `T` (Text).
+    WellKnown,
+    /// A media type, as defined in RFC 2046, such as `text/plain`.
+    Media,
+    /// An absolute URI, as defined in RFC 3986.
+    AbsoluteUri,
+    /// An NFC Forum external type, `domain:type`.
+    External,
+    /// A payload of unknown type.
+    Unknown,
+    /// A middle or last chunk of a chunked record. Parsed messages never
+    /// contain such records; their chunks are reassembled.
+    Unchanged,
+    /// A reserved value.
+    Reserved,
+}
+
+impl Tnf {
+    fn from_bits(bits: u8) -> Tnf {
+        match bits & 0x07 {
+            0x00 => Tnf::Empty,
+            0x01 => Tnf::WellKnown,
+            0x02 => Tnf::Media,
+            0x03 => Tnf::AbsoluteUri,
+            0x04 => Tnf::External,
+            0x05 => Tnf::Unknown,
+            0x06 => Tnf::Unchanged,
+            _ => Tnf::Reserved,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Tnf::Empty => 0x00,
+            Tnf::WellKnown => 0x01,
+            Tnf::Media => 0x02,
+            Tnf::AbsoluteUri => 0x03,
+            Tnf::External => 0x04,
+            Tnf::Unknown => 0x05,
+            Tnf::Unchanged => 0x06,
+            Tnf::Reserved => 0x07,
+        }
+    }
+}
+
+// The abbreviations of the URI record, by identifier code.
+const URI_PREFIXES: [&str; 36] = [
+    "",
+    "http://www.",
+    "https://www.",
+    "http://",
+    "https://",
+    "tel:",
+    "mailto:",
+    "ftp://anonymous:anonymous@",
+    "ftp://ftp.",
+    "ftps://",
+    "sftp://",
+    "smb://",
+    "nfs://",
+    "ftp://",
+    "dav://",
+    "news:",
+    "telnet://",
+    "imap:",
+    "rtsp://",
+    "urn:",
+    "pop:",
+    "sip:",
+    "sips:",
+    "tftp:",
+    "btspp://",
+    "btl2cap://",
+    "btgoep://",
+    "tcpobex://",
+    "irdaobex://",
+    "file://",
+    "urn:epc:id:",
+    "urn:epc:tag:",
+    "urn:epc:pat:",
+    "urn:epc:raw:",
+    "urn:epc:",
+    "urn:nfc:",
+];
+
+/// The content of a Text record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Text {
+    /// The IANA language code, such as `en` or `fr-CA`.
+    pub language: String,
+    /// The text.
+    pub text: String,
+}
+
+/// An NDEF record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Record {
+    tnf: Tnf,
+    record_type: Vec<u8>,
+    id: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl Record {
+    /// Create a record from its type name format, type and payload.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `record_type` is longer than 255 bytes, or
+    /// `payload` is longer than `u32::MAX` bytes.
+    pub fn new(tnf: Tnf, record_type: &[u8], payload: &[u8]) -> Record {
+        assert!(record_type.len() <= MAX_TYPE_LEN);
+        assert!(payload.len() as u64 <= u64::from(u32::MAX));
+        Record {
+            tnf,
+            record_type: record_type.to_vec(),
+            id: Vec::new(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    /// Create an empty record.
+    pub fn empty() -> Record {
+        Record::new(Tnf::Empty, &[], &[])
+    }
+
+    /// Create a URI record (well-known type `U`), abbreviating the URI
+    /// prefix.
+    pub fn uri(uri: &str) -> Record {
+        // The longest matching prefix, skipping the empty one.
+        let (code, prefix) = URI_PREFIXES
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|&(_, prefix)| uri.starts_with(prefix))
+            .max_by_key(|&(_, prefix)| prefix.len())
+            .unwrap_or((0, &""));
+        let mut payload = vec![code as u8];
+        payload.extend_from_slice(&uri.as_bytes()[prefix.len()..]);
+        Record::new(Tnf::WellKnown, b"U", &payload)
+    }
+
+    /// Create a Text record (well-known type `T`), encoded in UTF-8.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `language` is longer than 63 bytes.
+    pub fn text(text: &str, language: &str) -> Record {
+        assert!(language.len() <= 0x3F);
+        let mut payload = vec![language.len() as u8];
+        payload.extend_from_slice(language.as_bytes());
+        payload.extend_from_slice(text.as_bytes());
+        Record::new(Tnf::WellKnown, b"T", &payload)
+    }
+
+    /// Create a MIME record, with a media type such as `text/vcard`.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `media_type` is longer than 255 bytes.
+    pub fn mime(media_type: &str, payload: &[u8]) -> Record {
+        Record::new(Tnf::Media, media_type.as_bytes(), payload)
+    }
+
+    /// Create an external type record, with a type such as
+    /// `example.com:asset`.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `external_type` is longer than 255 bytes.
+    pub fn external(external_type: &str, payload: &[u8]) -> Record {
+        Record::new(Tnf::External, external_type.as_bytes(), payload)
+    }
+
+    /// Set the ID of the record, a URI.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `id` is longer than 255 bytes.
+    pub fn with_id(mut self, id: &[u8]) -> Record {
+        assert!(id.len() <= MAX_TYPE_LEN);
+        self.id = id.to_vec();
+        self
+    }
+
+    /// The type name format of the record.
+    pub fn tnf(&self) -> Tnf {
+        self.tnf
+    }
+
+    /// The type of the record, interpreted according to its type name
+    /// format.
+    pub fn record_type(&self) -> &[u8] {
+        &self.record_type
+    }
+
+    /// The ID of the record, empty if it has none.
+    pub fn id(&self) -> &[u8] {
+        &self.id
+    }
+
+    /// The payload of the record.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    fn is_well_known(&self, record_type: &[u8]) -> bool {
+        self.tnf == Tnf::WellKnown && self.record_type == record_type
+    }
+
+    /// Decode a URI record, expanding the URI prefix.
+    ///
+    /// Returns `None` if this is not a URI record, or if the URI is not
+    /// valid UTF-8.
+    pub fn to_uri(&self) -> Option<String> {
+        if !self.is_well_known(b"U") {
+            return None;
+        }
+        let (&code, rest) = self.payload.split_first()?;
+        // Reserved identifier codes are treated as no abbreviation.
+        let prefix = URI_PREFIXES.get(code as usize).unwrap_or(&"");
+        let rest = std::str::from_utf8(rest).ok()?;
+        Some(format!("{}{}", prefix, rest))
+    }
+
+    /// Decode a Text record, in UTF-8 or UTF-16.
+    ///
+    /// Returns `None` if this is not a Text record, or if it is not valid.
+    pub fn to_text(&self) -> Option<Text> {
+        if !self.is_well_known(b"T") {
+            return None;
+        }
+        let (&status, rest) = self.payload.split_first()?;
+        let language_len = (status & 0x3F) as usize;
+        if rest.len() < language_len {
+            return None;
+        }
+        let (language, text) = rest.split_at(language_len);
+        let language = std::str::from_utf8(language).ok()?.to_owned();
+        let text = if status & 0x80 == 0 {
+            std::str::from_utf8(text).ok()?.to_owned()
+        } else {
+            decode_utf16(text)?
+        };
+        Some(Text { language, text })
+    }
+
+    /// The media type of a MIME record.
+    pub fn media_type(&self) -> Option<&str> {
+        match self.tnf {
+            Tnf::Media => std::str::from_utf8(&self.record_type).ok(),
+            _ => None,
+        }
+    }
+
+    /// The type of an external type record.
+    pub fn external_type(&self) -> Option<&str> {
+        match self.tnf {
+            Tnf::External => std::str::from_utf8(&self.record_type).ok(),
+            _ => None,
+        }
+    }
+}
+
+// Decode UTF-16 text, big-endian unless a byte order mark says otherwise.
+fn decode_utf16(bytes: &[u8]) -> Option<String> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let (little_endian, bytes) = match *bytes {
+        [0xFF, 0xFE, ref rest @ ..] => (true, rest),
+        [0xFE, 0xFF, ref rest @ ..] => (false, rest),
+        _ => (false, bytes),
+    };
+    let units = bytes.chunks(2).map(|unit| {
+        if little_endian {
+            u16::from_le_bytes([unit[0], unit[1]])
+        } else {
+            u16::from_be_bytes([unit[0], unit[1]])
+        }
+    });
+    std::char::decode_utf16(units).collect::<Result<String, _>>().ok()
+}
+
+// A record as encoded, possibly a chunk.
+struct RawRecord<'a> {
+    flags: u8,
+    tnf: Tnf,
+    record_type: &'a [u8],
+    id: &'a [u8],
+    payload: &'a [u8],
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], NdefError> {
+    if bytes.len() < len {
+        return Err(NdefError::Truncated);
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn parse_raw_record<'a>(bytes: &mut &'a [u8]) -> Result<RawRecord<'a>, NdefError> {
+    let header = take(bytes, 1)?[0];
+    let type_len = take(bytes, 1)?[0] as usize;
+    let payload_len = if header & SR != 0 {
+        take(bytes, 1)?[0] as usize
+    } else {
+        let len = take(bytes, 4)?;
+        u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize
+    };
+    let id_len = if header & IL != 0 {
+        take(bytes, 1)?[0] as usize
+    } else {
+        0
+    };
+    Ok(RawRecord {
+        flags: header & !0x07,
+        tnf: Tnf::from_bits(header),
+        record_type: take(bytes, type_len)?,
+        id: take(bytes, id_len)?,
+        payload: take(bytes, payload_len)?,
+    })
+}
+
+fn encode_raw_record(raw: &RawRecord, out: &mut Vec<u8>) {
+    let mut header = raw.flags | raw.tnf.bits();
+    if raw.payload.len() <= 0xFF {
+        header |= SR;
+    }
+    if !raw.id.is_empty() {
+        header |= IL;
+    }
+    out.push(header);
+    out.push(raw.record_type.len() as u8);
+    if raw.payload.len() <= 0xFF {
+        out.push(raw.payload.len() as u8);
+    } else {
+        out.extend_from_slice(&(raw.payload.len() as u32).to_be_bytes());
+    }
+    if !raw.id.is_empty() {
+        out.push(raw.id.len() as u8);
+    }
+    out.extend_from_slice(raw.record_type);
+    out.extend_from_slice(raw.id);
+    out.extend_from_slice(raw.payload);
+}
+
+/// An NDEF message.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Message {
+    records: Vec<Record>,
+}
+
+impl Message {
+    /// Create a message from its records.
+    pub fn new(records: Vec<Record>) -> Message {
+        Message { records }
+    }
+
+    /// Parse an NDEF message, reassembling chunked records.
+    ///
+    /// Empty data gives a message with no records.
+    pub fn parse(mut bytes: &[u8]) -> Result<Message, NdefError> {
+        let mut records = Vec::new();
+        let mut chunked: Option<Record> = None;
+        let mut first = true;
+        while !bytes.is_empty() {
+            let raw = parse_raw_record(&mut bytes)?;
+            if (raw.flags & MB != 0) != first {
+                return Err(NdefError::InvalidMessageBegin);
+            }
+            first = false;
+            match chunked.take() {
+                Some(mut record) => {
+                    if raw.tnf != Tnf::Unchanged || !raw.record_type.is_empty() || !raw.id.is_empty() {
+                        return Err(NdefError::InvalidChunk);
+                    }
+                    record.payload.extend_from_slice(raw.payload);
+                    if raw.flags & CF != 0 {
+                        chunked = Some(record);
+                    } else {
+                        records.push(record);
+                    }
+                }
+                None => {
+                    if raw.tnf == Tnf::Unchanged {
+                        return Err(NdefError::InvalidChunk);
+                    }
+                    let record = Record::new(raw.tnf, raw.record_type, raw.payload).with_id(raw.id);
+                    if raw.flags & CF != 0 {
+                        chunked = Some(record);
+                    } else {
+                        records.push(record);
+                    }
+                }
+            }
+            if raw.flags & ME != 0 {
+                if chunked.is_some() {
+                    return Err(NdefError::InvalidChunk);
+                }
+                if !bytes.is_empty() {
+                    return Err(NdefError::TrailingBytes(bytes.len()));
+                }
+                return Ok(Message { records });
+            }
+        }
+        if first {
+            Ok(Message { records })
+        } else {
+            Err(NdefError::MissingMessageEnd)
+        }
+    }
+
+    /// The records of the message.
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Take the records of the message.
+    pub fn into_records(self) -> Vec<Record> {
+        self.records
+    }
+
+    /// Whether the message has no records.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Serialize the message, with no chunked records.
+    ///
+    /// A message with no records gives empty data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode(None)
+    }
+
+    /// Serialize the message, splitting payloads longer than `chunk_size`
+    /// into chunked records.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `chunk_size` is 0.
+    pub fn to_bytes_chunked(&self, chunk_size: usize) -> Vec<u8> {
+        assert!(chunk_size > 0);
+        self.encode(Some(chunk_size))
+    }
+
+    fn encode(&self, chunk_size: Option<usize>) -> Vec<u8> {
+        let mut raws = Vec::new();
+        for record in &self.records {
+            let chunk_size = chunk_size.unwrap_or(usize::MAX);
+            if record.payload.len() <= chunk_size {
+                raws.push(RawRecord {
+                    flags: 0,
+                    tnf: record.tnf,
+                    record_type: &record.record_type,
+                    id: &record.id,
+                    payload: &record.payload,
+                });
+                continue;
+            }
+            let chunk_count = (record.payload.len() + chunk_size - 1) / chunk_size;
+            for (i, chunk) in record.payload.chunks(chunk_size).enumerate() {
+                let first = i == 0;
+                raws.push(RawRecord {
+                    flags: if i + 1 < chunk_count { CF } else { 0 },
+                    tnf: if first { record.tnf } else { Tnf::Unchanged },
+                    record_type: if first { &record.record_type } else { &[] },
+                    id: if first { &record.id } else { &[] },
+                    payload: chunk,
+                });
+            }
+        }
+        if let Some(first) = raws.first_mut() {
+            first.flags |= MB;
+        }
+        if let Some(last) = raws.last_mut() {
+            last.flags |= ME;
+        }
+        let mut out = Vec::new();
+        for raw in &raws {
+            encode_raw_record(raw, &mut out);
+        }
+        out
+    }
+}
+
+impl From<Vec<Record>> for Message {
+    fn from(records: Vec<Record>) -> Message {
+        Message::new(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_record() {
+        let record = Record::uri("https://www.example.com/");
+        assert_eq!(record.payload(), b"\x02example.com/");
+        assert_eq!(record.to_uri().unwrap(), "https://www.example.com/");
+        assert_eq!(Record::uri("x-custom:1").payload(), b"\x00x-custom:1");
+
+        let message = Message::parse(b"\xD1\x01\x0D\x55\x04example.com/").unwrap();
+        assert_eq!(message.records()[0].to_uri().unwrap(), "https://example.com/");
+        assert_eq!(message.records()[0].to_text(), None);
+    }
+
+    #[test]
+    fn text_record() {
+        let message = Message::parse(b"\xD1\x01\x08\x54\x02enHello").unwrap();
+        let text = message.records()[0].to_text().unwrap();
+        assert_eq!(text.language, "en");
+        assert_eq!(text.text, "Hello");
+
+        let utf16 = Record::new(Tnf::WellKnown, b"T", b"\x82fr\xFF\xFEH\x00i\x00");
+        assert_eq!(utf16.to_text().unwrap().text, "Hi");
+        let odd = Record::new(Tnf::WellKnown, b"T", b"\x82fr\x00H\x00");
+        assert_eq!(odd.to_text(), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = Message::new(vec![
+            Record::mime("text/plain", b"hello").with_id(b"#1"),
+            Record::external("example.com:asset", &[0x42; 300]),
+            Record::empty(),
+        ]);
+        let bytes = message.to_bytes();
+        assert_eq!(&bytes[..4], b"\x9A\x0A\x05\x02");
+        assert_eq!(Message::parse(&bytes).unwrap(), message);
+        assert_eq!(Message::parse(&message.to_bytes_chunked(100)).unwrap(), message);
+        assert_eq!(Message::new(vec![]).to_bytes(), b"");
+    }
+
+    #[test]
+    fn chunked() {
+        let message = Message::new(vec![Record::mime("a/b", b"abcde")]);
+        let bytes = message.to_bytes_chunked(2);
+        assert_eq!(bytes, b"\xB2\x03\x02a/bab\x36\x00\x02cd\x56\x00\x01e".to_vec());
+        assert_eq!(Message::parse(&bytes).unwrap(), message);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Message::parse(b"").unwrap(), Message::default());
+        assert_eq!(Message::parse(b"\xD1\x01\x05\x55"), Err(NdefError::Truncated));
+        assert_eq!(Message::parse(b"\x51\x01\x00\x55"), Err(NdefError::InvalidMessageBegin));
+        assert_eq!(Message::parse(b"\x91\x01\x00\x55"), Err(NdefError::MissingMessageEnd));
+        assert_eq!(
+            Message::parse(b"\xD1\x01\x00\x55\x00"),
+            Err(NdefError::TrailingBytes(1))
+        );
+        assert_eq!(Message::parse(b"\xD6\x00\x00"), Err(NdefError::InvalidChunk));
+        assert_eq!(Message::parse(b"\xF1\x01\x00\x55"), Err(NdefError::InvalidChunk));
+    }
+
+    #[test]
+    #[should_panic]
+    fn record_type_too_long() {
+        Record::mime(&"a".repeat(256), b"");
+    }
+
+    #[test]
+    #[should_panic]
+    fn id_too_long() {
+        Record::empty().with_id(&[0; 256]);
+    }
+}
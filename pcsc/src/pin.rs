@@ -0,0 +1,651 @@
+//! Secure PIN entry on pinpad readers, per PC/SC Part 10.
+//!
+//! With `Card::verify_pin()` and `Card::modify_pin()`, the PIN is entered
+//! on the reader's keypad and inserted into the command APDU by the reader,
+//! so it never reaches the host. The commands are described by a
+//! [`PinVerify`](struct.PinVerify.html) or
+//! [`PinModify`](struct.PinModify.html), which encode the
+//! `PIN_VERIFY_STRUCTURE` and `PIN_MODIFY_STRUCTURE` of PC/SC Part 10, and
+//! a [`PinFormat`](struct.PinFormat.html), which tells the reader how to
+//! insert the PIN.
+//!
+//! ```no_run
+//! use pcsc::pin::{PinEncoding, PinFormat, PinVerify};
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//!
+//! // VERIFY of PIN 80, with the ASCII PIN padded with FF to 8 bytes.
+//! let format = PinFormat::new(PinEncoding::Ascii).with_block_size(8);
+//! let apdu = [0x00, 0x20, 0x00, 0x80, 0x08, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+//! let verify = PinVerify::new(format, &apdu).with_pin_length(6, 8).with_timeout(30);
+//! match card.verify_pin(&verify) {
+//!     Ok(_) => println!("PIN verified"),
+//!     Err(err) => println!("PIN not verified: {}", err),
+//! }
+//! ```
+
+use std::error;
+use std::fmt;
+
+use bitflags::bitflags;
+
+use crate::apdu::{Response, StatusWord};
+use crate::features::Feature;
+use crate::{Card, Error, MAX_BUFFER_SIZE};
+
+/// An error entering a PIN on a pinpad reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinError {
+    /// The PC/SC call failed.
+    Pcsc(Error),
+    /// The reader does not support the PIN entry feature.
+    Unsupported,
+    /// The user did not enter the PIN in time.
+    Timeout,
+    /// The user cancelled the PIN entry.
+    Cancelled,
+    /// The new PIN and its confirmation do not match.
+    Mismatch,
+    /// The entered PIN is shorter or longer than allowed.
+    InvalidLength,
+    /// The card returned a status word which does not indicate success,
+    /// for example `StatusWord::Counter` after a wrong PIN.
+    Status(StatusWord),
+    /// The reply is shorter than a status word.
+    MalformedResponse,
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PinError::Pcsc(ref err) => write!(f, "{}", err),
+            PinError::Unsupported => f.write_str("the reader does not support PIN entry"),
+            PinError::Timeout => f.write_str("PIN entry timed out"),
+            PinError::Cancelled => f.write_str("PIN entry cancelled by the user"),
+            PinError::Mismatch => f.write_str("the new PIN entries do not match"),
+            PinError::InvalidLength => f.write_str("the PIN is too short or too long"),
+            PinError::Status(sw) => write!(f, "{}", sw),
+            PinError::MalformedResponse => f.write_str("response shorter than a status word"),
+        }
+    }
+}
+
+impl error::Error for PinError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            PinError::Pcsc(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for PinError {
+    fn from(err: Error) -> PinError {
+        PinError::Pcsc(err)
+    }
+}
+
+/// The encoding of the PIN digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PinEncoding {
+    /// One digit per byte, with values 0 to 9.
+    Binary,
+    /// Two digits per byte.
+    Bcd,
+    /// One ASCII digit per byte.
+    Ascii,
+}
+
+/// How the reader inserts the PIN into the command APDU.
+///
+/// This encodes the `bmFormatString`, `bmPINBlockString` and
+/// `bmPINLengthFormat` fields. Positions are relative to the first byte of
+/// the command data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PinFormat {
+    format_string: u8,
+    pin_block_string: u8,
+    pin_length_format: u8,
+}
+
+impl PinFormat {
+    /// A PIN in the given encoding, left justified at the start of the
+    /// command data, without a PIN length field.
+    pub fn new(encoding: PinEncoding) -> PinFormat {
+        let encoding = match encoding {
+            PinEncoding::Binary => 0x00,
+            PinEncoding::Bcd => 0x01,
+            PinEncoding::Ascii => 0x02,
+        };
+        PinFormat {
+            // Positions in bytes.
+            format_string: 0x80 | encoding,
+            pin_block_string: 0x00,
+            pin_length_format: 0x00,
+        }
+    }
+
+    /// A format from the raw `bmFormatString`, `bmPINBlockString` and
+    /// `bmPINLengthFormat` fields.
+    pub fn from_raw(format_string: u8, pin_block_string: u8, pin_length_format: u8) -> PinFormat {
+        PinFormat {
+            format_string,
+            pin_block_string,
+            pin_length_format,
+        }
+    }
+
+    /// The position of the PIN, in bytes.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `position` is over 15.
+    pub fn with_position(mut self, position: u8) -> PinFormat {
+        assert!(position <= 0x0F);
+        self.format_string = 0x80 | position << 3 | (self.format_string & 0x07);
+        self
+    }
+
+    /// Whether the PIN is right justified in its block.
+    pub fn with_right_justified(mut self, right_justified: bool) -> PinFormat {
+        if right_justified {
+            self.format_string |= 0x04;
+        } else {
+            self.format_string &= !0x04;
+        }
+        self
+    }
+
+    /// The size of the PIN block, in bytes, which the reader takes from
+    /// the APDU template as padding.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `size` is over 15.
+    pub fn with_block_size(mut self, size: u8) -> PinFormat {
+        assert!(size <= 0x0F);
+        self.pin_block_string = (self.pin_block_string & 0xF0) | size;
+        self
+    }
+
+    /// A field in which the reader stores the PIN length, at `position`
+    /// bits into the command data, of `size` bits.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `position` or `size` is over 15.
+    pub fn with_length_field(mut self, position: u8, size: u8) -> PinFormat {
+        assert!(position <= 0x0F && size <= 0x0F);
+        self.pin_block_string = size << 4 | (self.pin_block_string & 0x0F);
+        self.pin_length_format = position;
+        self
+    }
+
+    /// The raw `bmFormatString`, `bmPINBlockString` and
+    /// `bmPINLengthFormat` fields.
+    pub fn to_bytes(self) -> [u8; 3] {
+        [self.format_string, self.pin_block_string, self.pin_length_format]
+    }
+}
+
+bitflags! {
+    /// The conditions which end the PIN entry.
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+    pub struct EntryValidation: u8 {
+        /// The maximum PIN length is reached.
+        const MAX_SIZE = 0x01;
+        /// The validation key is pressed.
+        const KEY_PRESSED = 0x02;
+        /// The timeout expired.
+        const TIMEOUT = 0x04;
+    }
+}
+
+// The fields common to the verify and modify structures.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PinEntry {
+    timeout: u8,
+    timeout2: u8,
+    format: PinFormat,
+    min_len: u8,
+    max_len: u8,
+    entry_validation: EntryValidation,
+    number_message: u8,
+    lang_id: u16,
+    apdu: Vec<u8>,
+}
+
+impl PinEntry {
+    fn new(format: PinFormat, apdu: &[u8]) -> PinEntry {
+        PinEntry {
+            timeout: 0,
+            timeout2: 0,
+            format,
+            min_len: 4,
+            max_len: 8,
+            entry_validation: EntryValidation::KEY_PRESSED,
+            number_message: 0x01,
+            lang_id: 0x0409,
+            apdu: apdu.to_vec(),
+        }
+    }
+
+    // wPINMaxExtraDigit: the minimum in the high byte, the maximum in the
+    // low byte.
+    fn max_extra_digit(&self) -> [u8; 2] {
+        u16::from_be_bytes([self.min_len, self.max_len]).to_le_bytes()
+    }
+
+    fn write_data(&self, bytes: &mut Vec<u8>) {
+        // bTeoPrologue, which is only used with T=1 block transfers.
+        bytes.extend_from_slice(&[0; 3]);
+        bytes.extend_from_slice(&(self.apdu.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.apdu);
+    }
+}
+
+macro_rules! pin_entry_setters {
+    ($name:ident) => {
+        impl $name {
+            /// The timeout for the first key press, in seconds; 0 is the
+            /// reader's default.
+            pub fn with_timeout(mut self, seconds: u8) -> $name {
+                self.entry.timeout = seconds;
+                self
+            }
+
+            /// The timeout after the first key press, in seconds; 0 is the
+            /// reader's default.
+            pub fn with_timeout2(mut self, seconds: u8) -> $name {
+                self.entry.timeout2 = seconds;
+                self
+            }
+
+            /// The minimum and maximum PIN length, in digits. The default is
+            /// 4 to 8.
+            pub fn with_pin_length(mut self, min: u8, max: u8) -> $name {
+                self.entry.min_len = min;
+                self.entry.max_len = max;
+                self
+            }
+
+            /// The conditions which end the PIN entry. The default is
+            /// `EntryValidation::KEY_PRESSED`.
+            pub fn with_entry_validation(mut self, entry_validation: EntryValidation) -> $name {
+                self.entry.entry_validation = entry_validation;
+                self
+            }
+
+            /// The number of messages to display, 0xFF for the reader's
+            /// default. The default is 1.
+            pub fn with_number_message(mut self, number_message: u8) -> $name {
+                self.entry.number_message = number_message;
+                self
+            }
+
+            /// The language of the messages. The default is 0x0409
+            /// (English, United States).
+            pub fn with_lang_id(mut self, lang_id: u16) -> $name {
+                self.entry.lang_id = lang_id;
+                self
+            }
+        }
+    };
+}
+
+/// A PIN verification, encoded as a `PIN_VERIFY_STRUCTURE`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PinVerify {
+    entry: PinEntry,
+    msg_index: u8,
+}
+
+impl PinVerify {
+    /// A PIN verification with the given PIN format and APDU template.
+    ///
+    /// The APDU template is the complete command APDU, with placeholder
+    /// bytes where the reader inserts the PIN.
+    pub fn new(format: PinFormat, apdu: &[u8]) -> PinVerify {
+        PinVerify {
+            entry: PinEntry::new(format, apdu),
+            msg_index: 0x00,
+        }
+    }
+
+    /// The index of the message to display. The default is 0.
+    pub fn with_message_index(mut self, msg_index: u8) -> PinVerify {
+        self.msg_index = msg_index;
+        self
+    }
+
+    /// Encode the `PIN_VERIFY_STRUCTURE`, in little-endian byte order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let entry = &self.entry;
+        let mut bytes = Vec::with_capacity(19 + entry.apdu.len());
+        bytes.extend_from_slice(&[entry.timeout, entry.timeout2]);
+        bytes.extend_from_slice(&entry.format.to_bytes());
+        bytes.extend_from_slice(&entry.max_extra_digit());
+        bytes.extend_from_slice(&[entry.entry_validation.bits(), entry.number_message]);
+        bytes.extend_from_slice(&entry.lang_id.to_le_bytes());
+        bytes.push(self.msg_index);
+        entry.write_data(&mut bytes);
+        bytes
+    }
+}
+
+pin_entry_setters!(PinVerify);
+
+/// A PIN modification, encoded as a `PIN_MODIFY_STRUCTURE`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PinModify {
+    entry: PinEntry,
+    insertion_offset_old: u8,
+    insertion_offset_new: u8,
+    confirm_pin: u8,
+    msg_indexes: [u8; 3],
+}
+
+impl PinModify {
+    /// A PIN modification with the given PIN format and APDU template.
+    ///
+    /// By default, the current PIN is entered first, followed by the new
+    /// PIN and its confirmation.
+    pub fn new(format: PinFormat, apdu: &[u8]) -> PinModify {
+        PinModify {
+            entry: PinEntry::new(format, apdu),
+            insertion_offset_old: 0,
+            insertion_offset_new: 0,
+            confirm_pin: 0x03,
+            msg_indexes: [0x00, 0x01, 0x02],
+        }
+    }
+
+    /// The positions of the current and the new PIN, in bytes, relative
+    /// to the position of the PIN format.
+    pub fn with_insertion_offsets(mut self, old: u8, new: u8) -> PinModify {
+        self.insertion_offset_old = old;
+        self.insertion_offset_new = new;
+        self
+    }
+
+    /// Whether the current PIN is entered, and whether the new PIN is
+    /// entered a second time for confirmation.
+    pub fn with_confirmation(mut self, current_pin: bool, confirm_new_pin: bool) -> PinModify {
+        self.confirm_pin = (current_pin as u8) << 1 | confirm_new_pin as u8;
+        self
+    }
+
+    /// The indexes of the messages to display, for the current PIN, the
+    /// new PIN and the confirmation. The default is 0, 1 and 2.
+    pub fn with_message_indexes(mut self, msg_indexes: [u8; 3]) -> PinModify {
+        self.msg_indexes = msg_indexes;
+        self
+    }
+
+    /// Encode the `PIN_MODIFY_STRUCTURE`, in little-endian byte order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let entry = &self.entry;
+        let mut bytes = Vec::with_capacity(24 + entry.apdu.len());
+        bytes.extend_from_slice(&[entry.timeout, entry.timeout2]);
+        bytes.extend_from_slice(&entry.format.to_bytes());
+        bytes.extend_from_slice(&[self.insertion_offset_old, self.insertion_offset_new]);
+        bytes.extend_from_slice(&entry.max_extra_digit());
+        bytes.extend_from_slice(&[self.confirm_pin, entry.entry_validation.bits(), entry.number_message]);
+        bytes.extend_from_slice(&entry.lang_id.to_le_bytes());
+        bytes.extend_from_slice(&self.msg_indexes);
+        entry.write_data(&mut bytes);
+        bytes
+    }
+}
+
+pin_entry_setters!(PinModify);
+
+// Decode the reply of a PIN entry: the card's response, or an error status
+// word of the reader.
+fn decode_reply(reply: &[u8]) -> Result<Response, PinError> {
+    if reply.len() < 2 {
+        return Err(PinError::MalformedResponse);
+    }
+    let (data, sw) = reply.split_at(reply.len() - 2);
+    match (sw[0], sw[1]) {
+        (0x64, 0x00) => Err(PinError::Timeout),
+        (0x64, 0x01) => Err(PinError::Cancelled),
+        (0x64, 0x02) => Err(PinError::Mismatch),
+        (0x64, 0x03) => Err(PinError::InvalidLength),
+        (sw1, sw2) => {
+            let status = StatusWord::new(sw1, sw2);
+            if status.is_success() {
+                Ok(Response::new(data.to_vec(), status))
+            } else {
+                Err(PinError::Status(status))
+            }
+        }
+    }
+}
+
+impl Card {
+    fn pin_entry(&self, feature: Feature, structure: &[u8]) -> Result<Response, PinError> {
+        let control_code = self.features()?.get(feature).ok_or(PinError::Unsupported)?;
+        let mut receive_buffer = [0; MAX_BUFFER_SIZE];
+        let reply = self.control(control_code, structure, &mut receive_buffer)?;
+        decode_reply(reply)
+    }
+
+    /// Verify a PIN entered on the reader's keypad.
+    ///
+    /// This discovers the reader's `Feature::VerifyPinDirect` control code,
+    /// and sends the verification to it. Returns the card's response if
+    /// its status word indicates success.
+    ///
+    /// If the reader has no such feature, `PinError::Unsupported` is
+    /// returned.
+    pub fn verify_pin(&self, verify: &PinVerify) -> Result<Response, PinError> {
+        self.pin_entry(Feature::VerifyPinDirect, &verify.to_bytes())
+    }
+
+    /// Change a PIN entered on the reader's keypad.
+    ///
+    /// This discovers the reader's `Feature::ModifyPinDirect` control code,
+    /// and sends the modification to it. Returns the card's response if
+    /// its status word indicates success.
+    ///
+    /// If the reader has no such feature, `PinError::Unsupported` is
+    /// returned.
+    pub fn modify_pin(&self, modify: &PinModify) -> Result<Response, PinError> {
+        self.pin_entry(Feature::ModifyPinDirect, &modify.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::CM_IOCTL_GET_FEATURE_REQUEST;
+    use crate::ffi::DWORD;
+    use crate::simulator::test_reader;
+    use std::sync::{Arc, Mutex};
+
+    const VERIFY_PIN_DIRECT: DWORD = 0x4233_0006;
+    const MODIFY_PIN_DIRECT: DWORD = 0x4233_0007;
+
+    // VERIFY of PIN 81, with a BCD PIN block of 8 bytes.
+    const VERIFY_APDU: &[u8] = &[
+        0x00, 0x20, 0x00, 0x81, 0x08, 0x20, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    ];
+
+    // A BCD PIN in the second byte of the data, right justified in a block
+    // of 8 bytes, with its length in the high nibble of the first byte.
+    fn format() -> PinFormat {
+        PinFormat::new(PinEncoding::Bcd)
+            .with_position(1)
+            .with_right_justified(true)
+            .with_block_size(8)
+            .with_length_field(4, 4)
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(PinFormat::new(PinEncoding::Binary).to_bytes(), [0x80, 0x00, 0x00]);
+        assert_eq!(PinFormat::new(PinEncoding::Bcd).to_bytes(), [0x81, 0x00, 0x00]);
+        assert_eq!(PinFormat::new(PinEncoding::Ascii).to_bytes(), [0x82, 0x00, 0x00]);
+        assert_eq!(format().to_bytes(), [0x8D, 0x48, 0x04]);
+        assert_eq!(
+            format().with_right_justified(false).with_position(15).to_bytes(),
+            [0xF9, 0x48, 0x04]
+        );
+        assert_eq!(PinFormat::from_raw(0x01, 0x47, 0x04).to_bytes(), [0x01, 0x47, 0x04]);
+    }
+
+    #[test]
+    fn verify_structure() {
+        let verify = PinVerify::new(format(), VERIFY_APDU)
+            .with_timeout(30)
+            .with_timeout2(5)
+            .with_pin_length(6, 12)
+            .with_entry_validation(EntryValidation::KEY_PRESSED | EntryValidation::TIMEOUT)
+            .with_number_message(0xFF)
+            .with_lang_id(0x0407)
+            .with_message_index(0x02);
+        let bytes = verify.to_bytes();
+        assert_eq!(
+            bytes[..19],
+            [
+                30, 5, // bTimeOut, bTimeOut2
+                0x8D, 0x48, 0x04, // bmFormatString, bmPINBlockString, bmPINLengthFormat
+                0x0C, 0x06, // wPINMaxExtraDigit
+                0x06, // bEntryValidationCondition
+                0xFF, // bNumberMessage
+                0x07, 0x04, // wLangId
+                0x02, // bMsgIndex
+                0x00, 0x00, 0x00, // bTeoPrologue
+                0x0D, 0x00, 0x00, 0x00, // ulDataLength
+            ]
+        );
+        assert_eq!(bytes[19..], *VERIFY_APDU);
+
+        let bytes = PinVerify::new(PinFormat::new(PinEncoding::Ascii), &[0; 300]).to_bytes();
+        assert_eq!(
+            bytes[..19],
+            [0, 0, 0x82, 0x00, 0x00, 0x08, 0x04, 0x02, 0x01, 0x09, 0x04, 0x00, 0, 0, 0, 0x2C, 0x01, 0x00, 0x00]
+        );
+        assert_eq!(bytes.len(), 19 + 300);
+    }
+
+    #[test]
+    fn modify_structure() {
+        let apdu = [0x00, 0x24, 0x00, 0x81, 0x10];
+        let modify = PinModify::new(format(), &apdu)
+            .with_timeout(30)
+            .with_pin_length(4, 8)
+            .with_insertion_offsets(0, 8)
+            .with_message_indexes([0x03, 0x04, 0x05]);
+        let bytes = modify.to_bytes();
+        assert_eq!(
+            bytes[..24],
+            [
+                30, 0, // bTimeOut, bTimeOut2
+                0x8D, 0x48, 0x04, // bmFormatString, bmPINBlockString, bmPINLengthFormat
+                0x00, 0x08, // bInsertionOffsetOld, bInsertionOffsetNew
+                0x08, 0x04, // wPINMaxExtraDigit
+                0x03, // bConfirmPIN
+                0x02, // bEntryValidationCondition
+                0x01, // bNumberMessage
+                0x09, 0x04, // wLangId
+                0x03, 0x04, 0x05, // bMsgIndex1, bMsgIndex2, bMsgIndex3
+                0x00, 0x00, 0x00, // bTeoPrologue
+                0x05, 0x00, 0x00, 0x00, // ulDataLength
+            ]
+        );
+        assert_eq!(bytes[24..], apdu);
+
+        let confirm_pin = |current_pin, confirm_new_pin| {
+            PinModify::new(format(), &apdu)
+                .with_confirmation(current_pin, confirm_new_pin)
+                .to_bytes()[9]
+        };
+        assert_eq!(confirm_pin(false, false), 0x00);
+        assert_eq!(confirm_pin(false, true), 0x01);
+        assert_eq!(confirm_pin(true, false), 0x02);
+        assert_eq!(confirm_pin(true, true), 0x03);
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(decode_reply(&[0x64, 0x00]), Err(PinError::Timeout));
+        assert_eq!(decode_reply(&[0x64, 0x01]), Err(PinError::Cancelled));
+        assert_eq!(decode_reply(&[0x64, 0x02]), Err(PinError::Mismatch));
+        assert_eq!(decode_reply(&[0x64, 0x03]), Err(PinError::InvalidLength));
+        assert_eq!(
+            decode_reply(&[0x64, 0x04]),
+            Err(PinError::Status(StatusWord::new(0x64, 0x04)))
+        );
+        assert_eq!(
+            decode_reply(&[0x63, 0xC2]),
+            Err(PinError::Status(StatusWord::new(0x63, 0xC2)))
+        );
+        assert_eq!(
+            decode_reply(&[0x90, 0x00]),
+            Ok(Response::new(vec![], StatusWord::Success))
+        );
+        assert_eq!(
+            decode_reply(&[0x01, 0x02, 0x90, 0x00]),
+            Ok(Response::new(vec![0x01, 0x02], StatusWord::Success))
+        );
+        assert_eq!(decode_reply(&[0x90]), Err(PinError::MalformedResponse));
+        assert_eq!(decode_reply(&[]), Err(PinError::MalformedResponse));
+    }
+
+    #[test]
+    fn pin_entry() {
+        let verify = PinVerify::new(format(), VERIFY_APDU).with_timeout(30);
+        let modify = PinModify::new(format(), &[0x00, 0x24, 0x00, 0x81, 0x10]);
+
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let recorded = commands.clone();
+        let card = test_reader(move |code, command: &[u8]| {
+            recorded.lock().unwrap().push((code, command.to_vec()));
+            match code {
+                CM_IOCTL_GET_FEATURE_REQUEST => Ok(vec![0x06, 0x04, 0x42, 0x33, 0x00, 0x06]),
+                VERIFY_PIN_DIRECT => Ok(vec![0x63, 0xC2]),
+                _ => Err(Error::InvalidParameter),
+            }
+        });
+
+        assert_eq!(
+            card.verify_pin(&verify),
+            Err(PinError::Status(StatusWord::new(0x63, 0xC2)))
+        );
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [
+                (CM_IOCTL_GET_FEATURE_REQUEST, vec![]),
+                (VERIFY_PIN_DIRECT, verify.to_bytes())
+            ]
+        );
+
+        // The reader has no MODIFY_PIN_DIRECT feature.
+        commands.lock().unwrap().clear();
+        assert_eq!(card.modify_pin(&modify), Err(PinError::Unsupported));
+        assert_eq!(*commands.lock().unwrap(), [(CM_IOCTL_GET_FEATURE_REQUEST, vec![])]);
+    }
+
+    #[test]
+    fn pin_entry_success() {
+        let modify = PinModify::new(format(), &[0x00, 0x24, 0x00, 0x81, 0x10]);
+
+        let card = test_reader(|code, _: &[u8]| match code {
+            CM_IOCTL_GET_FEATURE_REQUEST => Ok(vec![
+                0x06, 0x04, 0x42, 0x33, 0x00, 0x06, 0x07, 0x04, 0x42, 0x33, 0x00, 0x07,
+            ]),
+            VERIFY_PIN_DIRECT => Ok(vec![0x90, 0x00]),
+            MODIFY_PIN_DIRECT => Ok(vec![0x64, 0x02]),
+            _ => Err(Error::InvalidParameter),
+        });
+
+        let verify = PinVerify::new(format(), VERIFY_APDU);
+        assert_eq!(card.verify_pin(&verify), Ok(Response::new(vec![], StatusWord::Success)));
+        assert_eq!(card.modify_pin(&modify), Err(PinError::Mismatch));
+    }
+}
@@ -0,0 +1,1150 @@
+//! A simulated PC/SC environment.
+//!
+//! A [`Simulator`](struct.Simulator.html) is a `Backend` which serves
+//! virtual card readers and cards from within the process. Readers can be
+//! added and removed, and cards inserted and removed, at any time; waiting
+//! `Context::get_status_change()` calls wake up just like they would with
+//! a real PC/SC service. Commands transmitted to a virtual card are
+//! answered by a user-supplied [`CardHandler`](trait.CardHandler.html).
+//!
+//! ```no_run
+//! use pcsc::simulator::{Simulator, VirtualCard};
+//! use pcsc::*;
+//!
+//! let sim = Simulator::new();
+//! sim.add_reader("Virtual Reader 00 00").unwrap();
+//! let handler = |_command: &[u8]| Ok(vec![0x90, 0x00]);
+//! sim.insert_card("Virtual Reader 00 00", VirtualCard::new(&[0x3B, 0x00], handler))
+//!     .unwrap();
+//!
+//! let ctx = Context::establish_with_backend(sim.clone(), Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//! let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+//! let rapdu = card.transmit(b"\x00\xa4\x04\x00", &mut rapdu_buf).unwrap();
+//! assert_eq!(rapdu, b"\x90\x00");
+//! ```
+//!
+//! The simulation is shared by all clones of a `Simulator`, and all
+//! contexts established with it.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::backend::{Backend, RawStatus};
+use crate::{
+    ffi, Attribute, Disposition, Error, Protocol, Protocols, ReaderState, Scope, ShareMode, State, Status,
+    MAX_ATR_SIZE, PNP_NOTIFICATION,
+};
+use ffi::DWORD;
+
+/// Answers commands transmitted to a virtual card.
+///
+/// This trait is implemented for closures of the form
+/// `FnMut(&[u8]) -> Result<Vec<u8>, Error>`.
+pub trait CardHandler: Send {
+    /// Respond to an APDU command.
+    ///
+    /// Errors are returned from `Card::transmit()` as is.
+    fn transmit(&mut self, command: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Called when the card is reset, e.g. by disconnecting with
+    /// `Disposition::ResetCard`.
+    fn reset(&mut self) {}
+}
+
+impl<F> CardHandler for F
+where
+    F: FnMut(&[u8]) -> Result<Vec<u8>, Error> + Send,
+{
+    fn transmit(&mut self, command: &[u8]) -> Result<Vec<u8>, Error> {
+        self(command)
+    }
+}
+
+/// Answers control commands sent to a virtual reader.
+///
+/// This trait is implemented for closures of the form
+/// `FnMut(DWORD, &[u8]) -> Result<Vec<u8>, Error>`.
+pub trait ReaderHandler: Send {
+    /// Respond to a `Card::control()` command.
+    fn control(&mut self, control_code: DWORD, command: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+impl<F> ReaderHandler for F
+where
+    F: FnMut(DWORD, &[u8]) -> Result<Vec<u8>, Error> + Send,
+{
+    fn control(&mut self, control_code: DWORD, command: &[u8]) -> Result<Vec<u8>, Error> {
+        self(control_code, command)
+    }
+}
+
+/// A virtual card, to be inserted into a virtual reader.
+pub struct VirtualCard {
+    atr: Vec<u8>,
+    protocol: Protocol,
+    handler: Option<Box<dyn CardHandler>>,
+}
+
+impl VirtualCard {
+    /// Create a card with the given ATR, answering commands with `handler`.
+    ///
+    /// The card uses the T=1 protocol; see `protocol()`.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `atr` is longer than `MAX_ATR_SIZE`.
+    pub fn new<H: CardHandler + 'static>(atr: &[u8], handler: H) -> VirtualCard {
+        assert!(atr.len() <= MAX_ATR_SIZE);
+        VirtualCard {
+            atr: atr.to_vec(),
+            protocol: Protocol::T1,
+            handler: Some(Box::new(handler)),
+        }
+    }
+
+    /// Create a mute card, which does not answer to reset.
+    ///
+    /// The reader reports the card as `State::PRESENT | State::MUTE`, and
+    /// connecting to it fails with `Error::UnresponsiveCard`.
+    pub fn mute() -> VirtualCard {
+        VirtualCard {
+            atr: vec![],
+            protocol: Protocol::T1,
+            handler: None,
+        }
+    }
+
+    /// Set the protocol the card supports.
+    pub fn protocol(mut self, protocol: Protocol) -> VirtualCard {
+        self.protocol = protocol;
+        self
+    }
+}
+
+type SharedCardHandler = Arc<Mutex<Box<dyn CardHandler>>>;
+type SharedReaderHandler = Arc<Mutex<Box<dyn ReaderHandler>>>;
+
+struct InsertedCard {
+    // Distinguishes different insertions, to detect removed cards.
+    id: u64,
+    atr: Vec<u8>,
+    protocol: Protocol,
+    handler: Option<SharedCardHandler>,
+}
+
+struct Reader {
+    name: CString,
+    card: Option<InsertedCard>,
+    event_count: u32,
+    transaction: Option<ffi::SCARDHANDLE>,
+    handler: Option<SharedReaderHandler>,
+    attributes: HashMap<Attribute, Vec<u8>>,
+}
+
+struct ContextState {
+    waiting: usize,
+    cancelled: bool,
+}
+
+struct Connection {
+    reader: CString,
+    // None for direct connections made without a card.
+    card_id: Option<u64>,
+    share_mode: ShareMode,
+    protocol: Option<Protocol>,
+}
+
+#[derive(Default)]
+struct SimState {
+    next_handle: u32,
+    next_card_id: u64,
+    // Incremented whenever a reader is added or removed.
+    pnp_count: u32,
+    contexts: HashMap<ffi::SCARDCONTEXT, ContextState>,
+    connections: HashMap<ffi::SCARDHANDLE, Connection>,
+    readers: Vec<Reader>,
+}
+
+struct Shared {
+    state: Mutex<SimState>,
+    changed: Condvar,
+}
+
+/// A simulated PC/SC service with virtual readers and cards.
+///
+/// See the [module documentation](index.html).
+#[derive(Clone)]
+pub struct Simulator {
+    shared: Arc<Shared>,
+}
+
+fn to_reader_name(name: &str) -> Result<CString, Error> {
+    CString::new(name).map_err(|_| Error::InvalidValue)
+}
+
+fn copy_to_buffer(data: &[u8], buffer: Option<&mut [u8]>) -> Result<usize, Error> {
+    if let Some(buffer) = buffer {
+        if buffer.len() < data.len() {
+            return Err(Error::InsufficientBuffer);
+        }
+        buffer[..data.len()].copy_from_slice(data);
+    }
+    Ok(data.len())
+}
+
+fn negotiate(card: &InsertedCard, preferred_protocols: Protocols) -> Result<Protocol, Error> {
+    if preferred_protocols.contains(Protocols::from_bits_retain(card.protocol as DWORD)) {
+        Ok(card.protocol)
+    } else {
+        Err(Error::ProtoMismatch)
+    }
+}
+
+fn card_status() -> Status {
+    #[cfg(target_os = "windows")]
+    {
+        Status::SPECIFIC
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        Status::PRESENT | Status::POWERED | Status::SPECIFIC
+    }
+}
+
+impl SimState {
+    fn new_handle(&mut self) -> ffi::SCARDHANDLE {
+        self.next_handle += 1;
+        self.next_handle as ffi::SCARDHANDLE
+    }
+
+    fn reader_index(&self, name: &CStr) -> Option<usize> {
+        self.readers.iter().position(|reader| reader.name.as_c_str() == name)
+    }
+
+    fn reader_mut(&mut self, name: &CStr) -> Result<&mut Reader, Error> {
+        match self.reader_index(name) {
+            Some(index) => Ok(&mut self.readers[index]),
+            None => Err(Error::UnknownReader),
+        }
+    }
+
+    fn check_context(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error> {
+        if self.contexts.contains_key(&context) {
+            Ok(())
+        } else {
+            Err(Error::InvalidHandle)
+        }
+    }
+
+    // Returns the index of the connection's reader, checking that the
+    // connection is still usable.
+    fn check_card(&self, card: ffi::SCARDHANDLE) -> Result<usize, Error> {
+        let connection = self.connections.get(&card).ok_or(Error::InvalidHandle)?;
+        let index = self.reader_index(&connection.reader).ok_or(Error::ReaderUnavailable)?;
+        if let Some(card_id) = connection.card_id {
+            match self.readers[index].card {
+                Some(ref inserted) if inserted.id == card_id => {}
+                _ => return Err(Error::RemovedCard),
+            }
+        }
+        Ok(index)
+    }
+
+    fn reader_state(&self, index: usize) -> State {
+        let reader = &self.readers[index];
+        let mut state = match reader.card {
+            Some(InsertedCard { handler: None, .. }) => State::PRESENT | State::MUTE,
+            Some(_) => State::PRESENT,
+            None => State::EMPTY,
+        };
+        for connection in self.connections.values() {
+            if connection.reader == reader.name {
+                state |= State::INUSE;
+                if connection.share_mode == ShareMode::Exclusive {
+                    state |= State::EXCLUSIVE;
+                }
+            }
+        }
+        state
+    }
+
+    // Updates the reader states to the current simulation state, returning
+    // whether any of them changed.
+    fn update_reader_states(&self, readers: &mut [ReaderState]) -> bool {
+        let mut any_changed = false;
+        for rs in readers {
+            let current_state = rs.current_state();
+            let current_count = ((rs.inner.dwCurrentState >> 16) & 0xFFFF) as u32;
+            if current_state.contains(State::IGNORE) {
+                rs.set_event_state(State::IGNORE, 0);
+                continue;
+            }
+
+            let (state, count, atr): (State, u32, &[u8]) = if rs.name() == PNP_NOTIFICATION() {
+                (State::empty(), self.pnp_count & 0xFFFF, &[])
+            } else {
+                match self.reader_index(rs.name()) {
+                    None => (State::UNKNOWN, 0, &[]),
+                    Some(index) => {
+                        let reader = &self.readers[index];
+                        let atr = match reader.card {
+                            Some(ref card) => &card.atr[..],
+                            None => &[],
+                        };
+                        (self.reader_state(index), reader.event_count & 0xFFFF, atr)
+                    }
+                }
+            };
+
+            let changed = if rs.name() == PNP_NOTIFICATION() {
+                count != current_count
+            } else {
+                // A caller which leaves the upper 16 bits unset does not
+                // track the event count.
+                current_state.is_empty()
+                    || current_state - State::CHANGED != state
+                    || (current_count != 0 && count != current_count)
+            };
+            if changed {
+                any_changed = true;
+                rs.set_event_state(state | State::CHANGED, count);
+            } else {
+                rs.set_event_state(state, count);
+            }
+            rs.set_atr(atr);
+        }
+        any_changed
+    }
+
+    fn reset_card(&self, index: usize) -> Option<SharedCardHandler> {
+        self.readers[index].card.as_ref().and_then(|card| card.handler.clone())
+    }
+}
+
+impl Simulator {
+    /// Create a simulator without any readers.
+    pub fn new() -> Simulator {
+        Simulator {
+            shared: Arc::new(Shared {
+                state: Mutex::new(SimState::default()),
+                changed: Condvar::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SimState> {
+        self.shared.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, SimState>) -> MutexGuard<'a, SimState> {
+        self.shared.changed.wait(guard).unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn notify(&self) {
+        self.shared.changed.notify_all();
+    }
+
+    /// Add a virtual reader.
+    ///
+    /// Fails with `Error::DuplicateReader` if a reader with this name
+    /// already exists.
+    pub fn add_reader(&self, name: &str) -> Result<(), Error> {
+        let name = to_reader_name(name)?;
+        let mut state = self.lock();
+        if state.reader_index(&name).is_some() {
+            return Err(Error::DuplicateReader);
+        }
+        state.readers.push(Reader {
+            name,
+            card: None,
+            event_count: 0,
+            transaction: None,
+            handler: None,
+            attributes: HashMap::new(),
+        });
+        state.pnp_count = state.pnp_count.wrapping_add(1);
+        self.notify();
+        Ok(())
+    }
+
+    /// Remove a virtual reader, along with any card inserted into it.
+    ///
+    /// Connections to the reader fail with `Error::ReaderUnavailable`
+    /// afterwards.
+    pub fn remove_reader(&self, name: &str) -> Result<(), Error> {
+        let name = to_reader_name(name)?;
+        let mut state = self.lock();
+        let index = state.reader_index(&name).ok_or(Error::UnknownReader)?;
+        state.readers.remove(index);
+        state.pnp_count = state.pnp_count.wrapping_add(1);
+        self.notify();
+        Ok(())
+    }
+
+    /// Insert a virtual card into a reader.
+    ///
+    /// If a card is already inserted, it is removed first.
+    pub fn insert_card(&self, reader: &str, card: VirtualCard) -> Result<(), Error> {
+        let name = to_reader_name(reader)?;
+        let mut state = self.lock();
+        state.next_card_id += 1;
+        let id = state.next_card_id;
+        let reader = state.reader_mut(&name)?;
+        if reader.card.take().is_some() {
+            reader.event_count = reader.event_count.wrapping_add(1);
+        }
+        reader.card = Some(InsertedCard {
+            id,
+            atr: card.atr,
+            protocol: card.protocol,
+            handler: card.handler.map(|handler| Arc::new(Mutex::new(handler))),
+        });
+        reader.event_count = reader.event_count.wrapping_add(1);
+        self.notify();
+        Ok(())
+    }
+
+    /// Remove the card inserted into a reader.
+    ///
+    /// Connections to the card fail with `Error::RemovedCard` afterwards.
+    /// Does nothing if there is no card in the reader.
+    pub fn remove_card(&self, reader: &str) -> Result<(), Error> {
+        let name = to_reader_name(reader)?;
+        let mut state = self.lock();
+        let reader = state.reader_mut(&name)?;
+        if reader.card.take().is_some() {
+            reader.event_count = reader.event_count.wrapping_add(1);
+            reader.transaction = None;
+        }
+        self.notify();
+        Ok(())
+    }
+
+    /// Set the handler for `Card::control()` commands sent to a reader.
+    ///
+    /// Without a handler, control commands fail with
+    /// `Error::UnsupportedFeature`.
+    pub fn set_reader_handler<H: ReaderHandler + 'static>(&self, reader: &str, handler: H) -> Result<(), Error> {
+        let name = to_reader_name(reader)?;
+        let mut state = self.lock();
+        state.reader_mut(&name)?.handler = Some(Arc::new(Mutex::new(Box::new(handler))));
+        Ok(())
+    }
+
+    /// Set the value of a reader attribute, as returned by
+    /// `Card::get_attribute()`.
+    ///
+    /// `Attribute::AtrString`, `Attribute::CurrentProtocolType` and the
+    /// device name attributes are provided automatically, unless set
+    /// explicitly.
+    pub fn set_reader_attribute(&self, reader: &str, attribute: Attribute, value: &[u8]) -> Result<(), Error> {
+        let name = to_reader_name(reader)?;
+        let mut state = self.lock();
+        state.reader_mut(&name)?.attributes.insert(attribute, value.to_vec());
+        Ok(())
+    }
+
+    // Waits until no other connection holds a transaction on the card's
+    // reader, and returns the reader index.
+    fn wait_for_access<'a>(
+        &self,
+        mut state: MutexGuard<'a, SimState>,
+        card: ffi::SCARDHANDLE,
+    ) -> Result<(MutexGuard<'a, SimState>, usize), Error> {
+        loop {
+            let index = state.check_card(card)?;
+            match state.readers[index].transaction {
+                Some(holder) if holder != card => state = self.wait(state),
+                _ => return Ok((state, index)),
+            }
+        }
+    }
+
+    // Resets the card if the disposition asks for it, releasing the
+    // simulation lock first: `transmit()` calls the handler without the
+    // simulation lock, and the handler may take it.
+    fn apply_disposition(&self, state: MutexGuard<'_, SimState>, index: usize, disposition: Disposition) {
+        let handler = match disposition {
+            Disposition::ResetCard | Disposition::UnpowerCard => state.reset_card(index),
+            Disposition::LeaveCard | Disposition::EjectCard => None,
+        };
+        drop(state);
+        if let Some(handler) = handler {
+            handler.lock().unwrap_or_else(|err| err.into_inner()).reset();
+        }
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Simulator {
+        Simulator::new()
+    }
+}
+
+impl Backend for Simulator {
+    fn establish(&self, _scope: Scope) -> Result<ffi::SCARDCONTEXT, Error> {
+        let mut state = self.lock();
+        let handle = state.new_handle() as ffi::SCARDCONTEXT;
+        state.contexts.insert(
+            handle,
+            ContextState {
+                waiting: 0,
+                cancelled: false,
+            },
+        );
+        Ok(handle)
+    }
+
+    fn release(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error> {
+        let mut state = self.lock();
+        state.contexts.remove(&context).ok_or(Error::InvalidHandle)?;
+        Ok(())
+    }
+
+    fn is_valid(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error> {
+        self.lock().check_context(context)
+    }
+
+    fn cancel(&self, context: ffi::SCARDCONTEXT) -> Result<(), Error> {
+        let mut state = self.lock();
+        let context_state = state.contexts.get_mut(&context).ok_or(Error::InvalidHandle)?;
+        if context_state.waiting > 0 {
+            context_state.cancelled = true;
+            self.notify();
+        }
+        Ok(())
+    }
+
+    fn list_readers(&self, context: ffi::SCARDCONTEXT, buffer: Option<&mut [u8]>) -> Result<usize, Error> {
+        let state = self.lock();
+        state.check_context(context)?;
+        if state.readers.is_empty() {
+            return Err(Error::NoReadersAvailable);
+        }
+        let mut names = Vec::new();
+        for reader in &state.readers {
+            names.extend_from_slice(reader.name.as_bytes_with_nul());
+        }
+        names.push(0);
+        copy_to_buffer(&names, buffer)
+    }
+
+    fn get_status_change(
+        &self,
+        context: ffi::SCARDCONTEXT,
+        timeout: Option<Duration>,
+        readers: &mut [ReaderState],
+    ) -> Result<(), Error> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let mut state = self.lock();
+        state.check_context(context)?;
+        if readers.is_empty() {
+            return Ok(());
+        }
+
+        state.contexts.get_mut(&context).unwrap().waiting += 1;
+        let result = loop {
+            let context_state = match state.contexts.get_mut(&context) {
+                Some(context_state) => context_state,
+                None => break Err(Error::InvalidHandle),
+            };
+            if context_state.cancelled {
+                context_state.cancelled = false;
+                break Err(Error::Cancelled);
+            }
+            if state.update_reader_states(readers) {
+                break Ok(());
+            }
+            state = match deadline {
+                None => self.wait(state),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break Err(Error::Timeout);
+                    }
+                    self.shared
+                        .changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|err| err.into_inner())
+                        .0
+                }
+            };
+        };
+        if let Some(context_state) = state.contexts.get_mut(&context) {
+            context_state.waiting -= 1;
+        }
+        result
+    }
+
+    fn connect(
+        &self,
+        context: ffi::SCARDCONTEXT,
+        reader: &CStr,
+        share_mode: ShareMode,
+        preferred_protocols: Protocols,
+    ) -> Result<(ffi::SCARDHANDLE, Option<Protocol>), Error> {
+        let mut state = self.lock();
+        state.check_context(context)?;
+        let index = state.reader_index(reader).ok_or(Error::UnknownReader)?;
+
+        let (card_id, protocol) = match (&state.readers[index].card, share_mode) {
+            (None, ShareMode::Direct) => (None, None),
+            (None, _) => return Err(Error::NoSmartcard),
+            (Some(InsertedCard { handler: None, .. }), ShareMode::Direct) => (None, None),
+            (Some(InsertedCard { handler: None, .. }), _) => return Err(Error::UnresponsiveCard),
+            (Some(card), ShareMode::Direct) if preferred_protocols == Protocols::UNDEFINED => (Some(card.id), None),
+            (Some(card), _) => (Some(card.id), Some(negotiate(card, preferred_protocols)?)),
+        };
+
+        for connection in state.connections.values() {
+            if connection.reader.as_c_str() == reader
+                && (connection.share_mode == ShareMode::Exclusive || share_mode == ShareMode::Exclusive)
+            {
+                return Err(Error::SharingViolation);
+            }
+        }
+
+        let handle = state.new_handle();
+        state.connections.insert(
+            handle,
+            Connection {
+                reader: reader.to_owned(),
+                card_id,
+                share_mode,
+                protocol,
+            },
+        );
+        self.notify();
+        Ok((handle, protocol))
+    }
+
+    fn reconnect(
+        &self,
+        card: ffi::SCARDHANDLE,
+        share_mode: ShareMode,
+        preferred_protocols: Protocols,
+        initialization: Disposition,
+    ) -> Result<Option<Protocol>, Error> {
+        let mut state = self.lock();
+        let connection = state.connections.get(&card).ok_or(Error::InvalidHandle)?;
+        let index = state.reader_index(&connection.reader).ok_or(Error::ReaderUnavailable)?;
+
+        let (card_id, protocol) = match (&state.readers[index].card, share_mode) {
+            (None, ShareMode::Direct) => (None, None),
+            (None, _) => return Err(Error::NoSmartcard),
+            (Some(InsertedCard { handler: None, .. }), _) => return Err(Error::UnresponsiveCard),
+            (Some(inserted), ShareMode::Direct) if preferred_protocols == Protocols::UNDEFINED => {
+                (Some(inserted.id), None)
+            }
+            (Some(inserted), _) => (Some(inserted.id), Some(negotiate(inserted, preferred_protocols)?)),
+        };
+
+        for (&handle, other) in &state.connections {
+            if handle != card
+                && other.reader == state.readers[index].name
+                && (other.share_mode == ShareMode::Exclusive || share_mode == ShareMode::Exclusive)
+            {
+                return Err(Error::SharingViolation);
+            }
+        }
+
+        let connection = state.connections.get_mut(&card).unwrap();
+        connection.card_id = card_id;
+        connection.share_mode = share_mode;
+        connection.protocol = protocol;
+        self.notify();
+        self.apply_disposition(state, index, initialization);
+        Ok(protocol)
+    }
+
+    fn disconnect(&self, card: ffi::SCARDHANDLE, disposition: Disposition) -> Result<(), Error> {
+        let mut state = self.lock();
+        let connection = state.connections.remove(&card).ok_or(Error::InvalidHandle)?;
+        self.notify();
+        if let Some(index) = state.reader_index(&connection.reader) {
+            if state.readers[index].transaction == Some(card) {
+                state.readers[index].transaction = None;
+            }
+            let card_present = match (connection.card_id, &state.readers[index].card) {
+                (Some(card_id), Some(inserted)) => card_id == inserted.id,
+                _ => false,
+            };
+            if card_present {
+                self.apply_disposition(state, index, disposition);
+            }
+        }
+        Ok(())
+    }
+
+    fn begin_transaction(&self, card: ffi::SCARDHANDLE) -> Result<(), Error> {
+        let state = self.lock();
+        let (mut state, index) = self.wait_for_access(state, card)?;
+        state.readers[index].transaction = Some(card);
+        Ok(())
+    }
+
+    fn end_transaction(&self, card: ffi::SCARDHANDLE, disposition: Disposition) -> Result<(), Error> {
+        let mut state = self.lock();
+        let index = state.check_card(card)?;
+        if state.readers[index].transaction != Some(card) {
+            return Err(Error::NotTransacted);
+        }
+        state.readers[index].transaction = None;
+        self.notify();
+        self.apply_disposition(state, index, disposition);
+        Ok(())
+    }
+
+    fn status(
+        &self,
+        card: ffi::SCARDHANDLE,
+        names_buffer: Option<&mut [u8]>,
+        atr_buffer: Option<&mut [u8]>,
+    ) -> Result<RawStatus, Error> {
+        let state = self.lock();
+        let index = state.check_card(card)?;
+        let reader = &state.readers[index];
+        let connection = &state.connections[&card];
+
+        let mut names = reader.name.as_bytes_with_nul().to_vec();
+        names.push(0);
+        let (status, atr) = match (connection.card_id, &reader.card) {
+            (Some(_), Some(inserted)) => (card_status(), &inserted.atr[..]),
+            _ => (Status::ABSENT, &[][..]),
+        };
+
+        Ok(RawStatus {
+            names_len: copy_to_buffer(&names, names_buffer)?,
+            status,
+            protocol: connection.protocol,
+            atr_len: copy_to_buffer(atr, atr_buffer)?,
+        })
+    }
+
+    fn get_attribute(
+        &self,
+        card: ffi::SCARDHANDLE,
+        attribute: Attribute,
+        buffer: Option<&mut [u8]>,
+    ) -> Result<usize, Error> {
+        let state = self.lock();
+        let index = state.check_card(card)?;
+        let reader = &state.readers[index];
+        let connection = &state.connections[&card];
+
+        if let Some(value) = reader.attributes.get(&attribute) {
+            return copy_to_buffer(value, buffer);
+        }
+        match attribute {
+            Attribute::AtrString => match (connection.card_id, &reader.card) {
+                (Some(_), Some(inserted)) => copy_to_buffer(&inserted.atr, buffer),
+                _ => Err(Error::NoSmartcard),
+            },
+            Attribute::CurrentProtocolType => {
+                let raw_protocol = connection.protocol.map_or(0, |protocol| protocol as u32);
+                copy_to_buffer(&raw_protocol.to_le_bytes(), buffer)
+            }
+            Attribute::DeviceFriendlyName | Attribute::DeviceSystemName => {
+                copy_to_buffer(reader.name.as_bytes_with_nul(), buffer)
+            }
+            _ => Err(Error::UnsupportedFeature),
+        }
+    }
+
+    fn set_attribute(&self, card: ffi::SCARDHANDLE, attribute: Attribute, attribute_data: &[u8]) -> Result<(), Error> {
+        let mut state = self.lock();
+        let index = state.check_card(card)?;
+        state.readers[index]
+            .attributes
+            .insert(attribute, attribute_data.to_vec());
+        Ok(())
+    }
+
+    fn transmit(
+        &self,
+        card: ffi::SCARDHANDLE,
+        _protocol: Protocol,
+        send_buffer: &[u8],
+        receive_buffer: &mut [u8],
+    ) -> Result<usize, (Error, usize)> {
+        let handler = {
+            let state = self.lock();
+            let (state, index) = self.wait_for_access(state, card).map_err(|err| (err, 0))?;
+            match state.readers[index].card {
+                Some(InsertedCard {
+                    handler: Some(ref handler),
+                    ..
+                }) => Arc::clone(handler),
+                _ => return Err((Error::NoSmartcard, 0)),
+            }
+        };
+
+        // The handler is called without holding the simulation lock, so
+        // that it may manipulate the simulation itself.
+        let response = handler
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .transmit(send_buffer)
+            .map_err(|err| (err, 0))?;
+        if receive_buffer.len() < response.len() {
+            return Err((Error::InsufficientBuffer, response.len()));
+        }
+        receive_buffer[..response.len()].copy_from_slice(&response);
+        Ok(response.len())
+    }
+
+    fn control(
+        &self,
+        card: ffi::SCARDHANDLE,
+        control_code: DWORD,
+        send_buffer: &[u8],
+        receive_buffer: &mut [u8],
+    ) -> Result<usize, Error> {
+        let handler = {
+            let state = self.lock();
+            let (state, index) = self.wait_for_access(state, card)?;
+            match state.readers[index].handler {
+                Some(ref handler) => Arc::clone(handler),
+                None => return Err(Error::UnsupportedFeature),
+            }
+        };
+
+        let response = handler
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .control(control_code, send_buffer)?;
+        copy_to_buffer(&response, Some(receive_buffer))
+    }
+}
+
+// The reader of the test fixtures.
+#[cfg(test)]
+const TEST_READER: &str = "Virtual Reader 00 00";
+
+// The raw commands received by a test card.
+#[cfg(test)]
+pub(crate) type Commands = Arc<Mutex<Vec<Vec<u8>>>>;
+
+#[cfg(test)]
+fn connect_test_card<F>(sim: Simulator, mut handler: F) -> (crate::Card, Commands)
+where
+    F: FnMut(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let commands = Commands::default();
+    let recorded = Arc::clone(&commands);
+    let card = VirtualCard::new(&[0x3B, 0x00], move |command: &[u8]| {
+        recorded.lock().unwrap().push(command.to_vec());
+        Ok(handler(command))
+    });
+    sim.insert_card(TEST_READER, card).unwrap();
+    let ctx = crate::Context::establish_with_backend(sim, Scope::User).unwrap();
+    let card = ctx
+        .connect(&CString::new(TEST_READER).unwrap(), ShareMode::Shared, Protocols::ANY)
+        .unwrap();
+    (card, commands)
+}
+
+// Connects to a virtual card answering with `handler`, recording the
+// commands it receives. This is the fixture of the unit tests of the
+// modules which talk to a card.
+#[cfg(test)]
+pub(crate) fn test_card<F>(handler: F) -> (crate::Card, Commands)
+where
+    F: FnMut(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let sim = Simulator::new();
+    sim.add_reader(TEST_READER).unwrap();
+    connect_test_card(sim, handler)
+}
+
+// Connects to a card in a virtual reader answering control commands with
+// `handler`.
+#[cfg(test)]
+pub(crate) fn test_reader<F>(handler: F) -> crate::Card
+where
+    F: FnMut(DWORD, &[u8]) -> Result<Vec<u8>, Error> + Send + 'static,
+{
+    let sim = Simulator::new();
+    sim.add_reader(TEST_READER).unwrap();
+    sim.set_reader_handler(TEST_READER, handler).unwrap();
+    connect_test_card(sim, |_| vec![0x90, 0x00]).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Context;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    const READER: &str = "Virtual Reader 00 00";
+    const ATR: &[u8] = &[0x3B, 0x00];
+
+    fn reader_name() -> CString {
+        CString::new(READER).unwrap()
+    }
+
+    fn setup() -> (Simulator, Context) {
+        let sim = Simulator::new();
+        sim.add_reader(READER).unwrap();
+        let ctx = Context::establish_with_backend(sim.clone(), Scope::User).unwrap();
+        (sim, ctx)
+    }
+
+    fn echo_card() -> VirtualCard {
+        VirtualCard::new(ATR, |command: &[u8]| {
+            let mut response = command.to_vec();
+            response.extend_from_slice(&[0x90, 0x00]);
+            Ok(response)
+        })
+    }
+
+    // A card counting its resets.
+    struct ResetCounter(Arc<AtomicUsize>);
+
+    impl CardHandler for ResetCounter {
+        fn transmit(&mut self, _command: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(vec![0x90, 0x00])
+        }
+
+        fn reset(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // Returns the current state of the reader, as seen by a new caller.
+    fn current_state(ctx: &Context, name: CString) -> ReaderState {
+        let mut readers = [ReaderState::new(name, State::UNAWARE)];
+        ctx.get_status_change(Duration::from_secs(0), &mut readers).unwrap();
+        let [mut rs] = readers;
+        rs.sync_current_state();
+        rs
+    }
+
+    #[test]
+    fn transmit() {
+        let (sim, ctx) = setup();
+        sim.insert_card(READER, echo_card()).unwrap();
+        let card = ctx.connect(&reader_name(), ShareMode::Shared, Protocols::ANY).unwrap();
+        let mut buffer = [0; 16];
+        assert_eq!(
+            card.transmit(&[0x00, 0xB0], &mut buffer).unwrap(),
+            [0x00, 0xB0, 0x90, 0x00]
+        );
+        assert_eq!(card.transmit(&[0; 15], &mut buffer), Err(Error::InsufficientBuffer));
+
+        sim.remove_card(READER).unwrap();
+        assert_eq!(card.transmit(&[0x00, 0xB0], &mut buffer), Err(Error::RemovedCard));
+        sim.insert_card(READER, echo_card()).unwrap();
+        assert_eq!(card.transmit(&[0x00, 0xB0], &mut buffer), Err(Error::RemovedCard));
+    }
+
+    #[test]
+    fn connect_without_card() {
+        let (sim, ctx) = setup();
+        let result = ctx.connect(&reader_name(), ShareMode::Shared, Protocols::ANY);
+        assert_eq!(result.err(), Some(Error::NoSmartcard));
+        sim.insert_card(READER, VirtualCard::mute()).unwrap();
+        let result = ctx.connect(&reader_name(), ShareMode::Shared, Protocols::ANY);
+        assert_eq!(result.err(), Some(Error::UnresponsiveCard));
+    }
+
+    #[test]
+    fn insert_and_remove_wake_waiters() {
+        let (sim, ctx) = setup();
+        let rs = current_state(&ctx, reader_name());
+        assert_eq!(rs.event_state(), State::EMPTY | State::CHANGED);
+
+        let waiter = {
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                let mut readers = [rs];
+                ctx.get_status_change(Duration::from_secs(10), &mut readers).unwrap();
+                let [rs] = readers;
+                rs
+            })
+        };
+        sim.insert_card(READER, echo_card()).unwrap();
+        let mut rs = waiter.join().unwrap();
+        assert_eq!(rs.event_state(), State::PRESENT | State::CHANGED);
+        assert_eq!(rs.atr(), ATR);
+        assert_eq!(rs.event_count(), 1);
+
+        rs.sync_current_state();
+        let waiter = {
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                let mut readers = [rs];
+                ctx.get_status_change(Duration::from_secs(10), &mut readers).unwrap();
+                let [rs] = readers;
+                rs
+            })
+        };
+        sim.remove_card(READER).unwrap();
+        let rs = waiter.join().unwrap();
+        assert_eq!(rs.event_state(), State::EMPTY | State::CHANGED);
+        assert_eq!(rs.atr(), b"");
+        assert_eq!(rs.event_count(), 2);
+    }
+
+    #[test]
+    fn get_status_change_timeout() {
+        let (_sim, ctx) = setup();
+        let mut readers = [current_state(&ctx, reader_name())];
+        let result = ctx.get_status_change(Duration::from_millis(10), &mut readers);
+        assert_eq!(result, Err(Error::Timeout));
+    }
+
+    #[test]
+    fn event_count() {
+        let (sim, ctx) = setup();
+        sim.insert_card(READER, echo_card()).unwrap();
+        sim.remove_card(READER).unwrap();
+        let mut readers = [current_state(&ctx, reader_name())];
+        assert_eq!(readers[0].event_count(), 2);
+        sim.insert_card(READER, echo_card()).unwrap();
+        sim.remove_card(READER).unwrap();
+
+        // The state is the same, but the count tells about the missed
+        // insertion.
+        ctx.get_status_change(Duration::from_secs(0), &mut readers).unwrap();
+        assert_eq!(readers[0].event_state(), State::EMPTY | State::CHANGED);
+        assert_eq!(readers[0].event_count(), 4);
+
+        // A caller leaving the count unset is not told.
+        let mut readers = [ReaderState::new(reader_name(), State::EMPTY)];
+        let result = ctx.get_status_change(Duration::from_millis(10), &mut readers);
+        assert_eq!(result, Err(Error::Timeout));
+        assert_eq!(readers[0].event_state(), State::EMPTY);
+        assert_eq!(readers[0].event_count(), 4);
+    }
+
+    #[test]
+    fn unknown_and_ignored_readers() {
+        let (_sim, ctx) = setup();
+        let mut readers = [
+            ReaderState::new(CString::new("Missing Reader").unwrap(), State::UNAWARE),
+            ReaderState::new(reader_name(), State::IGNORE),
+        ];
+        ctx.get_status_change(Duration::from_secs(0), &mut readers).unwrap();
+        assert_eq!(readers[0].event_state(), State::UNKNOWN | State::CHANGED);
+        assert_eq!(readers[1].event_state(), State::IGNORE);
+    }
+
+    #[test]
+    fn pnp_notification() {
+        let (sim, ctx) = setup();
+        let rs = current_state(&ctx, PNP_NOTIFICATION().to_owned());
+        assert_eq!(rs.event_count(), 1);
+
+        let waiter = {
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                let mut readers = [rs];
+                ctx.get_status_change(Duration::from_secs(10), &mut readers).unwrap();
+                let [rs] = readers;
+                rs
+            })
+        };
+        sim.add_reader("Virtual Reader 01 00").unwrap();
+        let mut rs = waiter.join().unwrap();
+        assert!(rs.event_state().contains(State::CHANGED));
+        assert_eq!(rs.event_count(), 2);
+        assert_eq!(ctx.list_readers_owned().unwrap().len(), 2);
+
+        rs.sync_current_state();
+        let mut readers = [rs];
+        let result = ctx.get_status_change(Duration::from_millis(10), &mut readers);
+        assert_eq!(result, Err(Error::Timeout));
+        sim.remove_reader("Virtual Reader 01 00").unwrap();
+        ctx.get_status_change(Duration::from_secs(0), &mut readers).unwrap();
+        assert_eq!(readers[0].event_count(), 3);
+    }
+
+    #[test]
+    fn cancel() {
+        let (_sim, ctx) = setup();
+        let rs = current_state(&ctx, reader_name());
+        let (result_tx, result_rx) = mpsc::channel();
+        {
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                let mut readers = [rs];
+                result_tx.send(ctx.get_status_change(None, &mut readers)).unwrap();
+            });
+        }
+        // Cancelling before the waiter waits is not remembered, so retry.
+        let result = loop {
+            ctx.cancel().unwrap();
+            if let Ok(result) = result_rx.recv_timeout(Duration::from_millis(1)) {
+                break result;
+            }
+        };
+        assert_eq!(result, Err(Error::Cancelled));
+    }
+
+    #[test]
+    fn disposition() {
+        let (sim, ctx) = setup();
+        let resets = Arc::new(AtomicUsize::new(0));
+        sim.insert_card(READER, VirtualCard::new(ATR, ResetCounter(resets.clone())))
+            .unwrap();
+
+        let card = ctx.connect(&reader_name(), ShareMode::Shared, Protocols::ANY).unwrap();
+        card.disconnect(Disposition::LeaveCard).map_err(|(_, err)| err).unwrap();
+        assert_eq!(resets.load(Ordering::SeqCst), 0);
+
+        let mut card = ctx.connect(&reader_name(), ShareMode::Shared, Protocols::ANY).unwrap();
+        let tx = card.transaction().unwrap();
+        tx.end(Disposition::ResetCard).map_err(|(_, err)| err).unwrap();
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+
+        card.reconnect(ShareMode::Shared, Protocols::ANY, Disposition::UnpowerCard)
+            .unwrap();
+        assert_eq!(resets.load(Ordering::SeqCst), 2);
+
+        card.disconnect(Disposition::ResetCard).map_err(|(_, err)| err).unwrap();
+        assert_eq!(resets.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn reset_during_transmit() {
+        let (sim, ctx) = setup();
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (proceed_tx, proceed_rx) = mpsc::channel::<()>();
+        let handler = {
+            let sim = sim.clone();
+            let proceed_rx = Mutex::new(proceed_rx);
+            move |_command: &[u8]| {
+                entered_tx.send(()).unwrap();
+                proceed_rx.lock().unwrap().recv().unwrap();
+                // The handler may use the simulation while a reset waits.
+                sim.set_reader_attribute(READER, Attribute::VendorName, b"Test")
+                    .unwrap();
+                Ok(vec![0x90, 0x00])
+            }
+        };
+        sim.insert_card(READER, VirtualCard::new(ATR, handler)).unwrap();
+
+        let card = ctx.connect(&reader_name(), ShareMode::Shared, Protocols::ANY).unwrap();
+        let transmitter = thread::spawn(move || {
+            let mut buffer = [0; 2];
+            card.transmit(&[0x00, 0xB0], &mut buffer)
+                .map(|response| response.to_vec())
+        });
+        entered_rx.recv().unwrap();
+
+        let other = ctx.connect(&reader_name(), ShareMode::Shared, Protocols::ANY).unwrap();
+        let resetter = thread::spawn(move || other.disconnect(Disposition::ResetCard).map_err(|(_, err)| err));
+        thread::sleep(Duration::from_millis(20));
+        proceed_tx.send(()).unwrap();
+
+        assert_eq!(transmitter.join().unwrap(), Ok(vec![0x90, 0x00]));
+        assert_eq!(resetter.join().unwrap(), Ok(()));
+    }
+}
@@ -0,0 +1,353 @@
+//! Access to contactless storage cards, per PC/SC Part 3.
+//!
+//! Storage cards such as MIFARE Classic and MIFARE Ultralight do not
+//! understand APDUs. Instead, PC/SC readers accept pseudo-APDUs with the
+//! class byte FF, and translate them to the card's native commands.
+//! [`StorageCard`](struct.StorageCard.html) sends these pseudo-APDUs, and
+//! decodes their PC/SC Part 3 status words into a
+//! [`StorageError`](enum.StorageError.html).
+//!
+//! ```no_run
+//! use pcsc::storage::{KeyLocation, KeyType, StorageCard};
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//!
+//! // Read block 4 of a MIFARE Classic card, with the transport key.
+//! let storage = StorageCard::new(&card);
+//! println!("UID: {:02X?}", storage.uid().unwrap());
+//! storage.load_key(0, &[0xFF; 6], KeyLocation::Volatile).unwrap();
+//! storage.authenticate(4, KeyType::A, 0).unwrap();
+//! let block = storage.read_binary(4, 16).unwrap();
+//! ```
+
+use std::error;
+use std::fmt;
+
+use crate::apdu::{ApduError, Command, StatusWord, MAX_SHORT_LE};
+use crate::{Card, Error};
+
+/// An error accessing a storage card.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    /// The PC/SC call failed.
+    Pcsc(Error),
+    /// The response is shorter than a status word.
+    MalformedResponse,
+    /// The reader or card does not support the command (6A81).
+    NotSupported,
+    /// The addressed block does not exist (6A82, or 6581 for GENERAL
+    /// AUTHENTICATE).
+    OutOfRange,
+    /// The end of the data was reached before the expected length (6282).
+    EndOfData,
+    /// Part of the returned data may be corrupted (6281).
+    CorruptedData,
+    /// The write failed (6581).
+    MemoryFailure,
+    /// The block requires authentication first (6982).
+    SecurityStatusNotSatisfied,
+    /// The authentication failed (6300 or 6983).
+    AuthenticationFailed,
+    /// The key number is not valid (6988).
+    InvalidKeyNumber,
+    /// The key length is not correct (6989).
+    InvalidKeyLength,
+    /// The key cannot be used, or its type is not known (6984 or 6986).
+    KeyNotUsable,
+    /// The reader cannot store the key as requested (6982 to 6987, for
+    /// LOAD KEYS).
+    KeyStorageNotSupported,
+    /// Any other status word which does not indicate success.
+    Status(StatusWord),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StorageError::Pcsc(ref err) => write!(f, "{}", err),
+            StorageError::MalformedResponse => f.write_str("response shorter than a status word"),
+            StorageError::NotSupported => f.write_str("command not supported"),
+            StorageError::OutOfRange => f.write_str("block out of range"),
+            StorageError::EndOfData => f.write_str("end of data reached"),
+            StorageError::CorruptedData => f.write_str("part of the data may be corrupted"),
+            StorageError::MemoryFailure => f.write_str("memory failure"),
+            StorageError::SecurityStatusNotSatisfied => f.write_str("security status not satisfied"),
+            StorageError::AuthenticationFailed => f.write_str("authentication failed"),
+            StorageError::InvalidKeyNumber => f.write_str("invalid key number"),
+            StorageError::InvalidKeyLength => f.write_str("invalid key length"),
+            StorageError::KeyNotUsable => f.write_str("key not usable"),
+            StorageError::KeyStorageNotSupported => f.write_str("key storage not supported"),
+            StorageError::Status(sw) => write!(f, "{}", sw),
+        }
+    }
+}
+
+impl error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            StorageError::Pcsc(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for StorageError {
+    fn from(err: Error) -> StorageError {
+        StorageError::Pcsc(err)
+    }
+}
+
+/// The type of a MIFARE Classic key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    A,
+    B,
+}
+
+/// Where the reader stores a loaded key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyLocation {
+    /// In volatile memory, until the reader is reset.
+    Volatile,
+    /// In non-volatile memory.
+    NonVolatile,
+}
+
+// The pseudo-APDUs, which give the same status words different meanings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instruction {
+    GetData,
+    LoadKeys,
+    GeneralAuthenticate,
+    ReadBinary,
+    UpdateBinary,
+}
+
+fn decode_status(instruction: Instruction, status: StatusWord) -> StorageError {
+    match (instruction, status) {
+        (_, StatusWord::FunctionNotSupported) => StorageError::NotSupported,
+        (_, StatusWord::CorruptedData) => StorageError::CorruptedData,
+        (_, StatusWord::EndOfFile) => StorageError::EndOfData,
+        (Instruction::LoadKeys, StatusWord::SecureMessagingDataMissing) => StorageError::KeyStorageNotSupported,
+        (_, StatusWord::SecureMessagingDataIncorrect) => StorageError::InvalidKeyNumber,
+        (_, StatusWord::Other(0x69, 0x89)) => StorageError::InvalidKeyLength,
+        (Instruction::LoadKeys, StatusWord::SecurityStatusNotSatisfied)
+        | (Instruction::LoadKeys, StatusWord::AuthenticationBlocked)
+        | (Instruction::LoadKeys, StatusWord::ReferenceDataNotUsable)
+        | (Instruction::LoadKeys, StatusWord::ConditionsNotSatisfied)
+        | (Instruction::LoadKeys, StatusWord::NoCurrentEf) => StorageError::KeyStorageNotSupported,
+        (Instruction::GeneralAuthenticate, StatusWord::WarningChanged)
+        | (Instruction::GeneralAuthenticate, StatusWord::AuthenticationBlocked) => StorageError::AuthenticationFailed,
+        (Instruction::GeneralAuthenticate, StatusWord::ReferenceDataNotUsable)
+        | (Instruction::GeneralAuthenticate, StatusWord::NoCurrentEf) => StorageError::KeyNotUsable,
+        (Instruction::GeneralAuthenticate, StatusWord::MemoryFailure) => StorageError::OutOfRange,
+        (_, StatusWord::MemoryFailure) => StorageError::MemoryFailure,
+        (_, StatusWord::SecurityStatusNotSatisfied) => StorageError::SecurityStatusNotSatisfied,
+        (_, StatusWord::FileNotFound) => StorageError::OutOfRange,
+        (_, status) => StorageError::Status(status),
+    }
+}
+
+/// A contactless storage card, accessed with PC/SC Part 3
+/// pseudo-APDUs.
+///
+/// The pseudo-APDUs are answered by the reader, so they are sent with
+/// `Card::transmit()`; the card's `ApduOptions` do not apply. Blocks are
+/// addressed by number, as defined by the card: for example 16 byte blocks
+/// for MIFARE Classic, and 4 byte pages for MIFARE Ultralight.
+pub struct StorageCard<'card> {
+    card: &'card Card,
+}
+
+impl<'card> StorageCard<'card> {
+    /// Access `card` as a storage card.
+    pub fn new(card: &'card Card) -> StorageCard<'card> {
+        StorageCard { card }
+    }
+
+    /// The card this storage card is accessed through.
+    pub fn card(&self) -> &'card Card {
+        self.card
+    }
+
+    fn exchange(&self, instruction: Instruction, command: &Command) -> Result<Vec<u8>, StorageError> {
+        let response = match self.card.exchange_pseudo(command) {
+            Ok(response) => response,
+            Err(ApduError::Pcsc(err)) => return Err(StorageError::Pcsc(err)),
+            Err(ApduError::InvalidCommand(_)) => return Err(StorageError::Pcsc(Error::InvalidParameter)),
+            Err(_) => return Err(StorageError::MalformedResponse),
+        };
+        if response.status() == StatusWord::Success {
+            Ok(response.into_data())
+        } else {
+            Err(decode_status(instruction, response.status()))
+        }
+    }
+
+    fn get_data(&self, p1: u8) -> Result<Vec<u8>, StorageError> {
+        let command = Command::new(0xFF, 0xCA, p1, 0x00).with_le(MAX_SHORT_LE);
+        self.exchange(Instruction::GetData, &command)
+    }
+
+    /// Get the UID of the card (GET DATA, FF CA 00 00).
+    pub fn uid(&self) -> Result<Vec<u8>, StorageError> {
+        self.get_data(0x00)
+    }
+
+    /// Get the historical bytes of the card (GET DATA, FF CA 01 00).
+    ///
+    /// For an ISO 14443-4 type A card, these are the historical bytes of
+    /// its ATS.
+    pub fn historical_bytes(&self) -> Result<Vec<u8>, StorageError> {
+        self.get_data(0x01)
+    }
+
+    /// Load a key into the reader (LOAD KEYS, FF 82).
+    ///
+    /// The key is sent in plain to the reader, and stored as key
+    /// `key_number` for use with `authenticate()`.
+    pub fn load_key(&self, key_number: u8, key: &[u8], location: KeyLocation) -> Result<(), StorageError> {
+        let key_structure = match location {
+            KeyLocation::Volatile => 0x00,
+            KeyLocation::NonVolatile => 0x20,
+        };
+        let command = Command::new(0xFF, 0x82, key_structure, key_number).with_data(key);
+        self.exchange(Instruction::LoadKeys, &command).map(drop)
+    }
+
+    /// Authenticate to a block with a loaded key (GENERAL AUTHENTICATE,
+    /// FF 86).
+    pub fn authenticate(&self, block: u16, key_type: KeyType, key_number: u8) -> Result<(), StorageError> {
+        let [msb, lsb] = block.to_be_bytes();
+        let key_type = match key_type {
+            KeyType::A => 0x60,
+            KeyType::B => 0x61,
+        };
+        let command = Command::new(0xFF, 0x86, 0x00, 0x00).with_data(&[0x01, msb, lsb, key_type, key_number]);
+        self.exchange(Instruction::GeneralAuthenticate, &command).map(drop)
+    }
+
+    /// Read `len` bytes starting at a block (READ BINARY, FF B0).
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `len` is 0 or over `MAX_SHORT_LE`.
+    pub fn read_binary(&self, block: u16, len: usize) -> Result<Vec<u8>, StorageError> {
+        assert!(len > 0 && len <= MAX_SHORT_LE);
+        let [msb, lsb] = block.to_be_bytes();
+        let command = Command::new(0xFF, 0xB0, msb, lsb).with_le(len);
+        self.exchange(Instruction::ReadBinary, &command)
+    }
+
+    /// Write data starting at a block (UPDATE BINARY, FF D6).
+    ///
+    /// The data length must be what the card expects, usually a whole
+    /// block.
+    pub fn update_binary(&self, block: u16, data: &[u8]) -> Result<(), StorageError> {
+        let [msb, lsb] = block.to_be_bytes();
+        let command = Command::new(0xFF, 0xD6, msb, lsb).with_data(data);
+        self.exchange(Instruction::UpdateBinary, &command).map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apdu::ApduOptions;
+    use crate::simulator::{test_card, Commands};
+
+    const UID: &[u8] = &[0x04, 0x11, 0x22, 0x33];
+
+    // A reader with a card of four 16-byte blocks, which requires
+    // authentication with key A FFFFFFFFFFFF.
+    fn connect() -> (Card, Commands) {
+        let mut blocks = [[0u8; 16]; 4];
+        let mut key = None;
+        let mut authenticated = false;
+        test_card(move |bytes| {
+            let command = Command::parse(bytes).unwrap();
+            let block = u16::from_be_bytes([command.p1(), command.p2()]) as usize;
+            match command.ins() {
+                0xCA if command.p1() == 0x00 => [UID, &[0x90, 0x00]].concat(),
+                0xCA => vec![0x61, 0x10],
+                0x82 if command.p1() == 0x20 => vec![0x69, 0x86],
+                0x82 => {
+                    key = Some(command.data().to_vec());
+                    vec![0x90, 0x00]
+                }
+                0x86 => {
+                    authenticated = key.as_deref() == Some(&[0xFF; 6][..]) && command.data()[3] == 0x60;
+                    if authenticated {
+                        vec![0x90, 0x00]
+                    } else {
+                        vec![0x63, 0x00]
+                    }
+                }
+                _ if !authenticated => vec![0x69, 0x82],
+                _ if block >= blocks.len() => vec![0x6A, 0x82],
+                0xB0 => [&blocks[block][..command.le().unwrap()], &[0x90, 0x00]].concat(),
+                0xD6 => {
+                    blocks[block].copy_from_slice(command.data());
+                    vec![0x90, 0x00]
+                }
+                _ => vec![0x6D, 0x00],
+            }
+        })
+    }
+
+    #[test]
+    fn read_and_write() {
+        let (card, commands) = connect();
+        let storage = StorageCard::new(&card);
+        assert_eq!(storage.uid().unwrap(), UID);
+        assert_eq!(
+            storage.read_binary(1, 16),
+            Err(StorageError::SecurityStatusNotSatisfied)
+        );
+
+        storage.load_key(0, &[0xFF; 6], KeyLocation::Volatile).unwrap();
+        assert_eq!(
+            storage.authenticate(1, KeyType::B, 0),
+            Err(StorageError::AuthenticationFailed)
+        );
+        storage.authenticate(1, KeyType::A, 0).unwrap();
+        storage.update_binary(1, &[0x42; 16]).unwrap();
+        assert_eq!(storage.read_binary(1, 4).unwrap(), [0x42; 4]);
+        assert_eq!(storage.read_binary(4, 16), Err(StorageError::OutOfRange));
+
+        assert_eq!(
+            commands.lock().unwrap()[..4],
+            [
+                vec![0xFF, 0xCA, 0x00, 0x00, 0x00],
+                vec![0xFF, 0xB0, 0x00, 0x01, 0x10],
+                vec![0xFF, 0x82, 0x00, 0x00, 0x06, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+                vec![0xFF, 0x86, 0x00, 0x00, 0x05, 0x01, 0x00, 0x01, 0x61, 0x00],
+            ]
+        );
+    }
+
+    #[test]
+    fn load_key_status() {
+        let (card, _) = connect();
+        let storage = StorageCard::new(&card);
+        assert_eq!(
+            storage.load_key(0, &[0xFF; 6], KeyLocation::NonVolatile),
+            Err(StorageError::KeyStorageNotSupported)
+        );
+    }
+
+    #[test]
+    fn apdu_options_do_not_apply() {
+        let (mut card, commands) = connect();
+        card.set_apdu_options(ApduOptions::new().with_get_response(true));
+        let storage = StorageCard::new(&card);
+        assert_eq!(
+            storage.historical_bytes(),
+            Err(StorageError::Status(StatusWord::new(0x61, 0x10)))
+        );
+        assert_eq!(commands.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,848 @@
+//! BER-TLV data objects, as used by ISO 7816-4 card applications.
+//!
+//! Responses of card applications, such as the FCI template returned by
+//! SELECT, are usually sequences of BER-TLV data objects: a tag of one to
+//! four bytes, which also tells if the object is primitive or constructed,
+//! a definite length, and the value. The value of a constructed object is
+//! itself a sequence of data objects.
+//!
+//! [`parse()`](fn.parse.html) iterates over the data objects of a byte
+//! slice without copying them, and [`Tlv`](struct.Tlv.html) gives access
+//! to the children of constructed objects, by tag or by a path such as
+//! `6F/A5/BF0C`. Both display the data objects as an indented tree.
+//! [`TlvBuilder`](struct.TlvBuilder.html) encodes nested data objects,
+//! for example for command data.
+//! [`TlvDecode`](trait.TlvDecode.html) and
+//! [`TlvEncode`](trait.TlvEncode.html) map data objects to Rust types, and
+//! can be derived for structs with the `derive` feature.
+//!
+//! Malformed data gives a [`TlvError`](enum.TlvError.html); data objects
+//! are parsed lazily, one level at a time, so deeply nested input cannot
+//! exhaust the stack.
+//!
+//! ```
+//! use pcsc::tlv::{self, Tag, TlvBuilder};
+//!
+//! let fci = TlvBuilder::new()
+//!     .with_constructed(
+//!         Tag::new(0x6F),
+//!         TlvBuilder::new()
+//!             .with_primitive(Tag::new(0x84), &[0xA0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10])
+//!             .with_constructed(
+//!                 Tag::new(0xA5),
+//!                 TlvBuilder::new().with_primitive(Tag::new(0x50), b"VISA"),
+//!             ),
+//!     )
+//!     .into_bytes();
+//!
+//! let label = tlv::parse(&fci).get("6F/A5/50").unwrap().unwrap();
+//! assert_eq!(label.value(), b"VISA");
+//! println!("{}", tlv::parse(&fci));
+//! ```
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::hex;
+
+// The nesting depth up to which data objects are displayed as a tree.
+const MAX_DISPLAY_DEPTH: usize = 32;
+
+/// An error parsing BER-TLV data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TlvError {
+    /// A data object extends past the end of the data.
+    Truncated,
+    /// A tag is longer than 4 bytes.
+    InvalidTag,
+    /// A length field is longer than 4 bytes, or uses a reserved value.
+    InvalidLength,
+    /// A length field uses the indefinite form, which ISO 7816-4 does not
+    /// allow.
+    IndefiniteLength,
+    /// A path is not a sequence of tags in hex separated by `/`.
+    InvalidPath,
+}
+
+impl fmt::Display for TlvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlvError::Truncated => f.write_str("truncated data object"),
+            TlvError::InvalidTag => f.write_str("invalid tag"),
+            TlvError::InvalidLength => f.write_str("invalid length"),
+            TlvError::IndefiniteLength => f.write_str("indefinite length"),
+            TlvError::InvalidPath => f.write_str("invalid data object path"),
+        }
+    }
+}
+
+impl error::Error for TlvError {}
+
+/// The class of a tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Universal,
+    Application,
+    ContextSpecific,
+    Private,
+}
+
+/// A BER-TLV tag.
+///
+/// The tag is represented by its encoding as a big-endian number, for
+/// example `0x5F20` for the cardholder name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tag(u32);
+
+impl Tag {
+    /// Create a tag from its encoding.
+    pub const fn new(tag: u32) -> Tag {
+        Tag(tag)
+    }
+
+    /// The encoding of the tag, as a big-endian number.
+    pub fn value(self) -> u32 {
+        self.0
+    }
+
+    /// The encoding of the tag.
+    pub fn to_bytes(self) -> Vec<u8> {
+        let bytes = self.0.to_be_bytes();
+        let skip = bytes.iter().take(3).take_while(|&&byte| byte == 0x00).count();
+        bytes[skip..].to_vec()
+    }
+
+    fn first_byte(self) -> u8 {
+        self.to_bytes()[0]
+    }
+
+    /// The class of the tag.
+    pub fn class(self) -> Class {
+        match self.first_byte() >> 6 {
+            0 => Class::Universal,
+            1 => Class::Application,
+            2 => Class::ContextSpecific,
+            _ => Class::Private,
+        }
+    }
+
+    /// Whether the tag is of a constructed data object, whose value is a
+    /// sequence of data objects.
+    pub fn is_constructed(self) -> bool {
+        self.first_byte() & 0x20 != 0
+    }
+
+    // Parse a tag, returning it with the rest of the data.
+    fn parse(bytes: &[u8]) -> Result<(Tag, &[u8]), TlvError> {
+        let (&first, mut rest) = bytes.split_first().ok_or(TlvError::Truncated)?;
+        let mut tag = u32::from(first);
+        if first & 0x1F == 0x1F {
+            // Subsequent bytes, the last one with b8 clear.
+            let mut len = 1;
+            loop {
+                let (&byte, tail) = rest.split_first().ok_or(TlvError::Truncated)?;
+                len += 1;
+                if len > 4 {
+                    return Err(TlvError::InvalidTag);
+                }
+                tag = tag << 8 | u32::from(byte);
+                rest = tail;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+        }
+        Ok((Tag(tag), rest))
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(&self.to_bytes()))
+    }
+}
+
+impl FromStr for Tag {
+    type Err = TlvError;
+
+    /// Parse a tag in hex, such as `5F20`.
+    fn from_str(s: &str) -> Result<Tag, TlvError> {
+        let bytes = hex::decode(s).ok_or(TlvError::InvalidPath)?;
+        match Tag::parse(&bytes) {
+            Ok((tag, &[])) => Ok(tag),
+            _ => Err(TlvError::InvalidPath),
+        }
+    }
+}
+
+// Parse a length field, returning it with the rest of the data.
+fn parse_length(bytes: &[u8]) -> Result<(usize, &[u8]), TlvError> {
+    let (&first, rest) = bytes.split_first().ok_or(TlvError::Truncated)?;
+    match first {
+        0x00..=0x7F => Ok((first as usize, rest)),
+        0x80 => Err(TlvError::IndefiniteLength),
+        0x81..=0x84 => {
+            let count = (first & 0x7F) as usize;
+            if rest.len() < count {
+                return Err(TlvError::Truncated);
+            }
+            let (len, rest) = rest.split_at(count);
+            let len = len.iter().fold(0u64, |len, &byte| len << 8 | u64::from(byte));
+            Ok((len as usize, rest))
+        }
+        _ => Err(TlvError::InvalidLength),
+    }
+}
+
+/// Append the encoding of a length field to `out`, in the shortest
+/// definite form.
+pub fn encode_length(len: usize, out: &mut Vec<u8>) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = (len as u64).to_be_bytes();
+        let skip = bytes.iter().take_while(|&&byte| byte == 0x00).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+}
+
+/// Append the encoding of a data object to `out`.
+pub fn encode(tag: Tag, value: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&tag.to_bytes());
+    encode_length(value.len(), out);
+    out.extend_from_slice(value);
+}
+
+/// A BER-TLV data object, borrowed from the parsed data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tlv<'a> {
+    tag: Tag,
+    value: &'a [u8],
+    raw: &'a [u8],
+}
+
+impl<'a> Tlv<'a> {
+    /// Parse the data object at the start of `bytes`, returning it with
+    /// the rest of the data.
+    pub fn parse(bytes: &'a [u8]) -> Result<(Tlv<'a>, &'a [u8]), TlvError> {
+        let (tag, rest) = Tag::parse(bytes)?;
+        let (len, rest) = parse_length(rest)?;
+        if rest.len() < len {
+            return Err(TlvError::Truncated);
+        }
+        let (value, rest) = rest.split_at(len);
+        let raw = &bytes[..bytes.len() - rest.len()];
+        Ok((Tlv { tag, value, raw }, rest))
+    }
+
+    /// The tag of the data object.
+    pub fn tag(&self) -> Tag {
+        self.tag
+    }
+
+    /// The value of the data object.
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// The encoding of the data object: tag, length and value.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    /// Whether the data object is constructed.
+    pub fn is_constructed(&self) -> bool {
+        self.tag.is_constructed()
+    }
+
+    /// The data objects in the value of a constructed data object. A
+    /// primitive data object has none.
+    pub fn children(&self) -> Tlvs<'a> {
+        if self.is_constructed() {
+            parse(self.value)
+        } else {
+            parse(&[])
+        }
+    }
+
+    /// The first child with tag `tag`.
+    pub fn find(&self, tag: Tag) -> Result<Option<Tlv<'a>>, TlvError> {
+        self.children().find_tag(tag)
+    }
+
+    /// The first descendant at `path`, a sequence of tags in hex separated
+    /// by `/`, such as `A5/BF0C`.
+    pub fn get(&self, path: &str) -> Result<Option<Tlv<'a>>, TlvError> {
+        self.children().get(path)
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let indent = depth * 2;
+        if self.is_constructed() && depth < MAX_DISPLAY_DEPTH {
+            writeln!(f, "{:indent$}{}", "", self.tag, indent = indent)?;
+            self.children().fmt_tree(f, depth + 1)
+        } else {
+            writeln!(
+                f,
+                "{:indent$}{} ({}): {}",
+                "",
+                self.tag,
+                self.value.len(),
+                hex::encode(self.value),
+                indent = indent
+            )
+        }
+    }
+}
+
+impl<'a> fmt::Display for Tlv<'a> {
+    /// Display the data object as an indented tree.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_tree(f, 0)
+    }
+}
+
+/// An iterator over a sequence of BER-TLV data objects.
+///
+/// After an error, the iterator ends.
+#[derive(Debug, Clone)]
+pub struct Tlvs<'a> {
+    rest: &'a [u8],
+}
+
+/// Parse a sequence of BER-TLV data objects.
+///
+/// The 00 bytes which ISO 7816-4 allows before, between and after data
+/// objects are skipped.
+pub fn parse(bytes: &[u8]) -> Tlvs<'_> {
+    Tlvs { rest: bytes }
+}
+
+impl<'a> Iterator for Tlvs<'a> {
+    type Item = Result<Tlv<'a>, TlvError>;
+
+    fn next(&mut self) -> Option<Result<Tlv<'a>, TlvError>> {
+        let padding = self.rest.iter().take_while(|&&byte| byte == 0x00).count();
+        self.rest = &self.rest[padding..];
+        if self.rest.is_empty() {
+            return None;
+        }
+        match Tlv::parse(self.rest) {
+            Ok((tlv, rest)) => {
+                self.rest = rest;
+                Some(Ok(tlv))
+            }
+            Err(err) => {
+                self.rest = &[];
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<'a> Tlvs<'a> {
+    /// The first data object with tag `tag`.
+    pub fn find_tag(self, tag: Tag) -> Result<Option<Tlv<'a>>, TlvError> {
+        for tlv in self {
+            let tlv = tlv?;
+            if tlv.tag == tag {
+                return Ok(Some(tlv));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The first data object at `path`, a sequence of tags in hex separated
+    /// by `/`, such as `6F/A5/BF0C`.
+    ///
+    /// At each level, the first data object with the tag is followed.
+    pub fn get(self, path: &str) -> Result<Option<Tlv<'a>>, TlvError> {
+        let tags = path
+            .split('/')
+            .map(Tag::from_str)
+            .collect::<Result<Vec<Tag>, TlvError>>()?;
+        let mut tlvs = self;
+        let mut found = None;
+        for tag in tags {
+            let tlv = match tlvs.find_tag(tag)? {
+                Some(tlv) => tlv,
+                None => return Ok(None),
+            };
+            tlvs = tlv.children();
+            found = Some(tlv);
+        }
+        Ok(found)
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        for tlv in self.clone() {
+            match tlv {
+                Ok(tlv) => tlv.fmt_tree(f, depth)?,
+                Err(err) => writeln!(f, "{:indent$}{}", "", err, indent = depth * 2)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for Tlvs<'a> {
+    /// Display the data objects as an indented tree.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_tree(f, 0)
+    }
+}
+
+/// A builder for the encoding of a sequence of BER-TLV data objects.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TlvBuilder {
+    bytes: Vec<u8>,
+}
+
+impl TlvBuilder {
+    /// Create an empty sequence.
+    pub fn new() -> TlvBuilder {
+        TlvBuilder { bytes: Vec::new() }
+    }
+
+    /// Append a primitive data object.
+    pub fn with_primitive(mut self, tag: Tag, value: &[u8]) -> TlvBuilder {
+        encode(tag, value, &mut self.bytes);
+        self
+    }
+
+    /// Append a constructed data object, with the data objects of
+    /// `children` as its value.
+    pub fn with_constructed(mut self, tag: Tag, children: TlvBuilder) -> TlvBuilder {
+        encode(tag, &children.bytes, &mut self.bytes);
+        self
+    }
+
+    /// The encoding of the data objects.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Take the encoding of the data objects.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// The reason a data object could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecodeErrorKind {
+    /// A required data object is missing.
+    Missing,
+    /// The data is not valid BER-TLV.
+    Malformed(TlvError),
+    /// The value of a data object is not valid for its type.
+    InvalidValue,
+}
+
+impl fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeErrorKind::Missing => f.write_str("missing data object"),
+            DecodeErrorKind::Malformed(err) => write!(f, "{}", err),
+            DecodeErrorKind::InvalidValue => f.write_str("invalid value"),
+        }
+    }
+}
+
+/// An error decoding a data object with `TlvDecode`.
+///
+/// The error tells which data object failed, by its path from the decoded
+/// data, such as `6F/A5/50`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DecodeError {
+    path: Vec<Tag>,
+    kind: DecodeErrorKind,
+}
+
+impl DecodeError {
+    /// Create an error, for the decoded data itself.
+    pub fn new(kind: DecodeErrorKind) -> DecodeError {
+        DecodeError { path: Vec::new(), kind }
+    }
+
+    /// Locate the error in the data object with tag `tag`, prepending it to
+    /// the path.
+    pub fn within(mut self, tag: Tag) -> DecodeError {
+        self.path.insert(0, tag);
+        self
+    }
+
+    /// The tags of the data objects leading to the failed data object.
+    pub fn path(&self) -> &[Tag] {
+        &self.path
+    }
+
+    /// The reason of the error.
+    pub fn kind(&self) -> DecodeErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, tag) in self.path.iter().enumerate() {
+            let separator = if i + 1 < self.path.len() { "/" } else { ": " };
+            write!(f, "{}{}", tag, separator)?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl error::Error for DecodeError {}
+
+impl From<TlvError> for DecodeError {
+    fn from(err: TlvError) -> DecodeError {
+        DecodeError::new(DecodeErrorKind::Malformed(err))
+    }
+}
+
+/// A type which can be decoded from BER-TLV data.
+///
+/// It is implemented for byte vectors and arrays, `String` (UTF-8), and
+/// unsigned integers (big-endian). With the `derive` feature, it can be
+/// derived for structs whose fields are data objects:
+///
+/// ```
+/// # #[cfg(feature = "derive")]
+/// # fn main() {
+/// use pcsc::tlv::{TlvDecode, TlvEncode};
+///
+/// #[derive(Debug, PartialEq, TlvDecode, TlvEncode)]
+/// struct ProprietaryTemplate {
+///     #[tlv(tag = 0x50)]
+///     label: Option<String>,
+///     #[tlv(tag = 0x87)]
+///     priority: Option<u8>,
+/// }
+///
+/// #[derive(Debug, PartialEq, TlvDecode, TlvEncode)]
+/// #[tlv(tag = 0x6F)]
+/// struct Fci {
+///     #[tlv(tag = 0x84)]
+///     df_name: Vec<u8>,
+///     #[tlv(tag = 0xA5)]
+///     proprietary: ProprietaryTemplate,
+/// }
+///
+/// let fci = Fci {
+///     df_name: vec![0xA0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10],
+///     proprietary: ProprietaryTemplate {
+///         label: Some("VISA".to_owned()),
+///         priority: None,
+///     },
+/// };
+/// assert_eq!(Fci::decode_tlv(&fci.encode_tlv()).unwrap(), fci);
+///
+/// let err = Fci::decode_tlv(&[0x6F, 0x05, 0xA5, 0x03, 0x87, 0x01, 0x01]).unwrap_err();
+/// assert_eq!(err.to_string(), "6F/84: missing data object");
+/// # }
+/// # #[cfg(not(feature = "derive"))]
+/// # fn main() {}
+/// ```
+///
+/// Each field has the tag of its data object, set with `#[tlv(tag = ...)]`.
+/// A field of type `Option<T>` is optional, and a field of type `Vec<T>`
+/// with `#[tlv(tag = ..., repeated)]` collects all the data objects with
+/// the tag. Data objects with other tags are ignored. A struct with a tag
+/// is decoded from the data object with that tag, otherwise from a
+/// sequence of data objects, as found in the value of a constructed data
+/// object.
+pub trait TlvDecode: Sized {
+    /// Decode the value of a data object.
+    fn decode_value(value: &[u8]) -> Result<Self, DecodeError>;
+
+    /// Decode BER-TLV data, such as a response.
+    ///
+    /// By default, the data is decoded as the value of a data object.
+    fn decode_tlv(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode_value(bytes)
+    }
+}
+
+/// A type which can be encoded as BER-TLV data.
+///
+/// See `TlvDecode` for deriving it.
+pub trait TlvEncode {
+    /// Append the encoding of the value of a data object to `out`.
+    fn encode_value(&self, out: &mut Vec<u8>);
+
+    /// Encode as BER-TLV data, such as command data.
+    ///
+    /// By default, this is the value of a data object.
+    fn encode_tlv(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_value(&mut out);
+        out
+    }
+
+    /// Append the encoding of a data object with tag `tag` and this value
+    /// to `out`.
+    fn encode_with_tag(&self, tag: Tag, out: &mut Vec<u8>) {
+        let mut value = Vec::new();
+        self.encode_value(&mut value);
+        encode(tag, &value, out);
+    }
+}
+
+impl TlvDecode for Vec<u8> {
+    fn decode_value(value: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        Ok(value.to_vec())
+    }
+}
+
+impl TlvEncode for Vec<u8> {
+    fn encode_value(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl<const N: usize> TlvDecode for [u8; N] {
+    fn decode_value(value: &[u8]) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        if value.len() != N {
+            return Err(DecodeError::new(DecodeErrorKind::InvalidValue));
+        }
+        array.copy_from_slice(value);
+        Ok(array)
+    }
+}
+
+impl<const N: usize> TlvEncode for [u8; N] {
+    fn encode_value(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl TlvDecode for String {
+    fn decode_value(value: &[u8]) -> Result<String, DecodeError> {
+        String::from_utf8(value.to_vec()).map_err(|_| DecodeError::new(DecodeErrorKind::InvalidValue))
+    }
+}
+
+impl TlvEncode for String {
+    fn encode_value(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+macro_rules! impl_tlv_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl TlvDecode for $ty {
+                /// Decode a big-endian integer, of at most the size of the
+                /// type.
+                fn decode_value(value: &[u8]) -> Result<$ty, DecodeError> {
+                    if value.is_empty() || value.len() > std::mem::size_of::<$ty>() {
+                        return Err(DecodeError::new(DecodeErrorKind::InvalidValue));
+                    }
+                    Ok(value.iter().fold(0u64, |n, &byte| n << 8 | u64::from(byte)) as $ty)
+                }
+            }
+
+            impl TlvEncode for $ty {
+                /// Encode a big-endian integer, of the size of the type.
+                fn encode_value(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_tlv_integer!(u8, u16, u32, u64);
+
+#[cfg(feature = "derive")]
+pub use pcsc_derive::{TlvDecode, TlvEncode};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_one(bytes: &[u8]) -> Result<Tlv<'_>, TlvError> {
+        Tlv::parse(bytes).map(|(tlv, _)| tlv)
+    }
+
+    #[test]
+    fn tags() {
+        let (tlv, rest) = Tlv::parse(&[0x5F, 0x20, 0x01, 0x41, 0x90]).unwrap();
+        assert_eq!(tlv.tag(), Tag::new(0x5F20));
+        assert_eq!(tlv.value(), b"A");
+        assert_eq!(tlv.as_bytes(), [0x5F, 0x20, 0x01, 0x41]);
+        assert_eq!(rest, [0x90]);
+
+        let tlv = parse_one(&[0xDF, 0x81, 0x82, 0x03, 0x00]).unwrap();
+        assert_eq!(tlv.tag(), Tag::new(0xDF81_8203));
+        assert_eq!(tlv.tag().to_bytes(), [0xDF, 0x81, 0x82, 0x03]);
+        assert_eq!(tlv.tag().class(), Class::Private);
+        assert!(!tlv.is_constructed());
+
+        // 5-byte tags are not allowed.
+        assert_eq!(
+            parse_one(&[0xDF, 0x81, 0x82, 0x83, 0x04, 0x00]),
+            Err(TlvError::InvalidTag)
+        );
+
+        assert_eq!(Tag::new(0x6F).class(), Class::Application);
+        assert!(Tag::new(0xBF0C).is_constructed());
+        assert_eq!(Tag::new(0x9F38).to_string(), "9F38");
+        assert_eq!("bf0c".parse::<Tag>(), Ok(Tag::new(0xBF0C)));
+        assert_eq!("5F".parse::<Tag>(), Err(TlvError::InvalidPath));
+        assert_eq!("8484".parse::<Tag>(), Err(TlvError::InvalidPath));
+        assert_eq!("xy".parse::<Tag>(), Err(TlvError::InvalidPath));
+    }
+
+    #[test]
+    fn truncated() {
+        for bytes in [
+            &[0x5F][..],
+            &[0x5F, 0x81],
+            &[0x84],
+            &[0x84, 0x81],
+            &[0x84, 0x82, 0x01],
+            &[0x84, 0x02, 0x00],
+            &[0x84, 0x81, 0x80],
+        ] {
+            assert_eq!(parse_one(bytes), Err(TlvError::Truncated), "{:02X?}", bytes);
+        }
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(parse_one(&[0x84, 0x80, 0x00, 0x00]), Err(TlvError::IndefiniteLength));
+        assert_eq!(
+            parse_one(&[0x84, 0x85, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            Err(TlvError::InvalidLength)
+        );
+        assert_eq!(parse_one(&[0x84, 0xFF]), Err(TlvError::InvalidLength));
+        assert_eq!(
+            parse_one(&[0x84, 0x84, 0xFF, 0xFF, 0xFF, 0xFF]),
+            Err(TlvError::Truncated)
+        );
+
+        for &len in &[0, 0x7F, 0x80, 0xFF, 0x100, 0x1_0000] {
+            let value = vec![0xAA; len];
+            let mut bytes = Vec::new();
+            encode(Tag::new(0x53), &value, &mut bytes);
+            let header_len = match len {
+                0..=0x7F => 2,
+                0x80..=0xFF => 3,
+                0x100..=0xFFFF => 4,
+                _ => 5,
+            };
+            assert_eq!(bytes.len(), header_len + len);
+            assert_eq!(parse_one(&bytes).unwrap().value(), &value[..]);
+        }
+        // Non-minimal lengths are accepted.
+        assert_eq!(parse_one(&[0x84, 0x82, 0x00, 0x01, 0xAA]).unwrap().value(), [0xAA]);
+    }
+
+    #[test]
+    fn sequence() {
+        let tlvs: Vec<_> = parse(&[0x00, 0x84, 0x01, 0xAA, 0x00, 0x00, 0x50, 0x00, 0x00]).collect();
+        assert_eq!(tlvs.len(), 2);
+        assert_eq!(tlvs[0].unwrap().value(), [0xAA]);
+        assert_eq!(tlvs[1].unwrap().tag(), Tag::new(0x50));
+
+        // The iterator ends after an error.
+        let mut tlvs = parse(&[0x84, 0x01, 0xAA, 0x50, 0x05, 0x84, 0x00]);
+        assert!(tlvs.next().unwrap().is_ok());
+        assert_eq!(tlvs.next(), Some(Err(TlvError::Truncated)));
+        assert_eq!(tlvs.next(), None);
+    }
+
+    fn fci() -> Vec<u8> {
+        TlvBuilder::new()
+            .with_constructed(
+                Tag::new(0x6F),
+                TlvBuilder::new()
+                    .with_primitive(Tag::new(0x84), &[0xA0, 0x00, 0x00, 0x00, 0x03])
+                    .with_constructed(
+                        Tag::new(0xA5),
+                        TlvBuilder::new()
+                            .with_primitive(Tag::new(0x50), b"VISA")
+                            .with_constructed(
+                                Tag::new(0xBF0C),
+                                TlvBuilder::new().with_primitive(Tag::new(0x9F4D), &[0x0B, 0x0A]),
+                            ),
+                    ),
+            )
+            .into_bytes()
+    }
+
+    #[test]
+    fn builder() {
+        assert_eq!(
+            fci(),
+            [
+                0x6F, 0x17, 0x84, 0x05, 0xA0, 0x00, 0x00, 0x00, 0x03, 0xA5, 0x0E, 0x50, 0x04, 0x56, 0x49, 0x53, 0x41,
+                0xBF, 0x0C, 0x05, 0x9F, 0x4D, 0x02, 0x0B, 0x0A,
+            ]
+        );
+        assert_eq!(TlvBuilder::new().as_bytes(), b"");
+    }
+
+    #[test]
+    fn paths() {
+        let fci = fci();
+        assert_eq!(
+            parse(&fci).get("6F/A5/BF0C/9F4D").unwrap().unwrap().value(),
+            [0x0B, 0x0A]
+        );
+        let a5 = parse(&fci).get("6F/A5").unwrap().unwrap();
+        assert_eq!(a5.get("50").unwrap().unwrap().value(), b"VISA");
+        assert_eq!(a5.find(Tag::new(0x50)).unwrap().unwrap().value(), b"VISA");
+        assert_eq!(a5.find(Tag::new(0x84)).unwrap(), None);
+        assert_eq!(parse(&fci).get("6F/A5/87").unwrap(), None);
+        assert_eq!(parse(&fci).get("6F/88").unwrap(), None);
+        // Primitive data objects have no children.
+        assert_eq!(parse(&fci).get("6F/84/A0").unwrap(), None);
+        assert_eq!(parse(&fci).get("6F//A5"), Err(TlvError::InvalidPath));
+        assert_eq!(parse(&fci).get("6F/A5G"), Err(TlvError::InvalidPath));
+        assert_eq!(
+            parse(&[0x6F, 0x02, 0xA5, 0x05]).get("6F/A5/50"),
+            Err(TlvError::Truncated)
+        );
+    }
+
+    #[test]
+    fn display() {
+        let fci = fci();
+        assert_eq!(
+            parse(&fci).to_string(),
+            "6F\n  84 (5): A000000003\n  A5\n    50 (4): 56495341\n    BF0C\n      9F4D (2): 0B0A\n"
+        );
+        assert_eq!(
+            parse(&[0x6F, 0x02, 0x84, 0x05]).to_string(),
+            "6F\n  truncated data object\n"
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let mut bytes = vec![0x80, 0x00];
+        for _ in 0..1000 {
+            let mut outer = Vec::new();
+            encode(Tag::new(0xA0), &bytes, &mut outer);
+            bytes = outer;
+        }
+
+        // Display stops nesting at MAX_DISPLAY_DEPTH.
+        let display = parse(&bytes).to_string();
+        assert_eq!(display.lines().count(), MAX_DISPLAY_DEPTH + 1);
+        assert!(display
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with(&" ".repeat(MAX_DISPLAY_DEPTH * 2)));
+
+        let path = vec!["A0"; 1000].join("/") + "/80";
+        assert_eq!(parse(&bytes).get(&path).unwrap().unwrap().value(), b"");
+    }
+}
@@ -0,0 +1,813 @@
+//! Recording and replaying APDU traffic.
+//!
+//! A [`Recorder`](struct.Recorder.html) wraps a `Card` and records every
+//! command and response exchanged with it into a
+//! [`Trace`](struct.Trace.html). Traces can be saved as text, and loaded
+//! back with `str::parse()`.
+//!
+//! A [`Replay`](struct.Replay.html) serves a recorded trace back as a
+//! virtual card of the [`simulator`](../simulator/index.html), which is
+//! useful for regression tests of code which talks to a card. The replay
+//! panics as soon as the commands sent deviate from the recording.
+//!
+//! ## Trace format
+//!
+//! ```text
+//! pcsc-trace 1
+//! reader Gemalto PC Twin Reader 00 00
+//! atr 3B8F8001804F0CA000000306030001000000006A
+//! protocol T1
+//! 0.000012 transmit FFCA000000 04A1B2C3D49000
+//! 0.004517 transmit 0084000008 11223344556677889000
+//! 0.006120 transmit 0082000008................ 9000
+//! 0.009815 control 0x42000D48 - !0x80100022
+//! ```
+//!
+//! Each exchange line holds the time since the start of the recording in
+//! seconds, the kind of exchange (with the control code for `control`),
+//! the command and the response. Byte strings are written in hex, with `-`
+//! for an empty one. A response starting with `!` is an error code. Command
+//! bytes written as `..` are ignored during replay, see
+//! [`Exchange::ignore()`](struct.Exchange.html#method.ignore).
+
+use std::error;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::simulator::{CardHandler, ReaderHandler, Simulator, VirtualCard};
+use crate::{ffi, hex, Card, Error, Protocol};
+use ffi::{DWORD, LONG};
+
+/// The kind of a recorded exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExchangeKind {
+    /// An APDU sent with `Card::transmit()`.
+    Transmit,
+    /// A reader command sent with `Card::control()`, with its control code.
+    Control(DWORD),
+}
+
+/// A single command and its response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    /// Time since the start of the recording, when the command was sent.
+    pub elapsed: Duration,
+    /// The kind of exchange.
+    pub kind: ExchangeKind,
+    /// The command bytes.
+    pub command: Vec<u8>,
+    /// Offsets of command bytes which are not compared during replay.
+    pub ignored: Vec<usize>,
+    /// The response, or the error returned instead.
+    pub response: Result<Vec<u8>, Error>,
+}
+
+impl Exchange {
+    /// Ignore the command bytes in `range` during replay.
+    ///
+    /// This allows replaying commands which contain data which is
+    /// different on every run, such as a random challenge. The ignored
+    /// bytes are set to zero.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `range` is out of bounds of the command.
+    pub fn ignore(&mut self, range: Range<usize>) {
+        for offset in range {
+            self.command[offset] = 0;
+            if !self.ignored.contains(&offset) {
+                self.ignored.push(offset);
+            }
+        }
+        self.ignored.sort_unstable();
+    }
+
+    /// Check whether `command` matches the recorded command, skipping
+    /// ignored bytes.
+    pub fn matches(&self, command: &[u8]) -> bool {
+        command.len() == self.command.len()
+            && command
+                .iter()
+                .zip(&self.command)
+                .enumerate()
+                .all(|(offset, (a, b))| a == b || self.ignored.contains(&offset))
+    }
+}
+
+/// A recording of the traffic with a card.
+///
+/// A trace is written in its text format with `to_string()`, and read back
+/// with `str::parse()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    /// The name of the reader the card was in.
+    pub reader: String,
+    /// The card's ATR.
+    pub atr: Vec<u8>,
+    /// The active protocol.
+    pub protocol: Option<Protocol>,
+    /// The recorded exchanges, in order.
+    pub exchanges: Vec<Exchange>,
+}
+
+/// An error parsing a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTraceError {
+    line: usize,
+    message: String,
+}
+
+impl ParseTraceError {
+    fn new(line: usize, message: &str) -> ParseTraceError {
+        ParseTraceError {
+            line,
+            message: message.to_owned(),
+        }
+    }
+
+    /// The line number (starting at 1) at which the error occurred.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid trace at line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for ParseTraceError {}
+
+fn write_bytes(f: &mut fmt::Formatter, bytes: &[u8], ignored: &[usize]) -> fmt::Result {
+    if bytes.is_empty() {
+        return f.write_str("-");
+    }
+    for (offset, byte) in bytes.iter().enumerate() {
+        if ignored.contains(&offset) {
+            f.write_str("..")?;
+        } else {
+            write!(f, "{:02X}", byte)?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_bytes(s: &str) -> Option<(Vec<u8>, Vec<usize>)> {
+    if s == "-" {
+        return Some((vec![], vec![]));
+    }
+    if !s.is_ascii() || s.len() % 2 != 0 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(s.len() / 2);
+    let mut ignored = Vec::new();
+    for offset in 0..s.len() / 2 {
+        let pair = &s[offset * 2..offset * 2 + 2];
+        if pair == ".." {
+            ignored.push(offset);
+            bytes.push(0);
+        } else {
+            bytes.push(hex::decode(pair)?[0]);
+        }
+    }
+    Some((bytes, ignored))
+}
+
+fn parse_u32(s: &str) -> Option<u32> {
+    let digits = s.strip_prefix("0x")?;
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn parse_elapsed(s: &str) -> Option<Duration> {
+    let mut parts = s.splitn(2, '.');
+    let secs = parts.next()?.parse::<u64>().ok()?;
+    let nanos = match parts.next() {
+        Some(fraction)
+            if !fraction.is_empty() && fraction.len() <= 9 && fraction.bytes().all(|c| c.is_ascii_digit()) =>
+        {
+            fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32)
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    Some(Duration::new(secs, nanos))
+}
+
+fn protocol_name(protocol: Option<Protocol>) -> &'static str {
+    match protocol {
+        Some(Protocol::T0) => "T0",
+        Some(Protocol::T1) => "T1",
+        Some(Protocol::RAW) => "RAW",
+        None => "-",
+    }
+}
+
+fn parse_protocol(s: &str) -> Option<Option<Protocol>> {
+    match s {
+        "T0" => Some(Some(Protocol::T0)),
+        "T1" => Some(Some(Protocol::T1)),
+        "RAW" => Some(Some(Protocol::RAW)),
+        "-" => Some(None),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "pcsc-trace 1")?;
+        writeln!(f, "reader {}", self.reader)?;
+        f.write_str("atr ")?;
+        write_bytes(f, &self.atr, &[])?;
+        writeln!(f)?;
+        writeln!(f, "protocol {}", protocol_name(self.protocol))?;
+        for exchange in &self.exchanges {
+            write!(
+                f,
+                "{}.{:06} ",
+                exchange.elapsed.as_secs(),
+                exchange.elapsed.subsec_micros()
+            )?;
+            match exchange.kind {
+                ExchangeKind::Transmit => f.write_str("transmit ")?,
+                ExchangeKind::Control(code) => write!(f, "control {:#010X} ", code)?,
+            }
+            write_bytes(f, &exchange.command, &exchange.ignored)?;
+            f.write_str(" ")?;
+            match exchange.response {
+                Ok(ref response) => write_bytes(f, response, &[])?,
+                Err(err) => write!(f, "!{:#010X}", err as u32)?,
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Trace {
+    type Err = ParseTraceError;
+
+    fn from_str(s: &str) -> Result<Trace, ParseTraceError> {
+        let mut lines = s
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim_end()))
+            .filter(|&(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        let mut header = |key: &str| {
+            let (number, line) = lines
+                .next()
+                .ok_or_else(|| ParseTraceError::new(0, "unexpected end of trace"))?;
+            match line.strip_prefix(key) {
+                Some(rest) if rest.starts_with(' ') => Ok((number, rest[1..].to_owned())),
+                _ => Err(ParseTraceError::new(number, &format!("expected `{}`", key))),
+            }
+        };
+
+        let (number, version) = header("pcsc-trace")?;
+        if version != "1" {
+            return Err(ParseTraceError::new(number, "unsupported trace version"));
+        }
+        let (_, reader) = header("reader")?;
+        let (number, atr) = header("atr")?;
+        let atr = match parse_bytes(&atr) {
+            Some((atr, ref ignored)) if ignored.is_empty() => atr,
+            _ => return Err(ParseTraceError::new(number, "invalid ATR")),
+        };
+        let (number, protocol) = header("protocol")?;
+        let protocol = parse_protocol(&protocol).ok_or_else(|| ParseTraceError::new(number, "invalid protocol"))?;
+
+        let mut exchanges = Vec::new();
+        for (number, line) in lines {
+            let error = |message: &str| ParseTraceError::new(number, message);
+            let mut fields = line.split_whitespace();
+            let elapsed = fields
+                .next()
+                .and_then(parse_elapsed)
+                .ok_or_else(|| error("invalid time"))?;
+            let kind = match fields.next() {
+                Some("transmit") => ExchangeKind::Transmit,
+                Some("control") => {
+                    let code = fields
+                        .next()
+                        .and_then(parse_u32)
+                        .ok_or_else(|| error("invalid control code"))?;
+                    ExchangeKind::Control(code as DWORD)
+                }
+                _ => return Err(error("expected `transmit` or `control`")),
+            };
+            let (command, ignored) = fields
+                .next()
+                .and_then(parse_bytes)
+                .ok_or_else(|| error("invalid command"))?;
+            let response = match fields.next() {
+                Some(response) if response.starts_with('!') => {
+                    let err = parse_u32(&response[1..])
+                        .and_then(|raw| Error::try_from_raw(raw as LONG))
+                        .ok_or_else(|| error("invalid error code"))?;
+                    Err(err)
+                }
+                Some(response) => match parse_bytes(response) {
+                    Some((response, ref ignored)) if ignored.is_empty() => Ok(response),
+                    _ => return Err(error("invalid response")),
+                },
+                None => return Err(error("missing response")),
+            };
+            if fields.next().is_some() {
+                return Err(error("trailing characters"));
+            }
+            exchanges.push(Exchange {
+                elapsed,
+                kind,
+                command,
+                ignored,
+                response,
+            });
+        }
+
+        Ok(Trace {
+            reader,
+            atr,
+            protocol,
+            exchanges,
+        })
+    }
+}
+
+/// Records the traffic with a card.
+///
+/// The recorder has the same `transmit()`, `transmit2()` and `control()`
+/// methods as `Card`, which forward to the card and record the exchange.
+///
+/// ```no_run
+/// use pcsc::trace::Recorder;
+/// use pcsc::*;
+///
+/// let ctx = Context::establish(Scope::User).unwrap();
+/// let readers = ctx.list_readers_owned().unwrap();
+/// let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+///
+/// let mut recorder = Recorder::new(&card).unwrap();
+/// let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+/// recorder.transmit(b"\xff\xca\x00\x00\x00", &mut rapdu_buf).unwrap();
+/// std::fs::write("card.trace", recorder.into_trace().to_string()).unwrap();
+/// ```
+pub struct Recorder<'card> {
+    card: &'card Card,
+    start: Instant,
+    trace: Trace,
+}
+
+impl<'card> Recorder<'card> {
+    /// Start recording the traffic with `card`.
+    ///
+    /// The reader name, ATR and protocol are taken from
+    /// `Card::status2_owned()`.
+    pub fn new(card: &'card Card) -> Result<Recorder<'card>, Error> {
+        let status = card.status2_owned()?;
+        let reader = status
+            .reader_names()
+            .first()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Recorder {
+            card,
+            start: Instant::now(),
+            trace: Trace {
+                reader,
+                atr: status.atr().to_vec(),
+                protocol: status.protocol2(),
+                exchanges: Vec::new(),
+            },
+        })
+    }
+
+    /// The card being recorded.
+    pub fn card(&self) -> &'card Card {
+        self.card
+    }
+
+    /// The trace recorded so far.
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// Stop recording, and return the recorded trace.
+    pub fn into_trace(self) -> Trace {
+        self.trace
+    }
+
+    fn record(&mut self, elapsed: Duration, kind: ExchangeKind, command: &[u8], response: Result<&[u8], Error>) {
+        self.trace.exchanges.push(Exchange {
+            // Traces have microsecond precision.
+            elapsed: Duration::new(elapsed.as_secs(), elapsed.subsec_micros() * 1000),
+            kind,
+            command: command.to_vec(),
+            ignored: Vec::new(),
+            response: response.map(|response| response.to_vec()),
+        });
+    }
+
+    /// Transmit an APDU command to the card, and record it.
+    ///
+    /// See `Card::transmit()`.
+    pub fn transmit<'buf>(&mut self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], Error> {
+        self.transmit2(send_buffer, receive_buffer).map_err(|(err, _)| err)
+    }
+
+    /// Transmit an APDU command to the card, and record it.
+    ///
+    /// See `Card::transmit2()`.
+    pub fn transmit2<'buf>(
+        &mut self,
+        send_buffer: &[u8],
+        receive_buffer: &'buf mut [u8],
+    ) -> Result<&'buf [u8], (Error, usize)> {
+        let elapsed = self.start.elapsed();
+        let result = self.card.transmit2(send_buffer, receive_buffer);
+        let response = match result {
+            Ok(response) => Ok(response),
+            Err((err, _)) => Err(err),
+        };
+        self.record(elapsed, ExchangeKind::Transmit, send_buffer, response);
+        result
+    }
+
+    /// Send a command directly to the reader, and record it.
+    ///
+    /// See `Card::control()`.
+    pub fn control<'buf>(
+        &mut self,
+        control_code: DWORD,
+        send_buffer: &[u8],
+        receive_buffer: &'buf mut [u8],
+    ) -> Result<&'buf [u8], Error> {
+        let elapsed = self.start.elapsed();
+        let result = self.card.control(control_code, send_buffer, receive_buffer);
+        self.record(elapsed, ExchangeKind::Control(control_code), send_buffer, result);
+        result
+    }
+}
+
+struct ReplayState {
+    trace: Trace,
+    position: usize,
+}
+
+/// Serves a recorded trace as a virtual card.
+///
+/// The replay answers commands with the recorded responses, in order. If a
+/// command does not match the next recorded one, or there are no more
+/// recorded exchanges, the replay panics.
+///
+/// Clones of a `Replay` share their position in the trace.
+///
+/// ```no_run
+/// use pcsc::simulator::Simulator;
+/// use pcsc::trace::{Replay, Trace};
+/// use pcsc::*;
+///
+/// let trace: Trace = std::fs::read_to_string("card.trace").unwrap().parse().unwrap();
+/// let replay = Replay::new(trace);
+/// let sim = Simulator::new();
+/// replay.install(&sim).unwrap();
+///
+/// let ctx = Context::establish_with_backend(sim, Scope::User).unwrap();
+/// // ... run the code under test against `ctx` ...
+/// replay.assert_complete();
+/// ```
+#[derive(Clone)]
+pub struct Replay {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl Replay {
+    /// Create a replay of `trace`.
+    pub fn new(trace: Trace) -> Replay {
+        Replay {
+            state: Arc::new(Mutex::new(ReplayState { trace, position: 0 })),
+        }
+    }
+
+    /// Create a virtual card with the recorded ATR and protocol, which
+    /// answers APDU commands from the trace.
+    pub fn card(&self) -> VirtualCard {
+        let state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let card = VirtualCard::new(&state.trace.atr, self.clone());
+        match state.trace.protocol {
+            Some(protocol) => card.protocol(protocol),
+            None => card,
+        }
+    }
+
+    /// Add the recorded reader to `simulator`, if it does not exist yet,
+    /// and insert the replay card into it.
+    ///
+    /// The replay also answers control commands sent to the reader.
+    pub fn install(&self, simulator: &Simulator) -> Result<(), Error> {
+        let reader = self
+            .state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .trace
+            .reader
+            .clone();
+        match simulator.add_reader(&reader) {
+            Ok(()) | Err(Error::DuplicateReader) => {}
+            Err(err) => return Err(err),
+        }
+        simulator.set_reader_handler(&reader, self.clone())?;
+        simulator.insert_card(&reader, self.card())
+    }
+
+    /// The number of recorded exchanges which have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        let state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state.trace.exchanges.len() - state.position
+    }
+
+    /// Check that the entire trace has been replayed.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if some recorded exchanges were not replayed.
+    pub fn assert_complete(&self) {
+        let remaining = self.remaining();
+        if remaining != 0 {
+            panic!(
+                "trace replay incomplete: {} recorded exchanges were not replayed",
+                remaining
+            );
+        }
+    }
+
+    fn replay(&self, kind: ExchangeKind, command: &[u8]) -> Result<Vec<u8>, Error> {
+        let mismatch = {
+            let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+            let position = state.position;
+            match state.trace.exchanges.get(position) {
+                Some(exchange) if exchange.kind == kind && exchange.matches(command) => {
+                    let response = exchange.response.clone();
+                    state.position += 1;
+                    return response;
+                }
+                Some(exchange) => format!(
+                    "trace replay mismatch at exchange {}:\n  expected: {:?} {}\n       got: {:?} {}",
+                    position,
+                    exchange.kind,
+                    PatternDisplay(exchange),
+                    kind,
+                    hex::encode(command),
+                ),
+                None => format!(
+                    "trace replay exhausted after {} exchanges, got: {:?} {}",
+                    position,
+                    kind,
+                    hex::encode(command),
+                ),
+            }
+        };
+        // Panic after releasing the lock, so the replay is not poisoned.
+        panic!("{}", mismatch);
+    }
+}
+
+struct PatternDisplay<'a>(&'a Exchange);
+
+impl<'a> fmt::Display for PatternDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_bytes(f, &self.0.command, &self.0.ignored)
+    }
+}
+
+impl CardHandler for Replay {
+    fn transmit(&mut self, command: &[u8]) -> Result<Vec<u8>, Error> {
+        self.replay(ExchangeKind::Transmit, command)
+    }
+}
+
+impl ReaderHandler for Replay {
+    fn control(&mut self, control_code: DWORD, command: &[u8]) -> Result<Vec<u8>, Error> {
+        self.replay(ExchangeKind::Control(control_code), command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Protocols, Scope, ShareMode, MAX_BUFFER_SIZE};
+    use std::ffi::CString;
+
+    const READER: &str = "Virtual Reader 00 00";
+    const ATR: &[u8] = &[0x3B, 0x00];
+    const GET_CHALLENGE: &[u8] = &[0x00, 0x84, 0x00, 0x00, 0x08];
+    const CHALLENGE: &[u8] = &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x90, 0x00];
+    const EXTERNAL_AUTHENTICATE: &[u8] = &[0x00, 0x82, 0x00, 0x00, 0x08, 1, 2, 3, 4, 5, 6, 7, 8];
+    const CONTROL_CODE: DWORD = 0x4200_0D48;
+
+    const TRACE: &str = "\
+pcsc-trace 1
+reader Virtual Reader 00 00
+atr 3B00
+protocol T1
+0.000012 transmit 0084000008 11223344556677889000
+0.004517 transmit 0082000008................ 9000
+0.006120 control 0x42000D48 - -
+0.009815 control 0x42000D48 01 !0x8010001F
+";
+
+    fn connect(sim: Simulator) -> Card {
+        let ctx = Context::establish_with_backend(sim, Scope::User).unwrap();
+        ctx.connect(&CString::new(READER).unwrap(), ShareMode::Shared, Protocols::ANY)
+            .unwrap()
+    }
+
+    fn record() -> Trace {
+        let sim = Simulator::new();
+        sim.add_reader(READER).unwrap();
+        sim.set_reader_handler(READER, |_code: DWORD, command: &[u8]| {
+            if command.is_empty() {
+                Ok(vec![])
+            } else {
+                Err(Error::UnsupportedFeature)
+            }
+        })
+        .unwrap();
+        sim.insert_card(
+            READER,
+            VirtualCard::new(ATR, |command: &[u8]| {
+                if command == GET_CHALLENGE {
+                    Ok(CHALLENGE.to_vec())
+                } else {
+                    Ok(vec![0x90, 0x00])
+                }
+            }),
+        )
+        .unwrap();
+        let card = connect(sim);
+
+        let mut recorder = Recorder::new(&card).unwrap();
+        let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+        assert_eq!(recorder.transmit(GET_CHALLENGE, &mut rapdu_buf), Ok(CHALLENGE));
+        assert_eq!(
+            recorder.transmit(EXTERNAL_AUTHENTICATE, &mut rapdu_buf),
+            Ok(&[0x90, 0x00][..])
+        );
+        assert_eq!(recorder.control(CONTROL_CODE, &[], &mut rapdu_buf), Ok(&[][..]));
+        assert_eq!(
+            recorder.control(CONTROL_CODE, &[0x01], &mut rapdu_buf),
+            Err(Error::UnsupportedFeature)
+        );
+        recorder.into_trace()
+    }
+
+    fn install(trace: Trace) -> (Replay, Card) {
+        let replay = Replay::new(trace);
+        let sim = Simulator::new();
+        replay.install(&sim).unwrap();
+        (replay, connect(sim))
+    }
+
+    // Send the recorded commands, with a different authentication
+    // cryptogram.
+    fn run(card: &Card) {
+        let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+        assert_eq!(card.transmit(GET_CHALLENGE, &mut rapdu_buf), Ok(CHALLENGE));
+        let mut command = EXTERNAL_AUTHENTICATE.to_vec();
+        command[5..].copy_from_slice(&[8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(card.transmit(&command, &mut rapdu_buf), Ok(&[0x90, 0x00][..]));
+        assert_eq!(card.control(CONTROL_CODE, &[], &mut rapdu_buf), Ok(&[][..]));
+        assert_eq!(
+            card.control(CONTROL_CODE, &[0x01], &mut rapdu_buf),
+            Err(Error::UnsupportedFeature)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut trace = record();
+        assert_eq!(trace.reader, READER);
+        assert_eq!(trace.atr, ATR);
+        assert_eq!(trace.protocol, Some(Protocol::T1));
+        assert_eq!(trace.exchanges.len(), 4);
+        assert_eq!(trace.exchanges[2].kind, ExchangeKind::Control(CONTROL_CODE));
+        assert_eq!(trace.exchanges[3].response, Err(Error::UnsupportedFeature));
+
+        trace.exchanges[1].ignore(5..13);
+        let text = trace.to_string();
+        assert!(text.starts_with("pcsc-trace 1\nreader Virtual Reader 00 00\natr 3B00\nprotocol T1\n"));
+        let lines: Vec<_> = text.lines().map(|line| line.split_once(' ').unwrap().1).collect();
+        assert_eq!(
+            lines[4..],
+            [
+                "transmit 0084000008 11223344556677889000",
+                "transmit 0082000008................ 9000",
+                "control 0x42000D48 - -",
+                "control 0x42000D48 01 !0x8010001F",
+            ]
+        );
+
+        let parsed: Trace = text.parse().unwrap();
+        assert_eq!(parsed, trace);
+        let (replay, card) = install(parsed);
+        run(&card);
+        replay.assert_complete();
+    }
+
+    #[test]
+    fn parse() {
+        let trace: Trace = TRACE.parse().unwrap();
+        assert_eq!(trace.exchanges[0].elapsed, Duration::from_micros(12));
+        assert_eq!(trace.exchanges[1].elapsed, Duration::from_micros(4517));
+        assert_eq!(
+            trace.exchanges[1].command,
+            [0x00, 0x82, 0x00, 0x00, 0x08, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(trace.exchanges[1].ignored, [5, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(trace.exchanges[2].command, []);
+        assert_eq!(trace.exchanges[2].response, Ok(vec![]));
+        assert_eq!(trace.to_string(), TRACE);
+        let (replay, card) = install(trace);
+        run(&card);
+        replay.assert_complete();
+
+        // Blank lines and comments are skipped.
+        let commented = format!("# A comment.\n\n{}", TRACE);
+        assert_eq!(commented.parse::<Trace>(), TRACE.parse());
+    }
+
+    #[test]
+    fn parse_errors() {
+        fn error(trace: &str) -> (usize, String) {
+            let err = trace.parse::<Trace>().unwrap_err();
+            (err.line(), err.to_string())
+        }
+
+        let header = "pcsc-trace 1\nreader R\natr 3B00\nprotocol T1\n";
+        assert_eq!(error("pcsc-trace 2\n").0, 1);
+        assert_eq!(error("# Comment\npcsc-trace 2\n").0, 2);
+        assert_eq!(error("pcsc-trace 1\nreader R\natr 3B0\n").0, 3);
+        assert_eq!(error("pcsc-trace 1\nreader R\natr 3B00\nprotocol T2\n").0, 4);
+
+        let cases = [
+            ("0.1 transmit 00ZZ 9000", "invalid command"),
+            ("0.1 transmit 00A4 90..", "invalid response"),
+            ("0.1 transmit 00A4 9000 6A82", "trailing characters"),
+            ("0.1 transmit 00A4", "missing response"),
+            ("0.1 control 42000D48 - -", "invalid control code"),
+            ("0.1 control 0x42000D48 - !0x1", "invalid error code"),
+            ("0.1 receive 00A4 9000", "expected `transmit` or `control`"),
+            ("0.1234567890 transmit 00A4 9000", "invalid time"),
+        ];
+        for &(line, message) in &cases {
+            let trace = format!("{}0.0 transmit 00 9000\n\n{}\n", header, line);
+            let (number, err) = error(&trace);
+            assert_eq!(number, 7, "{}", line);
+            assert!(err.contains(message), "{}: {}", line, err);
+        }
+    }
+
+    #[test]
+    fn ignore() {
+        let mut exchange = TRACE.parse::<Trace>().unwrap().exchanges.remove(0);
+        exchange.ignore(2..4);
+        exchange.ignore(3..5);
+        assert_eq!(exchange.ignored, [2, 3, 4]);
+        assert_eq!(exchange.command, [0x00, 0x84, 0x00, 0x00, 0x00]);
+        assert!(exchange.matches(&[0x00, 0x84, 0x12, 0x34, 0x56]));
+        assert!(!exchange.matches(&[0x80, 0x84, 0x12, 0x34, 0x56]));
+        assert!(!exchange.matches(&[0x00, 0x84, 0x12, 0x34]));
+    }
+
+    #[test]
+    #[should_panic(expected = "trace replay mismatch at exchange 1")]
+    fn replay_mismatch() {
+        let (_replay, card) = install(TRACE.parse().unwrap());
+        let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+        card.transmit(GET_CHALLENGE, &mut rapdu_buf).unwrap();
+        let _ = card.transmit(&[0x00, 0xA4, 0x04, 0x00, 0x00], &mut rapdu_buf);
+    }
+
+    #[test]
+    #[should_panic(expected = "trace replay exhausted after 4 exchanges")]
+    fn replay_exhausted() {
+        let (_replay, card) = install(TRACE.parse().unwrap());
+        run(&card);
+        let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+        let _ = card.transmit(GET_CHALLENGE, &mut rapdu_buf);
+    }
+
+    #[test]
+    #[should_panic(expected = "trace replay incomplete: 3 recorded exchanges were not replayed")]
+    fn replay_incomplete() {
+        let (replay, card) = install(TRACE.parse().unwrap());
+        assert_eq!(replay.remaining(), 4);
+        let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
+        card.transmit(GET_CHALLENGE, &mut rapdu_buf).unwrap();
+        assert_eq!(replay.remaining(), 3);
+        replay.assert_complete();
+    }
+}
@@ -0,0 +1,500 @@
+//! Raw RF frames in a transparent session, per PC/SC Part 3.
+//!
+//! Tags which do not understand APDUs, nor the storage card pseudo-APDUs
+//! of the [`storage`](../storage/index.html) module, can be driven with raw
+//! ISO 14443 or FeliCa frames. The reader is switched to a transparent
+//! session with the Manage Session command (FF C2 00 00), frames are
+//! exchanged with the Transparent Exchange command (FF C2 00 01), and the
+//! protocol is selected with the Switch Protocol command (FF C2 00 02).
+//!
+//! Each command carries BER-TLV [`DataObject`](enum.DataObject.html)s, and
+//! the reader answers with data objects too, decoded by
+//! [`TransparentResponse`](struct.TransparentResponse.html).
+//! [`TransparentSession`](struct.TransparentSession.html) wraps the
+//! commands.
+//!
+//! ```no_run
+//! use pcsc::transparent::{DataObject, TransmissionFlags, TransparentSession};
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//!
+//! let session = TransparentSession::start(&card).unwrap();
+//! // READ of page 0 of an NFC Forum Type 2 tag, with the CRC added by
+//! // the reader, and a 50ms timeout.
+//! let response = session
+//!     .exchange(&[
+//!         DataObject::Flags(TransmissionFlags::empty()),
+//!         DataObject::Timer(50_000),
+//!         DataObject::Transceive(vec![0x30, 0x00]),
+//!     ])
+//!     .unwrap();
+//! println!("{:02X?}", response.icc_response());
+//! session.end().unwrap();
+//! ```
+
+use std::error;
+use std::fmt;
+
+use bitflags::bitflags;
+
+use crate::apdu::{ApduError, Command, StatusWord, MAX_SHORT_LE};
+use crate::tlv::{self, Tag};
+use crate::{Card, Error};
+
+/// An error in a transparent session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransparentError {
+    /// The PC/SC call failed.
+    Pcsc(Error),
+    /// The reader returned a status word which does not indicate success.
+    Status(StatusWord),
+    /// A data object of the command failed, as reported in the error
+    /// status data object.
+    DataObject {
+        /// The position of the failed data object in the command, starting
+        /// at 1.
+        index: u8,
+        /// The status of the failed data object.
+        status: StatusWord,
+    },
+    /// The response is not valid BER-TLV.
+    MalformedResponse,
+}
+
+impl fmt::Display for TransparentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransparentError::Pcsc(ref err) => write!(f, "{}", err),
+            TransparentError::Status(sw) => write!(f, "{}", sw),
+            TransparentError::DataObject { index, status } => {
+                write!(f, "data object {} failed: {}", index, status)
+            }
+            TransparentError::MalformedResponse => f.write_str("malformed transparent session response"),
+        }
+    }
+}
+
+impl error::Error for TransparentError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            TransparentError::Pcsc(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for TransparentError {
+    fn from(err: Error) -> TransparentError {
+        TransparentError::Pcsc(err)
+    }
+}
+
+// Tags of the data objects.
+const TAG_VERSION: Tag = Tag::new(0x80);
+const TAG_START_SESSION: Tag = Tag::new(0x81);
+const TAG_END_SESSION: Tag = Tag::new(0x82);
+const TAG_RF_OFF: Tag = Tag::new(0x83);
+const TAG_RF_ON: Tag = Tag::new(0x84);
+const TAG_SWITCH_PROTOCOL: Tag = Tag::new(0x8F);
+const TAG_FLAGS: Tag = Tag::new(0x90);
+const TAG_TRANSMIT_BIT_FRAMING: Tag = Tag::new(0x91);
+const TAG_RECEIVE_BIT_FRAMING: Tag = Tag::new(0x92);
+const TAG_TRANSMIT: Tag = Tag::new(0x93);
+const TAG_RECEIVE: Tag = Tag::new(0x94);
+const TAG_TRANSCEIVE: Tag = Tag::new(0x95);
+const TAG_RESPONSE_STATUS: Tag = Tag::new(0x96);
+const TAG_ICC_RESPONSE: Tag = Tag::new(0x97);
+const TAG_ERROR_STATUS: Tag = Tag::new(0xC0);
+const TAG_TIMER: Tag = Tag::new(0x5F46);
+const TAG_GET_PARAMETER: Tag = Tag::new(0xFF6D);
+const TAG_SET_PARAMETER: Tag = Tag::new(0xFF6E);
+
+bitflags! {
+    /// The CRC and parity handling of a transparent exchange.
+    ///
+    /// By default (no flags), the reader appends the CRC and parity bits
+    /// to transmitted frames, and checks and removes them from received
+    /// frames.
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+    pub struct TransmissionFlags: u16 {
+        /// Do not append the CRC to transmitted frames.
+        const NO_TRANSMIT_CRC = 0x0001;
+        /// Keep the CRC in received frames.
+        const KEEP_RECEIVE_CRC = 0x0002;
+        /// Do not insert parity bits in transmitted frames.
+        const NO_TRANSMIT_PARITY = 0x0004;
+        /// Do not expect parity bits in received frames.
+        const NO_RECEIVE_PARITY = 0x0008;
+    }
+}
+
+bitflags! {
+    /// The status of a received frame, from the response status data
+    /// object.
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+    pub struct ResponseStatus: u8 {
+        const CRC_ERROR = 0x01;
+        const COLLISION = 0x02;
+        const PARITY_ERROR = 0x04;
+        const FRAMING_ERROR = 0x08;
+    }
+}
+
+/// The RF protocol of the Switch Protocol command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RfProtocol {
+    Iso14443A,
+    Iso14443B,
+    Iso15693,
+    Felica,
+    /// Another protocol, with its code.
+    Other(u8),
+}
+
+impl RfProtocol {
+    fn code(self) -> u8 {
+        match self {
+            RfProtocol::Iso14443A => 0x00,
+            RfProtocol::Iso14443B => 0x01,
+            RfProtocol::Iso15693 => 0x02,
+            RfProtocol::Felica => 0x03,
+            RfProtocol::Other(code) => code,
+        }
+    }
+}
+
+/// A data object of a transparent session command.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DataObject {
+    /// Start the transparent session.
+    StartSession,
+    /// End the transparent session.
+    EndSession,
+    /// Turn the RF field off.
+    RfOff,
+    /// Turn the RF field on.
+    RfOn,
+    /// The timeout of the following data objects, in microseconds.
+    Timer(u32),
+    /// Get parameters of the protocol, by their tags.
+    GetParameters(Vec<u8>),
+    /// Set parameters of the protocol, by their tags and values.
+    SetParameters(Vec<(u8, Vec<u8>)>),
+    /// The CRC and parity handling of the following frames.
+    Flags(TransmissionFlags),
+    /// The number of valid bits in the last byte of transmitted frames, 0
+    /// for all.
+    TransmitBitFraming(u8),
+    /// The number of valid bits in the last byte of received frames, 0 for
+    /// all.
+    ReceiveBitFraming(u8),
+    /// Transmit a frame, without waiting for a response.
+    Transmit(Vec<u8>),
+    /// Receive a frame.
+    Receive,
+    /// Transmit a frame and receive the response.
+    Transceive(Vec<u8>),
+    /// Switch to a protocol, at a layer (2, 3 or 4).
+    SwitchProtocol(RfProtocol, u8),
+    /// Any other data object, with its tag and value.
+    Other(Tag, Vec<u8>),
+}
+
+impl DataObject {
+    /// Append the BER-TLV encoding of the data object to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            DataObject::StartSession => tlv::encode(TAG_START_SESSION, &[], out),
+            DataObject::EndSession => tlv::encode(TAG_END_SESSION, &[], out),
+            DataObject::RfOff => tlv::encode(TAG_RF_OFF, &[], out),
+            DataObject::RfOn => tlv::encode(TAG_RF_ON, &[], out),
+            DataObject::Timer(microseconds) => tlv::encode(TAG_TIMER, &microseconds.to_le_bytes(), out),
+            DataObject::GetParameters(ref tags) => {
+                let mut value = Vec::new();
+                for &tag in tags {
+                    tlv::encode(Tag::new(u32::from(tag)), &[], &mut value);
+                }
+                tlv::encode(TAG_GET_PARAMETER, &value, out);
+            }
+            DataObject::SetParameters(ref parameters) => {
+                let mut value = Vec::new();
+                for &(tag, ref parameter) in parameters {
+                    tlv::encode(Tag::new(u32::from(tag)), parameter, &mut value);
+                }
+                tlv::encode(TAG_SET_PARAMETER, &value, out);
+            }
+            DataObject::Flags(flags) => tlv::encode(TAG_FLAGS, &flags.bits().to_le_bytes(), out),
+            DataObject::TransmitBitFraming(bits) => tlv::encode(TAG_TRANSMIT_BIT_FRAMING, &[bits], out),
+            DataObject::ReceiveBitFraming(bits) => tlv::encode(TAG_RECEIVE_BIT_FRAMING, &[bits], out),
+            DataObject::Transmit(ref frame) => tlv::encode(TAG_TRANSMIT, frame, out),
+            DataObject::Receive => tlv::encode(TAG_RECEIVE, &[], out),
+            DataObject::Transceive(ref frame) => tlv::encode(TAG_TRANSCEIVE, frame, out),
+            DataObject::SwitchProtocol(protocol, layer) => {
+                tlv::encode(TAG_SWITCH_PROTOCOL, &[protocol.code(), layer], out)
+            }
+            DataObject::Other(tag, ref value) => tlv::encode(tag, value, out),
+        }
+    }
+}
+
+/// The command of a transparent session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Function {
+    /// Manage Session (FF C2 00 00).
+    ManageSession,
+    /// Transparent Exchange (FF C2 00 01).
+    TransparentExchange,
+    /// Switch Protocol (FF C2 00 02).
+    SwitchProtocol,
+}
+
+/// Build the command APDU of a transparent session command.
+pub fn command(function: Function, objects: &[DataObject]) -> Command {
+    let p2 = match function {
+        Function::ManageSession => 0x00,
+        Function::TransparentExchange => 0x01,
+        Function::SwitchProtocol => 0x02,
+    };
+    let mut data = Vec::new();
+    for object in objects {
+        object.encode(&mut data);
+    }
+    Command::new(0xFF, 0xC2, 0x00, p2)
+        .with_data(&data)
+        .with_le(MAX_SHORT_LE)
+}
+
+/// The data objects of the response to a transparent session command.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransparentResponse {
+    objects: Vec<(Tag, Vec<u8>)>,
+}
+
+impl TransparentResponse {
+    /// Decode the response data of a transparent session command.
+    pub fn parse(data: &[u8]) -> Result<TransparentResponse, TransparentError> {
+        let objects = tlv::parse(data)
+            .map(|object| object.map(|object| (object.tag(), object.value().to_vec())))
+            .collect::<Result<_, _>>()
+            .map_err(|_| TransparentError::MalformedResponse)?;
+        Ok(TransparentResponse { objects })
+    }
+
+    /// The data objects, with their tags, in order.
+    pub fn objects(&self) -> &[(Tag, Vec<u8>)] {
+        &self.objects
+    }
+
+    /// The value of the first data object with tag `tag`.
+    pub fn get(&self, tag: Tag) -> Option<&[u8]> {
+        self.objects
+            .iter()
+            .find(|&&(object_tag, _)| object_tag == tag)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// The error status data objects (C0): the position of the data object
+    /// in the command, starting at 1 (0 for the command as a whole), and
+    /// its status.
+    pub fn error_status(&self) -> Vec<(u8, StatusWord)> {
+        self.objects
+            .iter()
+            .filter_map(|(tag, value)| match (*tag, &value[..]) {
+                (TAG_ERROR_STATUS, &[index, sw1, sw2]) => Some((index, StatusWord::new(sw1, sw2))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The frame received from the card (ICC response, 97).
+    pub fn icc_response(&self) -> Option<&[u8]> {
+        self.get(TAG_ICC_RESPONSE)
+    }
+
+    /// The status of the received frame (96).
+    pub fn response_status(&self) -> Option<ResponseStatus> {
+        self.get(TAG_RESPONSE_STATUS)
+            .and_then(|value| value.first())
+            .map(|&status| ResponseStatus::from_bits_retain(status))
+    }
+
+    /// The number of valid bits in the last byte of the received frame
+    /// (92), 0 for all.
+    pub fn receive_bit_framing(&self) -> Option<u8> {
+        self.get(TAG_RECEIVE_BIT_FRAMING)
+            .and_then(|value| value.first().copied())
+    }
+
+    /// The version of the transparent session implementation of the
+    /// reader (80).
+    pub fn version(&self) -> Option<&[u8]> {
+        self.get(TAG_VERSION)
+    }
+
+    /// The parameters returned for `DataObject::GetParameters`, by their
+    /// tags.
+    pub fn parameters(&self) -> Vec<(u8, Vec<u8>)> {
+        let parameters = self.get(TAG_GET_PARAMETER).unwrap_or(&[]);
+        tlv::parse(parameters)
+            .filter_map(|parameter| parameter.ok())
+            .filter(|parameter| parameter.tag().value() <= 0xFF)
+            .map(|parameter| (parameter.tag().value() as u8, parameter.value().to_vec()))
+            .collect()
+    }
+}
+
+/// A transparent session with a contactless card.
+///
+/// The session is ended when dropped, ignoring errors; use `end()` to
+/// check them.
+///
+/// The commands are answered by the reader, so they are sent with
+/// `Card::transmit()`; the card's `ApduOptions` do not apply.
+pub struct TransparentSession<'card> {
+    card: &'card Card,
+    ended: bool,
+}
+
+impl<'card> TransparentSession<'card> {
+    /// Start a transparent session with `card`.
+    pub fn start(card: &'card Card) -> Result<TransparentSession<'card>, TransparentError> {
+        let session = TransparentSession { card, ended: false };
+        session.manage(&[DataObject::StartSession])?;
+        Ok(session)
+    }
+
+    /// The card of the session.
+    pub fn card(&self) -> &'card Card {
+        self.card
+    }
+
+    /// Send a transparent session command, and decode its response.
+    ///
+    /// If the reader reports an error for a data object, it is returned
+    /// as `TransparentError::DataObject`.
+    pub fn send(&self, function: Function, objects: &[DataObject]) -> Result<TransparentResponse, TransparentError> {
+        let response = match self.card.exchange_pseudo(&command(function, objects)) {
+            Ok(response) => response,
+            Err(ApduError::Pcsc(err)) => return Err(TransparentError::Pcsc(err)),
+            Err(ApduError::InvalidCommand(_)) => return Err(TransparentError::Pcsc(Error::InvalidParameter)),
+            Err(_) => return Err(TransparentError::MalformedResponse),
+        };
+        if response.status() != StatusWord::Success {
+            return Err(TransparentError::Status(response.status()));
+        }
+        let response = TransparentResponse::parse(response.data())?;
+        if let Some(&(index, status)) = response.error_status().iter().find(|(_, status)| !status.is_success()) {
+            return Err(TransparentError::DataObject { index, status });
+        }
+        Ok(response)
+    }
+
+    /// Send a Manage Session command.
+    pub fn manage(&self, objects: &[DataObject]) -> Result<TransparentResponse, TransparentError> {
+        self.send(Function::ManageSession, objects)
+    }
+
+    /// Send a Transparent Exchange command.
+    pub fn exchange(&self, objects: &[DataObject]) -> Result<TransparentResponse, TransparentError> {
+        self.send(Function::TransparentExchange, objects)
+    }
+
+    /// Switch to a protocol, at a layer (2, 3 or 4).
+    pub fn switch_protocol(&self, protocol: RfProtocol, layer: u8) -> Result<TransparentResponse, TransparentError> {
+        self.send(Function::SwitchProtocol, &[DataObject::SwitchProtocol(protocol, layer)])
+    }
+
+    /// Transmit a frame and return the received frame, with the reader's
+    /// current CRC and parity handling.
+    ///
+    /// If the reader returns no frame, `TransparentError::MalformedResponse`
+    /// is returned.
+    pub fn transceive(&self, frame: &[u8]) -> Result<Vec<u8>, TransparentError> {
+        let response = self.exchange(&[DataObject::Transceive(frame.to_vec())])?;
+        response
+            .icc_response()
+            .map(|frame| frame.to_vec())
+            .ok_or(TransparentError::MalformedResponse)
+    }
+
+    /// End the session.
+    pub fn end(mut self) -> Result<(), TransparentError> {
+        self.ended = true;
+        self.manage(&[DataObject::EndSession]).map(drop)
+    }
+}
+
+impl<'card> Drop for TransparentSession<'card> {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _err = self.manage(&[DataObject::EndSession]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apdu::ApduOptions;
+    use crate::simulator::{test_card, Commands};
+
+    // Connects to a reader which echoes transceived frames reversed, and
+    // answers Switch Protocol with 61XX.
+    fn connect() -> (Card, Commands) {
+        test_card(|bytes| {
+            let command = Command::parse(bytes).unwrap();
+            let mut response = vec![0xC0, 0x03, 0x00, 0x90, 0x00];
+            match (command.p2(), command.data()) {
+                (0x01, [0x95, _, frame @ ..]) => {
+                    response.extend_from_slice(&[0x97, frame.len() as u8]);
+                    response.extend(frame.iter().rev());
+                }
+                (0x01, _) => response = vec![0xC0, 0x03, 0x01, 0x64, 0x01],
+                (0x02, _) => return vec![0x61, 0x05],
+                _ => {}
+            }
+            response.extend_from_slice(&[0x90, 0x00]);
+            response
+        })
+    }
+
+    #[test]
+    fn session() {
+        let (card, commands) = connect();
+        let session = TransparentSession::start(&card).unwrap();
+        assert_eq!(session.transceive(&[0x30, 0x04]).unwrap(), [0x04, 0x30]);
+        assert_eq!(
+            session.exchange(&[DataObject::Timer(50_000)]),
+            Err(TransparentError::DataObject {
+                index: 1,
+                status: StatusWord::new(0x64, 0x01)
+            })
+        );
+        session.end().unwrap();
+
+        let commands = commands.lock().unwrap();
+        assert_eq!(commands[0], [0xFF, 0xC2, 0x00, 0x00, 0x02, 0x81, 0x00, 0x00]);
+        assert_eq!(
+            commands[1],
+            [0xFF, 0xC2, 0x00, 0x01, 0x04, 0x95, 0x02, 0x30, 0x04, 0x00]
+        );
+        assert_eq!(commands[3], [0xFF, 0xC2, 0x00, 0x00, 0x02, 0x82, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn apdu_options_do_not_apply() {
+        let (mut card, commands) = connect();
+        card.set_apdu_options(ApduOptions::new().with_get_response(true));
+        let session = TransparentSession::start(&card).unwrap();
+        assert_eq!(
+            session.switch_protocol(RfProtocol::Iso14443A, 3),
+            Err(TransparentError::Status(StatusWord::new(0x61, 0x05)))
+        );
+        drop(session);
+        // Start, Switch Protocol and End, without GET RESPONSE.
+        assert_eq!(commands.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,839 @@
+//! Memory access and NDEF messages on NFC Forum Type 2 tags.
+//!
+//! Type 2 tags, such as NTAG21x and MIFARE Ultralight, are storage cards
+//! with 4 byte pages. Pages 0 to 2 hold the UID and the static lock bytes,
+//! page 3 the Capability Container, and the data area starts at page 4.
+//! The data area holds TLV blocks: Lock Control and Memory Control TLVs,
+//! which locate the dynamic lock bytes and reserved memory, the NDEF
+//! Message TLV, and the Terminator TLV.
+//!
+//! [`Type2Tag`](struct.Type2Tag.html) reads and writes pages with the
+//! PC/SC Part 3 pseudo-APDUs of the [`storage`](../storage/index.html)
+//! module, and sends the NTAG21x GET_VERSION, READ_SIG and PWD_AUTH
+//! commands in a [`transparent`](../transparent/index.html) session.
+//!
+//! Writes which would set lock bits, and so make pages permanently read
+//! only, are refused unless allowed with `Type2Tag::with_lock_bit_writes()`.
+//!
+//! ```no_run
+//! use pcsc::ndef::{Message, Record};
+//! use pcsc::type2::Type2Tag;
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//!
+//! let tag = Type2Tag::new(&card).unwrap();
+//! println!("{:?}", tag.get_version().unwrap().name());
+//! tag.write_message(&Message::new(vec![Record::uri("https://example.com/asset/42")]))
+//!     .unwrap();
+//! ```
+
+use std::error;
+use std::fmt;
+
+use crate::ndef::{Message, NdefError};
+use crate::storage::{StorageCard, StorageError};
+use crate::transparent::{TransparentError, TransparentSession};
+use crate::Card;
+
+/// The size of a page.
+pub const PAGE_SIZE: usize = 4;
+
+// The byte addresses of the static lock bytes, the Capability Container
+// and the data area.
+const STATIC_LOCK_BYTES: (usize, usize) = (10, 12);
+const CAPABILITY_CONTAINER_PAGE: u16 = 3;
+const DATA_AREA_ADDRESS: usize = 16;
+
+// The TLV block tags.
+const TAG_NULL: u8 = 0x00;
+const TAG_LOCK_CONTROL: u8 = 0x01;
+const TAG_MEMORY_CONTROL: u8 = 0x02;
+const TAG_NDEF_MESSAGE: u8 = 0x03;
+const TAG_TERMINATOR: u8 = 0xFE;
+
+/// An error accessing a Type 2 tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type2Error {
+    /// A READ BINARY or UPDATE BINARY pseudo-APDU failed.
+    Storage(StorageError),
+    /// A transparent exchange failed.
+    Transparent(TransparentError),
+    /// The Capability Container is not valid: the tag is not formatted
+    /// for NDEF.
+    InvalidCapabilityContainer,
+    /// The major mapping version of the Capability Container is not
+    /// supported.
+    UnsupportedVersion(u8),
+    /// The TLV blocks of the data area are not valid.
+    InvalidTlv,
+    /// The data area is not readable.
+    ReadProtected,
+    /// The data area is not writable.
+    ReadOnly,
+    /// The write would set lock bits of the page.
+    LockBitsWouldBeSet(u16),
+    /// The NDEF message does not fit in the data area.
+    MessageTooLong(usize),
+    /// The NDEF message is not valid.
+    Ndef(NdefError),
+    /// The tag answered a command with a NAK.
+    Nak(u8),
+    /// The tag's response has an unexpected length.
+    MalformedResponse,
+}
+
+impl fmt::Display for Type2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Type2Error::Storage(ref err) => write!(f, "{}", err),
+            Type2Error::Transparent(ref err) => write!(f, "{}", err),
+            Type2Error::InvalidCapabilityContainer => f.write_str("invalid Capability Container"),
+            Type2Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported mapping version {}.{}", version >> 4, version & 0x0F)
+            }
+            Type2Error::InvalidTlv => f.write_str("invalid TLV block"),
+            Type2Error::ReadProtected => f.write_str("data area not readable"),
+            Type2Error::ReadOnly => f.write_str("data area not writable"),
+            Type2Error::LockBitsWouldBeSet(page) => write!(f, "write would set lock bits in page {}", page),
+            Type2Error::MessageTooLong(len) => write!(f, "NDEF message too long ({} bytes)", len),
+            Type2Error::Ndef(ref err) => write!(f, "{}", err),
+            Type2Error::Nak(nak) => write!(f, "NAK {:X}", nak),
+            Type2Error::MalformedResponse => f.write_str("unexpected response length"),
+        }
+    }
+}
+
+impl error::Error for Type2Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Type2Error::Storage(ref err) => Some(err),
+            Type2Error::Transparent(ref err) => Some(err),
+            Type2Error::Ndef(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<StorageError> for Type2Error {
+    fn from(err: StorageError) -> Type2Error {
+        Type2Error::Storage(err)
+    }
+}
+
+impl From<TransparentError> for Type2Error {
+    fn from(err: TransparentError) -> Type2Error {
+        Type2Error::Transparent(err)
+    }
+}
+
+impl From<NdefError> for Type2Error {
+    fn from(err: NdefError) -> Type2Error {
+        Type2Error::Ndef(err)
+    }
+}
+
+/// The Capability Container of a Type 2 tag (page 3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CapabilityContainer {
+    /// The mapping version, major version in the high nibble.
+    pub version: u8,
+    /// The size of the data area, in bytes.
+    pub data_area_size: usize,
+    /// The read access condition: 0 for access without any security.
+    pub read_access: u8,
+    /// The write access condition: 0 for access without any security, F
+    /// for no write access.
+    pub write_access: u8,
+}
+
+impl CapabilityContainer {
+    /// Parse the content of page 3.
+    pub fn parse(bytes: &[u8]) -> Result<CapabilityContainer, Type2Error> {
+        match *bytes {
+            [0xE1, version, size, access] => {
+                if version >> 4 != 1 {
+                    return Err(Type2Error::UnsupportedVersion(version));
+                }
+                Ok(CapabilityContainer {
+                    version,
+                    data_area_size: size as usize * 8,
+                    read_access: access >> 4,
+                    write_access: access & 0x0F,
+                })
+            }
+            _ => Err(Type2Error::InvalidCapabilityContainer),
+        }
+    }
+
+    /// Whether the data area can be read without any security.
+    pub fn is_readable(&self) -> bool {
+        self.read_access == 0x0
+    }
+
+    /// Whether the data area can be written without any security.
+    pub fn is_writable(&self) -> bool {
+        self.write_access == 0x0
+    }
+}
+
+/// The dynamic lock bits, from a Lock Control TLV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LockControl {
+    /// The byte address of the first lock byte.
+    pub address: usize,
+    /// The number of lock bits.
+    pub bits: usize,
+    /// The number of bytes locked by each lock bit.
+    pub bytes_locked_per_bit: usize,
+}
+
+impl LockControl {
+    fn parse(value: &[u8]) -> Option<LockControl> {
+        match *value {
+            [position, bits, page_control] => Some(LockControl {
+                address: control_address(position, page_control),
+                bits: if bits == 0 { 256 } else { bits as usize },
+                bytes_locked_per_bit: 1 << (page_control >> 4),
+            }),
+            _ => None,
+        }
+    }
+
+    // The default dynamic lock bits, when there is no Lock Control TLV:
+    // right after the data area, one bit per 8 bytes beyond the first 48.
+    fn default_for(data_area_size: usize) -> Option<LockControl> {
+        if data_area_size <= 48 {
+            return None;
+        }
+        Some(LockControl {
+            address: DATA_AREA_ADDRESS + data_area_size,
+            bits: (data_area_size - 48 + 7) / 8,
+            bytes_locked_per_bit: 8,
+        })
+    }
+
+    /// The byte addresses of the lock bytes, as a range.
+    pub fn bytes(&self) -> (usize, usize) {
+        (self.address, self.address + (self.bits + 7) / 8)
+    }
+}
+
+/// Reserved memory, from a Memory Control TLV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryControl {
+    /// The byte address of the reserved memory.
+    pub address: usize,
+    /// The size of the reserved memory, in bytes.
+    pub size: usize,
+}
+
+impl MemoryControl {
+    fn parse(value: &[u8]) -> Option<MemoryControl> {
+        match *value {
+            [position, size, page_control] => Some(MemoryControl {
+                address: control_address(position, page_control),
+                size: if size == 0 { 256 } else { size as usize },
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Decode the position of a Lock or Memory Control TLV: the page address
+// and byte offset nibbles, with the page size in the page control byte.
+fn control_address(position: u8, page_control: u8) -> usize {
+    let bytes_per_page = 1usize << (page_control & 0x0F);
+    (position >> 4) as usize * bytes_per_page + (position & 0x0F) as usize
+}
+
+/// A TLV block of the data area.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Tlv {
+    Null,
+    LockControl(LockControl),
+    MemoryControl(MemoryControl),
+    /// An NDEF Message TLV, with the encoded message.
+    NdefMessage(Vec<u8>),
+    /// A proprietary TLV, with its tag and value.
+    Proprietary(u8, Vec<u8>),
+    Terminator,
+}
+
+// The data area and its TLV blocks, with the lock and reserved bytes which
+// TLV blocks skip.
+struct Layout {
+    data: Vec<u8>,
+    tlvs: Vec<Tlv>,
+    lock_bytes: Vec<(usize, usize)>,
+    skipped: Vec<(usize, usize)>,
+    // The byte address of the first NDEF Message TLV, or where one goes:
+    // at the Terminator TLV, or else after the last TLV block.
+    ndef_address: usize,
+}
+
+impl Layout {
+    fn parse(data: Vec<u8>) -> Result<Layout, Type2Error> {
+        let end = DATA_AREA_ADDRESS + data.len();
+        let mut layout = Layout {
+            data,
+            tlvs: Vec::new(),
+            lock_bytes: vec![STATIC_LOCK_BYTES],
+            skipped: Vec::new(),
+            ndef_address: DATA_AREA_ADDRESS,
+        };
+        let mut address = DATA_AREA_ADDRESS;
+        let mut has_lock_control = false;
+        let mut ndef_address = None;
+        let mut free_address = DATA_AREA_ADDRESS;
+        while let Some(tlv_address) = layout.next_address(address, end) {
+            let tag = layout.data[tlv_address - DATA_AREA_ADDRESS];
+            address = tlv_address + 1;
+            match tag {
+                TAG_NULL => {
+                    layout.tlvs.push(Tlv::Null);
+                    continue;
+                }
+                TAG_TERMINATOR => {
+                    layout.tlvs.push(Tlv::Terminator);
+                    free_address = tlv_address;
+                    break;
+                }
+                TAG_NDEF_MESSAGE if ndef_address.is_none() => ndef_address = Some(tlv_address),
+                _ => {}
+            }
+            let mut len = layout.read(&mut address, end, 1)?[0] as usize;
+            if len == 0xFF {
+                let long = layout.read(&mut address, end, 2)?;
+                len = u16::from_be_bytes([long[0], long[1]]) as usize;
+            }
+            let value = layout.read(&mut address, end, len)?;
+            let tlv = match tag {
+                TAG_LOCK_CONTROL => {
+                    let control = LockControl::parse(&value).ok_or(Type2Error::InvalidTlv)?;
+                    has_lock_control = true;
+                    layout.lock_bytes.push(control.bytes());
+                    layout.skipped.push(control.bytes());
+                    Tlv::LockControl(control)
+                }
+                TAG_MEMORY_CONTROL => {
+                    let control = MemoryControl::parse(&value).ok_or(Type2Error::InvalidTlv)?;
+                    layout.skipped.push((control.address, control.address + control.size));
+                    Tlv::MemoryControl(control)
+                }
+                TAG_NDEF_MESSAGE => Tlv::NdefMessage(value),
+                tag => Tlv::Proprietary(tag, value),
+            };
+            free_address = address;
+            layout.tlvs.push(tlv);
+        }
+        layout.ndef_address = ndef_address.unwrap_or(free_address);
+        if !has_lock_control {
+            if let Some(control) = LockControl::default_for(end - DATA_AREA_ADDRESS) {
+                layout.lock_bytes.push(control.bytes());
+            }
+        }
+        Ok(layout)
+    }
+
+    // The first address from `address` which is not skipped, if any.
+    fn next_address(&self, mut address: usize, end: usize) -> Option<usize> {
+        while let Some(&(_, skipped_end)) = self
+            .skipped
+            .iter()
+            .find(|&&(start, skipped_end)| start <= address && address < skipped_end)
+        {
+            address = skipped_end;
+        }
+        if address < end {
+            Some(address)
+        } else {
+            None
+        }
+    }
+
+    fn read(&self, address: &mut usize, end: usize, len: usize) -> Result<Vec<u8>, Type2Error> {
+        let mut value = Vec::with_capacity(len);
+        for _ in 0..len {
+            let next = self.next_address(*address, end).ok_or(Type2Error::InvalidTlv)?;
+            value.push(self.data[next - DATA_AREA_ADDRESS]);
+            *address = next + 1;
+        }
+        Ok(value)
+    }
+
+    fn is_lock_byte(&self, address: usize) -> bool {
+        self.lock_bytes
+            .iter()
+            .any(|&(start, end)| start <= address && address < end)
+    }
+}
+
+/// The version of an NTAG21x or MIFARE Ultralight EV1 tag, from
+/// GET_VERSION.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TagVersion {
+    pub vendor_id: u8,
+    pub product_type: u8,
+    pub product_subtype: u8,
+    pub major_version: u8,
+    pub minor_version: u8,
+    /// The storage size code: the size is between 2^(n/2) and 2^(n/2+1)
+    /// bytes.
+    pub storage_size: u8,
+    pub protocol_type: u8,
+}
+
+impl TagVersion {
+    /// The product name, for known NXP tags.
+    pub fn name(&self) -> Option<&'static str> {
+        match (self.vendor_id, self.product_type, self.storage_size) {
+            (0x04, 0x04, 0x0F) => Some("NTAG213"),
+            (0x04, 0x04, 0x11) => Some("NTAG215"),
+            (0x04, 0x04, 0x13) => Some("NTAG216"),
+            (0x04, 0x03, 0x0B) => Some("MIFARE Ultralight EV1 (MF0UL11)"),
+            (0x04, 0x03, 0x0E) => Some("MIFARE Ultralight EV1 (MF0UL21)"),
+            _ => None,
+        }
+    }
+}
+
+/// An NFC Forum Type 2 tag.
+pub struct Type2Tag<'card> {
+    storage: StorageCard<'card>,
+    capability_container: CapabilityContainer,
+    lock_bit_writes: bool,
+}
+
+impl<'card> Type2Tag<'card> {
+    /// Access `card` as a Type 2 tag, and read its Capability Container.
+    pub fn new(card: &'card Card) -> Result<Type2Tag<'card>, Type2Error> {
+        let storage = StorageCard::new(card);
+        let page = storage.read_binary(CAPABILITY_CONTAINER_PAGE, PAGE_SIZE)?;
+        let capability_container = CapabilityContainer::parse(page.get(..PAGE_SIZE).unwrap_or(&page))?;
+        Ok(Type2Tag {
+            storage,
+            capability_container,
+            lock_bit_writes: false,
+        })
+    }
+
+    /// Allow writes which set lock bits.
+    ///
+    /// ## Note
+    ///
+    /// Lock bits cannot be cleared: the pages they lock become permanently
+    /// read only.
+    pub fn with_lock_bit_writes(mut self, lock_bit_writes: bool) -> Type2Tag<'card> {
+        self.lock_bit_writes = lock_bit_writes;
+        self
+    }
+
+    /// Whether writes which set lock bits are allowed.
+    pub fn lock_bit_writes(&self) -> bool {
+        self.lock_bit_writes
+    }
+
+    /// The card of the tag.
+    pub fn card(&self) -> &'card Card {
+        self.storage.card()
+    }
+
+    /// The Capability Container of the tag.
+    pub fn capability_container(&self) -> &CapabilityContainer {
+        &self.capability_container
+    }
+
+    /// Read `count` pages starting at `page`.
+    pub fn read_pages(&self, page: u16, count: usize) -> Result<Vec<u8>, Type2Error> {
+        let len = count * PAGE_SIZE;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            // READ returns 4 pages at once.
+            let offset = (data.len() / PAGE_SIZE) as u16;
+            let chunk = self.storage.read_binary(page + offset, 16)?;
+            if chunk.len() < PAGE_SIZE || chunk.len() % PAGE_SIZE != 0 {
+                return Err(Type2Error::MalformedResponse);
+            }
+            data.extend_from_slice(&chunk);
+        }
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Write a page.
+    ///
+    /// The write is refused with `Type2Error::LockBitsWouldBeSet` if it
+    /// would set static or dynamic lock bits, unless allowed with
+    /// `with_lock_bit_writes()`.
+    pub fn write_page(&self, page: u16, data: &[u8; PAGE_SIZE]) -> Result<(), Type2Error> {
+        let layout = self.layout()?;
+        let current = self.read_pages(page, 1)?;
+        self.write_page_checked(&layout, page, data, &current)
+    }
+
+    fn write_page_checked(&self, layout: &Layout, page: u16, data: &[u8], current: &[u8]) -> Result<(), Type2Error> {
+        if !self.lock_bit_writes {
+            let address = page as usize * PAGE_SIZE;
+            let sets_lock_bits = data
+                .iter()
+                .zip(current)
+                .enumerate()
+                .any(|(i, (&new, &old))| layout.is_lock_byte(address + i) && new & !old != 0);
+            if sets_lock_bits {
+                return Err(Type2Error::LockBitsWouldBeSet(page));
+            }
+        }
+        Ok(self.storage.update_binary(page, data)?)
+    }
+
+    fn layout(&self) -> Result<Layout, Type2Error> {
+        if !self.capability_container.is_readable() {
+            return Err(Type2Error::ReadProtected);
+        }
+        let size = self.capability_container.data_area_size;
+        let first_page = (DATA_AREA_ADDRESS / PAGE_SIZE) as u16;
+        let mut data = self.read_pages(first_page, (size + PAGE_SIZE - 1) / PAGE_SIZE)?;
+        data.truncate(size);
+        Layout::parse(data)
+    }
+
+    /// Read the TLV blocks of the data area, up to the Terminator TLV.
+    pub fn read_tlvs(&self) -> Result<Vec<Tlv>, Type2Error> {
+        Ok(self.layout()?.tlvs)
+    }
+
+    /// Read the NDEF message, from the first NDEF Message TLV.
+    ///
+    /// A tag with no NDEF Message TLV gives a message with no records.
+    pub fn read_message(&self) -> Result<Message, Type2Error> {
+        for tlv in self.read_tlvs()? {
+            if let Tlv::NdefMessage(message) = tlv {
+                return Ok(Message::parse(&message)?);
+            }
+        }
+        Ok(Message::default())
+    }
+
+    /// Write an NDEF message, in place of the first NDEF Message TLV.
+    ///
+    /// Without an NDEF Message TLV, the message is written in place of the
+    /// Terminator TLV, or after the last TLV block. The TLV blocks before
+    /// it are kept, and the message skips the lock bytes and reserved
+    /// memory located by the Lock Control and Memory Control TLVs. Any TLV
+    /// blocks after it are overwritten. The length of the NDEF
+    /// Message TLV is set to 0 while the message is written, so that an
+    /// interrupted write leaves an empty message rather than a corrupted
+    /// one.
+    pub fn write_message(&self, message: &Message) -> Result<(), Type2Error> {
+        if !self.capability_container.is_writable() {
+            return Err(Type2Error::ReadOnly);
+        }
+        let layout = self.layout()?;
+        let end = DATA_AREA_ADDRESS + layout.data.len();
+        let message = message.to_bytes();
+        let (length_field, empty_length_field) = if message.len() < 0xFF {
+            (vec![message.len() as u8], vec![0x00])
+        } else if message.len() <= 0xFFFE {
+            let [high, low] = (message.len() as u16).to_be_bytes();
+            (vec![0xFF, high, low], vec![0xFF, 0x00, 0x00])
+        } else {
+            return Err(Type2Error::MessageTooLong(message.len()));
+        };
+
+        // Lay out the TLV, skipping lock bytes and reserved memory.
+        let mut tlv = vec![TAG_NDEF_MESSAGE];
+        tlv.extend_from_slice(&length_field);
+        tlv.extend_from_slice(&message);
+        let mut addresses = Vec::with_capacity(tlv.len() + 1);
+        let mut address = layout.ndef_address;
+        while addresses.len() < tlv.len() + 1 {
+            match layout.next_address(address, end) {
+                Some(next) => {
+                    addresses.push(next);
+                    address = next + 1;
+                }
+                None => break,
+            }
+        }
+        if addresses.len() < tlv.len() {
+            return Err(Type2Error::MessageTooLong(message.len()));
+        }
+        // The Terminator TLV is omitted if the message fills the data area.
+        if addresses.len() > tlv.len() {
+            tlv.push(TAG_TERMINATOR);
+        }
+
+        let mut empty = layout.data.clone();
+        let mut full = layout.data.clone();
+        for (i, (&address, &byte)) in addresses.iter().zip(&tlv).enumerate() {
+            full[address - DATA_AREA_ADDRESS] = byte;
+            empty[address - DATA_AREA_ADDRESS] = if i == 0 {
+                TAG_NDEF_MESSAGE
+            } else if i <= length_field.len() {
+                empty_length_field[i - 1]
+            } else {
+                byte
+            };
+        }
+
+        // Write the empty length first, then the message, then the length.
+        let length_pages: Vec<usize> = addresses[..=length_field.len()]
+            .iter()
+            .map(|&address| address / PAGE_SIZE)
+            .collect();
+        let mut current = layout.data.clone();
+        let mut write = |image: &[u8], page: usize| -> Result<(), Type2Error> {
+            let start = page * PAGE_SIZE - DATA_AREA_ADDRESS;
+            let end = (start + PAGE_SIZE).min(image.len());
+            let mut data = [0x00; PAGE_SIZE];
+            data[..end - start].copy_from_slice(&image[start..end]);
+            let mut old = [0x00; PAGE_SIZE];
+            old[..end - start].copy_from_slice(&current[start..end]);
+            if data != old {
+                self.write_page_checked(&layout, page as u16, &data, &old)?;
+                current[start..end].copy_from_slice(&image[start..end]);
+            }
+            Ok(())
+        };
+        let first_page = DATA_AREA_ADDRESS / PAGE_SIZE;
+        let last_page = (end + PAGE_SIZE - 1) / PAGE_SIZE;
+        for &page in &length_pages {
+            write(&empty, page)?;
+        }
+        for page in first_page..last_page {
+            if !length_pages.contains(&page) {
+                write(&full, page)?;
+            }
+        }
+        for &page in &length_pages {
+            write(&full, page)?;
+        }
+        Ok(())
+    }
+
+    fn transceive(&self, frame: &[u8]) -> Result<Vec<u8>, Type2Error> {
+        let session = TransparentSession::start(self.card())?;
+        let response = session.transceive(frame)?;
+        session.end()?;
+        Ok(response)
+    }
+
+    fn command(&self, frame: &[u8], response_len: usize) -> Result<Vec<u8>, Type2Error> {
+        let response = self.transceive(frame)?;
+        match response.len() {
+            len if len == response_len => Ok(response),
+            1 => Err(Type2Error::Nak(response[0] & 0x0F)),
+            _ => Err(Type2Error::MalformedResponse),
+        }
+    }
+
+    /// Get the version of an NTAG21x or MIFARE Ultralight EV1 tag
+    /// (GET_VERSION, 60).
+    pub fn get_version(&self) -> Result<TagVersion, Type2Error> {
+        let version = self.command(&[0x60], 8)?;
+        Ok(TagVersion {
+            vendor_id: version[1],
+            product_type: version[2],
+            product_subtype: version[3],
+            major_version: version[4],
+            minor_version: version[5],
+            storage_size: version[6],
+            protocol_type: version[7],
+        })
+    }
+
+    /// Read the 32 byte originality signature of an NTAG21x tag (READ_SIG,
+    /// 3C).
+    pub fn read_signature(&self) -> Result<Vec<u8>, Type2Error> {
+        self.command(&[0x3C, 0x00], 32)
+    }
+
+    /// Authenticate with a 4 byte password (PWD_AUTH, 1B), and return the
+    /// 2 byte password acknowledge (PACK).
+    ///
+    /// ## Note
+    ///
+    /// The command is sent in its own transparent session. Readers keep
+    /// the RF field on when the session ends, so the authentication holds
+    /// for the following commands, until the tag is deselected.
+    pub fn pwd_auth(&self, password: &[u8; 4]) -> Result<[u8; 2], Type2Error> {
+        let mut frame = vec![0x1B];
+        frame.extend_from_slice(password);
+        let pack = self.command(&frame, 2)?;
+        Ok([pack[0], pack[1]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apdu::Command;
+    use crate::ndef::Record;
+    use crate::simulator::test_card;
+    use std::sync::{Arc, Mutex};
+
+    // An NTAG213: 45 pages, with a 144 byte data area.
+    const PAGES: usize = 45;
+    const CC: [u8; 4] = [0xE1, 0x10, 0x12, 0x00];
+
+    fn data_area(tlvs: &[u8]) -> Vec<u8> {
+        let mut data = tlvs.to_vec();
+        data.resize(144, 0x00);
+        data
+    }
+
+    // Connects to a tag with the given data area, returning the card and
+    // the tag memory.
+    fn connect(data: &[u8]) -> (Card, Arc<Mutex<Vec<u8>>>) {
+        let mut image = vec![0x00; PAGES * PAGE_SIZE];
+        image[12..16].copy_from_slice(&CC);
+        image[16..16 + data.len()].copy_from_slice(data);
+        let memory = Arc::new(Mutex::new(image));
+
+        let image = memory.clone();
+        let (card, _) = test_card(move |bytes| {
+            let command = Command::parse(bytes).unwrap();
+            let address = command.p2() as usize * PAGE_SIZE;
+            let mut image = image.lock().unwrap();
+            match command.ins() {
+                0xB0 if address < image.len() => {
+                    // READ wraps around to page 0.
+                    let mut pages: Vec<u8> = image.iter().cycle().skip(address).take(16).copied().collect();
+                    pages.extend_from_slice(&[0x90, 0x00]);
+                    pages
+                }
+                0xD6 if address < image.len() && command.data().len() == PAGE_SIZE => {
+                    image[address..address + PAGE_SIZE].copy_from_slice(command.data());
+                    vec![0x90, 0x00]
+                }
+                _ => vec![0x6A, 0x82],
+            }
+        });
+        (card, memory)
+    }
+
+    #[test]
+    fn parse_capability_container() {
+        let cc = CapabilityContainer::parse(&CC).unwrap();
+        assert_eq!(cc.data_area_size, 144);
+        assert!(cc.is_readable() && cc.is_writable());
+        assert!(!CapabilityContainer::parse(&[0xE1, 0x10, 0x12, 0x0F])
+            .unwrap()
+            .is_writable());
+        assert_eq!(
+            CapabilityContainer::parse(&[0xE1, 0x20, 0x12, 0x00]),
+            Err(Type2Error::UnsupportedVersion(0x20))
+        );
+        assert_eq!(
+            CapabilityContainer::parse(&[0x00; 4]),
+            Err(Type2Error::InvalidCapabilityContainer)
+        );
+    }
+
+    #[test]
+    fn layout() {
+        // A Lock Control TLV locating 2 lock bytes at address 0x82, over a
+        // Null, proprietary and NDEF Message TLV.
+        let layout = Layout::parse(data_area(&[
+            0x01, 0x03, 0x82, 0x10, 0x44, 0x00, 0xFD, 0x01, 0xAA, 0x03, 0x02, 0xD0, 0x00, 0xFE,
+        ]))
+        .unwrap();
+        assert_eq!(
+            layout.tlvs,
+            [
+                Tlv::LockControl(LockControl {
+                    address: 0x82,
+                    bits: 16,
+                    bytes_locked_per_bit: 16,
+                }),
+                Tlv::Null,
+                Tlv::Proprietary(0xFD, vec![0xAA]),
+                Tlv::NdefMessage(vec![0xD0, 0x00]),
+                Tlv::Terminator,
+            ]
+        );
+        assert_eq!(layout.ndef_address, 16 + 9);
+        assert_eq!(layout.lock_bytes, [STATIC_LOCK_BYTES, (0x82, 0x84)]);
+        assert!(layout.is_lock_byte(0x83) && !layout.is_lock_byte(0x84));
+
+        // Without an NDEF Message TLV, the message goes at the Terminator
+        // TLV, or after the last TLV block.
+        let layout = Layout::parse(data_area(&[0xFD, 0x01, 0xAA, 0x00, 0xFE])).unwrap();
+        assert_eq!(layout.ndef_address, 16 + 4);
+        let layout = Layout::parse(data_area(&[0xFD, 0x01, 0xAA])).unwrap();
+        assert_eq!(layout.ndef_address, 16 + 3);
+        let layout = Layout::parse(data_area(&[])).unwrap();
+        assert_eq!(layout.ndef_address, 16);
+        // The default dynamic lock bytes follow the data area.
+        assert_eq!(layout.lock_bytes, [STATIC_LOCK_BYTES, (160, 162)]);
+
+        assert!(Layout::parse(data_area(&[0x03, 0xFF, 0x01])).is_err());
+        assert!(Layout::parse(data_area(&[0x01, 0x02, 0x00, 0x00])).is_err());
+    }
+
+    #[test]
+    fn layout_skips_reserved_memory() {
+        // 4 reserved bytes at address 0x18, in the middle of the NDEF
+        // Message TLV.
+        let layout = Layout::parse(data_area(&[
+            0x02, 0x03, 0x18, 0x04, 0x04, 0x03, 0x03, 0xD0, 0xEE, 0xEE, 0xEE, 0xEE, 0x00, 0x00, 0xFE,
+        ]))
+        .unwrap();
+        assert_eq!(layout.tlvs[1], Tlv::NdefMessage(vec![0xD0, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn write_message() {
+        let (card, memory) = connect(&data_area(&[0xFD, 0x01, 0xAA, 0xFE]));
+        let tag = Type2Tag::new(&card).unwrap();
+        assert_eq!(tag.read_message().unwrap(), Message::default());
+
+        let message = Message::new(vec![Record::uri("https://example.com/asset/42")]);
+        tag.write_message(&message).unwrap();
+        assert_eq!(tag.read_message().unwrap(), message);
+        let tlvs = tag.read_tlvs().unwrap();
+        assert_eq!(tlvs[0], Tlv::Proprietary(0xFD, vec![0xAA]));
+        assert_eq!(tlvs[1], Tlv::NdefMessage(message.to_bytes()));
+        assert_eq!(tlvs[2], Tlv::Terminator);
+
+        assert_eq!(
+            memory.lock().unwrap()[16..21],
+            [0xFD, 0x01, 0xAA, 0x03, message.to_bytes().len() as u8]
+        );
+
+        let too_long = Message::new(vec![Record::mime("a/b", &[0; 140])]);
+        assert_eq!(
+            tag.write_message(&too_long),
+            Err(Type2Error::MessageTooLong(too_long.to_bytes().len()))
+        );
+    }
+
+    #[test]
+    fn lock_bits() {
+        let (card, memory) = connect(&data_area(&[]));
+        let tag = Type2Tag::new(&card).unwrap();
+
+        // The static lock bytes are bytes 2 and 3 of page 2.
+        assert_eq!(
+            tag.write_page(2, &[0x00, 0x00, 0x00, 0x01]),
+            Err(Type2Error::LockBitsWouldBeSet(2))
+        );
+        // The dynamic lock bytes are the first bytes of page 40.
+        assert_eq!(
+            tag.write_page(40, &[0x80, 0x00, 0x00, 0x00]),
+            Err(Type2Error::LockBitsWouldBeSet(40))
+        );
+        tag.write_page(40, &[0x00, 0x00, 0xAA, 0x00]).unwrap();
+        assert_eq!(memory.lock().unwrap()[8..12], [0x00; 4]);
+
+        let tag = tag.with_lock_bit_writes(true);
+        tag.write_page(2, &[0x00, 0x00, 0x00, 0x01]).unwrap();
+        assert_eq!(memory.lock().unwrap()[8..12], [0x00, 0x00, 0x00, 0x01]);
+        // Bits which are already set may be written again.
+        let tag = tag.with_lock_bit_writes(false);
+        tag.write_page(2, &[0x00, 0x00, 0x00, 0x01]).unwrap();
+    }
+}
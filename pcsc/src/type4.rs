@@ -0,0 +1,472 @@
+//! Reading and writing NDEF messages on NFC Forum Type 4 tags.
+//!
+//! Type 4 tags are ISO 14443-4 cards with the NDEF Tag Application
+//! (D2760000850101). The application holds the Capability Container file
+//! (E103), which gives the maximum APDU sizes and the NDEF file to use,
+//! and the NDEF file, which holds the length of the NDEF message and the
+//! message itself.
+//!
+//! [`Type4Tag`](struct.Type4Tag.html) selects the application, reads the
+//! [`CapabilityContainer`](struct.CapabilityContainer.html), and reads and
+//! writes the NDEF file in chunks of at most MLe and MLc bytes.
+//!
+//! ```no_run
+//! use pcsc::ndef::{Message, Record};
+//! use pcsc::type4::Type4Tag;
+//! use pcsc::*;
+//!
+//! let ctx = Context::establish(Scope::User).unwrap();
+//! let readers = ctx.list_readers_owned().unwrap();
+//! let card = ctx.connect(&readers[0], ShareMode::Shared, Protocols::ANY).unwrap();
+//!
+//! let tag = Type4Tag::select(&card).unwrap();
+//! tag.write_message(&Message::new(vec![Record::uri("https://example.com/asset/42")]))
+//!     .unwrap();
+//! for record in tag.read_message().unwrap().records() {
+//!     println!("{:?}", record.to_uri());
+//! }
+//! ```
+
+use std::error;
+use std::fmt;
+
+use crate::apdu::{ApduError, Command, StatusWord, MAX_SHORT_DATA, MAX_SHORT_LE};
+use crate::ndef::{Message, NdefError};
+use crate::{Card, Error};
+
+/// The AID of the NDEF Tag Application.
+pub const NDEF_APPLICATION_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+
+/// The file identifier of the Capability Container file.
+pub const CAPABILITY_CONTAINER_FILE_ID: u16 = 0xE103;
+
+// READ BINARY and UPDATE BINARY with the even instruction byte address at
+// most 15 bits of offset.
+const MAX_OFFSET: usize = 0x7FFF;
+
+/// An error accessing a Type 4 tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type4Error {
+    /// The PC/SC call failed.
+    Pcsc(Error),
+    /// The tag returned a status word which does not indicate success.
+    Status(StatusWord),
+    /// The response is shorter than a status word.
+    MalformedResponse,
+    /// The Capability Container is not valid.
+    InvalidCapabilityContainer,
+    /// The major mapping version of the Capability Container is not
+    /// supported.
+    UnsupportedVersion(u8),
+    /// The NDEF file is not readable.
+    ReadProtected,
+    /// The NDEF file is not writable.
+    ReadOnly,
+    /// The NDEF message length is over the size of the NDEF file, or over
+    /// the offsets which READ BINARY and UPDATE BINARY can address.
+    MessageTooLong(usize),
+    /// The NDEF message is not valid.
+    Ndef(NdefError),
+}
+
+impl fmt::Display for Type4Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Type4Error::Pcsc(ref err) => write!(f, "{}", err),
+            Type4Error::Status(sw) => write!(f, "{}", sw),
+            Type4Error::MalformedResponse => f.write_str("response shorter than a status word"),
+            Type4Error::InvalidCapabilityContainer => f.write_str("invalid Capability Container"),
+            Type4Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported mapping version {}.{}", version >> 4, version & 0x0F)
+            }
+            Type4Error::ReadProtected => f.write_str("NDEF file not readable"),
+            Type4Error::ReadOnly => f.write_str("NDEF file not writable"),
+            Type4Error::MessageTooLong(len) => write!(f, "NDEF message too long ({} bytes)", len),
+            Type4Error::Ndef(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for Type4Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Type4Error::Pcsc(ref err) => Some(err),
+            Type4Error::Ndef(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for Type4Error {
+    fn from(err: Error) -> Type4Error {
+        Type4Error::Pcsc(err)
+    }
+}
+
+impl From<NdefError> for Type4Error {
+    fn from(err: NdefError) -> Type4Error {
+        Type4Error::Ndef(err)
+    }
+}
+
+/// The NDEF File Control TLV of the Capability Container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NdefFileControl {
+    /// The file identifier of the NDEF file.
+    pub file_id: u16,
+    /// The maximum size of the NDEF file, including its length field.
+    pub max_size: u32,
+    /// The read access condition: 00 for access without any security.
+    pub read_access: u8,
+    /// The write access condition: 00 for access without any security,
+    /// FF for no write access.
+    pub write_access: u8,
+    /// Whether the file uses the 4 byte length field of the extended NDEF
+    /// File Control TLV (mapping version 3.0), instead of 2 bytes.
+    pub extended: bool,
+}
+
+impl NdefFileControl {
+    /// Whether the NDEF file can be read without any security.
+    pub fn is_readable(&self) -> bool {
+        self.read_access == 0x00
+    }
+
+    /// Whether the NDEF file can be written without any security.
+    pub fn is_writable(&self) -> bool {
+        self.write_access == 0x00
+    }
+
+    fn length_field_size(&self) -> usize {
+        if self.extended {
+            4
+        } else {
+            2
+        }
+    }
+}
+
+/// The Capability Container of a Type 4 tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CapabilityContainer {
+    /// The mapping version, major version in the high nibble.
+    pub version: u8,
+    /// MLe, the maximum data length of a READ BINARY response.
+    pub max_le: u16,
+    /// MLc, the maximum data length of an UPDATE BINARY command.
+    pub max_lc: u16,
+    /// The NDEF file.
+    pub ndef_file: NdefFileControl,
+}
+
+impl CapabilityContainer {
+    /// Parse the content of the Capability Container file.
+    pub fn parse(bytes: &[u8]) -> Result<CapabilityContainer, Type4Error> {
+        let (cc_len, version, max_le, max_lc, tlv) = match *bytes {
+            [len_high, len_low, version, le_high, le_low, lc_high, lc_low, ref tlv @ ..] => (
+                u16::from_be_bytes([len_high, len_low]) as usize,
+                version,
+                u16::from_be_bytes([le_high, le_low]),
+                u16::from_be_bytes([lc_high, lc_low]),
+                tlv,
+            ),
+            _ => return Err(Type4Error::InvalidCapabilityContainer),
+        };
+        if cc_len > bytes.len() {
+            return Err(Type4Error::InvalidCapabilityContainer);
+        }
+        if !(2..=3).contains(&(version >> 4)) {
+            return Err(Type4Error::UnsupportedVersion(version));
+        }
+        let ndef_file = match *tlv {
+            [0x04, 0x06, id_high, id_low, size_high, size_low, read_access, write_access, ..] => NdefFileControl {
+                file_id: u16::from_be_bytes([id_high, id_low]),
+                max_size: u32::from(u16::from_be_bytes([size_high, size_low])),
+                read_access,
+                write_access,
+                extended: false,
+            },
+            [0x06, 0x08, id_high, id_low, s0, s1, s2, s3, read_access, write_access, ..] => NdefFileControl {
+                file_id: u16::from_be_bytes([id_high, id_low]),
+                max_size: u32::from_be_bytes([s0, s1, s2, s3]),
+                read_access,
+                write_access,
+                extended: true,
+            },
+            _ => return Err(Type4Error::InvalidCapabilityContainer),
+        };
+        // MLe and MLc are at least 000F and 0001.
+        if max_le < 0x0F || max_lc < 0x01 {
+            return Err(Type4Error::InvalidCapabilityContainer);
+        }
+        Ok(CapabilityContainer {
+            version,
+            max_le,
+            max_lc,
+            ndef_file,
+        })
+    }
+}
+
+/// An NFC Forum Type 4 tag, with its NDEF Tag Application selected.
+///
+/// The commands are sent with `Card::exchange()` as short APDUs, so the
+/// chunks are also limited to `MAX_SHORT_LE` and `MAX_SHORT_DATA` bytes.
+pub struct Type4Tag<'card> {
+    card: &'card Card,
+    capability_container: CapabilityContainer,
+}
+
+impl<'card> Type4Tag<'card> {
+    /// Select the NDEF Tag Application of `card`, and read its Capability
+    /// Container.
+    pub fn select(card: &'card Card) -> Result<Type4Tag<'card>, Type4Error> {
+        let command = Command::new(0x00, 0xA4, 0x04, 0x00)
+            .with_data(&NDEF_APPLICATION_AID)
+            .with_le(MAX_SHORT_LE);
+        exchange(card, &command)?;
+        select_file(card, CAPABILITY_CONTAINER_FILE_ID)?;
+        let header = read_binary(card, 0, 2)?;
+        let cc_len = match *header {
+            [high, low] => u16::from_be_bytes([high, low]) as usize,
+            _ => return Err(Type4Error::InvalidCapabilityContainer),
+        };
+        if !(0x0F..=MAX_SHORT_LE).contains(&cc_len) {
+            return Err(Type4Error::InvalidCapabilityContainer);
+        }
+        let cc = read_binary(card, 0, cc_len)?;
+        let capability_container = CapabilityContainer::parse(&cc)?;
+        select_file(card, capability_container.ndef_file.file_id)?;
+        Ok(Type4Tag {
+            card,
+            capability_container,
+        })
+    }
+
+    /// The card of the tag.
+    pub fn card(&self) -> &'card Card {
+        self.card
+    }
+
+    /// The Capability Container of the tag.
+    pub fn capability_container(&self) -> &CapabilityContainer {
+        &self.capability_container
+    }
+
+    fn max_le(&self) -> usize {
+        usize::from(self.capability_container.max_le).min(MAX_SHORT_LE)
+    }
+
+    fn max_lc(&self) -> usize {
+        usize::from(self.capability_container.max_lc).min(MAX_SHORT_DATA)
+    }
+
+    /// Read the content of the NDEF file: the NDEF message, without its
+    /// length field.
+    pub fn read_ndef(&self) -> Result<Vec<u8>, Type4Error> {
+        let file = &self.capability_container.ndef_file;
+        if !file.is_readable() {
+            return Err(Type4Error::ReadProtected);
+        }
+        let field_size = file.length_field_size();
+        let field = read_binary(self.card, 0, field_size)?;
+        if field.len() != field_size {
+            return Err(Type4Error::MalformedResponse);
+        }
+        let len = field.iter().fold(0usize, |len, &byte| len << 8 | byte as usize);
+        if field_size + len > file.max_size as usize || field_size + len > MAX_OFFSET + 1 {
+            return Err(Type4Error::MessageTooLong(len));
+        }
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let chunk_len = (len - data.len()).min(self.max_le());
+            let chunk = read_binary(self.card, field_size + data.len(), chunk_len)?;
+            if chunk.is_empty() {
+                return Err(Type4Error::MalformedResponse);
+            }
+            data.extend_from_slice(&chunk);
+        }
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Read the NDEF message.
+    pub fn read_message(&self) -> Result<Message, Type4Error> {
+        Ok(Message::parse(&self.read_ndef()?)?)
+    }
+
+    /// Write the content of the NDEF file: an NDEF message, without its
+    /// length field.
+    ///
+    /// As specified for Type 4 tags, the length field is set to 0 while
+    /// the message is written, so that an interrupted write leaves an
+    /// empty NDEF file rather than a corrupted message.
+    pub fn write_ndef(&self, data: &[u8]) -> Result<(), Type4Error> {
+        let file = &self.capability_container.ndef_file;
+        if !file.is_writable() {
+            return Err(Type4Error::ReadOnly);
+        }
+        let field_size = file.length_field_size();
+        if field_size + data.len() > file.max_size as usize || field_size + data.len() > MAX_OFFSET + 1 {
+            return Err(Type4Error::MessageTooLong(data.len()));
+        }
+        let len = (data.len() as u32).to_be_bytes();
+        let len = &len[4 - field_size..];
+        update_binary(self.card, 0, &vec![0x00; field_size])?;
+        let mut offset = 0;
+        for chunk in data.chunks(self.max_lc()) {
+            update_binary(self.card, field_size + offset, chunk)?;
+            offset += chunk.len();
+        }
+        update_binary(self.card, 0, len)
+    }
+
+    /// Write an NDEF message.
+    pub fn write_message(&self, message: &Message) -> Result<(), Type4Error> {
+        self.write_ndef(&message.to_bytes())
+    }
+}
+
+fn exchange(card: &Card, command: &Command) -> Result<Vec<u8>, Type4Error> {
+    let response = match card.exchange(command) {
+        Ok(response) => response,
+        Err(ApduError::Pcsc(err)) => return Err(Type4Error::Pcsc(err)),
+        Err(ApduError::InvalidCommand(_)) => return Err(Type4Error::Pcsc(Error::InvalidParameter)),
+        Err(_) => return Err(Type4Error::MalformedResponse),
+    };
+    if response.status() == StatusWord::Success {
+        Ok(response.into_data())
+    } else {
+        Err(Type4Error::Status(response.status()))
+    }
+}
+
+fn select_file(card: &Card, file_id: u16) -> Result<(), Type4Error> {
+    let command = Command::new(0x00, 0xA4, 0x00, 0x0C).with_data(&file_id.to_be_bytes());
+    exchange(card, &command).map(drop)
+}
+
+fn read_binary(card: &Card, offset: usize, len: usize) -> Result<Vec<u8>, Type4Error> {
+    let [p1, p2] = (offset as u16).to_be_bytes();
+    let command = Command::new(0x00, 0xB0, p1, p2).with_le(len);
+    exchange(card, &command)
+}
+
+fn update_binary(card: &Card, offset: usize, data: &[u8]) -> Result<(), Type4Error> {
+    let [p1, p2] = (offset as u16).to_be_bytes();
+    let command = Command::new(0x00, 0xD6, p1, p2).with_data(data);
+    exchange(card, &command).map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndef::Record;
+    use crate::simulator::test_card;
+    use std::sync::{Arc, Mutex};
+
+    // Mapping version 2.0, MLe 000F, MLc 000D, NDEF file E104 of 0080
+    // bytes.
+    const CC: [u8; 15] = [
+        0x00, 0x0F, 0x20, 0x00, 0x0F, 0x00, 0x0D, 0x04, 0x06, 0xE1, 0x04, 0x00, 0x80, 0x00, 0x00,
+    ];
+
+    // Connects to a tag with the given Capability Container, returning the
+    // card and the content of its NDEF file.
+    fn connect(cc: &[u8]) -> (Card, Arc<Mutex<Vec<u8>>>) {
+        let ndef_file = Arc::new(Mutex::new(vec![0; 0x80]));
+        let file = ndef_file.clone();
+        let cc = cc.to_vec();
+        let mut selected = None;
+        let (card, _) = test_card(move |bytes| {
+            let command = Command::parse(bytes).unwrap();
+            let offset = u16::from_be_bytes([command.p1(), command.p2()]) as usize;
+            let mut file = file.lock().unwrap();
+            match (command.ins(), command.p1(), selected) {
+                (0xA4, 0x04, _) if command.data() == NDEF_APPLICATION_AID => vec![0x90, 0x00],
+                (0xA4, 0x00, _) => {
+                    selected = Some(u16::from_be_bytes([command.data()[0], command.data()[1]]));
+                    vec![0x90, 0x00]
+                }
+                (0xB0, _, Some(CAPABILITY_CONTAINER_FILE_ID)) => {
+                    let end = (offset + command.le().unwrap()).min(cc.len());
+                    [&cc[offset..end], &[0x90, 0x00]].concat()
+                }
+                (0xB0, _, Some(0xE104)) if command.le().unwrap() <= 0x0F => {
+                    let end = (offset + command.le().unwrap()).min(file.len());
+                    [&file[offset..end], &[0x90, 0x00]].concat()
+                }
+                (0xD6, _, Some(0xE104)) if command.data().len() <= 0x0D => {
+                    file[offset..offset + command.data().len()].copy_from_slice(command.data());
+                    vec![0x90, 0x00]
+                }
+                _ => vec![0x6A, 0x82],
+            }
+        });
+        (card, ndef_file)
+    }
+
+    #[test]
+    fn parse_capability_container() {
+        let cc = CapabilityContainer::parse(&CC).unwrap();
+        assert_eq!(cc.version, 0x20);
+        assert_eq!((cc.max_le, cc.max_lc), (0x0F, 0x0D));
+        assert_eq!(
+            cc.ndef_file,
+            NdefFileControl {
+                file_id: 0xE104,
+                max_size: 0x80,
+                read_access: 0x00,
+                write_access: 0x00,
+                extended: false,
+            }
+        );
+
+        let extended = [
+            0x00, 0x11, 0x30, 0x00, 0xFF, 0x00, 0xFF, 0x06, 0x08, 0xE1, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0xFF,
+        ];
+        let cc = CapabilityContainer::parse(&extended).unwrap();
+        assert!(cc.ndef_file.extended);
+        assert_eq!(cc.ndef_file.max_size, 0x10000);
+        assert!(!cc.ndef_file.is_writable());
+
+        let mut version = CC;
+        version[2] = 0x40;
+        assert_eq!(
+            CapabilityContainer::parse(&version),
+            Err(Type4Error::UnsupportedVersion(0x40))
+        );
+        assert_eq!(
+            CapabilityContainer::parse(&CC[..10]),
+            Err(Type4Error::InvalidCapabilityContainer)
+        );
+    }
+
+    #[test]
+    fn read_and_write() {
+        let (card, ndef_file) = connect(&CC);
+        let tag = Type4Tag::select(&card).unwrap();
+        assert_eq!(tag.capability_container().ndef_file.file_id, 0xE104);
+        assert_eq!(tag.read_message().unwrap(), Message::default());
+
+        let message = Message::new(vec![Record::uri("https://example.com/asset/42")]);
+        tag.write_message(&message).unwrap();
+        let bytes = message.to_bytes();
+        assert_eq!(ndef_file.lock().unwrap()[..2], (bytes.len() as u16).to_be_bytes());
+        assert_eq!(tag.read_ndef().unwrap(), bytes);
+        assert_eq!(tag.read_message().unwrap(), message);
+
+        assert_eq!(tag.write_ndef(&[0; 0x7F]), Err(Type4Error::MessageTooLong(0x7F)));
+        ndef_file.lock().unwrap()[..2].copy_from_slice(&[0x00, 0x7F]);
+        assert_eq!(tag.read_ndef(), Err(Type4Error::MessageTooLong(0x7F)));
+    }
+
+    #[test]
+    fn read_only() {
+        let mut cc = CC;
+        cc[14] = 0xFF;
+        let (card, _) = connect(&cc);
+        let tag = Type4Tag::select(&card).unwrap();
+        assert_eq!(tag.write_ndef(b""), Err(Type4Error::ReadOnly));
+    }
+}
@@ -0,0 +1,149 @@
+#![cfg(feature = "derive")]
+
+use pcsc::tlv::{DecodeErrorKind, Tag, TlvBuilder, TlvDecode, TlvEncode};
+
+#[derive(Debug, PartialEq, TlvDecode, TlvEncode)]
+struct Entry {
+    #[tlv(tag = 0x50)]
+    label: String,
+    #[tlv(tag = 0x87)]
+    priority: Option<u8>,
+}
+
+#[derive(Debug, PartialEq, TlvDecode, TlvEncode)]
+#[tlv(tag = 0x61)]
+struct Directory {
+    #[tlv(tag = 0x4F)]
+    aid: Vec<u8>,
+    #[tlv(tag = 0xA5, repeated)]
+    entries: Vec<Entry>,
+    #[tlv(tag = 0x5F2D)]
+    language: Option<[u8; 2]>,
+}
+
+// Fields named like the locals and parameters of the generated code.
+#[derive(Debug, PartialEq, TlvDecode, TlvEncode)]
+#[tlv(tag = 0x70)]
+struct Shadowing {
+    #[tlv(tag = 0x80)]
+    value: Vec<u8>,
+    #[tlv(tag = 0x81)]
+    object: Option<u16>,
+    #[tlv(tag = 0x82)]
+    out: u8,
+    #[tlv(tag = 0x83)]
+    tag: String,
+    #[tlv(tag = 0x84)]
+    bytes: Option<Vec<u8>>,
+    #[tlv(tag = 0x85)]
+    err: Option<u32>,
+    #[tlv(tag = 0x86)]
+    r#type: u8,
+}
+
+#[test]
+fn round_trip() {
+    let directory = Directory {
+        aid: vec![0xA0, 0x00, 0x00, 0x00, 0x03],
+        entries: vec![
+            Entry {
+                label: "VISA".to_owned(),
+                priority: Some(1),
+            },
+            Entry {
+                label: "PLUS".to_owned(),
+                priority: None,
+            },
+        ],
+        language: Some(*b"en"),
+    };
+    let bytes = directory.encode_tlv();
+    assert_eq!(
+        bytes,
+        TlvBuilder::new()
+            .with_constructed(
+                Tag::new(0x61),
+                TlvBuilder::new()
+                    .with_primitive(Tag::new(0x4F), &[0xA0, 0x00, 0x00, 0x00, 0x03])
+                    .with_constructed(
+                        Tag::new(0xA5),
+                        TlvBuilder::new()
+                            .with_primitive(Tag::new(0x50), b"VISA")
+                            .with_primitive(Tag::new(0x87), &[0x01]),
+                    )
+                    .with_constructed(
+                        Tag::new(0xA5),
+                        TlvBuilder::new().with_primitive(Tag::new(0x50), b"PLUS")
+                    )
+                    .with_primitive(Tag::new(0x5F2D), b"en"),
+            )
+            .into_bytes()
+    );
+    assert_eq!(Directory::decode_tlv(&bytes).unwrap(), directory);
+}
+
+#[test]
+fn shadowing_field_names() {
+    let shadowing = Shadowing {
+        value: vec![0x01, 0x02],
+        object: Some(0x0304),
+        out: 0x05,
+        tag: "tag".to_owned(),
+        bytes: None,
+        err: Some(0x0607_0809),
+        r#type: 0x0A,
+    };
+    let bytes = shadowing.encode_tlv();
+    assert_eq!(Shadowing::decode_tlv(&bytes).unwrap(), shadowing);
+
+    let mut value = Vec::new();
+    shadowing.encode_value(&mut value);
+    assert_eq!(Shadowing::decode_value(&value).unwrap(), shadowing);
+}
+
+#[test]
+fn optional_and_unknown() {
+    // The optional fields are absent, and the unknown data object is
+    // ignored.
+    let bytes = [0x61, 0x09, 0x4F, 0x02, 0xA0, 0x00, 0x9F, 0x08, 0x02, 0x00, 0x01];
+    let directory = Directory::decode_tlv(&bytes).unwrap();
+    assert_eq!(
+        directory,
+        Directory {
+            aid: vec![0xA0, 0x00],
+            entries: Vec::new(),
+            language: None,
+        }
+    );
+    assert_eq!(directory.encode_tlv(), [0x61, 0x04, 0x4F, 0x02, 0xA0, 0x00]);
+}
+
+#[test]
+fn first_occurrence() {
+    let bytes = [0x61, 0x08, 0x4F, 0x02, 0xA0, 0x00, 0x4F, 0x02, 0xB0, 0x00];
+    assert_eq!(Directory::decode_tlv(&bytes).unwrap().aid, [0xA0, 0x00]);
+}
+
+#[test]
+fn decode_errors() {
+    // The template itself is missing.
+    let err = Directory::decode_tlv(&[0x6F, 0x00]).unwrap_err();
+    assert_eq!(err.path(), [Tag::new(0x61)]);
+    assert_eq!(err.kind(), DecodeErrorKind::Missing);
+
+    // A required field is missing, in a nested data object.
+    let bytes = [0x61, 0x09, 0x4F, 0x00, 0xA5, 0x03, 0x87, 0x01, 0x01, 0xA5, 0x00];
+    let err = Directory::decode_tlv(&bytes).unwrap_err();
+    assert_eq!(err.path(), [Tag::new(0x61), Tag::new(0xA5), Tag::new(0x50)]);
+    assert_eq!(err.to_string(), "61/A5/50: missing data object");
+
+    // A value is not valid for its type.
+    let bytes = [0x61, 0x06, 0x4F, 0x00, 0x5F, 0x2D, 0x01, 0x65];
+    let err = Directory::decode_tlv(&bytes).unwrap_err();
+    assert_eq!(err.path(), [Tag::new(0x61), Tag::new(0x5F2D)]);
+    assert_eq!(err.kind(), DecodeErrorKind::InvalidValue);
+
+    // The data is truncated.
+    let err = Directory::decode_tlv(&[0x61, 0x04, 0x4F, 0x05, 0xA0, 0x00]).unwrap_err();
+    assert!(matches!(err.kind(), DecodeErrorKind::Malformed(_)));
+}